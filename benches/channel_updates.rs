@@ -0,0 +1,92 @@
+// Cost of the channel updates a crowded server sends when one client moves between
+// channels: every member of the channel it left gets the new list, either whole or
+// as a delta. To see what a change to generate_channel_updates wins, save a baseline
+// before it and compare after it:
+//
+//     cargo bench --bench channel_updates -- --save-baseline before
+//     cargo bench --bench channel_updates -- --baseline before
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, Hello, JoinChannel};
+use chat_common::packet_handling::CommandHandler;
+use chat_server_client::protocol::SOFTWARE_VERSION;
+use chat_server_client::server::{ChatServerConfig, ChatServerInternal};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+use wg_2024::network::NodeId;
+
+// Node ids are bytes, so at most this many clients fit next to the server
+const CLIENT_COUNTS: [u8; 3] = [100, 175, 250];
+const SERVER: NodeId = 0;
+
+fn send(server: &mut ChatServerInternal, from: NodeId, kind: MessageKind) -> usize {
+    let (replies, _) = server.handle_protocol_message(
+        ChatMessage {
+            own_id: u32::from(from),
+            request_id: 0,
+            message_kind: Some(kind),
+        },
+        from,
+    );
+    replies.len()
+}
+
+fn join(server: &mut ChatServerInternal, from: NodeId, channel: &str) -> usize {
+    send(
+        server,
+        from,
+        MessageKind::CliJoin(JoinChannel {
+            channel_id: None,
+            channel_name: channel.to_string(),
+        }),
+    )
+}
+
+// `clients` registered clients, all in "lobby", plus one channel of their own each so
+// that the list is long
+fn crowded_server(clients: u8, deltas: bool) -> ChatServerInternal {
+    let mut server = ChatServerInternal::with_config(SERVER, ChatServerConfig::default());
+    let capabilities = if deltas {
+        vec!["channel-delta".to_string()]
+    } else {
+        vec![]
+    };
+    for id in 1..=clients {
+        send(
+            &mut server,
+            id,
+            MessageKind::CliHello(Hello {
+                version: SOFTWARE_VERSION.to_string(),
+                capabilities: capabilities.clone(),
+                challenge: vec![0; 32],
+            }),
+        );
+        send(
+            &mut server,
+            id,
+            MessageKind::CliRegisterRequest(format!("user{id}")),
+        );
+        join(&mut server, id, &format!("room{id}"));
+        join(&mut server, id, "lobby");
+    }
+    server
+}
+
+fn channel_updates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("channel_updates");
+    for clients in CLIENT_COUNTS {
+        for (name, deltas) in [("full_list", false), ("delta", true)] {
+            let mut server = crowded_server(clients, deltas);
+            group.bench_with_input(BenchmarkId::new(name, clients), &clients, |b, _| {
+                // Leaving the lobby and coming back, every member hears of both
+                b.iter(|| {
+                    black_box(join(&mut server, 1, "games"));
+                    black_box(join(&mut server, 1, "lobby"));
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, channel_updates);
+criterion_main!(benches);
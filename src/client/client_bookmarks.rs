@@ -0,0 +1,213 @@
+use crate::client::client_history::HISTORY_PAGE_SIZE;
+use crate::client::ChatClientInternal;
+use chat_common::messages::{ChatMessage, MessageData};
+use common::slc_commands::ChatClientEvent;
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use wg_2024::network::NodeId;
+
+// Recently displayed messages kept so that they can be bookmarked by id
+const RECENT_KEPT: usize = 256;
+
+#[derive(Debug, Clone)]
+struct Bookmark {
+    message_id: u64,
+    channel_id: u64,
+    author: String,
+    text: String,
+    note: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct Bookmarks {
+    entries: Vec<Bookmark>,
+    // Messages bookmarks can be taken from, by message id
+    recent: BTreeMap<u64, MessageData>,
+    // File the bookmarks are kept in between runs, one per line
+    path: Option<PathBuf>,
+}
+
+impl Bookmarks {
+    pub(crate) fn load(path: Option<PathBuf>) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|data| data.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+        Self {
+            entries,
+            recent: BTreeMap::new(),
+            path,
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        match &self.path {
+            Some(path) => fs::write(
+                path,
+                self.entries
+                    .iter()
+                    .map(|x| {
+                        format!(
+                            "{}\t{}\t{}\t{}\t{}\n",
+                            x.message_id,
+                            x.channel_id,
+                            escape(&x.author),
+                            escape(&x.text),
+                            escape(&x.note)
+                        )
+                    })
+                    .collect::<String>(),
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+// Tabs and newlines separate fields and bookmarks, so they're escaped inside fields
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn parse_line(line: &str) -> Option<Bookmark> {
+    let mut fields = line.split('\t');
+    let bookmark = Bookmark {
+        message_id: fields.next()?.parse().ok()?,
+        channel_id: fields.next()?.parse().ok()?,
+        author: unescape(fields.next()?),
+        text: unescape(fields.next()?),
+        note: unescape(fields.next()?),
+    };
+    Some(bookmark)
+}
+
+impl ChatClientInternal {
+    pub(crate) fn remember_recent(&mut self, msg: &MessageData) {
+        self.bookmarks.recent.insert(msg.message_id, msg.clone());
+        while self.bookmarks.recent.len() > RECENT_KEPT {
+            self.bookmarks.recent.pop_first();
+        }
+    }
+
+    pub(crate) fn cmd_bookmark(
+        &mut self,
+        arg: &str,
+        note: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(msg) = arg
+            .parse::<u64>()
+            .ok()
+            .and_then(|id| self.bookmarks.recent.get(&id))
+        else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: No recent message with that id".to_string(),
+                )],
+            );
+        };
+        let bookmark = Bookmark {
+            message_id: msg.message_id,
+            channel_id: msg.channel_id,
+            author: msg.username.clone(),
+            text: msg.message.clone(),
+            note: note.trim().to_string(),
+        };
+        self.bookmarks
+            .entries
+            .retain(|x| x.message_id != bookmark.message_id);
+        self.bookmarks.entries.push(bookmark);
+        if let Err(e) = self.bookmarks.save() {
+            warn!(target: self.log_target.as_str(), "Could not save bookmarks: {e}");
+        }
+        (
+            vec![],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Bookmarked message {arg}"
+            ))],
+        )
+    }
+
+    // Lists bookmarks, or with an index fetches the history leading up to that bookmark
+    pub(crate) fn cmd_bookmarks(
+        &self,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        if arg.is_empty() {
+            let msg = if self.bookmarks.entries.is_empty() {
+                "[SYSTEM] No bookmarks yet, add one with /bookmark <id> [note]".to_string()
+            } else {
+                self.bookmarks.entries.iter().enumerate().fold(
+                    "[SYSTEM] Bookmarks:".to_string(),
+                    |mut acc, (i, x)| {
+                        acc.push_str(&format!(
+                            "\n[SYSTEM]    {}. [#{} @{}] {}",
+                            i + 1,
+                            self.channel_display_name(x.channel_id),
+                            x.author,
+                            x.text
+                        ));
+                        if !x.note.is_empty() {
+                            acc.push_str(&format!(" ({})", x.note));
+                        }
+                        acc
+                    },
+                )
+            };
+            return (vec![], vec![ChatClientEvent::MessageReceived(msg)]);
+        }
+        let Some(bookmark) = arg
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| self.bookmarks.entries.get(i.checked_sub(1)?))
+        else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: No such bookmark".to_string(),
+                )],
+            );
+        };
+        if self.currently_connected_server.is_none() {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Not connected to a server.".to_string(),
+                )],
+            );
+        }
+        (
+            self.fetch_history(
+                bookmark.channel_id,
+                Some(bookmark.message_id + 1),
+                HISTORY_PAGE_SIZE,
+            ),
+            vec![ChatClientEvent::MessageReceived(
+                "[SYSTEM] Fetching history up to the bookmark...".to_string(),
+            )],
+        )
+    }
+}
@@ -0,0 +1,108 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::ChannelDelta;
+use log::debug;
+
+impl ChatClientInternal {
+    // Applies a change to the channel list we got before. Applying one twice changes
+    // nothing, so a delta crossing a full list on the way does no harm
+    pub(crate) fn msg_srvchanneldelta(&mut self, delta: ChannelDelta) {
+        debug!(target: self.log_target.as_str(), "Applying channel delta: {delta:?}");
+        self.channels_list.retain(|chan| {
+            !delta.removed.contains(&chan.channel_id)
+                && !delta
+                    .added
+                    .iter()
+                    .any(|added| added.channel_id == chan.channel_id)
+        });
+        self.channels_list.extend(delta.added);
+        for change in delta.left {
+            if let Some(chan) = self
+                .channels_list
+                .iter_mut()
+                .find(|chan| chan.channel_id == change.channel_id)
+            {
+                chan.connected_clients
+                    .retain(|client| *client != change.client);
+            }
+        }
+        for change in delta.joined {
+            if let Some(chan) = self
+                .channels_list
+                .iter_mut()
+                .find(|chan| chan.channel_id == change.channel_id)
+            {
+                chan.connected_clients
+                    .retain(|client| *client != change.client);
+                chan.connected_clients.push(change.client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{ChatClientConfig, ChatClientInternal};
+    use chat_common::messages::{Channel, ChannelDelta, ChannelKind, ClientData, MemberChange};
+
+    fn client_data(username: &str) -> ClientData {
+        ClientData {
+            username: username.to_string(),
+            id: 5,
+            presence: "active".to_string(),
+            status: "online".to_string(),
+            status_text: String::new(),
+            current_channel: None,
+            color: 0,
+            role: "member".to_string(),
+        }
+    }
+
+    fn channel(id: u64, name: &str, members: Vec<ClientData>) -> Channel {
+        Channel {
+            channel_name: name.to_string(),
+            channel_id: id,
+            channel_is_group: true,
+            channel_kind: ChannelKind::Group,
+            messages_today: 0,
+            last_activity: 0,
+            unique_speakers: 0,
+            connected_clients: members,
+            creator: String::new(),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn deltas_apply_once_however_often_they_arrive() {
+        let mut client = ChatClientInternal::with_config(
+            1,
+            ChatClientConfig {
+                signing_key_dir: None,
+                ..ChatClientConfig::default()
+            },
+        );
+        client.channels_list = vec![channel(2, "games", vec![]), channel(18, "music", vec![])];
+        let delta = ChannelDelta {
+            added: vec![channel(34, "movies", vec![])],
+            removed: vec![18],
+            joined: vec![MemberChange {
+                channel_id: 2,
+                client: client_data("alice"),
+            }],
+            left: vec![],
+        };
+        client.msg_srvchanneldelta(delta.clone());
+        client.msg_srvchanneldelta(delta);
+        let mut ids = client
+            .channels_list
+            .iter()
+            .map(|x| x.channel_id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![2, 34]);
+        assert_eq!(
+            client.channels_list[0].connected_clients,
+            vec![client_data("alice")]
+        );
+    }
+}
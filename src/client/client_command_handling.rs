@@ -1,27 +1,26 @@
+use crate::client::client_commands::{
+    command_help, find_command, help_message, required_capability, suggest_command,
+};
+use crate::client::client_requests::RequestKind;
 use crate::client::ChatClientInternal;
+use crate::protocol::{
+    is_compatible_version, parse_server_type, personal_channel_id, server_type_name, Role,
+    SOFTWARE_VERSION,
+};
 use chat_common::messages::chat_message::MessageKind;
-use chat_common::messages::{ChatMessage, Empty, JoinChannel};
-use common::slc_commands::ChatClientEvent;
+use chat_common::messages::{
+    ChannelKind, ChatMessage, ClientData, Empty, JoinChannel, SendMessage, SetStatus, SetWelcome,
+};
+use common::slc_commands::{ChatClientEvent, ConnectionState, ServerType};
 use itertools::Itertools;
 use log::info;
 use wg_2024::network::NodeId;
 
 const SERVER_NOT_FOUND: &str = "[SYSTEM] Error: Server not found";
-const HELP_MESSAGE: &str = r"
-[SYSTEM] Commands:
-[SYSTEM]    /help - Display this message
-[SYSTEM]    /servers - Lists discovered servers
-[SYSTEM]    /connect <server_id> - Connect to a server
-[SYSTEM]    /register <username> - Register with a server. Username cannot contain spaces or '#' and '@'.
-[SYSTEM]    /unregister - Unregister from the current server.
-[SYSTEM]    /channels - List all channels available on the server.
-[SYSTEM]    /join <channel> - Join a channel. You can only be in one channel at a time.
-[SYSTEM]    /leave <channel> - Leave the current channel. You will still receive DMs and system communications.
-[SYSTEM]    /msg <user> <text> - Send a direct message to a user.
-";
 const NOT_CONNECTED_TO_SERVER: &str = "[SYSTEM] Error: Not connected to a server. Use /servers to find servers and /connect <server_id> to connect to a server before registering.";
 const USERNAME_DISALLOWED_CHARS: &str =
     "[SYSTEM] Error: Username cannot contain spaces, '#' or '@'";
+const NO_SUCH_SUGGESTION: &str = "[SYSTEM] Error: No such suggested username";
 const USER_NOT_FOUND: &str = "[SYSTEM] Error: User not found";
 const NO_ALL_CHAN: &str = "[SYSTEM] Error: No 'all' channel found";
 const PLEASE_REGISTER: &str =
@@ -34,6 +33,30 @@ const JOINING_CHAN: &str = "[SYSTEM] Joining channel...";
 const CREATING_CHAN: &str = "[SYSTEM] Creating channel...";
 const UNREGISTERING: &str = "[SYSTEM] Removing registration...";
 const NOT_REGISTERED_ERR: &str = "[SYSTEM] Not registered to this server!";
+const WELCOME_UPDATED: &str = "[SYSTEM] Updating channel welcome message...";
+const NO_SUCH_SPOILER: &str = "[SYSTEM] Error: No hidden message with that id";
+// Channels without messages for this long are listed apart
+const STALE_CHANNEL_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+fn unknown_command(command: &str) -> String {
+    match suggest_command(command) {
+        Some(suggestion) => format!(
+            "[SYSTEM] Unknown command {command}. Did you mean /{suggestion}? Use /help to list available commands."
+        ),
+        None => format!(
+            "[SYSTEM] Unknown command {command}. Use /help to list available commands."
+        ),
+    }
+}
+
+// "away: lunch" and the like, None for users simply online
+fn status_label(client: &ClientData) -> Option<String> {
+    match (client.status.as_str(), client.status_text.as_str()) {
+        ("online" | "", "") => None,
+        (status, "") => Some(status.to_string()),
+        (status, text) => Some(format!("{status}: {text}")),
+    }
+}
 
 impl ChatClientInternal {
     pub(crate) fn handle_command(
@@ -42,9 +65,22 @@ impl ChatClientInternal {
         arg: &str,
         freeform: &str,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
-        info!(target: format!("Client {}", self.own_id).as_str(), "Handling text command: [{} - {} - {}]", command, arg, freeform);
+        info!(target: self.log_target.as_str(), "Handling text command: [{} - {} - {}]", command, arg, freeform);
+        if let Some(capability) = required_capability(command) {
+            if let Some(server_id) = self.currently_connected_server {
+                if !self.server_supports(server_id, capability) {
+                    return (
+                        vec![],
+                        vec![ChatClientEvent::MessageReceived(format!(
+                            "[SYSTEM] Error: {} doesn't support /{command}",
+                            self.server_display_name(server_id)
+                        ))],
+                    );
+                }
+            }
+        }
         match command {
-            "register" | "unregister" | "channels" | "join" | "leave" | "msg" => {
+            command if find_command(command).is_some_and(|spec| spec.needs_server) => {
                 self.currently_connected_server.map_or_else(
                     || {
                         (
@@ -59,17 +95,39 @@ impl ChatClientInternal {
                     },
                 )
             }
-            "help" => (
-                vec![],
-                vec![ChatClientEvent::MessageReceived(HELP_MESSAGE.to_string())],
-            ),
-            "servers" => self.cmd_servers(),
-            "connect" => self.cmd_connect(arg),
+            "help" => {
+                let msg = match find_command(arg.trim_start_matches('/')) {
+                    Some(spec) => command_help(spec),
+                    None if arg.is_empty() => help_message(),
+                    None => unknown_command(arg),
+                };
+                (vec![], vec![ChatClientEvent::MessageReceived(msg)])
+            }
+            "servers" => self.cmd_servers(&[arg, freeform]),
+            "route" => self.cmd_route(arg),
+            "connect" if arg == "auto" => self.connect_best(Some(freeform)),
+            "connect" => self.cmd_connect(arg, freeform == "--force"),
+            "history" => self.cmd_history(arg),
+            "bookmark" => self.cmd_bookmark(arg, freeform),
+            "bookmarks" => self.cmd_bookmarks(arg),
+            "stats" => self.cmd_stats(),
+            "ping" => self.cmd_ping(arg),
+            "invites" => self.cmd_invites(),
+            "acceptfile" => self.cmd_answerfile(arg, true),
+            "declinefile" => self.cmd_answerfile(arg, false),
+            "reveal" => self.cmd_reveal(arg),
+            "emoji" => self.cmd_emoji(arg),
+            "links" => self.cmd_links(arg),
+            "info" => self.cmd_info(arg),
+            "raw" => self.cmd_raw(arg),
+            "trust" => self.cmd_trust(arg),
+            "forgetkey" => self.cmd_forgetkey(arg),
+            "watch" => self.cmd_watch(arg, freeform),
+            "key" => self.cmd_key(arg, freeform),
+            "spoiler" => self.handle_text_message(freeform, Some(arg.to_string())),
             _ => (
                 vec![],
-                vec![ChatClientEvent::MessageReceived(format!(
-                    "[SYSTEM] Unknown command {command}. Use /help to list available commands."
-                ))],
+                vec![ChatClientEvent::MessageReceived(unknown_command(command))],
             ),
         }
     }
@@ -83,73 +141,326 @@ impl ChatClientInternal {
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
         match command {
             "unregister" => self.cmd_unregister(server_id),
-            "channels" => self.cmd_channels(server_id),
+            "channels" => self.cmd_channels(server_id, arg),
+            "users" => self.cmd_users(server_id),
+            "status" => self.cmd_status(server_id, arg, freeform),
+            "search" => self.cmd_search(server_id, arg, freeform),
+            "welcome" => self.cmd_welcome(server_id, &format!("{arg} {freeform}")),
+            "moderate" => self.cmd_moderate(server_id, arg),
+            "readonly" => self.cmd_readonly(server_id, arg),
+            "slowmode" => self.cmd_slowmode(server_id, arg),
+            "invite" => self.cmd_invite(server_id, arg),
+            "inviteonly" => self.cmd_inviteonly(server_id, arg),
+            "votekick" => self.cmd_votekick(server_id, arg),
+            "react" => self.cmd_react(server_id, arg, freeform),
+            "kick" => self.cmd_remove_member(server_id, arg, false),
+            "ban" => self.cmd_remove_member(server_id, arg, true),
+            "op" => self.cmd_set_operator(server_id, arg, true),
+            "deop" => self.cmd_set_operator(server_id, arg, false),
+            "deletechannel" => self.cmd_delete_channel(server_id),
+            "approve" => self.cmd_decide(server_id, arg, true),
+            "reject" => self.cmd_decide(server_id, arg, false),
             "join" => self.cmd_join(server_id, arg),
+            "spectate" => self.cmd_spectate(server_id, arg),
             "leave" => self.cmd_leave(server_id),
             "msg" => self.cmd_msg(server_id, arg, freeform),
+            "sendfile" => self.cmd_sendfile(server_id, arg, freeform),
+            "register" if arg == "--suggested" => self.cmd_register_suggested(server_id, freeform),
             "register" => self.cmd_register(server_id, arg),
+            "nick" => self.cmd_nick(server_id, arg),
             _ => (
                 vec![],
-                vec![ChatClientEvent::MessageReceived(format!(
-                    "[SYSTEM] Unknown command {command}. Use /help to list available commands."
-                ))],
+                vec![ChatClientEvent::MessageReceived(unknown_command(command))],
             ),
         }
     }
 
-    fn cmd_connect(&mut self, arg: &str) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+    pub(crate) fn cmd_connect(
+        &mut self,
+        arg: &str,
+        force: bool,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let resolved = self.resolve_server(arg);
+        let server = resolved.and_then(|id| self.discovered_servers.get(&id).map(|srv| (id, srv)));
+        if let Some((id, _)) = server.filter(|(id, _)| !force && self.is_server_stale(*id)) {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Server {} is not responding. Use /connect {id} --force to connect anyway.",
+                    self.server_display_name(id)
+                ))],
+            );
+        }
+        if let Some((id, srv)) = server.filter(|(_, srv)| !srv.is_chat()) {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Server {} is a {} server, not a chat server. Use /servers chat to list the chat servers.",
+                    self.server_display_name(id),
+                    srv.server_type.map_or("unknown", server_type_name)
+                ))],
+            );
+        }
+        if let Some((id, srv)) = server.filter(|(_, srv)| !is_compatible_version(&srv.version)) {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Server {} runs version {}, which can't talk to this client (version {SOFTWARE_VERSION})",
+                    self.server_display_name(id),
+                    srv.version
+                ))],
+            );
+        }
+        if let Some(id) = resolved.filter(|id| self.has_key_mismatch(*id)) {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Server {} changed its key since first contact and may be an impostor. Use /trust {id} to accept the new key.",
+                    self.server_display_name(id)
+                ))],
+            );
+        }
+        let mut events = vec![];
+        if let Some(prev) = self.currently_connected_server {
+            self.set_connection_state(&mut events, prev, ConnectionState::Disconnected);
+        }
+        self.reset_flow_control(&mut events);
         self.channels_list.clear();
         self.currently_connected_server = None;
         self.currently_connected_channel = None;
-        match self
-            .discovered_servers
-            .iter()
-            .find(|(id, typ)| *typ == "chat" && id.to_string() == arg)
-        {
-            Some((id, _)) => {
-                self.currently_connected_server = Some(*id);
-                self.currently_connected_channel = None;
-                (
-                    vec![(
-                        *id,
-                        ChatMessage {
-                            own_id: u32::from(self.own_id),
-                            message_kind: Some(MessageKind::CliRequestChannels(Empty {})),
-                        },
-                    )],
-                    vec![ChatClientEvent::MessageReceived(format!(
-                        "[SYSTEM] Connecting to server {id}"
-                    ))],
-                )
+        match resolved {
+            Some(id) => {
+                self.currently_connected_server = Some(id);
+                self.set_connection_state(&mut events, id, ConnectionState::Connecting);
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Connecting to server {}",
+                    self.server_display_name(id)
+                )));
+                (vec![self.hello(id)], events)
             }
-            None => (
+            None => {
+                if let Ok(id) = arg.parse::<NodeId>() {
+                    self.set_connection_state(&mut events, id, ConnectionState::Failed);
+                }
+                events.push(ChatClientEvent::MessageReceived(
+                    SERVER_NOT_FOUND.to_string(),
+                ));
+                (vec![], events)
+            }
+        }
+    }
+
+    fn cmd_reveal(&mut self, arg: &str) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(mut msg) = arg
+            .parse::<u64>()
+            .ok()
+            .and_then(|id| self.hidden_messages.get(&id).cloned())
+        else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    NO_SUCH_SPOILER.to_string(),
+                )],
+            );
+        };
+        msg.content_warning = None;
+        let mut events = vec![];
+        self.msg_srvdistributemessage(&mut events, &msg);
+        (vec![], events)
+    }
+
+    fn cmd_route(&self, arg: &str) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let server = if arg.is_empty() {
+            self.currently_connected_server
+        } else {
+            self.resolve_server(arg)
+        };
+        let Some(server) = server else {
+            return (
                 vec![],
                 vec![ChatClientEvent::MessageReceived(
                     SERVER_NOT_FOUND.to_string(),
                 )],
+            );
+        };
+        let msg = match self.routes.get(&server) {
+            Some(hops) => format!(
+                "[SYSTEM] Route to {}: {}",
+                self.server_display_name(server),
+                hops.iter().join(" -> ")
             ),
-        }
+            None => format!(
+                "[SYSTEM] No chat traffic sent to {} yet",
+                self.server_display_name(server)
+            ),
+        };
+        (vec![], vec![ChatClientEvent::MessageReceived(msg)])
     }
 
-    fn cmd_servers(&self) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+    // `args` are --verbose and a server type to only list those, in any order
+    fn cmd_servers(&self, args: &[&str]) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let mut verbose = false;
+        let mut only = None;
+        for arg in args.iter().filter(|x| !x.is_empty()) {
+            match (*arg, parse_server_type(arg)) {
+                ("--verbose", _) => verbose = true,
+                (_, Some(typ)) => only = Some(typ),
+                _ => {
+                    return (
+                        vec![],
+                        vec![ChatClientEvent::MessageReceived(
+                            "[SYSTEM] Error: Usage: /servers [chat|media|text] [--verbose]"
+                                .to_string(),
+                        )],
+                    )
+                }
+            }
+        }
         let servers_list = self
             .discovered_servers
             .iter()
-            .filter(|(_, x)| x.as_str() == "chat")
-            .map(|(id, _)| id.to_string())
+            .filter(|(_, srv)| only.is_none_or(|typ| srv.server_type == Some(typ)))
+            .sorted_by_key(|(id, _)| **id)
+            .map(|(id, srv)| {
+                let name = self.server_display_name(*id);
+                let entry = match (srv.server_type, srv.capacity) {
+                    (Some(ServerType::ChatServer), Some(cap)) => format!(
+                        "{name} [chat] — {}/{cap} users, {} channels",
+                        srv.user_count, srv.channel_count
+                    ),
+                    (Some(ServerType::ChatServer), None) => format!(
+                        "{name} [chat] — {} users, {} channels",
+                        srv.user_count, srv.channel_count
+                    ),
+                    (Some(typ), _) => {
+                        format!("{name} [{}] (not a chat server)", server_type_name(typ))
+                    }
+                    (None, _) => format!("{name} [unknown] (not a chat server)"),
+                };
+                let entry = if verbose {
+                    let version = if srv.version.is_empty() {
+                        "unknown"
+                    } else {
+                        srv.version.as_str()
+                    };
+                    let uptime =
+                        (srv.uptime + self.now().saturating_duration_since(srv.discovered_at))
+                            .as_secs();
+                    let rtt = srv
+                        .rtt
+                        .map_or_else(|| "n/a".to_string(), |rtt| format!("{}ms", rtt.as_millis()));
+                    let max_len = if srv.max_message_len == 0 {
+                        "unknown".to_string()
+                    } else {
+                        format!("{} bytes", srv.max_message_len)
+                    };
+                    let capabilities = self.advertised_capabilities(*id).map_or_else(
+                        || "not advertised, commands are tried anyway and may fail".to_string(),
+                        |x| x.join(" "),
+                    );
+                    let last_reply = self
+                        .since_last_reply(*id)
+                        .map_or(0, |x| x.as_secs());
+                    format!(
+                        "{entry} (version {version}, up {}h{:02}m, RTT {rtt}, last reply {last_reply}s ago, messages up to {max_len}, capabilities: {capabilities})",
+                        uptime / 3600,
+                        uptime / 60 % 60
+                    )
+                } else {
+                    entry
+                };
+                let entry = if is_compatible_version(&srv.version) {
+                    entry
+                } else {
+                    format!("{entry} (incompatible)")
+                };
+                if self.is_server_stale(*id) {
+                    format!("{entry} (stale)")
+                } else {
+                    entry
+                }
+            })
             .join(", ");
+        let unadvertised = self.discovered_servers.iter().any(|(id, srv)| {
+            srv.is_chat()
+                && only.is_none_or(|typ| srv.server_type == Some(typ))
+                && self.advertised_capabilities(*id).is_none()
+        });
+        let note = if unadvertised && !verbose {
+            " (some servers don't advertise what they support, commands may fail on them, see --verbose)"
+        } else {
+            ""
+        };
         (
             vec![],
             vec![ChatClientEvent::MessageReceived(format!(
-                "[SYSTEM] Available servers: {servers_list}"
+                "[SYSTEM] Available servers: {servers_list}{note}"
             ))],
         )
     }
 
-    fn cmd_register(
+    fn cmd_register_suggested(
+        &mut self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        match arg
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| self.username_suggestions.get(n.checked_sub(1)?))
+            .cloned()
+        {
+            Some(username) => self.cmd_register(server_id, &username),
+            None => (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    NO_SUCH_SUGGESTION.to_string(),
+                )],
+            ),
+        }
+    }
+
+    // `/nick <name>`, the new name is taken once the server confirms it
+    fn cmd_nick(
         &self,
         server_id: NodeId,
         arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        if arg.is_empty() || arg.contains(['#', '@']) {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    USERNAME_DISALLOWED_CHARS.to_string(),
+                )],
+            );
+        }
+        if !self.server_usernames.contains_key(&server_id) {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Register first with /register <username>".to_string(),
+                )],
+            );
+        }
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliChangeUsername(arg.to_string())),
+                },
+            )],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Changing username to {arg}"
+            ))],
+        )
+    }
+
+    pub(crate) fn cmd_register(
+        &mut self,
+        server_id: NodeId,
+        arg: &str,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
         if arg.contains(' ') || arg.contains('#') || arg.contains('@') {
             (
@@ -158,101 +469,84 @@ impl ChatClientInternal {
                     USERNAME_DISALLOWED_CHARS.to_string(),
                 )],
             )
+        } else if let Some(prev) = self.server_usernames.get(&server_id) {
+            (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Already registered with username {prev}"
+                ))],
+            )
         } else {
-            self.server_usernames.get(&server_id).map_or_else(
-                || {
+            let request_id = self.start_request(server_id, RequestKind::Register(arg.to_string()));
+            (
+                vec![
                     (
-                        vec![
-                            (
-                                server_id,
-                                ChatMessage {
-                                    own_id: u32::from(self.own_id),
-                                    message_kind: Some(MessageKind::CliRegisterRequest(
-                                        arg.to_string(),
-                                    )),
-                                },
-                            ),
-                            (
-                                server_id,
-                                ChatMessage {
-                                    own_id: u32::from(self.own_id),
-                                    message_kind: Some(MessageKind::CliRequestChannels(Empty {})),
-                                },
-                            ),
-                        ],
-                        vec![ChatClientEvent::MessageReceived(format!(
-                            "[SYSTEM] Registering with username {arg}"
-                        ))],
-                    )
-                },
-                |prev| {
+                        server_id,
+                        ChatMessage {
+                            own_id: u32::from(self.own_id),
+                            request_id,
+                            message_kind: Some(MessageKind::CliRegisterRequest(arg.to_string())),
+                        },
+                    ),
                     (
-                        vec![],
-                        vec![ChatClientEvent::MessageReceived(format!(
-                            "[SYSTEM] Error: Already registered with username {prev}"
-                        ))],
-                    )
-                },
+                        server_id,
+                        ChatMessage {
+                            own_id: u32::from(self.own_id),
+                            request_id: 0,
+                            message_kind: Some(MessageKind::CliRequestChannels(Empty {})),
+                        },
+                    ),
+                ],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Registering with username {arg}"
+                ))],
             )
         }
     }
 
     fn cmd_msg(
-        &self,
+        &mut self,
         server_id: NodeId,
         arg: &str,
         freeform: &str,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
-        if self.server_usernames.contains_key(&server_id) {
-            let all_channel = self.channels_list.iter().find(|x| x.channel_id == 0x1);
-            all_channel.map_or_else(
-                || {
-                    (
-                        vec![],
-                        vec![ChatClientEvent::MessageReceived(NO_ALL_CHAN.to_string())],
-                    )
-                },
-                |all| {
-                    all.connected_clients
-                        .iter()
-                        .find(|x| x.username == arg)
-                        .map_or_else(
-                            || {
-                                (
-                                    vec![],
-                                    vec![ChatClientEvent::MessageReceived(
-                                        USER_NOT_FOUND.to_string(),
-                                    )],
-                                )
-                            },
-                            |dst_id| {
-                                (
-                                    vec![(
-                                        server_id,
-                                        ChatMessage {
-                                            own_id: u32::from(self.own_id),
-                                            message_kind: Some(MessageKind::SendMsg(
-                                                chat_common::messages::SendMessage {
-                                                    message: freeform.to_string(),
-                                                    channel_id: dst_id.id << 32 | 0x8,
-                                                },
-                                            )),
-                                        },
-                                    )],
-                                    vec![],
-                                )
-                            },
-                        )
-                },
-            )
-        } else {
-            (
+        if !self.server_usernames.contains_key(&server_id) {
+            return (
                 vec![],
                 vec![ChatClientEvent::MessageReceived(
                     PLEASE_REGISTER.to_string(),
                 )],
-            )
+            );
         }
+        let Some(all) = self
+            .channels_list
+            .iter()
+            .find(|x| x.channel_kind == ChannelKind::All)
+        else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(NO_ALL_CHAN.to_string())],
+            );
+        };
+        let Some(dst_id) = all.connected_clients.iter().find(|x| x.username == arg) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(USER_NOT_FOUND.to_string())],
+            );
+        };
+        let message = SendMessage {
+            message: self.transform_outgoing(freeform),
+            channel_id: personal_channel_id(dst_id.id),
+            channel_kind: Some(ChannelKind::Personal),
+            content_warning: None,
+            local_id: 0,
+            signature: String::new(),
+            signer_key: String::new(),
+            signed_at: 0,
+        };
+        let mut events = vec![];
+        let replies = self.send_chat_message(&mut events, server_id, message);
+        (replies, events)
     }
 
     fn cmd_leave(
@@ -262,14 +556,17 @@ impl ChatClientInternal {
         match self.currently_connected_channel {
             Some(..) => {
                 self.currently_connected_channel = None;
+                let mut replies = self.stop_typing();
+                replies.push((
+                    server_id,
+                    ChatMessage {
+                        own_id: self.own_id.into(),
+                        request_id: 0,
+                        message_kind: Some(MessageKind::CliLeave(Empty {})),
+                    },
+                ));
                 (
-                    vec![(
-                        server_id,
-                        ChatMessage {
-                            own_id: self.own_id.into(),
-                            message_kind: Some(MessageKind::CliLeave(Empty {})),
-                        },
-                    )],
+                    replies,
                     vec![ChatClientEvent::MessageReceived(LEAVING_CHAN.to_string())],
                 )
             }
@@ -283,7 +580,7 @@ impl ChatClientInternal {
     }
 
     fn cmd_join(
-        &self,
+        &mut self,
         server_id: NodeId,
         arg: &str,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
@@ -295,7 +592,11 @@ impl ChatClientInternal {
                 )],
             )
         } else {
-            self.channels_list
+            // Whatever we were typing was for the channel we're leaving
+            let mut replies = self.stop_typing();
+            let request_id = self.start_request(server_id, RequestKind::Join(arg.to_string()));
+            let (join, events) = self
+                .channels_list
                 .iter()
                 .find(|x| arg == x.channel_name)
                 .map_or_else(
@@ -305,6 +606,7 @@ impl ChatClientInternal {
                                 server_id,
                                 ChatMessage {
                                     own_id: u32::from(self.own_id),
+                                    request_id,
                                     message_kind: Some(MessageKind::CliJoin(JoinChannel {
                                         channel_id: None,
                                         channel_name: arg.to_string(),
@@ -320,6 +622,7 @@ impl ChatClientInternal {
                                 server_id,
                                 ChatMessage {
                                     own_id: u32::from(self.own_id),
+                                    request_id,
                                     message_kind: Some(MessageKind::CliJoin(JoinChannel {
                                         channel_id: Some(channel.channel_id),
                                         channel_name: String::new(),
@@ -329,45 +632,279 @@ impl ChatClientInternal {
                             vec![ChatClientEvent::MessageReceived(JOINING_CHAN.to_string())],
                         )
                     },
-                )
+                );
+            replies.extend(join);
+            (replies, events)
         }
     }
 
-    fn cmd_channels(
+    fn cmd_spectate(
         &self,
         server_id: NodeId,
+        arg: &str,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
-        let chan_list = self
+        if arg.is_empty() {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /spectate <channel>".to_string(),
+                )],
+            );
+        }
+        let channel_id = self
+            .channels_list
+            .iter()
+            .find(|x| arg == x.channel_name)
+            .map(|x| x.channel_id);
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliSpectate(JoinChannel {
+                        channel_id,
+                        channel_name: arg.to_string(),
+                    })),
+                },
+            )],
+            vec![ChatClientEvent::MessageReceived(JOINING_CHAN.to_string())],
+        )
+    }
+
+    // `/channels [--sort=activity]`, the list is shown once the server sends a fresh one
+    fn cmd_channels(
+        &mut self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let request_id = self.start_request(server_id, RequestKind::Channels(arg.to_string()));
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id,
+                    message_kind: Some(MessageKind::CliRequestChannels(Empty {})),
+                },
+            )],
+            vec![],
+        )
+    }
+
+    pub(crate) fn channel_list_text(&self, arg: &str) -> String {
+        let channels = self
             .channels_list
             .iter()
-            .filter(|x| x.channel_is_group && x.channel_id != 0x1)
-            .map(|x| format!("#{}", x.channel_name))
-            .join(",");
+            .filter(|x| x.channel_kind == ChannelKind::Group);
+        let chan_list = if arg == "--sort=activity" {
+            channels
+                .sorted_by(|a, b| {
+                    (b.messages_today, b.last_activity).cmp(&(a.messages_today, a.last_activity))
+                })
+                .map(|x| {
+                    format!(
+                        "#{} ({} today, {} speakers)",
+                        x.channel_name, x.messages_today, x.unique_speakers
+                    )
+                })
+                .join(",")
+        } else {
+            // Most recently active first, channels quiet for a while at the end
+            let now = self.wall_now();
+            let (active, stale): (Vec<_>, Vec<_>) = channels
+                .sorted_by(|a, b| b.last_activity.cmp(&a.last_activity))
+                .partition(|x| now.saturating_sub(x.last_activity) < STALE_CHANNEL_MS);
+            let mut list = active
+                .iter()
+                .map(|x| format!("#{}", x.channel_name))
+                .join(",");
+            if !stale.is_empty() {
+                list.push_str(&format!(
+                    " (quiet: {})",
+                    stale
+                        .iter()
+                        .map(|x| format!("#{}", x.channel_name))
+                        .join(",")
+                ));
+            }
+            list
+        };
         let user_list = self
             .channels_list
             .iter()
-            .find(|x| x.channel_id == 0x1)
+            .find(|x| x.channel_kind == ChannelKind::All)
             .map_or(String::new(), |x| {
                 x.connected_clients
                     .iter()
-                    .map(|x| format!("@{}", x.username))
+                    .map(|x| match status_label(x) {
+                        Some(status) => format!("@{} ({status})", x.username),
+                        None => format!("@{}", x.username),
+                    })
                     .join(",")
             });
-        let msg = format!(
-            "[SYSTEM] Available channels: {chan_list}\n[SYSTEM] Available IMs: {user_list}"
+        format!("[SYSTEM] Available channels: {chan_list}\n[SYSTEM] Available IMs: {user_list}")
+    }
+
+    // `/status <online|away|busy> [text]`, shown next to our name in user lists
+    fn cmd_status(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+        freeform: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        if !matches!(arg, "online" | "away" | "busy") {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /status <online|away|busy> [text]".to_string(),
+                )],
+            );
+        }
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliSetStatus(SetStatus {
+                        status: arg.to_string(),
+                        text: freeform.trim().to_string(),
+                    })),
+                },
+            )],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Status set to {arg}"
+            ))],
+        )
+    }
+
+    fn cmd_info(&self, arg: &str) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let channel = if arg.is_empty() {
+            self.currently_connected_channel
+                .and_then(|id| self.channels_list.iter().find(|x| x.channel_id == id))
+        } else {
+            self.channels_list
+                .iter()
+                .find(|x| x.channel_name == arg || x.channel_id.to_string() == arg)
+        };
+        let Some(channel) = channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: No such channel".to_string(),
+                )],
+            );
+        };
+        let creator = if channel.creator.is_empty() {
+            "the server".to_string()
+        } else {
+            format!("@{}", channel.creator)
+        };
+        let created = i64::try_from(channel.created_at)
+            .ok()
+            .filter(|x| *x > 0)
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map_or_else(
+                || "unknown".to_string(),
+                |at| at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            );
+        let mut msg = format!(
+            "[SYSTEM] #{} ({})\n[SYSTEM]    Created by {creator}, {created}\n[SYSTEM]    {} members, {} messages today",
+            channel.channel_name,
+            channel.channel_id,
+            channel.connected_clients.len(),
+            channel.messages_today
         );
+        // Older servers don't send roles
+        let operators = channel
+            .connected_clients
+            .iter()
+            .filter_map(|x| Some((Role::parse(&x.role)?, &x.username)))
+            .filter(|(role, _)| role.is_operator())
+            .sorted()
+            .map(|(role, username)| match role {
+                Role::Owner => format!("@{username} (owner)"),
+                _ => format!("@{username}"),
+            })
+            .join(", ");
+        if !operators.is_empty() {
+            msg.push_str(&format!("\n[SYSTEM]    Operators: {operators}"));
+        }
+        (vec![], vec![ChatClientEvent::MessageReceived(msg)])
+    }
+
+    fn cmd_users(&self, server_id: NodeId) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let users = self
+            .channels_list
+            .iter()
+            .find(|x| x.channel_kind == ChannelKind::All)
+            .map_or(String::new(), |all| {
+                all.connected_clients
+                    .iter()
+                    .sorted_by(|a, b| a.username.cmp(&b.username))
+                    .map(|client| {
+                        let channel = client
+                            .current_channel
+                            .and_then(|id| self.channels_list.iter().find(|x| x.channel_id == id))
+                            .map_or_else(
+                                || "no channel".to_string(),
+                                |chan| format!("in #{}", chan.channel_name),
+                            );
+                        let presence = status_label(client).map_or_else(
+                            || client.presence.clone(),
+                            |status| format!("{}, {status}", client.presence),
+                        );
+                        format!("[SYSTEM]    @{} ({presence}, {channel})", client.username)
+                    })
+                    .join("\n")
+            });
         (
             vec![(
                 server_id,
                 ChatMessage {
                     own_id: u32::from(self.own_id),
+                    request_id: 0,
                     message_kind: Some(MessageKind::CliRequestChannels(Empty {})),
                 },
             )],
-            vec![ChatClientEvent::MessageReceived(msg)],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Registered users:\n{users}"
+            ))],
         )
     }
 
+    fn cmd_welcome(
+        &self,
+        server_id: NodeId,
+        text: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        match self.currently_connected_channel {
+            Some(channel_id) => (
+                vec![(
+                    server_id,
+                    ChatMessage {
+                        own_id: u32::from(self.own_id),
+                        request_id: 0,
+                        message_kind: Some(MessageKind::CliSetWelcome(SetWelcome {
+                            channel_id,
+                            text: text.trim().to_string(),
+                        })),
+                    },
+                )],
+                vec![ChatClientEvent::MessageReceived(
+                    WELCOME_UPDATED.to_string(),
+                )],
+            ),
+            None => (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    NO_CHAN_CONNECTION.to_string(),
+                )],
+            ),
+        }
+    }
+
     fn cmd_unregister(
         &mut self,
         server_id: NodeId,
@@ -375,15 +912,20 @@ impl ChatClientInternal {
         match self.server_usernames.get(&server_id) {
             Some(_) => {
                 self.server_usernames.remove(&server_id);
+                let mut events = vec![];
+                self.reset_flow_control(&mut events);
+                self.set_connection_state(&mut events, server_id, ConnectionState::Connected);
+                events.push(ChatClientEvent::MessageReceived(UNREGISTERING.to_string()));
                 (
                     vec![(
                         server_id,
                         ChatMessage {
                             own_id: u32::from(self.own_id),
+                            request_id: 0,
                             message_kind: Some(MessageKind::CliCancelReg(Empty {})),
                         },
                     )],
-                    vec![ChatClientEvent::MessageReceived(UNREGISTERING.to_string())],
+                    events,
                 )
             }
             None => (
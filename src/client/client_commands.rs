@@ -0,0 +1,565 @@
+// Every slash command the client understands. The generic and per-command help
+// and the suggestions for mistyped commands are all generated from this table
+
+pub(crate) struct CommandSpec {
+    pub(crate) name: &'static str,
+    // Each way of calling the command: its arguments and what it does
+    pub(crate) forms: &'static [(&'static str, &'static str)],
+    pub(crate) examples: &'static [&'static str],
+    // Whether the command needs a connected server
+    pub(crate) needs_server: bool,
+}
+
+pub(crate) const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "help",
+        forms: &[
+            ("", "Display this message"),
+            ("<command>", "Show usage and examples of a command"),
+        ],
+        examples: &["/help", "/help connect"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "servers",
+        forms: &[
+            ("", "Lists discovered servers"),
+            ("<chat|media|text>", "Lists discovered servers of one type"),
+            (
+                "--verbose",
+                "Also show version, uptime, round trip time, message size limit and capabilities",
+            ),
+        ],
+        examples: &["/servers", "/servers --verbose", "/servers media"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "connect",
+        forms: &[
+            (
+                "<server_id|server_name> [--force]",
+                "Connect to a server, --force allows unresponsive ones",
+            ),
+            (
+                "auto [username]",
+                "Connect to the best available server, optionally registering",
+            ),
+        ],
+        examples: &[
+            "/connect 12",
+            "/connect lobby --force",
+            "/connect auto alice",
+        ],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "trust",
+        forms: &[(
+            "<server_id|server_name>",
+            "Accept the new key of a server whose key changed since first contact.",
+        )],
+        examples: &["/trust 12"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "forgetkey",
+        forms: &[(
+            "<username>",
+            "Forget the signing key seen first for a user and trust the next one they sign with.",
+        )],
+        examples: &["/forgetkey alice"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "route",
+        forms: &[(
+            "[server_id|server_name]",
+            "Show the hops the last chat message to a server took, the current one by default",
+        )],
+        examples: &["/route", "/route 12"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "register",
+        forms: &[
+            (
+                "<username>",
+                "Register with a server. Username cannot contain spaces or '#' and '@'.",
+            ),
+            (
+                "--suggested <n>",
+                "Register with the n-th username suggested after a refused registration.",
+            ),
+        ],
+        examples: &["/register alice", "/register --suggested 2"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "nick",
+        forms: &[(
+            "<username>",
+            "Change your username on the current server without unregistering.",
+        )],
+        examples: &["/nick alice2"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "unregister",
+        forms: &[("", "Unregister from the current server.")],
+        examples: &["/unregister"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "channels",
+        forms: &[
+            (
+                "",
+                "List all channels available on the server, most recently active first.",
+            ),
+            ("--sort=activity", "List channels by messages sent today."),
+        ],
+        examples: &["/channels", "/channels --sort=activity"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "users",
+        forms: &[(
+            "",
+            "List registered users with their presence and current channel.",
+        )],
+        examples: &["/users"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "status",
+        forms: &[(
+            "<online|away|busy> [text]",
+            "Set your status, shown to others next to your name.",
+        )],
+        examples: &["/status away lunch break", "/status online"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "join",
+        forms: &[(
+            "<channel>",
+            "Join a channel. You can only be in one channel at a time.",
+        )],
+        examples: &["/join general"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "spectate",
+        forms: &[(
+            "<channel>",
+            "Follow a channel read-only, without registering.",
+        )],
+        examples: &["/spectate general"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "leave",
+        forms: &[(
+            "",
+            "Leave the current channel. You will still receive DMs and system communications.",
+        )],
+        examples: &["/leave"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "msg",
+        forms: &[("<user> <text>", "Send a direct message to a user.")],
+        examples: &["/msg bob see you at 5"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "sendfile",
+        forms: &[(
+            "<user> <path>",
+            "Offer a file to a user, it is sent once they accept it.",
+        )],
+        examples: &["/sendfile bob notes.txt"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "acceptfile",
+        forms: &[("<id>", "Accept a file offered to you.")],
+        examples: &["/acceptfile 1"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "declinefile",
+        forms: &[("<id>", "Decline a file offered to you.")],
+        examples: &["/declinefile 1"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "spoiler",
+        forms: &[(
+            "<label> <text>",
+            "Send a message that others only see after revealing it.",
+        )],
+        examples: &["/spoiler ending the butler did it"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "reveal",
+        forms: &[("<id>", "Show a message hidden behind a content warning.")],
+        examples: &["/reveal 1718000000000"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "raw",
+        forms: &[("<id>", "Show a message as received, without masked words.")],
+        examples: &["/raw 1718000000000"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "key",
+        forms: &[
+            (
+                "<channel> <key>",
+                "Encrypt messages in a channel with a shared 256-bit key, given in hex.",
+            ),
+            ("<channel>", "Stop encrypting messages in a channel."),
+        ],
+        examples: &[
+            "/key secret 00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff",
+            "/key secret",
+        ],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "emoji",
+        forms: &[(
+            "list",
+            "List the :shortcodes: expanded to emoji in your messages.",
+        )],
+        examples: &["/emoji list"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "links",
+        forms: &[(
+            "[channel]",
+            "List links recently posted in a channel, the current one by default.",
+        )],
+        examples: &["/links", "/links general"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "info",
+        forms: &[(
+            "[channel]",
+            "Show who created a channel and when, the current one by default.",
+        )],
+        examples: &["/info", "/info general"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "watch",
+        forms: &[
+            (
+                "add|remove <word>",
+                "Get an alert when a word shows up in a message of a channel you're in.",
+            ),
+            (
+                "add|remove --server <word>",
+                "Also get messages with the word from channels you're not in.",
+            ),
+            ("list", "List watched words."),
+        ],
+        examples: &["/watch add deadline", "/watch add --server outage"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "history",
+        forms: &[(
+            "[--more]",
+            "Show recent messages of the current channel, --more goes further back.",
+        )],
+        examples: &["/history", "/history --more"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "react",
+        forms: &[(
+            "<id> <emoji>",
+            "React to a recent message in your channel, again to take the reaction back.",
+        )],
+        examples: &["/react 1718000000000 :thumbsup:", "/react 1718000000000 🎉"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "bookmark",
+        forms: &[(
+            "<id> [note]",
+            "Bookmark a recent message, optionally with a note. Kept across restarts.",
+        )],
+        examples: &["/bookmark 1718000000000 release date"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "bookmarks",
+        forms: &[
+            ("", "List your bookmarks."),
+            ("<n>", "Show the history leading up to the n-th bookmark."),
+        ],
+        examples: &["/bookmarks", "/bookmarks 2"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "votekick",
+        forms: &[(
+            "<user>",
+            "Vote to kick a user from the current channel when no operator is around.",
+        )],
+        examples: &["/votekick mallory"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "kick",
+        forms: &[(
+            "<user>",
+            "Remove a user from the current channel. Operators only.",
+        )],
+        examples: &["/kick mallory"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "ban",
+        forms: &[(
+            "<user>",
+            "Remove a user from the current channel and keep them from joining again. Operators only.",
+        )],
+        examples: &["/ban mallory"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "op",
+        forms: &[(
+            "<user>",
+            "Make a member of the current channel an operator. Operators only.",
+        )],
+        examples: &["/op alice"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "deop",
+        forms: &[(
+            "<user>",
+            "Take operator rights away from a member of the current channel. Owner only.",
+        )],
+        examples: &["/deop alice"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "deletechannel",
+        forms: &[(
+            "",
+            "Delete the current channel, removing everyone from it. Owner only.",
+        )],
+        examples: &["/deletechannel"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "moderate",
+        forms: &[(
+            "on|off",
+            "Make messages in the current channel wait for an operator's approval. Operators only.",
+        )],
+        examples: &["/moderate on"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "invite",
+        forms: &[(
+            "<user>",
+            "Invite a user to the current channel. Only operators can invite to invite-only channels.",
+        )],
+        examples: &["/invite alice"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "invites",
+        forms: &[("", "List the invitations you haven't used yet.")],
+        examples: &["/invites"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "inviteonly",
+        forms: &[(
+            "on|off",
+            "Only let invited users join the current channel. Operators only.",
+        )],
+        examples: &["/inviteonly on"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "readonly",
+        forms: &[(
+            "on|off",
+            "Only let operators send messages in the current channel. Operators only.",
+        )],
+        examples: &["/readonly on"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "slowmode",
+        forms: &[(
+            "<seconds>",
+            "Make members wait between messages in the current channel, 0 turns it off. Operators only.",
+        )],
+        examples: &["/slowmode 30", "/slowmode 0"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "approve",
+        forms: &[(
+            "<id>",
+            "Distribute a message awaiting approval. Operators only.",
+        )],
+        examples: &["/approve 1718000000000"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "reject",
+        forms: &[("<id>", "Drop a message awaiting approval. Operators only.")],
+        examples: &["/reject 1718000000000"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "stats",
+        forms: &[(
+            "",
+            "Show messages sent and received per channel, reconnects, RTT and losses.",
+        )],
+        examples: &["/stats"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "ping",
+        forms: &[
+            ("", "Measure the round trip time to every chat server"),
+            (
+                "<server_id|server_name>",
+                "Measure the round trip time to one server",
+            ),
+        ],
+        examples: &["/ping", "/ping lobby"],
+        needs_server: false,
+    },
+    CommandSpec {
+        name: "search",
+        forms: &[(
+            "--server <pattern>",
+            "Search the server's history of the current channel.",
+        )],
+        examples: &["/search --server deadline"],
+        needs_server: true,
+    },
+    CommandSpec {
+        name: "welcome",
+        forms: &[(
+            "<text>",
+            "Set the message shown to users joining the current channel. Empty text removes it.",
+        )],
+        examples: &["/welcome Be nice!", "/welcome"],
+        needs_server: true,
+    },
+];
+
+pub(crate) fn find_command(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+// Commands that only work on servers advertising a capability
+const REQUIRED_CAPABILITIES: &[(&str, &str)] = &[
+    ("history", "history"),
+    ("search", "search"),
+    ("sendfile", "file-transfer"),
+    ("key", "encryption"),
+    ("moderate", "moderation"),
+    ("approve", "moderation"),
+    ("reject", "moderation"),
+    ("kick", "moderation"),
+    ("ban", "moderation"),
+    ("op", "moderation"),
+    ("deop", "moderation"),
+    ("readonly", "moderation"),
+    ("slowmode", "moderation"),
+    ("inviteonly", "moderation"),
+];
+
+pub(crate) fn required_capability(name: &str) -> Option<&'static str> {
+    REQUIRED_CAPABILITIES
+        .iter()
+        .find(|(command, _)| *command == name)
+        .map(|(_, capability)| *capability)
+}
+
+fn usage_line(spec: &CommandSpec, args: &str) -> String {
+    if args.is_empty() {
+        format!("/{}", spec.name)
+    } else {
+        format!("/{} {args}", spec.name)
+    }
+}
+
+pub(crate) fn help_message() -> String {
+    let mut msg = String::from("[SYSTEM] Commands:");
+    for spec in COMMANDS {
+        for (args, description) in spec.forms {
+            msg.push_str(&format!(
+                "\n[SYSTEM]    {} - {description}",
+                usage_line(spec, args)
+            ));
+        }
+    }
+    msg
+}
+
+pub(crate) fn command_help(spec: &CommandSpec) -> String {
+    let mut msg = format!("[SYSTEM] Usage of /{}:", spec.name);
+    for (args, description) in spec.forms {
+        msg.push_str(&format!(
+            "\n[SYSTEM]    {} - {description}",
+            usage_line(spec, args)
+        ));
+    }
+    if spec.needs_server {
+        msg.push_str("\n[SYSTEM] Needs a connected server.");
+    }
+    msg.push_str("\n[SYSTEM] Examples:");
+    for example in spec.examples {
+        msg.push_str(&format!("\n[SYSTEM]    {example}"));
+    }
+    msg
+}
+
+// The known command closest to a mistyped one, if any is close enough to be meant
+pub(crate) fn suggest_command(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .map(|spec| (edit_distance(name, spec.name), spec.name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, name)| name)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
@@ -0,0 +1,291 @@
+use crate::client::{
+    ChatClientInternal, DiscoveredServer, PendingDiscovery, ServerSelectionPolicy,
+};
+use crate::clock::Instant;
+use crate::protocol::{is_compatible_version, DISCOVERY_ANY_TYPE};
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::ChatMessage;
+use common::slc_commands::ChatClientEvent;
+use log::{debug, info};
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    pub(crate) fn discovery_request(&self) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::DsvReq(DISCOVERY_ANY_TYPE.to_string())),
+        }
+    }
+
+    pub(crate) fn poll_discovery_timeouts(
+        &mut self,
+        now: Instant,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let mut replies = vec![];
+        let mut events = vec![];
+        let expired = self
+            .pending_discoveries
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            // Only expired entries were collected, so the lookup can't fail
+            let pending = self.pending_discoveries.get_mut(&id).unwrap();
+            if pending.attempts <= self.config.discovery_retries {
+                pending.attempts += 1;
+                pending.sent_at = now;
+                pending.deadline = now + self.config.discovery_timeout;
+                debug!(target: self.log_target.as_str(), "Retrying discovery of node {id} (attempt {})", pending.attempts);
+                replies.push((id, self.discovery_request()));
+            } else if pending.ping {
+                let attempts = pending.attempts;
+                self.pending_discoveries.remove(&id);
+                info!(target: self.log_target.as_str(), "Server {id} didn't answer the ping");
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Server {} did not answer the ping after {attempts} attempts",
+                    self.server_display_name(id)
+                )));
+            } else {
+                let attempts = pending.attempts;
+                self.pending_discoveries.remove(&id);
+                // Forget the node so that a later add_node can start over
+                self.discovered_nodes.remove(&id);
+                info!(target: self.log_target.as_str(), "Giving up discovery of node {id}");
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Server {id} did not answer discovery after {attempts} attempts"
+                )));
+            }
+        }
+        (replies, events)
+    }
+
+    // `/ping [server]`, measures again the round trip to one chat server or to all of
+    // them with a discovery request. The answer updates what LowestRtt picks from
+    pub(crate) fn cmd_ping(
+        &mut self,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let servers = if arg.is_empty() {
+            self.discovered_servers
+                .iter()
+                .filter(|(_, srv)| srv.is_chat())
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>()
+        } else {
+            self.resolve_server(arg).into_iter().collect()
+        };
+        if servers.is_empty() {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Server not found".to_string(),
+                )],
+            );
+        }
+        let now = self.now();
+        let mut replies = vec![];
+        for id in servers {
+            // The answer to a discovery already on its way updates the round trip too
+            if self.pending_discoveries.contains_key(&id) {
+                continue;
+            }
+            debug!(target: self.log_target.as_str(), "Pinging server {id}");
+            self.pending_discoveries.insert(
+                id,
+                PendingDiscovery {
+                    sent_at: now,
+                    deadline: now + self.config.discovery_timeout,
+                    attempts: 1,
+                    ping: true,
+                },
+            );
+            replies.push((id, self.discovery_request()));
+        }
+        (replies, vec![])
+    }
+
+    // Picks the chat server that best matches `policy` among the discovered ones
+    #[must_use]
+    pub fn select_server(&self, policy: ServerSelectionPolicy) -> Option<NodeId> {
+        let mut candidates = self
+            .discovered_servers
+            .iter()
+            .filter(|(id, srv)| srv.is_chat() && !self.is_server_stale(**id))
+            .filter(|(_, srv)| is_compatible_version(&srv.version))
+            .filter(|(id, _)| !self.has_key_mismatch(**id))
+            .filter(|(_, srv)| !srv.capacity.is_some_and(|cap| srv.user_count >= cap));
+        match policy {
+            ServerSelectionPolicy::LowestLoad => candidates
+                .min_by(|(_, a), (_, b)| {
+                    let load = |srv: &DiscoveredServer| {
+                        f64::from(srv.user_count) / f64::from(srv.capacity.unwrap_or(u32::MAX))
+                    };
+                    load(a).total_cmp(&load(b))
+                })
+                .map(|(id, _)| *id),
+            ServerSelectionPolicy::LowestRtt => candidates
+                .filter_map(|(id, srv)| srv.rtt.map(|rtt| (id, rtt)))
+                .min_by_key(|(_, rtt)| *rtt)
+                .map(|(id, _)| *id),
+            ServerSelectionPolicy::FirstResponding => candidates
+                .min_by_key(|(_, srv)| srv.discovered_at)
+                .map(|(id, _)| *id),
+        }
+    }
+
+    // Connects to the server chosen by the configured policy, registering right away if a
+    // username is given
+    pub fn connect_best(
+        &mut self,
+        username: Option<&str>,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(id) = self.select_server(self.config.selection_policy) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: No suitable chat server found".to_string(),
+                )],
+            );
+        };
+        info!(target: self.log_target.as_str(), "Automatically selected server {id}");
+        let (mut replies, mut events) = self.cmd_connect(&id.to_string(), false);
+        if let Some(username) = username.filter(|x| !x.is_empty()) {
+            let (r, e) = self.cmd_register(id, username);
+            replies.extend(r);
+            events.extend(e);
+        }
+        (replies, events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ChatClientConfig;
+    use crate::clock::ManualClock;
+    use crate::server::{ChatServerConfig, ChatServerInternal};
+    use chat_common::packet_handling::CommandHandler;
+    use std::time::Duration;
+
+    const NEAR: NodeId = 10;
+    const FAR: NodeId = 11;
+
+    fn client() -> (ChatClientInternal, ManualClock) {
+        let mut client =
+            ChatClientInternal::registered_in_games(ChatClientConfig::default(), &[NEAR, FAR]);
+        let clock = ManualClock::new();
+        client.set_clock(clock.clone());
+        (client, clock)
+    }
+
+    // Lets `server` answer what the client sent it
+    fn answer(
+        client: &mut ChatClientInternal,
+        server: &mut ChatServerInternal,
+        server_id: NodeId,
+        requests: &[(NodeId, ChatMessage)],
+    ) -> Vec<ChatClientEvent> {
+        let mut events = vec![];
+        for (_, request) in requests.iter().filter(|(to, _)| *to == server_id) {
+            let (responses, _) = server.handle_protocol_message(request.clone(), 1);
+            for (_, response) in responses {
+                events.extend(client.handle_protocol_message(response, server_id).1);
+            }
+        }
+        events
+    }
+
+    fn servers() -> (ChatServerInternal, ChatServerInternal) {
+        (
+            ChatServerInternal::with_config(NEAR, ChatServerConfig::default()),
+            ChatServerInternal::with_config(FAR, ChatServerConfig::default()),
+        )
+    }
+
+    #[test]
+    fn pings_measure_the_round_trip_again() {
+        let (mut client, clock) = client();
+        let (mut near, mut far) = servers();
+
+        let (requests, _) = client.cmd_ping("");
+        assert_eq!(requests.len(), 2);
+        clock.advance(Duration::from_millis(20));
+        let events = answer(&mut client, &mut near, NEAR, &requests);
+        clock.advance(Duration::from_millis(30));
+        answer(&mut client, &mut far, FAR, &requests);
+
+        assert!(events.iter().any(|x| matches!(
+            x,
+            ChatClientEvent::MessageReceived(text) if text.ends_with("answered in 20ms")
+        )));
+        assert_eq!(
+            client.discovered_servers[&NEAR].rtt,
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            client.discovered_servers[&FAR].rtt,
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            client.select_server(ServerSelectionPolicy::LowestRtt),
+            Some(NEAR)
+        );
+
+        // The near server got slow since
+        let (requests, _) = client.cmd_ping("");
+        answer(&mut client, &mut far, FAR, &requests);
+        clock.advance(Duration::from_millis(40));
+        answer(&mut client, &mut near, NEAR, &requests);
+        assert_eq!(
+            client.select_server(ServerSelectionPolicy::LowestRtt),
+            Some(FAR)
+        );
+    }
+
+    #[test]
+    fn pings_keep_the_order_servers_first_answered_in() {
+        let (mut client, clock) = client();
+        let (mut near, _) = servers();
+        let discovered_at = client.discovered_servers[&NEAR].discovered_at;
+
+        clock.advance(Duration::from_secs(60));
+        let (requests, _) = client.cmd_ping("10");
+        assert_eq!(requests.len(), 1);
+        answer(&mut client, &mut near, NEAR, &requests);
+
+        assert_eq!(
+            client.discovered_servers[&NEAR].discovered_at,
+            discovered_at
+        );
+        assert!(client.discovered_servers[&NEAR].rtt.is_some());
+        assert!(client.discovered_servers[&FAR].rtt.is_none());
+    }
+
+    #[test]
+    fn unanswered_pings_keep_the_server() {
+        let (mut client, clock) = client();
+        client.cmd_ping("11");
+        let mut events = vec![];
+        for _ in 0..=client.config.discovery_retries {
+            clock.advance(client.config.discovery_timeout);
+            events.extend(client.poll_discovery_timeouts(client.now()).1);
+        }
+
+        assert!(events.iter().any(|x| matches!(
+            x,
+            ChatClientEvent::MessageReceived(text) if text.contains("did not answer the ping")
+        )));
+        assert!(client.pending_discoveries.is_empty());
+        assert!(client.discovered_servers.contains_key(&FAR));
+    }
+
+    #[test]
+    fn unknown_servers_cannot_be_pinged() {
+        let (mut client, _) = client();
+        let (requests, events) = client.cmd_ping("nowhere");
+        assert!(requests.is_empty());
+        assert_eq!(events.len(), 1);
+    }
+}
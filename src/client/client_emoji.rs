@@ -0,0 +1,91 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::ChatMessage;
+use common::slc_commands::ChatClientEvent;
+use itertools::Itertools;
+use wg_2024::network::NodeId;
+
+// Shortcodes understood out of the box, `ChatClientConfig::emoji` can add or override some
+const BUILTIN_EMOJI: &[(&str, &str)] = &[
+    ("smile", "😄"),
+    ("grin", "😁"),
+    ("joy", "😂"),
+    ("wink", "😉"),
+    ("heart", "❤️"),
+    ("thumbsup", "👍"),
+    ("thumbsdown", "👎"),
+    ("clap", "👏"),
+    ("fire", "🔥"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("eyes", "👀"),
+    ("cry", "😢"),
+    ("rocket", "🚀"),
+    ("ok", "👌"),
+    ("wave", "👋"),
+];
+
+impl ChatClientInternal {
+    fn emoji(&self, shortcode: &str) -> Option<&str> {
+        self.config
+            .emoji
+            .get(shortcode)
+            .map(String::as_str)
+            .or_else(|| {
+                BUILTIN_EMOJI
+                    .iter()
+                    .find(|(code, _)| *code == shortcode)
+                    .map(|(_, emoji)| *emoji)
+            })
+    }
+
+    // Replaces every known `:shortcode:` in `text`, unknown ones are left as they are
+    pub(crate) fn expand_emoji(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(':') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let end = after.find(':');
+            match end.and_then(|end| self.emoji(&after[..end]).map(|x| (end, x))) {
+                Some((end, emoji)) => {
+                    out.push_str(emoji);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    out.push(':');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    pub(crate) fn cmd_emoji(
+        &self,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        if arg != "list" {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /emoji list".to_string(),
+                )],
+            );
+        }
+        let list = BUILTIN_EMOJI
+            .iter()
+            .map(|(code, _)| *code)
+            .chain(self.config.emoji.keys().map(String::as_str))
+            .unique()
+            .sorted()
+            .filter_map(|code| Some(format!(":{code}: {}", self.emoji(code)?)))
+            .join(", ");
+        (
+            vec![],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Emoji: {list}"
+            ))],
+        )
+    }
+}
@@ -0,0 +1,109 @@
+use crate::client::ChatClientInternal;
+use crate::secret::SecretKey;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chat_common::messages::{ChatMessage, MessageData};
+use common::slc_commands::ChatClientEvent;
+use itertools::Itertools;
+use rand::{rng, RngCore};
+use wg_2024::network::NodeId;
+
+// Text of messages in encrypted channels: this prefix, then the hex of nonce and
+// ciphertext. Servers relay it like any other text
+const ENCRYPTED_PREFIX: &str = "enc1:";
+const NONCE_LEN: usize = 12;
+
+fn encrypt_text(key: &[u8; 32], text: &str) -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rng().fill_bytes(&mut nonce);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    // Encryption only fails for inputs larger than the cipher's limit, way above a message
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), text.as_bytes())
+        .expect("message too large to encrypt");
+    let bytes = nonce.iter().chain(&ciphertext).copied().collect::<Vec<_>>();
+    format!("{ENCRYPTED_PREFIX}{}", to_hex(&bytes))
+}
+
+// None if the key is wrong or the message was tampered with
+fn decrypt_text(key: &[u8; 32], data: &str) -> Option<String> {
+    let bytes = parse_hex(data.strip_prefix(ENCRYPTED_PREFIX)?)?;
+    if bytes.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+    String::from_utf8(plaintext).ok()
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).join("")
+}
+
+pub(crate) fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl ChatClientInternal {
+    fn channel_key(&self, channel_id: u64) -> Option<&[u8; 32]> {
+        let chan = self
+            .channels_list
+            .iter()
+            .find(|chan| chan.channel_id == channel_id)?;
+        self.config
+            .channel_keys
+            .get(&chan.channel_name)
+            .map(SecretKey::as_bytes)
+    }
+
+    // Text to send in a channel, encrypted if we hold its key
+    pub(crate) fn seal_for_channel(&self, channel_id: u64, text: String) -> String {
+        match self.channel_key(channel_id) {
+            Some(key) if !text.starts_with(ENCRYPTED_PREFIX) => encrypt_text(key, &text),
+            _ => text,
+        }
+    }
+
+    // Copy of an incoming message with its text decrypted, or replaced by a notice when
+    // we can't decrypt it
+    pub(crate) fn open_incoming(&self, msg: &MessageData) -> MessageData {
+        let mut msg = msg.clone();
+        if msg.message.starts_with(ENCRYPTED_PREFIX) {
+            msg.message = match self.channel_key(msg.channel_id) {
+                Some(key) => decrypt_text(key, &msg.message).unwrap_or_else(|| {
+                    "[encrypted message, can't be decrypted with our key]".to_string()
+                }),
+                None => "[encrypted message, no key for this channel]".to_string(),
+            };
+        }
+        msg
+    }
+
+    pub(crate) fn cmd_key(
+        &mut self,
+        channel: &str,
+        hex: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let msg = if channel.is_empty() {
+            "[SYSTEM] Error: Usage: /key <channel> [key]".to_string()
+        } else if hex.is_empty() {
+            self.config.channel_keys.remove(channel);
+            format!("[SYSTEM] Messages in #{channel} are no longer encrypted")
+        } else if let Some(key) = parse_hex(hex).and_then(|x| <[u8; 32]>::try_from(x).ok()) {
+            self.config
+                .channel_keys
+                .insert(channel.to_string(), SecretKey::new(key));
+            format!("[SYSTEM] Messages in #{channel} are now encrypted")
+        } else {
+            "[SYSTEM] Error: The key must be 64 hexadecimal digits".to_string()
+        };
+        (vec![], vec![ChatClientEvent::MessageReceived(msg)])
+    }
+}
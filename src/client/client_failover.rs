@@ -0,0 +1,275 @@
+use crate::client::{ChatClientInternal, DiscoveredServer};
+use chat_common::messages::{ChatMessage, Migration, SendMessage};
+use common::slc_commands::{ChatClientEvent, ConnectionState};
+use log::info;
+use std::mem;
+use wg_2024::network::NodeId;
+
+// Messages kept to be sent again after leaving a server, the oldest are given up first
+const MAX_FAILOVER_OUTBOX: usize = 32;
+
+impl ChatClientInternal {
+    // Moves the session (username, group channel, undelivered messages) to another chat server
+    pub(crate) fn start_failover(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(old_server) = self.currently_connected_server else {
+            return vec![];
+        };
+        let Some(new_server) = self
+            .select_server(self.config.selection_policy)
+            .filter(|id| *id != old_server)
+        else {
+            events.push(ChatClientEvent::MessageReceived(
+                "[SYSTEM] Error: No other chat server available for failover".to_string(),
+            ));
+            return vec![];
+        };
+        info!(target: self.log_target.as_str(), "Failing over from server {old_server} to {new_server}");
+        self.stats.reconnects += 1;
+        let username = self.server_usernames.remove(&old_server);
+        let channel_name = self.current_group_channel_name();
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Server {} is unreachable, moving to {}",
+            self.server_display_name(old_server),
+            self.server_display_name(new_server)
+        )));
+        // Messages still waiting for the old server's send window are moved along too
+        let queued = self.take_flow_queue(events);
+        self.keep_for_replay(events, queued);
+        self.resume_session(events, new_server, username, channel_name)
+    }
+
+    // Keeps the messages written in the channel we're in, to be sent there again once
+    // we joined it back, on this server or another
+    pub(crate) fn keep_for_replay(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        messages: Vec<SendMessage>,
+    ) {
+        let channel = self.currently_connected_channel;
+        self.failover_outbox.extend(
+            messages
+                .into_iter()
+                .filter(|msg| Some(msg.channel_id) == channel),
+        );
+        // Local ids follow the order messages were written in
+        self.failover_outbox.sort_by_key(|msg| msg.local_id);
+        let excess = self
+            .failover_outbox
+            .len()
+            .saturating_sub(MAX_FAILOVER_OUTBOX);
+        for msg in self.failover_outbox.drain(..excess) {
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Error: Could not deliver message: {}",
+                msg.message
+            )));
+        }
+    }
+
+    pub(crate) fn replay_failover_outbox(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let (Some(server_id), Some(channel_id)) = (
+            self.currently_connected_server,
+            self.currently_connected_channel,
+        ) else {
+            return vec![];
+        };
+        let mut replies = vec![];
+        for message in mem::take(&mut self.failover_outbox) {
+            // Group channel ids follow from their names, so the channel joined back has
+            // the id the message was written for unless it clashed with another there
+            if message.channel_id != channel_id {
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Could not deliver message: {}",
+                    message.message
+                )));
+                continue;
+            }
+            replies.extend(self.send_chat_message(events, server_id, message));
+        }
+        replies
+    }
+
+    // The connected server moved, with all its state, to another node. We are still
+    // registered and in the same channel there, so only the server id changes
+    pub(crate) fn msg_srvmigrated(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        sender_id: NodeId,
+        migration: &Migration,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        #[allow(clippy::cast_possible_truncation)]
+        let new_server = migration.new_server_id as NodeId;
+        if self.currently_connected_server != Some(sender_id) || new_server == sender_id {
+            return vec![];
+        }
+        info!(target: self.log_target.as_str(), "Server {sender_id} migrated to {new_server}");
+        self.stats.reconnects += 1;
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Server {} moved to node {new_server}, reconnecting",
+            self.server_display_name(sender_id)
+        )));
+        if let Some(old) = self.discovered_servers.get(&sender_id).cloned() {
+            let now = self.now();
+            self.discovered_servers
+                .entry(new_server)
+                .or_insert(DiscoveredServer {
+                    rtt: None,
+                    last_reply: now,
+                    consecutive_failures: 0,
+                    ..old
+                });
+        }
+        self.discovered_nodes.insert(new_server);
+        self.routes.remove(&sender_id);
+        let state = self.connection_state;
+        self.set_connection_state(events, sender_id, ConnectionState::Disconnected);
+        self.currently_connected_server = Some(new_server);
+        if let Some(username) = self.server_usernames.remove(&sender_id) {
+            self.server_usernames.insert(new_server, username);
+        }
+        self.set_connection_state(events, new_server, state);
+        vec![
+            self.hello(new_server),
+            (new_server, self.discovery_request()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{ChatClientConfig, ChatClientInternal};
+    use crate::protocol::group_channel_id;
+    use chat_common::messages::chat_message::MessageKind;
+    use chat_common::messages::{ChannelKind, ChatMessage, Migration, SendMessage};
+    use common::slc_commands::ChatClientEvent;
+    use wg_2024::network::NodeId;
+
+    const SERVER: NodeId = 10;
+    const OTHER: NodeId = 11;
+
+    fn client_in_games(servers: &[NodeId]) -> ChatClientInternal {
+        ChatClientInternal::registered_in_games(
+            ChatClientConfig {
+                failover: true,
+                ..ChatClientConfig::default()
+            },
+            servers,
+        )
+    }
+
+    fn message(text: &str, local_id: u64) -> SendMessage {
+        SendMessage {
+            message: text.to_string(),
+            channel_id: group_channel_id("games", 0),
+            channel_kind: Some(ChannelKind::Group),
+            content_warning: None,
+            local_id,
+            signature: String::new(),
+            signer_key: String::new(),
+            signed_at: 0,
+        }
+    }
+
+    // Fails deliveries to SERVER until it counts as stale, losing `lost` the last time
+    fn make_stale(
+        client: &mut ChatClientInternal,
+        lost: Vec<SendMessage>,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<String>) {
+        let mut events = vec![];
+        for _ in 1..client.config.stale_failure_threshold {
+            client.record_send_failure(&mut events, SERVER, vec![]);
+        }
+        let replies = client.record_send_failure(&mut events, SERVER, lost);
+        let texts = events
+            .into_iter()
+            .filter_map(|event| match event {
+                ChatClientEvent::MessageReceived(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        (replies, texts)
+    }
+
+    #[test]
+    fn stale_servers_are_left_for_another_with_the_same_session() {
+        let mut client = client_in_games(&[SERVER, OTHER]);
+        let (replies, _) = make_stale(&mut client, vec![message("hi", 1)]);
+        assert_eq!(client.currently_connected_server, Some(OTHER));
+        assert!(replies.iter().all(|(id, _)| *id == OTHER));
+        let kinds = replies
+            .iter()
+            .filter_map(|(_, msg)| msg.message_kind.as_ref())
+            .collect::<Vec<_>>();
+        assert!(matches!(kinds[0], MessageKind::CliHello(..)));
+        assert!(kinds
+            .iter()
+            .any(|kind| matches!(kind, MessageKind::CliRegisterRequest(name) if name == "alice")));
+        assert!(kinds.iter().any(
+            |kind| matches!(kind, MessageKind::CliJoin(join) if join.channel_name == "games")
+        ));
+        assert_eq!(client.failover_outbox.len(), 1);
+    }
+
+    #[test]
+    fn lost_messages_are_sent_again_once_back_in_the_channel() {
+        let mut client = client_in_games(&[SERVER, OTHER]);
+        make_stale(&mut client, vec![message("hi", 1)]);
+        client.currently_connected_channel = Some(group_channel_id("games", 0));
+        let mut events = vec![];
+        let replies = client.replay_failover_outbox(&mut events);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0, OTHER);
+        assert!(matches!(
+            &replies[0].1.message_kind,
+            Some(MessageKind::SendMsg(msg)) if msg.message == "hi"
+        ));
+        assert!(client.failover_outbox.is_empty());
+    }
+
+    #[test]
+    fn without_another_server_the_client_stays() {
+        let mut client = client_in_games(&[SERVER]);
+        let (replies, texts) = make_stale(&mut client, vec![message("hi", 1)]);
+        assert!(replies.is_empty());
+        assert_eq!(client.currently_connected_server, Some(SERVER));
+        assert!(texts
+            .iter()
+            .any(|text| text.contains("No other chat server available")));
+    }
+
+    #[test]
+    fn migrated_servers_are_followed_without_registering_again() {
+        let mut client = client_in_games(&[SERVER]);
+        let mut events = vec![];
+        let replies = client.msg_srvmigrated(&mut events, SERVER, &Migration { new_server_id: 12 });
+        assert_eq!(client.currently_connected_server, Some(12));
+        assert_eq!(
+            client.currently_connected_channel,
+            Some(group_channel_id("games", 0))
+        );
+        assert_eq!(
+            client.server_usernames.get(&12).map(String::as_str),
+            Some("alice")
+        );
+        assert!(client.discovered_servers.contains_key(&12));
+        assert!(replies.iter().all(|(id, _)| *id == 12));
+        assert!(replies.iter().all(|(_, msg)| !matches!(
+            msg.message_kind,
+            Some(MessageKind::CliRegisterRequest(..))
+        )));
+    }
+
+    #[test]
+    fn migrations_of_other_servers_are_ignored() {
+        let mut client = client_in_games(&[SERVER, OTHER]);
+        let mut events = vec![];
+        let replies = client.msg_srvmigrated(&mut events, OTHER, &Migration { new_server_id: 12 });
+        assert!(replies.is_empty());
+        assert_eq!(client.currently_connected_server, Some(SERVER));
+    }
+}
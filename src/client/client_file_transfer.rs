@@ -0,0 +1,840 @@
+use crate::client::ChatClientInternal;
+use crate::clock::Instant;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, FileAnswer, FileChunk, FileMissing, FileOffer};
+use common::slc_commands::ChatClientEvent;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use wg_2024::network::NodeId;
+
+// Chunks asked for again in one request, the rest are asked for in the next one
+const MISSING_BATCH: usize = 64;
+
+// Chunks in a burst when the server never advertised a send window
+const DEFAULT_CHUNK_BURST: u32 = 8;
+
+// Offers to us waiting for an answer, further ones are declined right away
+const MAX_PENDING_OFFERS: usize = 16;
+
+// Offers left unanswered this long are dropped, on both sides
+const OFFER_LIFETIME: Duration = Duration::from_secs(600);
+
+// Names tried for a received file before giving up: "name", "1-name", "2-name"...
+const MAX_NAME_ATTEMPTS: u32 = 100;
+
+#[derive(Debug)]
+pub(crate) struct OutgoingFile {
+    server: NodeId,
+    peer: String,
+    file_name: String,
+    data: Vec<u8>,
+    chunk_size: usize,
+    offered_at: Instant,
+    // First chunk of the next burst, None until the peer accepts
+    next_chunk: Option<u32>,
+    next_burst: Instant,
+}
+
+impl OutgoingFile {
+    fn chunk_count(&self) -> u32 {
+        u32::try_from(self.data.len().div_ceil(self.chunk_size)).unwrap_or(u32::MAX)
+    }
+
+    fn chunk(&self, index: u32) -> Option<&[u8]> {
+        self.data
+            .chunks(self.chunk_size)
+            .nth(usize::try_from(index).ok()?)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct IncomingFile {
+    server: NodeId,
+    peer: String,
+    // As chosen by the sender, ours is the key in `FileTransfers::incoming`
+    transfer_id: u64,
+    file_name: String,
+    size: u64,
+    chunk_count: u32,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    // Bytes in `chunks`, never more than `size`
+    received_bytes: u64,
+    offered_at: Instant,
+    // When the last chunk came, None until we accept the file
+    last_chunk: Option<Instant>,
+    // Requests for missing chunks since the last one came
+    retries: u32,
+}
+
+impl IncomingFile {
+    fn missing(&self) -> Vec<u32> {
+        (0..self.chunk_count)
+            .filter(|x| !self.chunks.contains_key(x))
+            .take(MISSING_BATCH)
+            .collect()
+    }
+}
+
+// Files we offered, by transfer id, and files offered to us, by an id of our own that
+// /acceptfile and /declinefile take
+#[derive(Debug, Default)]
+pub(crate) struct FileTransfers {
+    last_id: u64,
+    outgoing: HashMap<u64, OutgoingFile>,
+    incoming: HashMap<u64, IncomingFile>,
+}
+
+impl FileTransfers {
+    fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn find_incoming(&self, server: NodeId, peer: &str, transfer_id: u64) -> Option<u64> {
+        self.incoming
+            .iter()
+            .find(|(_, x)| x.server == server && x.peer == peer && x.transfer_id == transfer_id)
+            .map(|(id, _)| *id)
+    }
+
+    fn find_outgoing(&self, server: NodeId, peer: &str, transfer_id: u64) -> Option<&OutgoingFile> {
+        self.outgoing
+            .get(&transfer_id)
+            .filter(|x| x.server == server && x.peer == peer)
+    }
+}
+
+impl ChatClientInternal {
+    fn file_message(&self, server: NodeId, kind: MessageKind) -> (NodeId, ChatMessage) {
+        (
+            server,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(kind),
+            },
+        )
+    }
+
+    fn file_chunk(
+        &self,
+        transfer_id: u64,
+        file: &OutgoingFile,
+        index: u32,
+    ) -> Option<(NodeId, ChatMessage)> {
+        let data = file.chunk(index)?.to_vec();
+        Some(self.file_message(
+            file.server,
+            MessageKind::CliFileChunk(FileChunk {
+                transfer_id,
+                peer: file.peer.clone(),
+                index,
+                data,
+            }),
+        ))
+    }
+
+    // Sends the next chunks of an accepted file, as many as the server's send window
+    // takes, so that a big file doesn't flood the network at once
+    fn next_file_burst(&mut self, transfer_id: u64, now: Instant) -> Vec<(NodeId, ChatMessage)> {
+        let burst = self.send_window().unwrap_or(DEFAULT_CHUNK_BURST).max(1);
+        let interval = self.config.file_chunk_interval;
+        let Some(file) = self.file_transfers.outgoing.get_mut(&transfer_id) else {
+            return vec![];
+        };
+        let Some(start) = file.next_chunk else {
+            return vec![];
+        };
+        let end = start.saturating_add(burst).min(file.chunk_count());
+        file.next_chunk = Some(end);
+        file.next_burst = now + interval;
+        let Some(file) = self.file_transfers.outgoing.get(&transfer_id) else {
+            return vec![];
+        };
+        (start..end)
+            .filter_map(|index| self.file_chunk(transfer_id, file, index))
+            .collect()
+    }
+
+    // Gives up on a file sent to us, telling the sender by declining it
+    fn abort_incoming(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        id: u64,
+        reason: &str,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(file) = self.file_transfers.incoming.remove(&id) else {
+            return vec![];
+        };
+        warn!(target: self.log_target.as_str(), "Dropping transfer {id} of {}: {reason}", file.file_name);
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Error: Receiving {} from @{} failed, {reason}",
+            file.file_name, file.peer
+        )));
+        vec![self.file_message(
+            file.server,
+            MessageKind::CliFileAnswer(FileAnswer {
+                transfer_id: file.transfer_id,
+                peer: file.peer,
+                accepted: false,
+            }),
+        )]
+    }
+
+    // `/sendfile <user> <path>`, the file goes out once the user accepts it
+    pub(crate) fn cmd_sendfile(
+        &mut self,
+        server_id: NodeId,
+        peer: &str,
+        path: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        if peer.is_empty() || path.is_empty() {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /sendfile <user> <path>".to_string(),
+                )],
+            );
+        }
+        let max_size = self.config.max_file_size;
+        let too_large = || {
+            (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: {path} is larger than {max_size} bytes"
+                ))],
+            )
+        };
+        // Checked before reading, so that a huge file is never loaded
+        if fs::metadata(path).is_ok_and(|x| x.len() > max_size) {
+            return too_large();
+        }
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) => {
+                return (
+                    vec![],
+                    vec![ChatClientEvent::MessageReceived(format!(
+                        "[SYSTEM] Error: Can't read {path}: {err}"
+                    ))],
+                )
+            }
+        };
+        if data.len() as u64 > max_size {
+            return too_large();
+        }
+        let now = self.now();
+        let file_name = Path::new(path)
+            .file_name()
+            .map_or_else(|| path.to_string(), |x| x.to_string_lossy().into_owned());
+        let file = OutgoingFile {
+            server: server_id,
+            peer: peer.to_string(),
+            file_name: file_name.clone(),
+            data,
+            chunk_size: self.config.file_chunk_size.max(1),
+            offered_at: now,
+            next_chunk: None,
+            next_burst: now,
+        };
+        let offer = FileOffer {
+            transfer_id: self.file_transfers.next_id(),
+            peer: peer.to_string(),
+            file_name: file_name.clone(),
+            size: file.data.len() as u64,
+            chunk_count: file.chunk_count(),
+        };
+        info!(target: self.log_target.as_str(), "Offering {file_name} to {peer} as transfer {}", offer.transfer_id);
+        let size = offer.size;
+        self.file_transfers.outgoing.insert(offer.transfer_id, file);
+        (
+            vec![self.file_message(server_id, MessageKind::CliFileOffer(offer))],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Offering {file_name} ({size} bytes) to @{peer}, waiting for them to accept..."
+            ))],
+        )
+    }
+
+    pub(crate) fn msg_srvfileoffer(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+        offer: FileOffer,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let pending = self
+            .file_transfers
+            .incoming
+            .values()
+            .filter(|x| x.last_chunk.is_none())
+            .count();
+        let refusal = if offer.size > self.config.max_file_size {
+            Some(format!(
+                "it's larger than {} bytes",
+                self.config.max_file_size
+            ))
+        } else if u64::from(offer.chunk_count) > offer.size {
+            // Every chunk carries at least a byte
+            Some("its chunk count doesn't match its size".to_string())
+        } else if pending >= MAX_PENDING_OFFERS {
+            Some(format!("{pending} other offers are waiting for an answer"))
+        } else {
+            None
+        };
+        if let Some(reason) = refusal {
+            info!(target: self.log_target.as_str(), "Declining {} from {}: {reason}", offer.file_name, offer.peer);
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Declined {} ({} bytes) from @{}, {reason}",
+                offer.file_name, offer.size, offer.peer
+            )));
+            return vec![self.file_message(
+                server,
+                MessageKind::CliFileAnswer(FileAnswer {
+                    transfer_id: offer.transfer_id,
+                    peer: offer.peer,
+                    accepted: false,
+                }),
+            )];
+        }
+        let now = self.now();
+        let id = self.file_transfers.next_id();
+        info!(target: self.log_target.as_str(), "{} offers {} as transfer {id}", offer.peer, offer.file_name);
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] @{} wants to send you {} ({} bytes), /acceptfile {id} or /declinefile {id}",
+            offer.peer, offer.file_name, offer.size
+        )));
+        self.file_transfers.incoming.insert(
+            id,
+            IncomingFile {
+                server,
+                peer: offer.peer,
+                transfer_id: offer.transfer_id,
+                file_name: offer.file_name,
+                size: offer.size,
+                chunk_count: offer.chunk_count,
+                chunks: BTreeMap::new(),
+                received_bytes: 0,
+                offered_at: now,
+                last_chunk: None,
+                retries: 0,
+            },
+        );
+        vec![]
+    }
+
+    // `/acceptfile <id>` and `/declinefile <id>`
+    pub(crate) fn cmd_answerfile(
+        &mut self,
+        arg: &str,
+        accept: bool,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(id) = arg.parse::<u64>().ok().filter(|id| {
+            self.file_transfers
+                .incoming
+                .get(id)
+                .is_some_and(|x| x.last_chunk.is_none())
+        }) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: No file offer {arg}, usage: /{} <id>",
+                    if accept { "acceptfile" } else { "declinefile" }
+                ))],
+            );
+        };
+        let now = self.now();
+        let mut events = vec![];
+        let mut replies = vec![];
+        if let Some(file) = self.file_transfers.incoming.get_mut(&id) {
+            file.last_chunk = Some(now);
+            let answer = FileAnswer {
+                transfer_id: file.transfer_id,
+                peer: file.peer.clone(),
+                accepted: accept,
+            };
+            let server = file.server;
+            replies.push(self.file_message(server, MessageKind::CliFileAnswer(answer)));
+        }
+        if !accept {
+            self.file_transfers.incoming.remove(&id);
+        } else if self
+            .file_transfers
+            .incoming
+            .get(&id)
+            .is_some_and(|x| x.chunk_count == 0)
+        {
+            replies.extend(self.finish_incoming(&mut events, id));
+        }
+        (replies, events)
+    }
+
+    pub(crate) fn msg_srvfileanswer(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+        answer: &FileAnswer,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(file) = self
+            .file_transfers
+            .find_outgoing(server, &answer.peer, answer.transfer_id)
+            .filter(|x| x.next_chunk.is_none() || !answer.accepted)
+        else {
+            return vec![];
+        };
+        if !answer.accepted {
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] @{} declined {}",
+                answer.peer, file.file_name
+            )));
+            self.file_transfers.outgoing.remove(&answer.transfer_id);
+            return vec![];
+        }
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Sending {} to @{}...",
+            file.file_name, answer.peer
+        )));
+        if let Some(file) = self.file_transfers.outgoing.get_mut(&answer.transfer_id) {
+            file.next_chunk = Some(0);
+        }
+        let now = self.now();
+        self.next_file_burst(answer.transfer_id, now)
+    }
+
+    pub(crate) fn msg_srvfilechunk(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+        chunk: FileChunk,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let now = self.now();
+        let Some(id) = self
+            .file_transfers
+            .find_incoming(server, &chunk.peer, chunk.transfer_id)
+        else {
+            return vec![];
+        };
+        let Some(file) = self
+            .file_transfers
+            .incoming
+            .get_mut(&id)
+            .filter(|x| x.last_chunk.is_some() && chunk.index < x.chunk_count)
+        else {
+            return vec![];
+        };
+        file.received_bytes += chunk.data.len() as u64;
+        if let Some(old) = file.chunks.insert(chunk.index, chunk.data) {
+            file.received_bytes -= old.len() as u64;
+        }
+        if file.received_bytes > file.size {
+            return self.abort_incoming(events, id, "it's larger than announced");
+        }
+        file.last_chunk = Some(now);
+        file.retries = 0;
+        let received = u32::try_from(file.chunks.len()).unwrap_or(u32::MAX);
+        events.push(ChatClientEvent::FileProgress {
+            transfer_id: id,
+            received,
+            total: file.chunk_count,
+        });
+        if received < file.chunk_count {
+            return vec![];
+        }
+        self.finish_incoming(events, id)
+    }
+
+    // Writes a complete file to the download directory and tells the sender it arrived,
+    // by asking for no missing chunks. A file of another size than announced is dropped
+    fn finish_incoming(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        id: u64,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        if self
+            .file_transfers
+            .incoming
+            .get(&id)
+            .is_some_and(|x| x.received_bytes != x.size)
+        {
+            return self.abort_incoming(events, id, "it isn't the size announced");
+        }
+        let Some(file) = self.file_transfers.incoming.remove(&id) else {
+            return vec![];
+        };
+        let data = file.chunks.into_values().flatten().collect::<Vec<_>>();
+        match self.save_download(id, &file.file_name, &data) {
+            Ok(path) => {
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Received {} from @{}, saved to {}",
+                    file.file_name,
+                    file.peer,
+                    path.display()
+                )));
+                events.push(ChatClientEvent::FileReceived(path));
+            }
+            Err(err) => events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Error: Can't save {} from @{}: {err}",
+                file.file_name, file.peer
+            ))),
+        }
+        vec![self.file_message(
+            file.server,
+            MessageKind::CliFileMissing(FileMissing {
+                transfer_id: file.transfer_id,
+                peer: file.peer,
+                chunks: vec![],
+            }),
+        )]
+    }
+
+    // Only the last component of the name the sender chose is used, and a file already
+    // there is never overwritten: the file is created new, under a numbered name if
+    // that one is taken
+    fn save_download(&self, id: u64, file_name: &str, data: &[u8]) -> io::Result<PathBuf> {
+        let dir = self
+            .config
+            .download_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let name = Path::new(file_name).file_name().map_or_else(
+            || format!("file-{id}"),
+            |x| x.to_string_lossy().into_owned(),
+        );
+        let mut path = dir.join(&name);
+        for attempt in 1..=MAX_NAME_ATTEMPTS {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut out) => {
+                    if let Err(e) = out.write_all(data) {
+                        let _ = fs::remove_file(&path);
+                        return Err(e);
+                    }
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    path = dir.join(format!("{attempt}-{name}"));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{MAX_NAME_ATTEMPTS} files named like {name} exist already"),
+        ))
+    }
+
+    pub(crate) fn msg_srvfilemissing(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+        missing: &FileMissing,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(file) =
+            self.file_transfers
+                .find_outgoing(server, &missing.peer, missing.transfer_id)
+        else {
+            return vec![];
+        };
+        if missing.chunks.is_empty() {
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] @{} received {}",
+                missing.peer, file.file_name
+            )));
+            self.file_transfers.outgoing.remove(&missing.transfer_id);
+            return vec![];
+        }
+        info!(target: self.log_target.as_str(), "Resending {} chunks of transfer {}", missing.chunks.len(), missing.transfer_id);
+        missing
+            .chunks
+            .iter()
+            .take(MISSING_BATCH)
+            .filter_map(|index| self.file_chunk(missing.transfer_id, file, *index))
+            .collect()
+    }
+
+    // Sends the next burst of the files being sent, drops offers left unanswered, asks
+    // again for the chunks of accepted files that stopped coming, and gives up on a file
+    // after `file_chunk_retries` requests went unanswered
+    pub(crate) fn poll_file_transfers(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        now: Instant,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut replies = vec![];
+        let due = self
+            .file_transfers
+            .outgoing
+            .iter()
+            .filter(|(_, x)| {
+                x.next_chunk.is_some_and(|next| next < x.chunk_count()) && now >= x.next_burst
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in due {
+            replies.extend(self.next_file_burst(id, now));
+        }
+        let unanswered = self
+            .file_transfers
+            .outgoing
+            .iter()
+            .filter(|(_, x)| x.next_chunk.is_none() && now - x.offered_at >= OFFER_LIFETIME)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in unanswered {
+            if let Some(file) = self.file_transfers.outgoing.remove(&id) {
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] @{} didn't answer the offer of {}, giving up",
+                    file.peer, file.file_name
+                )));
+            }
+        }
+        let expired = self
+            .file_transfers
+            .incoming
+            .iter()
+            .filter(|(_, x)| x.last_chunk.is_none() && now - x.offered_at >= OFFER_LIFETIME)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            replies.extend(self.abort_incoming(events, id, "the offer expired"));
+        }
+        let timeout = self.config.file_chunk_timeout;
+        let stalled = self
+            .file_transfers
+            .incoming
+            .iter()
+            .filter(|(_, x)| x.last_chunk.is_some_and(|last| now - last >= timeout))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in stalled {
+            let Some(file) = self.file_transfers.incoming.get_mut(&id) else {
+                continue;
+            };
+            if file.retries >= self.config.file_chunk_retries {
+                replies.extend(self.abort_incoming(events, id, "it stopped coming"));
+                continue;
+            }
+            file.retries += 1;
+            file.last_chunk = Some(now);
+            let missing = FileMissing {
+                transfer_id: file.transfer_id,
+                peer: file.peer.clone(),
+                chunks: file.missing(),
+            };
+            let server = file.server;
+            replies.push(self.file_message(server, MessageKind::CliFileMissing(missing)));
+        }
+        replies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ChatClientConfig;
+    use crate::server::{ChatServerConfig, ChatServerInternal};
+    use std::collections::VecDeque;
+
+    const SERVER: NodeId = 10;
+
+    // A directory of its own for each test, so that parallel tests never share files
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat_file_{test}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // alice is node 1 and bob node 2, both registered at SERVER. Files bob receives
+    // land in `downloads`
+    fn setup(downloads: &Path) -> (ChatServerInternal, [ChatClientInternal; 2]) {
+        let mut server = ChatServerInternal::with_config(SERVER, ChatServerConfig::default());
+        server.msg_cliregisterrequest(&mut vec![], 1, "alice".to_string());
+        server.msg_cliregisterrequest(&mut vec![], 2, "bob".to_string());
+        let client = |id, download_dir| {
+            ChatClientInternal::with_config(
+                id,
+                ChatClientConfig {
+                    signing_key_dir: None,
+                    download_dir,
+                    file_chunk_size: 4,
+                    ..ChatClientConfig::default()
+                },
+            )
+        };
+        let clients = [client(1, None), client(2, Some(downloads.to_path_buf()))];
+        (server, clients)
+    }
+
+    fn deliver(
+        client: &mut ChatClientInternal,
+        events: &mut Vec<ChatClientEvent>,
+        kind: MessageKind,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        match kind {
+            MessageKind::SrvFileOffer(offer) => client.msg_srvfileoffer(events, SERVER, offer),
+            MessageKind::SrvFileAnswer(answer) => client.msg_srvfileanswer(events, SERVER, &answer),
+            MessageKind::SrvFileChunk(chunk) => client.msg_srvfilechunk(events, SERVER, chunk),
+            MessageKind::SrvFileMissing(missing) => {
+                client.msg_srvfilemissing(events, SERVER, &missing)
+            }
+            other => panic!("unexpected message {other:?}"),
+        }
+    }
+
+    // Passes what node `from` sent through the server, and whatever the clients answer
+    // after it, until nobody has anything left to send
+    fn exchange(
+        server: &mut ChatServerInternal,
+        clients: &mut [ChatClientInternal; 2],
+        from: NodeId,
+        sent: Vec<(NodeId, ChatMessage)>,
+    ) -> Vec<ChatClientEvent> {
+        let mut events = vec![];
+        let mut queue = sent
+            .into_iter()
+            .map(|(_, msg)| (from, msg))
+            .collect::<VecDeque<_>>();
+        while let Some((from, msg)) = queue.pop_front() {
+            let mut relayed = vec![];
+            server.msg_clifiletransfer(
+                &mut relayed,
+                &mut vec![],
+                from,
+                msg.message_kind.expect("empty message"),
+            );
+            for (to, msg) in relayed {
+                let client = &mut clients[usize::from(to) - 1];
+                let replies = deliver(
+                    client,
+                    &mut events,
+                    msg.message_kind.expect("empty message"),
+                );
+                queue.extend(replies.into_iter().map(|(_, msg)| (to, msg)));
+            }
+        }
+        events
+    }
+
+    fn received(events: &[ChatClientEvent]) -> Vec<PathBuf> {
+        events
+            .iter()
+            .filter_map(|x| match x {
+                ChatClientEvent::FileReceived(path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepted_files_arrive_whole() {
+        let dir = scratch_dir("accepted");
+        let downloads = dir.join("downloads");
+        fs::create_dir_all(&downloads).unwrap();
+        let source = dir.join("notes.txt");
+        fs::write(&source, b"hello over the drones").unwrap();
+        let (mut server, mut clients) = setup(&downloads);
+
+        let (offer, _) = clients[0].cmd_sendfile(SERVER, "bob", source.to_str().unwrap());
+        let events = exchange(&mut server, &mut clients, 1, offer);
+        assert!(events.iter().any(|x| matches!(
+            x,
+            ChatClientEvent::MessageReceived(text) if text.contains("@alice wants to send you notes.txt")
+        )));
+        assert!(received(&events).is_empty());
+
+        let (answer, _) = clients[1].cmd_answerfile("1", true);
+        let events = exchange(&mut server, &mut clients, 2, answer);
+        let paths = received(&events);
+        assert_eq!(paths, vec![downloads.join("notes.txt")]);
+        assert_eq!(fs::read(&paths[0]).unwrap(), b"hello over the drones");
+        // 21 bytes in chunks of 4
+        assert!(events.iter().any(|x| matches!(
+            x,
+            ChatClientEvent::FileProgress {
+                received: 6,
+                total: 6,
+                ..
+            }
+        )));
+        assert!(clients[0].file_transfers.outgoing.is_empty());
+        assert!(clients[1].file_transfers.incoming.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_already_there_are_never_overwritten() {
+        let dir = scratch_dir("overwrite");
+        let downloads = dir.join("downloads");
+        fs::create_dir_all(&downloads).unwrap();
+        fs::write(downloads.join("notes.txt"), b"keep me").unwrap();
+        let source = dir.join("notes.txt");
+        fs::write(&source, b"new notes").unwrap();
+        let (mut server, mut clients) = setup(&downloads);
+
+        let (offer, _) = clients[0].cmd_sendfile(SERVER, "bob", source.to_str().unwrap());
+        exchange(&mut server, &mut clients, 1, offer);
+        let (answer, _) = clients[1].cmd_answerfile("1", true);
+        let events = exchange(&mut server, &mut clients, 2, answer);
+
+        assert_eq!(received(&events), vec![downloads.join("1-notes.txt")]);
+        assert_eq!(fs::read(downloads.join("notes.txt")).unwrap(), b"keep me");
+        assert_eq!(
+            fs::read(downloads.join("1-notes.txt")).unwrap(),
+            b"new notes"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn declined_files_are_forgotten_by_the_sender() {
+        let dir = scratch_dir("declined");
+        let source = dir.join("notes.txt");
+        fs::write(&source, b"not wanted").unwrap();
+        let (mut server, mut clients) = setup(&dir);
+
+        let (offer, _) = clients[0].cmd_sendfile(SERVER, "bob", source.to_str().unwrap());
+        exchange(&mut server, &mut clients, 1, offer);
+        let (answer, _) = clients[1].cmd_answerfile("1", false);
+        let events = exchange(&mut server, &mut clients, 2, answer);
+
+        assert!(events.iter().any(|x| matches!(
+            x,
+            ChatClientEvent::MessageReceived(text) if text.contains("@bob declined notes.txt")
+        )));
+        assert!(clients[0].file_transfers.outgoing.is_empty());
+        assert!(clients[1].file_transfers.incoming.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn offers_that_cannot_be_right_are_declined_at_once() {
+        let dir = scratch_dir("refused");
+        let (_, [_, mut bob]) = setup(&dir);
+        let offer = |transfer_id, size, chunk_count| FileOffer {
+            transfer_id,
+            peer: "alice".to_string(),
+            file_name: "big.bin".to_string(),
+            size,
+            chunk_count,
+        };
+        let max = bob.config.max_file_size;
+
+        for offer in [offer(1, max + 1, 1), offer(2, 3, 4)] {
+            let transfer_id = offer.transfer_id;
+            let replies = bob.msg_srvfileoffer(&mut vec![], SERVER, offer);
+            assert!(matches!(
+                &replies[..],
+                [(SERVER, ChatMessage { message_kind: Some(MessageKind::CliFileAnswer(answer)), .. })]
+                    if answer.transfer_id == transfer_id && !answer.accepted && answer.peer == "alice"
+            ));
+        }
+        assert!(bob.file_transfers.incoming.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
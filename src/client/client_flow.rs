@@ -0,0 +1,335 @@
+use crate::client::ChatClientInternal;
+use crate::compression::{compress_text, COMPRESSION_CAPABILITY};
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, FlowCredit, SendMessage};
+use common::slc_commands::{ChatClientEvent, SendStatus};
+use log::{debug, info};
+use std::collections::VecDeque;
+use wg_2024::network::NodeId;
+
+// Send window advertised by the connected server
+#[derive(Debug)]
+pub(crate) struct FlowControl {
+    window: u32,
+    // Chat messages sent and acknowledged since registering, both counted by us
+    sent: u64,
+    acked: u64,
+    // Messages waiting for the window to open, oldest first
+    queue: VecDeque<SendMessage>,
+}
+
+impl FlowControl {
+    fn has_credit(&self) -> bool {
+        self.sent - self.acked < u64::from(self.window)
+    }
+}
+
+impl ChatClientInternal {
+    // Window of the connected server, None before it advertised one
+    pub(crate) fn send_window(&self) -> Option<u32> {
+        self.flow.as_ref().map(|x| x.window)
+    }
+
+    // Sends a chat message now if the server's window allows it, buffers it otherwise.
+    // Servers that never advertised a window aren't limited
+    pub(crate) fn send_chat_message(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+        mut message: SendMessage,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        *self.stats.sent.entry(message.channel_id).or_default() += 1;
+        let local_id = self.track_outgoing(events, &message);
+        message.message = self.seal_for_channel(message.channel_id, message.message);
+        self.sign_outgoing(server_id, &mut message);
+        // The signature covers the text as the recipients will see it, once the server
+        // decompressed it
+        if self.server_has_capability(server_id, COMPRESSION_CAPABILITY) {
+            if let Some(compressed) = compress_text(&message.message) {
+                message.message = compressed;
+            }
+        }
+        message.local_id = local_id;
+        if let Some(flow) = self.flow.as_mut() {
+            if !flow.has_credit() || !flow.queue.is_empty() {
+                debug!(target: self.log_target.as_str(), "Send window full, queueing message");
+                flow.queue.push_back(message);
+                return vec![];
+            }
+            flow.sent += 1;
+        }
+        self.set_send_status(events, message.local_id, SendStatus::Sent);
+        self.track_sent(server_id, &message);
+        vec![(
+            server_id,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(MessageKind::SendMsg(message)),
+            },
+        )]
+    }
+
+    pub(crate) fn msg_srvflowcredit(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+        credit: &FlowCredit,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        if self.currently_connected_server != Some(server_id) {
+            return vec![];
+        }
+        // Before the first credit nothing was counted, but what we sent meanwhile is
+        // acknowledged by it all the same
+        let newly_acked = match &self.flow {
+            Some(flow) => credit.acked.min(flow.sent).saturating_sub(flow.acked),
+            None => credit.acked.min(self.unacked_count()),
+        };
+        let flow = self.flow.get_or_insert_with(|| FlowControl {
+            window: credit.window,
+            sent: credit.acked,
+            acked: credit.acked,
+            queue: VecDeque::new(),
+        });
+        flow.window = credit.window;
+        // Credits can arrive out of order, only ever move forward
+        flow.acked = flow.acked.max(credit.acked).min(flow.sent);
+        self.record_acks(events, newly_acked);
+        self.send_queued(events, server_id)
+    }
+
+    // A message the server never got, or never confirmed in time, no longer takes up
+    // room in its window. Otherwise lost messages would close the window for good
+    pub(crate) fn release_credit(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+        local_id: u64,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        // Only counted while it awaited the acknowledgement of the connected server
+        if self.currently_connected_server != Some(server_id) || !self.stop_awaiting_ack(local_id) {
+            return vec![];
+        }
+        let Some(flow) = self.flow.as_mut() else {
+            return vec![];
+        };
+        flow.sent = flow.sent.saturating_sub(1).max(flow.acked);
+        self.send_queued(events, server_id)
+    }
+
+    // Sends queued messages as far as the window allows
+    fn send_queued(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(flow) = self.flow.as_mut() else {
+            return vec![];
+        };
+        let mut sent = vec![];
+        while flow.has_credit() {
+            let Some(message) = flow.queue.pop_front() else {
+                break;
+            };
+            flow.sent += 1;
+            sent.push(message);
+        }
+        sent.into_iter()
+            .map(|message| {
+                self.set_send_status(events, message.local_id, SendStatus::Sent);
+                self.track_sent(server_id, &message);
+                (
+                    server_id,
+                    ChatMessage {
+                        own_id: u32::from(self.own_id),
+                        request_id: 0,
+                        message_kind: Some(MessageKind::SendMsg(message)),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    // Messages taken out of the queue count as failed, they're sent again as new ones
+    pub(crate) fn take_flow_queue(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+    ) -> Vec<SendMessage> {
+        let queued = self
+            .flow
+            .as_mut()
+            .map(|flow| flow.queue.drain(..).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|message| self.take_original(message.local_id))
+            .collect::<Vec<_>>();
+        for message in &queued {
+            self.set_send_status(events, message.local_id, SendStatus::Failed);
+        }
+        self.forget_unacked();
+        queued
+    }
+
+    // Forgets the window of the previous server, reporting messages that never left
+    pub(crate) fn reset_flow_control(&mut self, events: &mut Vec<ChatClientEvent>) {
+        self.forget_unacked();
+        let Some(flow) = self.flow.take() else {
+            return;
+        };
+        for message in &flow.queue {
+            self.set_send_status(events, message.local_id, SendStatus::Failed);
+        }
+        if !flow.queue.is_empty() {
+            info!(target: self.log_target.as_str(), "Discarding {} queued messages", flow.queue.len());
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] {} queued message(s) were not sent",
+                flow.queue.len()
+            )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{ChatClientConfig, ChatClientInternal};
+    use crate::protocol::ALL_CHANNEL_ID;
+    use chat_common::messages::chat_message::MessageKind;
+    use chat_common::messages::{ChatMessage, FlowCredit, SendMessage};
+    use wg_2024::network::NodeId;
+
+    const SERVER: NodeId = 10;
+
+    fn connected_client() -> ChatClientInternal {
+        let mut client = ChatClientInternal::with_config(
+            1,
+            ChatClientConfig {
+                signing_key_dir: None,
+                ..ChatClientConfig::default()
+            },
+        );
+        client.currently_connected_server = Some(SERVER);
+        client
+    }
+
+    fn message(text: &str) -> SendMessage {
+        SendMessage {
+            message: text.to_string(),
+            channel_id: ALL_CHANNEL_ID,
+            channel_kind: None,
+            content_warning: None,
+            local_id: 0,
+            signature: String::new(),
+            signer_key: String::new(),
+            signed_at: 0,
+        }
+    }
+
+    fn local_id(sent: &[(NodeId, ChatMessage)]) -> u64 {
+        match &sent[0].1.message_kind {
+            Some(MessageKind::SendMsg(msg)) => msg.local_id,
+            other => panic!("expected a chat message, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn messages_wait_for_the_window_to_open() {
+        let mut client = connected_client();
+        let mut events = vec![];
+        client.msg_srvflowcredit(
+            &mut events,
+            SERVER,
+            &FlowCredit {
+                window: 2,
+                acked: 0,
+            },
+        );
+        assert_eq!(
+            client
+                .send_chat_message(&mut events, SERVER, message("a"))
+                .len(),
+            1
+        );
+        assert_eq!(
+            client
+                .send_chat_message(&mut events, SERVER, message("b"))
+                .len(),
+            1
+        );
+        assert!(client
+            .send_chat_message(&mut events, SERVER, message("c"))
+            .is_empty());
+        let released = client.msg_srvflowcredit(
+            &mut events,
+            SERVER,
+            &FlowCredit {
+                window: 2,
+                acked: 1,
+            },
+        );
+        assert_eq!(released.len(), 1);
+    }
+
+    #[test]
+    fn credits_out_of_order_do_not_reopen_the_window() {
+        let mut client = connected_client();
+        let mut events = vec![];
+        client.msg_srvflowcredit(
+            &mut events,
+            SERVER,
+            &FlowCredit {
+                window: 1,
+                acked: 0,
+            },
+        );
+        client.send_chat_message(&mut events, SERVER, message("a"));
+        client.send_chat_message(&mut events, SERVER, message("b"));
+        assert_eq!(
+            client
+                .msg_srvflowcredit(
+                    &mut events,
+                    SERVER,
+                    &FlowCredit {
+                        window: 1,
+                        acked: 1
+                    }
+                )
+                .len(),
+            1
+        );
+        assert!(client
+            .msg_srvflowcredit(
+                &mut events,
+                SERVER,
+                &FlowCredit {
+                    window: 1,
+                    acked: 0
+                }
+            )
+            .is_empty());
+        assert!(client
+            .send_chat_message(&mut events, SERVER, message("c"))
+            .is_empty());
+    }
+
+    #[test]
+    fn lost_messages_give_their_credit_back() {
+        let mut client = connected_client();
+        let mut events = vec![];
+        client.msg_srvflowcredit(
+            &mut events,
+            SERVER,
+            &FlowCredit {
+                window: 1,
+                acked: 0,
+            },
+        );
+        let sent = client.send_chat_message(&mut events, SERVER, message("a"));
+        assert!(client
+            .send_chat_message(&mut events, SERVER, message("b"))
+            .is_empty());
+        let lost = local_id(&sent);
+        assert_eq!(client.release_credit(&mut events, SERVER, lost).len(), 1);
+        // Only released once, it no longer awaits an acknowledgement
+        assert!(client.release_credit(&mut events, SERVER, lost).is_empty());
+    }
+}
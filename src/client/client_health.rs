@@ -0,0 +1,64 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::{ChatMessage, SendMessage};
+use common::slc_commands::ChatClientEvent;
+use log::{debug, info};
+use std::time::Duration;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    pub(crate) fn is_server_stale(&self, id: NodeId) -> bool {
+        self.discovered_servers
+            .get(&id)
+            .is_some_and(|srv| srv.consecutive_failures >= self.config.stale_failure_threshold)
+    }
+
+    // How long ago a discovered server last answered, its discovery counting as an answer
+    #[must_use]
+    pub fn since_last_reply(&self, id: NodeId) -> Option<Duration> {
+        self.discovered_servers
+            .get(&id)
+            .map(|srv| self.now().saturating_duration_since(srv.last_reply))
+    }
+
+    pub(crate) fn record_server_reply(&mut self, id: NodeId) {
+        let now = self.now();
+        if let Some(srv) = self.discovered_servers.get_mut(&id) {
+            srv.last_reply = now;
+            srv.consecutive_failures = 0;
+        }
+    }
+
+    pub(crate) fn record_send_failure(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        id: NodeId,
+        lost: Vec<SendMessage>,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(srv) = self.discovered_servers.get_mut(&id) else {
+            return vec![];
+        };
+        srv.consecutive_failures += 1;
+        debug!(target: self.log_target.as_str(), "Server {id} has {} consecutive delivery failures", srv.consecutive_failures);
+        // Only warn once, when the threshold is crossed
+        if srv.consecutive_failures == self.config.stale_failure_threshold
+            && self.currently_connected_server == Some(id)
+        {
+            info!(target: self.log_target.as_str(), "Connected server {id} became stale");
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Warning: Server {} is not responding, messages may be lost",
+                self.server_display_name(id)
+            )));
+            // Only the failure that makes us leave the server keeps what was lost, to be
+            // sent again where we go. Earlier ones were just reported
+            if self.config.failover {
+                self.keep_for_replay(events, lost);
+                return self.start_failover(events);
+            }
+            if self.config.auto_reconnect {
+                self.keep_for_replay(events, lost);
+                return self.start_reconnect(events);
+            }
+        }
+        vec![]
+    }
+}
@@ -0,0 +1,174 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{
+    ChatMessage, FetchHistory, HistoryPage, MessageData, MissedActivity, SearchMessages,
+    SearchResults,
+};
+use common::slc_commands::ChatClientEvent;
+use itertools::Itertools;
+use log::info;
+use wg_2024::network::NodeId;
+
+pub(crate) const HISTORY_PAGE_SIZE: u32 = 20;
+
+impl ChatClientInternal {
+    // Asks the connected server for up to `limit` messages of `channel_id` older than `before`,
+    // or for the most recent ones when `before` is None
+    pub fn fetch_history(
+        &self,
+        channel_id: u64,
+        before: Option<u64>,
+        limit: u32,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        self.currently_connected_server
+            .map(|server_id| {
+                (
+                    server_id,
+                    ChatMessage {
+                        own_id: u32::from(self.own_id),
+                        request_id: 0,
+                        message_kind: Some(MessageKind::CliFetchHistory(FetchHistory {
+                            channel_id,
+                            before_message_id: before,
+                            limit,
+                        })),
+                    },
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+
+    pub(crate) fn cmd_history(
+        &self,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(channel_id) = self.currently_connected_channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: You are not connected to a channel.".to_string(),
+                )],
+            );
+        };
+        let before = if arg == "--more" {
+            self.history_oldest.get(&channel_id).copied()
+        } else {
+            None
+        };
+        (
+            self.fetch_history(channel_id, before, HISTORY_PAGE_SIZE),
+            vec![ChatClientEvent::MessageReceived(
+                "[SYSTEM] Fetching history...".to_string(),
+            )],
+        )
+    }
+
+    pub(crate) fn record_seen_message(&mut self, msg: &MessageData) {
+        self.history_oldest
+            .entry(msg.channel_id)
+            .and_modify(|oldest| *oldest = (*oldest).min(msg.message_id))
+            .or_insert(msg.message_id);
+    }
+
+    pub(crate) fn msg_srvhistory(&mut self, events: &mut Vec<ChatClientEvent>, page: HistoryPage) {
+        info!(target: self.log_target.as_str(), "Received {} history messages for channel {}", page.messages.len(), page.channel_id);
+        if page.messages.is_empty() {
+            events.push(ChatClientEvent::MessageReceived(
+                "[SYSTEM] No older messages.".to_string(),
+            ));
+            return;
+        }
+        for msg in &page.messages {
+            self.record_seen_message(msg);
+            self.msg_srvdistributemessage(events, msg);
+        }
+        if page.has_more {
+            events.push(ChatClientEvent::MessageReceived(
+                "[SYSTEM] Use /history --more to see older messages.".to_string(),
+            ));
+        }
+    }
+
+    pub(crate) fn cmd_search(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+        freeform: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        // The client only keeps a few recent messages, so searches always go to the server
+        let query = if arg == "--server" {
+            freeform.to_string()
+        } else {
+            format!("{arg} {freeform}")
+        };
+        let query = query.trim();
+        match (self.currently_connected_channel, query.is_empty()) {
+            (Some(channel_id), false) => (
+                vec![(
+                    server_id,
+                    ChatMessage {
+                        own_id: u32::from(self.own_id),
+                        request_id: 0,
+                        message_kind: Some(MessageKind::CliSearchMessages(SearchMessages {
+                            channel_id,
+                            query: query.to_string(),
+                            limit: HISTORY_PAGE_SIZE,
+                        })),
+                    },
+                )],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Searching for \"{query}\"..."
+                ))],
+            ),
+            (Some(_), true) => (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /search --server <pattern>".to_string(),
+                )],
+            ),
+            (None, _) => (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: You are not connected to a channel.".to_string(),
+                )],
+            ),
+        }
+    }
+
+    pub(crate) fn msg_srvsearchresults(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        results: &SearchResults,
+    ) {
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] {} result(s) for \"{}\":",
+            results.messages.len(),
+            results.query
+        )));
+        for msg in &results.messages {
+            self.msg_srvdistributemessage(events, msg);
+        }
+    }
+
+    pub(crate) fn msg_srvmissedactivity(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        summary: &MissedActivity,
+    ) {
+        events.push(ChatClientEvent::MessageReceived(
+            "[SYSTEM] While you were away...".to_string(),
+        ));
+        for chan in &summary.channels {
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM]    #{}: {} new message(s), latest from {}",
+                chan.channel_name,
+                chan.missed_count,
+                chan.last_senders.iter().map(|x| format!("@{x}")).join(", ")
+            )));
+            for msg in &chan.mentions {
+                self.msg_srvdistributemessage(events, msg);
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::client::ChatClientInternal;
+use log::warn;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Inputs that carry secrets, kept for recall but never written to the file
+const SECRET_COMMANDS: &[&str] = &["/key "];
+
+// Only readable by its owner where the platform allows it
+fn open_history_file(path: &Path, append: bool) -> io::Result<fs::File> {
+    let mut options = OpenOptions::new();
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    options.create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+// What the user typed, commands included, for up-arrow style recall by frontends
+#[derive(Debug)]
+pub(crate) struct InputHistory {
+    // Oldest first
+    entries: VecDeque<String>,
+    // Entry currently recalled, None when not browsing
+    cursor: Option<usize>,
+    limit: usize,
+    // File the entries are kept in between runs, one per line. Entries are appended to
+    // it and it's trimmed to the limit when loaded
+    path: Option<PathBuf>,
+}
+
+impl InputHistory {
+    pub(crate) fn load(limit: usize, path: Option<PathBuf>, log_target: &str) -> Self {
+        let mut entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|data| data.lines().map(str::to_string).collect::<VecDeque<_>>())
+            .unwrap_or_default();
+        if entries.len() > limit {
+            while entries.len() > limit {
+                entries.pop_front();
+            }
+            if let Some(path) = &path {
+                let trimmed = open_history_file(path, false).and_then(|mut file| {
+                    file.write_all(
+                        entries
+                            .iter()
+                            .map(|x| format!("{x}\n"))
+                            .collect::<String>()
+                            .as_bytes(),
+                    )
+                });
+                if let Err(e) = trimmed {
+                    warn!(target: log_target, "Could not trim input history {}: {e}", path.display());
+                }
+            }
+        }
+        Self {
+            entries,
+            cursor: None,
+            limit,
+            path,
+        }
+    }
+
+    fn record(&mut self, input: &str) -> io::Result<()> {
+        self.cursor = None;
+        // Multi-line inputs can't be stored one per line, and they're not worth recalling
+        if input.trim().is_empty()
+            || input.contains('\n')
+            || self.entries.back().is_some_and(|last| last == input)
+        {
+            return Ok(());
+        }
+        self.entries.push_back(input.to_string());
+        while self.entries.len() > self.limit {
+            self.entries.pop_front();
+        }
+        match &self.path {
+            Some(path) if !SECRET_COMMANDS.iter().any(|x| input.starts_with(x)) => {
+                open_history_file(path, true)?.write_all(format!("{input}\n").as_bytes())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn previous_entry(&mut self) -> Option<&str> {
+        let cursor = match self.cursor {
+            Some(0) => 0,
+            Some(cursor) => cursor - 1,
+            None => self.entries.len().checked_sub(1)?,
+        };
+        self.cursor = Some(cursor);
+        self.entries.get(cursor).map(String::as_str)
+    }
+
+    // None once past the newest entry, meaning the input line should be empty again
+    fn next_entry(&mut self) -> Option<&str> {
+        let cursor = self.cursor? + 1;
+        if cursor >= self.entries.len() {
+            self.cursor = None;
+            return None;
+        }
+        self.cursor = Some(cursor);
+        self.entries.get(cursor).map(String::as_str)
+    }
+}
+
+impl ChatClientInternal {
+    pub(crate) fn record_input(&mut self, input: &str) {
+        if let Err(e) = self.input_history.record(input) {
+            warn!(target: self.log_target.as_str(), "Could not save input history: {e}");
+        }
+    }
+
+    // Steps back through what the user typed, staying on the oldest entry
+    pub fn previous_input(&mut self) -> Option<String> {
+        self.input_history.previous_entry().map(str::to_string)
+    }
+
+    pub fn next_input(&mut self) -> Option<String> {
+        self.input_history.next_entry().map(str::to_string)
+    }
+}
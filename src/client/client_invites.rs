@@ -0,0 +1,126 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, Invite, MemberAction, SetInviteOnly};
+use common::slc_commands::ChatClientEvent;
+use log::info;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    pub(crate) fn msg_srvinvitereceived(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+        invite: Invite,
+    ) {
+        info!(target: self.log_target.as_str(), "Invited to channel {} by {}", invite.channel_id, invite.from);
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] @{} invited you to #{}, accept with /join {}",
+            invite.from, invite.channel_name, invite.channel_name
+        )));
+        self.invites
+            .retain(|(id, x)| *id != server || x.channel_id != invite.channel_id);
+        self.invites.push((server, invite));
+    }
+
+    // Joining a channel uses up its invitation
+    pub(crate) fn forget_invite(&mut self, server: NodeId, channel_id: u64) {
+        self.invites
+            .retain(|(id, x)| *id != server || x.channel_id != channel_id);
+    }
+
+    pub(crate) fn cmd_invites(&self) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let msg = if self.invites.is_empty() {
+            "[SYSTEM] No pending invitations".to_string()
+        } else {
+            self.invites.iter().fold(
+                "[SYSTEM] Pending invitations:".to_string(),
+                |mut acc, (server, x)| {
+                    acc.push_str(&format!(
+                        "\n[SYSTEM]    #{} on {} from @{}",
+                        x.channel_name,
+                        self.server_display_name(*server),
+                        x.from
+                    ));
+                    acc
+                },
+            )
+        };
+        (vec![], vec![ChatClientEvent::MessageReceived(msg)])
+    }
+
+    pub(crate) fn cmd_invite(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(channel_id) = self.currently_connected_channel.filter(|_| !arg.is_empty()) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /invite <user>, from the channel to invite to"
+                        .to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliInvite(MemberAction {
+                        channel_id,
+                        username: arg.to_string(),
+                    })),
+                },
+            )],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Inviting {arg} to #{}...",
+                self.channel_display_name(channel_id)
+            ))],
+        )
+    }
+
+    pub(crate) fn cmd_inviteonly(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let invite_only = match arg {
+            "on" => true,
+            "off" => false,
+            _ => {
+                return (
+                    vec![],
+                    vec![ChatClientEvent::MessageReceived(
+                        "[SYSTEM] Error: Usage: /inviteonly on|off".to_string(),
+                    )],
+                )
+            }
+        };
+        let Some(channel_id) = self.currently_connected_channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: You are not connected to a channel.".to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliSetInviteOnly(SetInviteOnly {
+                        channel_id,
+                        invite_only,
+                    })),
+                },
+            )],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Turning invite-only mode {arg}..."
+            ))],
+        )
+    }
+}
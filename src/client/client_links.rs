@@ -0,0 +1,89 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::{ChatMessage, MessageData};
+use common::slc_commands::ChatClientEvent;
+use wg_2024::network::NodeId;
+
+// Links remembered per channel for /links
+const LINKS_KEPT: usize = 20;
+
+#[derive(Debug)]
+pub(crate) struct SeenLink {
+    username: String,
+    url: String,
+}
+
+// URLs in a message: words starting with a scheme or "www.", without the punctuation
+// around them
+fn find_urls(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| {
+            word.trim_start_matches(['(', '<', '[', '"', '\''])
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '>', ']', '"', '\''])
+        })
+        .filter(|word| {
+            ["http://", "https://", "www."]
+                .iter()
+                .any(|prefix| word.len() > prefix.len() && word.starts_with(prefix))
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+impl ChatClientInternal {
+    // Tells the frontend about the links in a message and remembers them for /links
+    pub(crate) fn record_links(&mut self, events: &mut Vec<ChatClientEvent>, msg: &MessageData) {
+        let urls = find_urls(&msg.message);
+        if urls.is_empty() {
+            return;
+        }
+        let seen = self.links.entry(msg.channel_id).or_default();
+        for url in &urls {
+            seen.push_back(SeenLink {
+                username: msg.username.clone(),
+                url: url.clone(),
+            });
+        }
+        while seen.len() > LINKS_KEPT {
+            seen.pop_front();
+        }
+        events.push(ChatClientEvent::LinksReceived {
+            channel: msg.channel_id,
+            message_id: msg.message_id,
+            urls,
+        });
+    }
+
+    pub(crate) fn cmd_links(
+        &self,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let channel = if arg.is_empty() {
+            self.currently_connected_channel
+        } else {
+            self.channels_list
+                .iter()
+                .find(|chan| chan.channel_name == arg || chan.channel_id.to_string() == arg)
+                .map(|chan| chan.channel_id)
+        };
+        let Some(channel) = channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: No such channel".to_string(),
+                )],
+            );
+        };
+        let msg = match self.links.get(&channel).filter(|x| !x.is_empty()) {
+            Some(links) => {
+                links
+                    .iter()
+                    .fold("[SYSTEM] Recent links:".to_string(), |mut acc, link| {
+                        acc.push_str(&format!("\n[SYSTEM]    @{}: {}", link.username, link.url));
+                        acc
+                    })
+            }
+            None => "[SYSTEM] No links seen in this channel yet".to_string(),
+        };
+        (vec![], vec![ChatClientEvent::MessageReceived(msg)])
+    }
+}
@@ -0,0 +1,59 @@
+use crate::client::{ChatClientInternal, HIDDEN_KEPT};
+use chat_common::messages::{ChatMessage, MessageData};
+use common::slc_commands::ChatClientEvent;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    // Keeps the original of a message whose text isn't fully displayed
+    pub(crate) fn remember_hidden(&mut self, msg: &MessageData) {
+        self.hidden_messages.insert(msg.message_id, msg.clone());
+        while self.hidden_messages.len() > HIDDEN_KEPT {
+            self.hidden_messages.pop_first();
+        }
+    }
+
+    // Replaces every configured word in `text` with asterisks, ignoring case
+    pub(crate) fn mask_words(&self, text: &str) -> String {
+        if self.config.masked_words.is_empty() {
+            return text.to_string();
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if self
+                .config
+                .masked_words
+                .iter()
+                .any(|x| x.to_lowercase() == word.to_lowercase())
+            {
+                out.extend(word.chars().map(|_| '*'));
+            } else {
+                out.push_str(&word);
+            }
+            word.clear();
+            out.push(c);
+        }
+        // Drop the sentinel space
+        out.pop();
+        out
+    }
+
+    pub(crate) fn cmd_raw(&self, arg: &str) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let msg = match arg
+            .parse::<u64>()
+            .ok()
+            .and_then(|id| self.hidden_messages.get(&id))
+        {
+            Some(msg) => format!(
+                "[SYSTEM] Message {} from @{}: {}",
+                msg.message_id, msg.username, msg.message
+            ),
+            None => "[SYSTEM] Error: No masked message with that id".to_string(),
+        };
+        (vec![], vec![ChatClientEvent::MessageReceived(msg)])
+    }
+}
@@ -0,0 +1,85 @@
+use crate::client::ChatClientInternal;
+use crate::protocol::SYSTEM_USERNAME;
+use chat_common::messages::MessageData;
+use common::slc_commands::ChatClientEvent;
+use itertools::Itertools;
+use std::cmp::Reverse;
+
+// Whether `text` contains `@username` as a whole word, ignoring case
+fn mentions(text: &str, username: &str) -> bool {
+    let needle = format!("@{}", username.to_lowercase());
+    let text = text.to_lowercase();
+    text.match_indices(&needle).any(|(start, _)| {
+        text[start + needle.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace() || ",.!?:;)'\"".contains(c))
+    })
+}
+
+impl ChatClientInternal {
+    pub(crate) fn record_speaker(&mut self, msg: &MessageData) {
+        if msg.username == SYSTEM_USERNAME {
+            return;
+        }
+        let last = self
+            .last_spoke
+            .entry(msg.channel_id)
+            .or_default()
+            .entry(msg.username.clone())
+            .or_default();
+        *last = (*last).max(msg.timestamp);
+    }
+
+    // Tells front-ends about messages from others naming us, so they can highlight them
+    pub(crate) fn check_mention(&self, events: &mut Vec<ChatClientEvent>, msg: &MessageData) {
+        let Some(own_username) = self
+            .currently_connected_server
+            .and_then(|id| self.server_usernames.get(&id))
+        else {
+            return;
+        };
+        if msg.username != *own_username && mentions(&msg.message, own_username) {
+            events.push(ChatClientEvent::Mentioned {
+                channel: msg.channel_id,
+                from: msg.username.clone(),
+                text: msg.message.clone(),
+            });
+        }
+    }
+
+    // Usernames in the current channel starting with `prefix`, for `@`-completion. Those
+    // who spoke most recently come first, the others follow alphabetically
+    #[must_use]
+    pub fn mention_candidates(&self, prefix: &str) -> Vec<String> {
+        let Some(channel_id) = self.currently_connected_channel else {
+            return vec![];
+        };
+        let Some(channel) = self
+            .channels_list
+            .iter()
+            .find(|chan| chan.channel_id == channel_id)
+        else {
+            return vec![];
+        };
+        let own_username = self
+            .currently_connected_server
+            .and_then(|id| self.server_usernames.get(&id));
+        let prefix = prefix.trim_start_matches('@').to_lowercase();
+        let last_spoke = self.last_spoke.get(&channel_id);
+        channel
+            .connected_clients
+            .iter()
+            .map(|client| &client.username)
+            .filter(|username| Some(*username) != own_username)
+            .filter(|username| username.to_lowercase().starts_with(&prefix))
+            .sorted_by_key(|username| {
+                (
+                    Reverse(last_spoke.and_then(|x| x.get(*username)).copied()),
+                    (*username).clone(),
+                )
+            })
+            .cloned()
+            .collect()
+    }
+}
@@ -1,49 +1,93 @@
 use crate::client::ChatClientInternal;
-use chat_common::messages::chat_message::MessageKind;
 use chat_common::messages::ChatMessage;
 use common::slc_commands::ChatClientEvent;
 use log::info;
 use wg_2024::network::NodeId;
 
 impl ChatClientInternal {
+    // A line starting with a command may chain further commands or text after `;`,
+    // which are run in order. `\;` stands for a literal `;`
     pub(crate) fn handle_message(
         &mut self,
         message: &str,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
-        info!(target: format!("Client {}", self.own_id).as_str(), "Handling text message: {:?}", message);
+        if !message.starts_with('/') {
+            return self.handle_single_message(message);
+        }
+        let mut replies = vec![];
+        let mut events = vec![];
+        for part in split_chained(message) {
+            let (r, e) = self.handle_single_message(&part);
+            replies.extend(r);
+            events.extend(e);
+        }
+        (replies, events)
+    }
+
+    fn handle_single_message(
+        &mut self,
+        message: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        info!(target: self.log_target.as_str(), "Handling text message: {:?}", message);
         if message.starts_with('/') {
             let msg = message.chars().skip(1).collect::<String>();
             let (cmd, remainder) = msg.split_once(' ').unwrap_or((msg.as_str(), ""));
-            info!(target: format!("Client {}", self.own_id).as_str(), "First split: {cmd}, {remainder}");
+            info!(target: self.log_target.as_str(), "First split: {cmd}, {remainder}");
             let (arg, freeform) = remainder.split_once(' ').unwrap_or((remainder, ""));
-            info!(target: format!("Client {}", self.own_id).as_str(), "First split: {arg}, {remainder}");
+            info!(target: self.log_target.as_str(), "First split: {arg}, {remainder}");
             return self.handle_command(cmd, arg, freeform);
         }
-        self.handle_text_message(message)
+        self.handle_text_message(message, None)
     }
 
-    fn handle_text_message(
-        &self,
+    // Sends text to the current channel, hidden behind `content_warning` if set
+    pub(crate) fn handle_text_message(
+        &mut self,
         message: &str,
+        content_warning: Option<String>,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
         match (self.currently_connected_server, self.currently_connected_channel) {
             (Some(connected_server), Some(connected_channel)) => {
-                if self.server_usernames.contains_key(&connected_server) {
-                    (
-                        vec![(
-                            connected_server,
-                            ChatMessage {
-                                own_id: u32::from(self.own_id),
-                                message_kind: Some(MessageKind::SendMsg(
-                                    chat_common::messages::SendMessage {
-                                        message: message.to_string(),
-                                        channel_id: connected_channel,
-                                    },
-                                )),
-                            },
-                        )],
+                if let Some(remaining) = self.slow_mode_remaining(connected_channel) {
+                    return (
                         vec![],
-                    )
+                        vec![ChatClientEvent::MessageReceived(format!(
+                            "[SYSTEM] Slow mode is on, wait {}s before sending again",
+                            remaining.as_millis().div_ceil(1000)
+                        ))],
+                    );
+                }
+                if self.server_usernames.contains_key(&connected_server) {
+                    let text = self.transform_outgoing(message);
+                    let parts = match self.max_message_len(connected_server) {
+                        Some(max) => self.split_outgoing(connected_channel, text, max),
+                        None => Some(vec![text]),
+                    };
+                    let Some(parts) = parts else {
+                        return (
+                            vec![],
+                            vec![ChatClientEvent::MessageReceived(format!(
+                                "[SYSTEM] Error: Message too long, this server takes up to {} bytes per message",
+                                self.max_message_len(connected_server).unwrap_or_default()
+                            ))],
+                        );
+                    };
+                    let mut replies = self.stop_typing();
+                    let mut events = vec![];
+                    for part in parts {
+                        let message = chat_common::messages::SendMessage {
+                            message: part,
+                            channel_id: connected_channel,
+                            channel_kind: self.channel_kind(connected_channel),
+                            content_warning: content_warning.clone(),
+                            local_id: 0,
+                            signature: String::new(),
+                            signer_key: String::new(),
+                            signed_at: 0,
+                        };
+                        replies.extend(self.send_chat_message(&mut events, connected_server, message));
+                    }
+                    (replies, events)
                 } else {
                     (
                         vec![],
@@ -72,3 +116,30 @@ impl ChatClientInternal {
         }
     }
 }
+
+// Splits a line at every `;` not escaped by a backslash, dropping empty parts
+fn split_chained(line: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next @ (';' | '\\')) => current.push(next),
+                Some(next) => {
+                    current.push(c);
+                    current.push(next);
+                }
+                None => current.push(c),
+            },
+            ';' => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts
+        .into_iter()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
@@ -0,0 +1,327 @@
+use crate::client::ChatClientInternal;
+use crate::protocol::ModerationState;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{
+    ChatMessage, Kicked, MemberAction, MessageData, ModerationDecision, ModerationStatus,
+    SetModerated, SetReadOnly, VoteKick,
+};
+use common::slc_commands::ChatClientEvent;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    pub(crate) fn channel_display_name(&self, channel_id: u64) -> String {
+        self.channels_list
+            .iter()
+            .find(|chan| chan.channel_id == channel_id)
+            .map_or_else(|| channel_id.to_string(), |chan| chan.channel_name.clone())
+    }
+
+    pub(crate) fn msg_srvmoderationstatus(
+        &self,
+        events: &mut Vec<ChatClientEvent>,
+        status: &ModerationStatus,
+    ) {
+        let channel = self.channel_display_name(status.channel_id);
+        let text = match ModerationState::parse(&status.status) {
+            Some(ModerationState::Pending) => format!(
+                "[SYSTEM] Your message {} in #{channel} awaits an operator's approval",
+                status.message_id
+            ),
+            Some(ModerationState::Approved) => format!(
+                "[SYSTEM] Your message {} in #{channel} was approved",
+                status.message_id
+            ),
+            Some(ModerationState::Rejected) => format!(
+                "[SYSTEM] Your message {} in #{channel} was rejected",
+                status.message_id
+            ),
+            Some(ModerationState::Expired) => format!(
+                "[SYSTEM] Your message {} in #{channel} expired, no operator approved it in time",
+                status.message_id
+            ),
+            None => format!(
+                "[SYSTEM] Your message {} in #{channel} is {}",
+                status.message_id, status.status
+            ),
+        };
+        events.push(ChatClientEvent::MessageReceived(text));
+    }
+
+    pub(crate) fn msg_srvkicked(&mut self, events: &mut Vec<ChatClientEvent>, kicked: &Kicked) {
+        if self.currently_connected_channel == Some(kicked.channel_id) {
+            self.currently_connected_channel = None;
+        }
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] You were removed from #{}: {}",
+            self.channel_display_name(kicked.channel_id),
+            kicked.reason
+        )));
+    }
+
+    pub(crate) fn msg_srvpendingapproval(
+        &self,
+        events: &mut Vec<ChatClientEvent>,
+        msg: &MessageData,
+    ) {
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Awaiting approval in #{} from @{}: {} (/approve {id} or /reject {id})",
+            self.channel_display_name(msg.channel_id),
+            msg.username,
+            msg.message,
+            id = msg.message_id
+        )));
+    }
+
+    pub(crate) fn cmd_decide(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+        approve: bool,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let (Some(channel_id), Ok(message_id)) =
+            (self.currently_connected_channel, arg.parse::<u64>())
+        else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /approve <id> or /reject <id>, from the message's channel"
+                        .to_string(),
+                )],
+            );
+        };
+        let decision = ModerationDecision {
+            channel_id,
+            message_id,
+        };
+        let kind = if approve {
+            MessageKind::CliApproveMessage(decision)
+        } else {
+            MessageKind::CliRejectMessage(decision)
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(kind),
+                },
+            )],
+            vec![],
+        )
+    }
+
+    pub(crate) fn cmd_votekick(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(channel_id) = self.currently_connected_channel.filter(|_| !arg.is_empty()) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /votekick <user>, from the user's channel".to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliVoteKick(VoteKick {
+                        channel_id,
+                        username: arg.to_string(),
+                    })),
+                },
+            )],
+            vec![],
+        )
+    }
+
+    // `/kick <user>` and `/ban <user>`, both in the current channel. Operators only
+    pub(crate) fn cmd_remove_member(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+        ban: bool,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(channel_id) = self.currently_connected_channel.filter(|_| !arg.is_empty()) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Usage: /{} <user>, from the user's channel",
+                    if ban { "ban" } else { "kick" }
+                ))],
+            );
+        };
+        let action = MemberAction {
+            channel_id,
+            username: arg.to_string(),
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(if ban {
+                        MessageKind::CliBan(action)
+                    } else {
+                        MessageKind::CliKick(action)
+                    }),
+                },
+            )],
+            vec![],
+        )
+    }
+
+    // `/op <user>` and `/deop <user>`, in the current channel
+    pub(crate) fn cmd_set_operator(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+        operator: bool,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(channel_id) = self.currently_connected_channel.filter(|_| !arg.is_empty()) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Usage: /{} <user>, from the user's channel",
+                    if operator { "op" } else { "deop" }
+                ))],
+            );
+        };
+        let action = MemberAction {
+            channel_id,
+            username: arg.to_string(),
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(if operator {
+                        MessageKind::CliOp(action)
+                    } else {
+                        MessageKind::CliDeop(action)
+                    }),
+                },
+            )],
+            vec![],
+        )
+    }
+
+    pub(crate) fn cmd_delete_channel(
+        &self,
+        server_id: NodeId,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(channel_id) = self.currently_connected_channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: You are not connected to a channel.".to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliDeleteChannel(channel_id)),
+                },
+            )],
+            vec![],
+        )
+    }
+
+    pub(crate) fn cmd_moderate(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let moderated = match arg {
+            "on" => true,
+            "off" => false,
+            _ => {
+                return (
+                    vec![],
+                    vec![ChatClientEvent::MessageReceived(
+                        "[SYSTEM] Error: Usage: /moderate on|off".to_string(),
+                    )],
+                )
+            }
+        };
+        let Some(channel_id) = self.currently_connected_channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: You are not connected to a channel.".to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliSetModerated(SetModerated {
+                        channel_id,
+                        moderated,
+                    })),
+                },
+            )],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Turning moderation {arg}..."
+            ))],
+        )
+    }
+
+    pub(crate) fn cmd_readonly(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let read_only = match arg {
+            "on" => true,
+            "off" => false,
+            _ => {
+                return (
+                    vec![],
+                    vec![ChatClientEvent::MessageReceived(
+                        "[SYSTEM] Error: Usage: /readonly on|off".to_string(),
+                    )],
+                )
+            }
+        };
+        let Some(channel_id) = self.currently_connected_channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: You are not connected to a channel.".to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliSetReadOnly(SetReadOnly {
+                        channel_id,
+                        read_only,
+                    })),
+                },
+            )],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Turning read-only mode {arg}..."
+            ))],
+        )
+    }
+}
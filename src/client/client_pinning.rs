@@ -0,0 +1,201 @@
+use crate::client::client_encryption::parse_hex;
+use crate::client::ChatClientInternal;
+use crate::protocol::identity_proof;
+use chat_common::messages::ChatMessage;
+use common::slc_commands::{ChatClientEvent, ConnectionState};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use wg_2024::network::NodeId;
+
+// Server keys pinned the first time the server proves it owns one, by signing the
+// challenge of our hello. A server presenting another key later may be a different node
+// impersonating it, so it's kept apart until the user confirms the change
+#[derive(Debug)]
+pub(crate) struct PinnedKeys {
+    keys: HashMap<NodeId, String>,
+    // Servers that presented a key other than the pinned one, with that key
+    mismatches: HashMap<NodeId, String>,
+    // Servers that advertised a key they couldn't sign with, until they do
+    unproven: HashSet<NodeId>,
+    // File the pins are kept in between runs, one "<node id> <key>" per line
+    path: Option<PathBuf>,
+}
+
+impl PinnedKeys {
+    pub(crate) fn load(path: Option<PathBuf>) -> Self {
+        let keys = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|data| {
+                data.lines()
+                    .filter_map(|line| {
+                        let (id, key) = line.split_once(' ')?;
+                        Some((id.parse().ok()?, key.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            keys,
+            mismatches: HashMap::new(),
+            unproven: HashSet::new(),
+            path,
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        match &self.path {
+            Some(path) => {
+                let mut lines = self
+                    .keys
+                    .iter()
+                    .map(|(id, key)| format!("{id} {key}\n"))
+                    .collect::<Vec<_>>();
+                lines.sort_unstable();
+                fs::write(path, lines.concat())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl ChatClientInternal {
+    // Raises the alarm when a server advertises a key other than the pinned one. Keys
+    // are only pinned once the server proved it owns them, see check_server_identity.
+    // Servers that don't advertise a key can't be checked
+    pub(crate) fn check_server_key(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+        key: &str,
+    ) {
+        if key.is_empty() {
+            return;
+        }
+        let Some(pinned) = self.pinned_keys.keys.get(&server_id).cloned() else {
+            return;
+        };
+        if pinned == key {
+            self.pinned_keys.mismatches.remove(&server_id);
+            return;
+        }
+        if self
+            .pinned_keys
+            .mismatches
+            .get(&server_id)
+            .map(String::as_str)
+            == Some(key)
+        {
+            return;
+        }
+        warn!(target: self.log_target.as_str(), "Server {server_id} presented key {key}, pinned {pinned}");
+        self.pinned_keys
+            .mismatches
+            .insert(server_id, key.to_string());
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] WARNING: Server {} presented a different key than on first contact! Another node may be impersonating it. Use /trust {server_id} if you know why the key changed.",
+            self.server_display_name(server_id)
+        )));
+        events.push(ChatClientEvent::ServerKeyChanged {
+            server: server_id,
+            pinned,
+            presented: key.to_string(),
+        });
+    }
+
+    // Checks the signature of the challenge we sent in the hello against the key the
+    // server advertised, and pins that key the first time it checks out. A server that
+    // can't sign merely repeats a key it saw somewhere, it's disconnected
+    pub(crate) fn check_server_identity(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+        challenge: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        let Some(key) = self
+            .discovered_servers
+            .get(&server_id)
+            .map(|srv| srv.public_key.clone())
+            .filter(|x| !x.is_empty())
+        else {
+            return true;
+        };
+        let verifying_key = parse_hex(&key)
+            .and_then(|x| <[u8; 32]>::try_from(x).ok())
+            .and_then(|x| VerifyingKey::from_bytes(&x).ok());
+        let proven = match (verifying_key, Signature::from_slice(signature)) {
+            (Some(verifying_key), Ok(signature)) => verifying_key
+                .verify(&identity_proof(challenge, server_id), &signature)
+                .is_ok(),
+            _ => false,
+        };
+        if !proven {
+            warn!(target: self.log_target.as_str(), "Server {server_id} can't prove it owns key {key}");
+            self.pinned_keys.unproven.insert(server_id);
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] WARNING: Server {} couldn't prove it owns the key it advertises! Another node may be impersonating it.",
+                self.server_display_name(server_id)
+            )));
+            if self.currently_connected_server == Some(server_id) {
+                self.set_connection_state(events, server_id, ConnectionState::Disconnected);
+                self.currently_connected_server = None;
+                self.currently_connected_channel = None;
+            }
+            return false;
+        }
+        self.pinned_keys.unproven.remove(&server_id);
+        if !self.pinned_keys.keys.contains_key(&server_id) {
+            info!(target: self.log_target.as_str(), "Pinning key of server {server_id}");
+            self.pinned_keys.keys.insert(server_id, key);
+            self.save_pinned_keys();
+        }
+        true
+    }
+
+    pub(crate) fn has_key_mismatch(&self, server_id: NodeId) -> bool {
+        self.pinned_keys.mismatches.contains_key(&server_id)
+            || self.pinned_keys.unproven.contains(&server_id)
+    }
+
+    fn save_pinned_keys(&self) {
+        if let Err(e) = self.pinned_keys.save() {
+            warn!(target: self.log_target.as_str(), "Could not save pinned server keys: {e}");
+        }
+    }
+
+    pub(crate) fn cmd_trust(
+        &mut self,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Some(server_id) = self.resolve_server(arg) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Server not found".to_string(),
+                )],
+            );
+        };
+        let Some(key) = self.pinned_keys.mismatches.remove(&server_id) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Server {} still has the key pinned on first contact",
+                    self.server_display_name(server_id)
+                ))],
+            );
+        };
+        self.pinned_keys.keys.insert(server_id, key);
+        self.save_pinned_keys();
+        (
+            vec![],
+            vec![ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Now trusting the new key of server {}",
+                self.server_display_name(server_id)
+            ))],
+        )
+    }
+}
@@ -0,0 +1,103 @@
+use crate::client::ChatClientInternal;
+use crate::protocol::personal_channel_id;
+use chat_common::messages::{ChannelKind, ChatMessage, MessageData, SendMessage};
+use common::slc_commands::ChatClientEvent;
+use std::fmt::{Debug, Formatter};
+use wg_2024::network::NodeId;
+
+// What a plugin wants done with an incoming chat message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginAction {
+    // Show the message as usual
+    Pass,
+    // Don't show the message, later plugins aren't asked
+    Suppress,
+    // Show the message followed by a note
+    Annotate(String),
+    // Show the message and answer it in the channel it was sent to
+    Respond(String),
+}
+
+// Extension point for bots, loggers and bridges. Plugins are asked in the order they
+// were added, about every live message distributed by the connected server
+pub trait ClientPlugin: Send {
+    fn on_incoming(&mut self, msg: &MessageData) -> PluginAction;
+}
+
+#[derive(Default)]
+pub(crate) struct Plugins(Vec<Box<dyn ClientPlugin>>);
+
+impl Debug for Plugins {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Plugins({})", self.0.len())
+    }
+}
+
+impl ChatClientInternal {
+    pub fn add_plugin(&mut self, plugin: Box<dyn ClientPlugin>) {
+        self.plugins.0.push(plugin);
+    }
+
+    // Shows an incoming message, after letting every plugin act on it
+    pub(crate) fn deliver_incoming(
+        &mut self,
+        server_id: NodeId,
+        events: &mut Vec<ChatClientEvent>,
+        msg: &MessageData,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut notes = vec![];
+        let mut responses = vec![];
+        for plugin in &mut self.plugins.0 {
+            match plugin.on_incoming(msg) {
+                PluginAction::Pass => {}
+                PluginAction::Suppress => return vec![],
+                PluginAction::Annotate(note) => notes.push(note),
+                PluginAction::Respond(text) => responses.push(text),
+            }
+        }
+        self.msg_srvdistributemessage(events, msg);
+        if msg.content_warning.is_none() {
+            self.record_links(events, msg);
+        }
+        for note in notes {
+            events.push(ChatClientEvent::MessageReceived(format!("    ({note})")));
+        }
+        // Our own messages come back too, answering them could make two bots loop
+        if self.server_usernames.get(&server_id) == Some(&msg.username) {
+            return vec![];
+        }
+        let Some((channel_id, channel_kind)) = self.response_channel(msg) else {
+            return vec![];
+        };
+        let mut replies = vec![];
+        for text in responses {
+            let message = SendMessage {
+                message: text,
+                channel_id,
+                channel_kind: Some(channel_kind),
+                content_warning: None,
+                local_id: 0,
+                signature: String::new(),
+                signer_key: String::new(),
+                signed_at: 0,
+            };
+            replies.extend(self.send_chat_message(events, server_id, message));
+        }
+        replies
+    }
+
+    // Private messages are answered in the sender's personal channel, the rest where
+    // they were sent
+    fn response_channel(&self, msg: &MessageData) -> Option<(u64, ChannelKind)> {
+        if msg.channel_id != self.own_channel_id {
+            return Some((msg.channel_id, msg.channel_kind));
+        }
+        self.channels_list
+            .iter()
+            .find(|x| x.channel_kind == ChannelKind::All)?
+            .connected_clients
+            .iter()
+            .find(|x| x.username == msg.username)
+            .map(|x| (personal_channel_id(x.id), ChannelKind::Personal))
+    }
+}
@@ -0,0 +1,53 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, React, ReactionUpdate};
+use common::slc_commands::ChatClientEvent;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    // `/react <message_id> <emoji>`, reacting again with the same emoji takes it back.
+    // Shortcodes like `:thumbsup:` are expanded first
+    pub(crate) fn cmd_react(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+        freeform: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let emoji = self.expand_emoji(freeform.trim());
+        let Some(message_id) = arg.parse::<u64>().ok().filter(|_| !emoji.is_empty()) else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /react <message_id> <emoji>".to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliReact(React { message_id, emoji })),
+                },
+            )],
+            vec![],
+        )
+    }
+
+    pub(crate) fn msg_srvreactionupdate(
+        &self,
+        events: &mut Vec<ChatClientEvent>,
+        update: ReactionUpdate,
+    ) {
+        events.push(ChatClientEvent::ReactionsUpdated {
+            channel: update.channel_id,
+            message_id: update.message_id,
+            reactions: update
+                .reactions
+                .into_iter()
+                .map(|x| (x.emoji, x.count))
+                .collect(),
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use crate::client::{ChatClientInternal, PendingDiscovery};
+use crate::clock::Instant;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage, JoinChannel};
+use common::slc_commands::{ChatClientEvent, ConnectionState, SendStatus};
+use log::info;
+use wg_2024::network::NodeId;
+
+// What to restore once a lost server answers discovery again
+#[derive(Debug)]
+pub(crate) struct PendingReconnect {
+    pub(crate) server: NodeId,
+    pub(crate) username: Option<String>,
+    pub(crate) channel_name: Option<String>,
+}
+
+impl ChatClientInternal {
+    // Name of the group channel we are in, to join it again elsewhere or later
+    pub(crate) fn current_group_channel_name(&self) -> Option<String> {
+        self.currently_connected_channel.and_then(|id| {
+            self.channels_list
+                .iter()
+                .find(|chan| chan.channel_id == id && chan.channel_kind == ChannelKind::Group)
+                .map(|chan| chan.channel_name.clone())
+        })
+    }
+
+    // Connects to a server and registers and joins as we were before, used after a
+    // failover or a reconnection
+    pub(crate) fn resume_session(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+        username: Option<String>,
+        channel_name: Option<String>,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let (mut replies, connect_events) = self.cmd_connect(&server.to_string(), false);
+        events.extend(connect_events);
+        if let Some(username) = username {
+            let (register_replies, register_events) = self.cmd_register(server, &username);
+            replies.extend(register_replies);
+            events.extend(register_events);
+        }
+        match channel_name {
+            Some(channel_name) => replies.push((
+                server,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliJoin(JoinChannel {
+                        channel_id: None,
+                        channel_name,
+                    })),
+                },
+            )),
+            // Nowhere to replay the queued messages
+            None => self.failover_outbox.clear(),
+        }
+        replies
+    }
+
+    // The connected server stopped answering: drop the connection, then discover the
+    // server again and pick up where we left off once it answers
+    pub(crate) fn start_reconnect(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(server) = self.currently_connected_server else {
+            return vec![];
+        };
+        info!(target: self.log_target.as_str(), "Lost server {server}, reconnecting");
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Lost connection to server {}, reconnecting...",
+            self.server_display_name(server)
+        )));
+        let channel_name = self.current_group_channel_name();
+        self.keep_unsent(events, server);
+        self.reconnect = Some(PendingReconnect {
+            server,
+            username: self.server_usernames.remove(&server),
+            channel_name,
+        });
+        self.set_connection_state(events, server, ConnectionState::Disconnected);
+        self.currently_connected_server = None;
+        self.currently_connected_channel = None;
+        self.channels_list.clear();
+        let now = self.now();
+        self.pending_discoveries.insert(
+            server,
+            PendingDiscovery {
+                sent_at: now,
+                deadline: now + self.config.discovery_timeout,
+                attempts: 1,
+                ping: false,
+            },
+        );
+        vec![(server, self.discovery_request())]
+    }
+
+    // Called on every discovery response, resumes a pending reconnection to that server
+    pub(crate) fn finish_reconnect(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let Some(pending) = self.reconnect.take_if(|x| x.server == server) else {
+            return vec![];
+        };
+        self.stats.reconnects += 1;
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Server {} is back",
+            self.server_display_name(server)
+        )));
+        self.resume_session(events, server, pending.username, pending.channel_name)
+    }
+
+    // The connected server answered a chat message with NOT_REGISTERED although we had
+    // registered, most likely because it restarted. Registers again with the same name,
+    // joins the channel we were in and sends what the server didn't take once there.
+    // None if we weren't registered there, then the error is just shown
+    pub(crate) fn recover_registration(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+    ) -> Option<Vec<(NodeId, ChatMessage)>> {
+        if self.currently_connected_server != Some(server) {
+            return None;
+        }
+        let username = self.server_usernames.remove(&server)?;
+        info!(target: self.log_target.as_str(), "Server {server} forgot our registration, registering again");
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Server {} no longer knows us, registering again as {username}",
+            self.server_display_name(server)
+        )));
+        let channel_name = self.current_group_channel_name();
+        self.keep_unsent(events, server);
+        Some(self.resume_session(events, server, Some(username), channel_name))
+    }
+
+    // Moves the messages `server` didn't confirm and those still waiting for its send
+    // window to the outbox, as the user wrote them, to be sealed and signed again once
+    // we're back in the channel. They go out again under new local ids
+    pub(crate) fn keep_unsent(&mut self, events: &mut Vec<ChatClientEvent>, server: NodeId) {
+        let unconfirmed = self.take_unconfirmed(server);
+        for msg in &unconfirmed {
+            self.set_send_status(events, msg.local_id, SendStatus::Failed);
+        }
+        let mut unsent = unconfirmed;
+        unsent.extend(self.take_flow_queue(events));
+        self.keep_for_replay(events, unsent);
+    }
+
+    // Counts a connected server that sent nothing for a while as lost. Servers ping
+    // their clients regularly, so silence means the path or the server is gone
+    pub(crate) fn poll_server_silence(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        now: Instant,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        if !self.config.auto_reconnect {
+            return vec![];
+        }
+        let silent = self
+            .currently_connected_server
+            .and_then(|id| self.discovered_servers.get(&id))
+            .is_some_and(|srv| now - srv.last_reply >= self.config.server_silence_timeout);
+        if silent {
+            self.start_reconnect(events)
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{ChatClientConfig, ChatClientInternal};
+    use chat_common::messages::chat_message::MessageKind;
+    use chat_common::messages::ChatMessage;
+    use std::time::Duration;
+    use wg_2024::network::NodeId;
+
+    const SERVER: NodeId = 10;
+
+    fn client(auto_reconnect: bool) -> ChatClientInternal {
+        ChatClientInternal::registered_in_games(
+            ChatClientConfig {
+                auto_reconnect,
+                ..ChatClientConfig::default()
+            },
+            &[SERVER],
+        )
+    }
+
+    // Polls once the server has been silent for `silence`
+    fn poll_after(
+        client: &mut ChatClientInternal,
+        silence: Duration,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let now = client.now() + silence;
+        client.poll_server_silence(&mut vec![], now)
+    }
+
+    fn registers_and_joins_games(replies: &[(NodeId, ChatMessage)]) -> bool {
+        let kinds = replies
+            .iter()
+            .filter(|(id, _)| *id == SERVER)
+            .filter_map(|(_, msg)| msg.message_kind.as_ref())
+            .collect::<Vec<_>>();
+        kinds
+            .iter()
+            .any(|kind| matches!(kind, MessageKind::CliRegisterRequest(name) if name == "alice"))
+            && kinds.iter().any(
+                |kind| matches!(kind, MessageKind::CliJoin(join) if join.channel_name == "games"),
+            )
+    }
+
+    #[test]
+    fn silent_servers_are_discovered_again() {
+        let mut client = client(true);
+        let timeout = client.config.server_silence_timeout;
+        assert!(poll_after(&mut client, timeout - Duration::from_millis(1)).is_empty());
+        assert_eq!(client.currently_connected_server, Some(SERVER));
+        let replies = poll_after(&mut client, timeout);
+        assert_eq!(client.currently_connected_server, None);
+        assert!(matches!(
+            replies.as_slice(),
+            [(
+                SERVER,
+                ChatMessage {
+                    message_kind: Some(MessageKind::DsvReq(..)),
+                    ..
+                }
+            )]
+        ));
+    }
+
+    #[test]
+    fn the_session_resumes_once_the_server_answers() {
+        let mut client = client(true);
+        let timeout = client.config.server_silence_timeout;
+        poll_after(&mut client, timeout);
+        let mut events = vec![];
+        assert!(client.finish_reconnect(&mut events, SERVER + 1).is_empty());
+        let replies = client.finish_reconnect(&mut events, SERVER);
+        assert_eq!(client.currently_connected_server, Some(SERVER));
+        assert!(registers_and_joins_games(&replies));
+        // Only resumed once
+        assert!(client.finish_reconnect(&mut events, SERVER).is_empty());
+    }
+
+    #[test]
+    fn silence_is_ignored_without_auto_reconnect() {
+        let mut client = client(false);
+        assert!(poll_after(&mut client, Duration::from_secs(3600)).is_empty());
+        assert_eq!(client.currently_connected_server, Some(SERVER));
+    }
+
+    #[test]
+    fn forgotten_registrations_are_renewed() {
+        let mut client = client(false);
+        let mut events = vec![];
+        assert!(client
+            .recover_registration(&mut events, SERVER + 1)
+            .is_none());
+        let replies = client
+            .recover_registration(&mut events, SERVER)
+            .expect("registration not renewed");
+        assert!(registers_and_joins_games(&replies));
+    }
+}
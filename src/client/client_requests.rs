@@ -0,0 +1,119 @@
+use crate::client::ChatClientInternal;
+use crate::clock::Instant;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::ChatMessage;
+use common::slc_commands::ChatClientEvent;
+use log::{debug, info};
+use std::collections::HashMap;
+use wg_2024::network::NodeId;
+
+// Commands whose answer we wait for. The server copies the request id of a message into
+// every reply it sends us about it, so the answer can be told apart from unrelated
+// updates and errors can name the command that caused them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RequestKind {
+    Register(String),
+    Join(String),
+    // With the argument of /channels, which decides how the list is shown
+    Channels(String),
+}
+
+impl RequestKind {
+    fn describe(&self) -> String {
+        match self {
+            Self::Register(username) => format!("/register {username}"),
+            Self::Join(channel) => format!("/join {channel}"),
+            Self::Channels(_) => "/channels".to_string(),
+        }
+    }
+
+    // Whether `kind` is what this request is waiting for, rather than a side effect
+    fn is_answered_by(&self, kind: &MessageKind) -> bool {
+        matches!(
+            (self, kind),
+            (_, MessageKind::Err(_))
+                | (Self::Register(_), MessageKind::SrvConfirmReg(_))
+                | (Self::Join(_), MessageKind::SrvChannelCreationSuccessful(_))
+                | (Self::Channels(_), MessageKind::SrvReturnChannels(_))
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PendingRequest {
+    server: NodeId,
+    pub(crate) kind: RequestKind,
+    sent_at: Instant,
+}
+
+impl PendingRequest {
+    pub(crate) fn describe(&self) -> String {
+        self.kind.describe()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PendingRequests {
+    // Request ids start at 1, 0 means the message isn't a request
+    last_id: u64,
+    by_id: HashMap<u64, PendingRequest>,
+}
+
+impl ChatClientInternal {
+    // Returns the id to put in the request sent to `server`
+    pub(crate) fn start_request(&mut self, server: NodeId, kind: RequestKind) -> u64 {
+        self.pending_requests.last_id += 1;
+        let request_id = self.pending_requests.last_id;
+        let sent_at = self.now();
+        self.pending_requests.by_id.insert(
+            request_id,
+            PendingRequest {
+                server,
+                kind,
+                sent_at,
+            },
+        );
+        request_id
+    }
+
+    // Gives up on requests the server didn't answer within `request_timeout`
+    pub(crate) fn poll_request_timeouts(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        now: Instant,
+    ) {
+        let timeout = self.config.request_timeout;
+        let expired = self
+            .pending_requests
+            .by_id
+            .iter()
+            .filter(|(_, pending)| now - pending.sent_at >= timeout)
+            .map(|(request_id, _)| *request_id)
+            .collect::<Vec<_>>();
+        for request_id in expired {
+            let Some(pending) = self.pending_requests.by_id.remove(&request_id) else {
+                continue;
+            };
+            info!(target: self.log_target.as_str(), "Request {request_id} to server {} timed out", pending.server);
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Error: {} timed out, server {} didn't answer",
+                pending.describe(),
+                self.server_display_name(pending.server)
+            )));
+        }
+    }
+
+    // The request `message` answers, if any, which is then no longer pending
+    pub(crate) fn take_answered_request(
+        &mut self,
+        message: &ChatMessage,
+    ) -> Option<PendingRequest> {
+        let kind = message.message_kind.as_ref()?;
+        let pending = self.pending_requests.by_id.get(&message.request_id)?;
+        if message.own_id != u32::from(pending.server) || !pending.kind.is_answered_by(kind) {
+            return None;
+        }
+        debug!(target: self.log_target.as_str(), "Request {} answered", message.request_id);
+        self.pending_requests.by_id.remove(&message.request_id)
+    }
+}
@@ -0,0 +1,252 @@
+use crate::client::ChatClientInternal;
+use crate::clock::Instant;
+use crate::protocol::ReceiptStatus;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, SendMessage, SendReceipt};
+use common::slc_commands::{ChatClientEvent, SendStatus};
+use itertools::Itertools;
+use log::info;
+use std::collections::{HashMap, VecDeque};
+use wg_2024::network::NodeId;
+
+// Where each outgoing chat message is on its way: queued for the send window, handed
+// to the network, acknowledged by the server, and finally delivered or failed
+#[derive(Debug, Default)]
+pub(crate) struct SendTracker {
+    // Local ids start at 1, 0 tells the server the message isn't tracked
+    last_local_id: u64,
+    // Messages not delivered or failed yet, by local id
+    statuses: HashMap<u64, SendStatus>,
+    // Messages sent but not acknowledged, in the order the server acknowledges them
+    unacked: VecDeque<u64>,
+    // Messages handed to the network that the server hasn't sent a receipt for yet
+    pending: HashMap<u64, PendingSend>,
+    // Messages not delivered or failed yet as they were before sealing, signing and
+    // compression, by local id, to be sent again elsewhere or shown in errors
+    originals: HashMap<u64, SendMessage>,
+}
+
+// A sent message kept until the server confirms it, to be sent again if it doesn't
+#[derive(Debug)]
+struct PendingSend {
+    server: NodeId,
+    message: SendMessage,
+    sent_at: Instant,
+    // Times it was sent again so far
+    retries: u32,
+}
+
+// Statuses only ever move forward, a late acknowledgement can't undo a delivery
+fn rank(status: SendStatus) -> u8 {
+    match status {
+        SendStatus::Queued => 0,
+        SendStatus::Sent => 1,
+        SendStatus::Accepted => 2,
+        SendStatus::Delivered | SendStatus::Failed => 3,
+    }
+}
+
+impl ChatClientInternal {
+    pub(crate) fn track_outgoing(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        original: &SendMessage,
+    ) -> u64 {
+        self.send_tracker.last_local_id += 1;
+        let local_id = self.send_tracker.last_local_id;
+        self.send_tracker
+            .statuses
+            .insert(local_id, SendStatus::Queued);
+        self.send_tracker.originals.insert(
+            local_id,
+            SendMessage {
+                local_id,
+                ..original.clone()
+            },
+        );
+        events.push(ChatClientEvent::SendStatusChanged {
+            local_id,
+            status: SendStatus::Queued,
+        });
+        local_id
+    }
+
+    pub(crate) fn set_send_status(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        local_id: u64,
+        status: SendStatus,
+    ) {
+        let Some(current) = self.send_tracker.statuses.get(&local_id).copied() else {
+            return;
+        };
+        if rank(status) <= rank(current) {
+            return;
+        }
+        match status {
+            SendStatus::Delivered | SendStatus::Failed => {
+                self.send_tracker.statuses.remove(&local_id);
+                self.send_tracker.pending.remove(&local_id);
+                self.send_tracker.originals.remove(&local_id);
+            }
+            _ => {
+                self.send_tracker.statuses.insert(local_id, status);
+            }
+        }
+        if status == SendStatus::Sent {
+            self.send_tracker.unacked.push_back(local_id);
+        }
+        events.push(ChatClientEvent::SendStatusChanged { local_id, status });
+        if status == SendStatus::Delivered {
+            events.push(ChatClientEvent::MessageDelivered(local_id));
+        }
+    }
+
+    // Keeps a message just handed to the network until the server's receipt comes
+    pub(crate) fn track_sent(&mut self, server: NodeId, message: &SendMessage) {
+        let now = self.now();
+        self.send_tracker.pending.insert(
+            message.local_id,
+            PendingSend {
+                server,
+                message: message.clone(),
+                sent_at: now,
+                retries: 0,
+            },
+        );
+    }
+
+    // Sends messages the server didn't confirm in time again, up to `delivery_retries`
+    // times, then fails them. The server recognizes a retransmission by its local id and
+    // only confirms it again. Messages held for approval got a receipt, an operator may
+    // take a while
+    pub(crate) fn poll_delivery_timeouts(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        now: Instant,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut replies = vec![];
+        let mut failed = vec![];
+        for (local_id, pending) in &mut self.send_tracker.pending {
+            if now - pending.sent_at < self.config.delivery_timeout {
+                continue;
+            }
+            // Nobody to send it again to once we left that server
+            if pending.retries >= self.config.delivery_retries
+                || self.currently_connected_server != Some(pending.server)
+            {
+                failed.push((*local_id, pending.server));
+                continue;
+            }
+            pending.retries += 1;
+            pending.sent_at = now;
+            info!(target: self.log_target.as_str(), "Sending message {local_id} again, attempt {}", pending.retries + 1);
+            replies.push((
+                pending.server,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SendMsg(pending.message.clone())),
+                },
+            ));
+        }
+        let timed_out = !failed.is_empty();
+        for (local_id, server) in failed {
+            replies.extend(self.release_credit(events, server, local_id));
+            self.set_send_status(events, local_id, SendStatus::Failed);
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Error: The server didn't confirm message {local_id} in time"
+            )));
+        }
+        // A server that doesn't confirm our messages is as bad as one we can't reach
+        if let Some(server_id) = self.currently_connected_server.filter(|_| timed_out) {
+            replies.extend(self.record_send_failure(events, server_id, vec![]));
+        }
+        replies
+    }
+
+    // Stops tracking what `server` hasn't confirmed yet, to send it again as new messages.
+    // The messages are the originals, with their local ids
+    pub(crate) fn take_unconfirmed(&mut self, server: NodeId) -> Vec<SendMessage> {
+        let local_ids = self
+            .send_tracker
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.server == server)
+            .map(|(local_id, _)| *local_id)
+            .sorted()
+            .collect::<Vec<_>>();
+        local_ids
+            .into_iter()
+            .filter_map(|local_id| {
+                self.send_tracker.pending.remove(&local_id)?;
+                self.send_tracker.originals.remove(&local_id)
+            })
+            .collect()
+    }
+
+    // A tracked message as it was before sealing, signing and compression
+    pub(crate) fn take_original(&mut self, local_id: u64) -> Option<SendMessage> {
+        self.send_tracker.originals.remove(&local_id)
+    }
+
+    // The server acknowledges chat messages one by one, in the order it got them
+    pub(crate) fn record_acks(&mut self, events: &mut Vec<ChatClientEvent>, count: u64) {
+        for _ in 0..count {
+            let Some(local_id) = self.send_tracker.unacked.pop_front() else {
+                return;
+            };
+            self.set_send_status(events, local_id, SendStatus::Accepted);
+        }
+    }
+
+    // Whether the message awaited an acknowledgement. It won't get one anymore, so the
+    // next acknowledgement is for the message sent after it
+    pub(crate) fn stop_awaiting_ack(&mut self, local_id: u64) -> bool {
+        let Some(index) = self
+            .send_tracker
+            .unacked
+            .iter()
+            .position(|x| *x == local_id)
+        else {
+            return false;
+        };
+        self.send_tracker.unacked.remove(index);
+        true
+    }
+
+    pub(crate) fn unacked_count(&self) -> u64 {
+        self.send_tracker.unacked.len() as u64
+    }
+
+    // Messages already handed to a server we're leaving will never be acknowledged.
+    // What became of them is unknown, so they're just no longer tracked
+    pub(crate) fn forget_unacked(&mut self) {
+        for local_id in self.send_tracker.unacked.drain(..) {
+            self.send_tracker.statuses.remove(&local_id);
+        }
+        self.send_tracker.pending.clear();
+        let statuses = &self.send_tracker.statuses;
+        self.send_tracker
+            .originals
+            .retain(|local_id, _| statuses.contains_key(local_id));
+    }
+
+    pub(crate) fn msg_srvsendreceipt(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        receipt: &SendReceipt,
+    ) {
+        self.send_tracker.pending.remove(&receipt.local_id);
+        match ReceiptStatus::parse(&receipt.status) {
+            Some(ReceiptStatus::Delivered) => {
+                self.set_send_status(events, receipt.local_id, SendStatus::Delivered);
+            }
+            Some(ReceiptStatus::Rejected) => {
+                self.set_send_status(events, receipt.local_id, SendStatus::Failed);
+            }
+            // Held for approval, moderation status messages tell the rest
+            _ => self.set_send_status(events, receipt.local_id, SendStatus::Accepted),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::client::ChatClientInternal;
+use crate::compression::COMPRESSION_CAPABILITY;
+use crate::protocol::SOFTWARE_VERSION;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, Hello, Welcome};
+use common::slc_commands::{ChatClientEvent, ConnectionState};
+use log::info;
+use rand::{rng, RngCore};
+use wg_2024::network::NodeId;
+
+// Optional protocol features this client understands, announced in the hello
+const CLIENT_CAPABILITIES: &[&str] = &[
+    "flow-control",
+    "send-receipts",
+    "moderation",
+    "channel-delta",
+    COMPRESSION_CAPABILITY,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SessionState {
+    // Hello sent, waiting for the welcome
+    Greeting,
+    Welcomed,
+}
+
+// Our side of the handshake with a server
+#[derive(Debug)]
+pub(crate) struct ServerSession {
+    pub(crate) state: SessionState,
+    pub(crate) token: u64,
+    pub(crate) motd: String,
+    pub(crate) send_window: u32,
+    pub(crate) history_page_size: u32,
+    pub(crate) capabilities: Vec<String>,
+    // Changes whenever the server restarts
+    pub(crate) incarnation: u64,
+    // Sent in the last hello, the server signs it to prove its identity
+    pub(crate) challenge: [u8; 32],
+}
+
+impl ChatClientInternal {
+    // Whether `server` announced `capability` in its welcome
+    pub(crate) fn server_has_capability(&self, server: NodeId, capability: &str) -> bool {
+        self.sessions
+            .get(&server)
+            .is_some_and(|x| x.capabilities.iter().any(|c| c == capability))
+    }
+
+    // What `server` advertised when discovered, else in its welcome, None if nothing
+    pub(crate) fn advertised_capabilities(&self, server: NodeId) -> Option<&[String]> {
+        self.discovered_servers
+            .get(&server)
+            .map(|srv| srv.capabilities.as_slice())
+            .filter(|x| !x.is_empty())
+            .or_else(|| {
+                self.sessions
+                    .get(&server)
+                    .map(|x| x.capabilities.as_slice())
+                    .filter(|x| !x.is_empty())
+            })
+    }
+
+    // Whether `server` handles what needs `capability`. Servers that advertise nothing
+    // are given the benefit of the doubt, so commands sent to them may still fail
+    pub(crate) fn server_supports(&self, server: NodeId, capability: &str) -> bool {
+        self.advertised_capabilities(server)
+            .is_none_or(|x| x.iter().any(|c| c == capability))
+    }
+
+    // Opens a session with a server, which answers with a welcome and its channel list
+    pub(crate) fn hello(&mut self, server_id: NodeId) -> (NodeId, ChatMessage) {
+        let session = self
+            .sessions
+            .entry(server_id)
+            .or_insert_with(|| ServerSession {
+                state: SessionState::Greeting,
+                token: 0,
+                motd: String::new(),
+                send_window: 0,
+                history_page_size: 0,
+                capabilities: vec![],
+                incarnation: 0,
+                challenge: [0; 32],
+            });
+        session.state = SessionState::Greeting;
+        rng().fill_bytes(&mut session.challenge);
+        let challenge = session.challenge.to_vec();
+        (
+            server_id,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(MessageKind::CliHello(Hello {
+                    version: SOFTWARE_VERSION.to_string(),
+                    capabilities: CLIENT_CAPABILITIES
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                    challenge,
+                })),
+            },
+        )
+    }
+
+    // The server has no session for us, most likely because it restarted. Greets it
+    // again unless a hello is on its way already, the refused request has to be repeated
+    pub(crate) fn msg_handshake_required(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        if self.currently_connected_server != Some(server_id)
+            || self
+                .sessions
+                .get(&server_id)
+                .is_some_and(|x| x.state == SessionState::Greeting)
+        {
+            return vec![];
+        }
+        info!(target: self.log_target.as_str(), "Server {server_id} lost our session, greeting it again");
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Error: Server {} lost our session, reconnecting. Try again once welcomed",
+            self.server_display_name(server_id)
+        )));
+        vec![self.hello(server_id)]
+    }
+
+    pub(crate) fn msg_srvwelcome(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server_id: NodeId,
+        welcome: Welcome,
+    ) {
+        let Some(session) = self
+            .sessions
+            .get_mut(&server_id)
+            .filter(|x| x.state == SessionState::Greeting)
+        else {
+            info!(target: self.log_target.as_str(), "Ignoring unexpected welcome from server {server_id}");
+            return;
+        };
+        let challenge = session.challenge;
+        if !self.check_server_identity(events, server_id, &challenge, &welcome.identity_signature) {
+            return;
+        }
+        let Some(session) = self.sessions.get_mut(&server_id) else {
+            return;
+        };
+        info!(target: self.log_target.as_str(), "Welcomed by server {server_id}");
+        // A server seen before under another incarnation lost everything we had there
+        let restarted = session.incarnation != 0 && session.incarnation != welcome.incarnation;
+        *session = ServerSession {
+            state: SessionState::Welcomed,
+            token: welcome.session_token,
+            motd: welcome.motd,
+            send_window: welcome.send_window,
+            history_page_size: welcome.history_page_size,
+            capabilities: welcome.capabilities,
+            incarnation: welcome.incarnation,
+            challenge,
+        };
+        let motd = session.motd.clone();
+        let session_capabilities = session.capabilities.clone();
+        if restarted {
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Server {} restarted since the last visit",
+                self.server_display_name(server_id)
+            )));
+        }
+        if let Some(srv) = self.discovered_servers.get_mut(&server_id) {
+            if !welcome.server_name.is_empty() {
+                srv.name = Some(welcome.server_name);
+            }
+        }
+        if self.currently_connected_server == Some(server_id) {
+            if self.connection_state == ConnectionState::Connecting {
+                self.set_connection_state(events, server_id, ConnectionState::Connected);
+            }
+            // Servers that send notices show it once we register
+            let noticed = session_capabilities.iter().any(|x| x == "system-notice");
+            if !motd.is_empty() && !noticed {
+                events.push(ChatClientEvent::MessageReceived(format!("[SYSTEM] {motd}")));
+            }
+        }
+    }
+}
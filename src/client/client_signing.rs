@@ -0,0 +1,265 @@
+use crate::client::client_encryption::{parse_hex, to_hex};
+use crate::client::ChatClientInternal;
+use crate::secret::SecretKey;
+use chat_common::messages::{ChatMessage, MessageData, SendMessage};
+use common::slc_commands::ChatClientEvent;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::{info, warn};
+use rand::{rng, RngCore};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use wg_2024::network::NodeId;
+
+// Signatures made this long before the server stamped the message, or after, don't
+// count, so that an old message can't be passed off as a new one
+const MAX_SIGNATURE_AGE_MS: u64 = 5 * 60 * 1000;
+
+// The configured key, else the one kept for this node in `dir`, created there the first
+// time. A new key every run without either, or when the file can't be used
+pub(crate) fn signing_key(
+    own_id: NodeId,
+    configured: Option<&SecretKey>,
+    dir: Option<&Path>,
+    log_target: &str,
+) -> SigningKey {
+    if let Some(key) = configured {
+        return SigningKey::from_bytes(key.as_bytes());
+    }
+    let mut seed = [0u8; 32];
+    let Some(path) = dir.map(|dir| dir.join(format!("{own_id}.key"))) else {
+        rng().fill_bytes(&mut seed);
+        return SigningKey::from_bytes(&seed);
+    };
+    if let Some(seed) = fs::read_to_string(&path)
+        .ok()
+        .and_then(|x| parse_hex(x.trim()))
+        .and_then(|x| <[u8; 32]>::try_from(x).ok())
+    {
+        return SigningKey::from_bytes(&seed);
+    }
+    rng().fill_bytes(&mut seed);
+    match write_key_file(&path, &seed) {
+        Ok(()) => info!(target: log_target, "Created signing key {}", path.display()),
+        Err(e) => {
+            warn!(target: log_target, "Could not keep the signing key in {}, others won't recognize our messages after a restart: {e}", path.display());
+        }
+    }
+    SigningKey::from_bytes(&seed)
+}
+
+// Only readable by its owner where the platform allows it. An existing file is never
+// overwritten, it may be a key that just couldn't be read
+fn write_key_file(path: &Path, seed: &[u8; 32]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(to_hex(seed).as_bytes())
+}
+
+// Signatures cover who sent the message, when, and in which channel, so that it can't be
+// replayed under another name, later or elsewhere
+fn signed_bytes(username: &str, signed_at: u64, channel_id: u64, text: &str) -> Vec<u8> {
+    let mut bytes = u32::try_from(username.len())
+        .unwrap_or(u32::MAX)
+        .to_be_bytes()
+        .to_vec();
+    bytes.extend_from_slice(username.as_bytes());
+    bytes.extend_from_slice(&signed_at.to_be_bytes());
+    bytes.extend_from_slice(&channel_id.to_be_bytes());
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+fn check_signature(key: &str, signature: &str, signed: &[u8]) -> bool {
+    let key = parse_hex(key)
+        .and_then(|x| <[u8; 32]>::try_from(x).ok())
+        .and_then(|x| VerifyingKey::from_bytes(&x).ok());
+    let signature = parse_hex(signature)
+        .and_then(|x| <[u8; 64]>::try_from(x).ok())
+        .map(|x| Signature::from_bytes(&x));
+    match (key, signature) {
+        (Some(key), Some(signature)) => key.verify(signed, &signature).is_ok(),
+        _ => false,
+    }
+}
+
+impl ChatClientInternal {
+    // Signs the text as it goes out, after encryption, so that servers and drones can't
+    // change it unnoticed. The name is the one we registered with on that server, which
+    // is the one recipients see
+    pub(crate) fn sign_outgoing(&self, server_id: NodeId, message: &mut SendMessage) {
+        let username = self
+            .server_usernames
+            .get(&server_id)
+            .map_or("", String::as_str);
+        message.signed_at = self.wall_now();
+        let signature = self.signing_key.sign(&signed_bytes(
+            username,
+            message.signed_at,
+            message.channel_id,
+            &message.message,
+        ));
+        message.signature = to_hex(&signature.to_bytes());
+        message.signer_key = to_hex(self.signing_key.verifying_key().as_bytes());
+    }
+
+    // Whether a message carries a valid, recent signature by the key its sender used the
+    // first time we heard from them. This is trust on first use: whoever signs first
+    // under a name is believed to own it, until the user forgets that key with
+    // /forgetkey. Unsigned messages aren't verified either
+    pub(crate) fn verify_incoming(&mut self, msg: &MessageData) -> bool {
+        if msg.signed_at.abs_diff(msg.timestamp) > MAX_SIGNATURE_AGE_MS {
+            return false;
+        }
+        // Users of linked servers are shown as "name@server", they signed as "name"
+        let home_name = msg.username.rsplit_once('@').map(|(name, _)| name);
+        let signed = [Some(msg.username.as_str()), home_name]
+            .into_iter()
+            .flatten()
+            .any(|username| {
+                check_signature(
+                    &msg.signer_key,
+                    &msg.signature,
+                    &signed_bytes(username, msg.signed_at, msg.channel_id, &msg.message),
+                )
+            });
+        if !signed {
+            return false;
+        }
+        let pinned = self
+            .user_keys
+            .entry(msg.username.clone())
+            .or_insert_with(|| msg.signer_key.clone());
+        if *pinned != msg.signer_key {
+            warn!(target: self.log_target.as_str(), "{} signed with a key other than the one seen first", msg.username);
+            return false;
+        }
+        true
+    }
+
+    // Forgets the key pinned for a user, the next one they sign with is trusted instead
+    pub(crate) fn cmd_forgetkey(
+        &mut self,
+        username: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let text = if self.user_keys.remove(username).is_some() {
+            format!("[SYSTEM] Forgot the key of {username}, the next one they sign with will be trusted")
+        } else {
+            format!("[SYSTEM] Error: No key pinned for {username}")
+        };
+        (vec![], vec![ChatClientEvent::MessageReceived(text)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ChatClientConfig;
+    use crate::protocol::ALL_CHANNEL_ID;
+    use chat_common::messages::ChannelKind;
+
+    const NOW: u64 = 1_700_000_000_000;
+
+    fn signed_message(
+        key: &SigningKey,
+        username: &str,
+        signed_as: &str,
+        text: &str,
+    ) -> MessageData {
+        let signature = key.sign(&signed_bytes(signed_as, NOW, ALL_CHANNEL_ID, text));
+        MessageData {
+            username: username.to_string(),
+            timestamp: NOW,
+            message: text.to_string(),
+            channel_id: ALL_CHANNEL_ID,
+            channel_kind: ChannelKind::All,
+            color: 0,
+            message_id: NOW,
+            content_warning: None,
+            signature: to_hex(&signature.to_bytes()),
+            signer_key: to_hex(key.verifying_key().as_bytes()),
+            signed_at: NOW,
+            local_id: 1,
+        }
+    }
+
+    fn client() -> ChatClientInternal {
+        ChatClientInternal::with_config(
+            1,
+            ChatClientConfig {
+                // Keeps tests from writing a key file
+                signing_key_dir: None,
+                ..ChatClientConfig::default()
+            },
+        )
+    }
+
+    #[test]
+    fn signed_messages_verify() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut client = client();
+        assert!(client.verify_incoming(&signed_message(&key, "alice", "alice", "hi")));
+    }
+
+    #[test]
+    fn changed_text_name_or_time_fails() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut client = client();
+        let msg = signed_message(&key, "alice", "alice", "hi");
+        let tampered = [
+            MessageData {
+                message: "bye".to_string(),
+                ..msg.clone()
+            },
+            MessageData {
+                username: "mallory".to_string(),
+                ..msg.clone()
+            },
+            MessageData {
+                signed_at: NOW - 1,
+                ..msg.clone()
+            },
+            MessageData {
+                channel_id: ALL_CHANNEL_ID + 1,
+                ..msg
+            },
+        ];
+        for msg in &tampered {
+            assert!(!client.verify_incoming(msg));
+        }
+    }
+
+    #[test]
+    fn old_signatures_fail() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut client = client();
+        let msg = MessageData {
+            timestamp: NOW + MAX_SIGNATURE_AGE_MS + 1,
+            ..signed_message(&key, "alice", "alice", "hi")
+        };
+        assert!(!client.verify_incoming(&msg));
+    }
+
+    #[test]
+    fn the_first_key_seen_is_trusted_until_forgotten() {
+        let first = SigningKey::from_bytes(&[1; 32]);
+        let second = SigningKey::from_bytes(&[2; 32]);
+        let mut client = client();
+        assert!(client.verify_incoming(&signed_message(&first, "alice", "alice", "hi")));
+        assert!(!client.verify_incoming(&signed_message(&second, "alice", "alice", "hi")));
+        client.cmd_forgetkey("alice");
+        assert!(client.verify_incoming(&signed_message(&second, "alice", "alice", "hi")));
+    }
+
+    #[test]
+    fn users_of_linked_servers_sign_with_their_home_name() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut client = client();
+        assert!(client.verify_incoming(&signed_message(&key, "alice@lobby", "alice", "hi")));
+    }
+}
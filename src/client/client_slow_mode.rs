@@ -0,0 +1,92 @@
+use crate::client::ChatClientInternal;
+use crate::clock::Instant;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, ErrorMessage, SetSlowMode};
+use common::slc_commands::ChatClientEvent;
+use std::time::Duration;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    // The server refused a message because of slow mode. Until the wait is over messages
+    // for that channel are refused here, with the time left. Servers that don't name the
+    // channel mean the one we're in
+    pub(crate) fn msg_slow_mode(&mut self, events: &mut Vec<ChatClientEvent>, err: &ErrorMessage) {
+        let Some(channel) = Some(err.channel_id)
+            .filter(|x| *x != 0)
+            .or(self.currently_connected_channel)
+        else {
+            return;
+        };
+        let remaining = Duration::from_millis(err.retry_after_ms);
+        self.slow_mode_until.insert(channel, self.now() + remaining);
+        events.push(ChatClientEvent::MessageReceived(format!(
+            "[SYSTEM] Slow mode is on in #{}, you can send again in {}s",
+            self.channel_display_name(channel),
+            remaining.as_millis().div_ceil(1000)
+        )));
+        events.push(ChatClientEvent::SlowMode { channel, remaining });
+    }
+
+    // Time left before we may send in `channel`, None if we may now
+    pub(crate) fn slow_mode_remaining(&self, channel: u64) -> Option<Duration> {
+        let until = self.slow_mode_until.get(&channel)?;
+        Some(until.saturating_duration_since(self.now())).filter(|x| !x.is_zero())
+    }
+
+    pub(crate) fn poll_slow_mode(&mut self, events: &mut Vec<ChatClientEvent>, now: Instant) {
+        let over = self
+            .slow_mode_until
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(channel, _)| *channel)
+            .collect::<Vec<_>>();
+        for channel in over {
+            self.slow_mode_until.remove(&channel);
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] You can send in #{} again",
+                self.channel_display_name(channel)
+            )));
+            events.push(ChatClientEvent::SlowMode {
+                channel,
+                remaining: Duration::ZERO,
+            });
+        }
+    }
+
+    pub(crate) fn cmd_slowmode(
+        &self,
+        server_id: NodeId,
+        arg: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let Ok(seconds) = arg.parse::<u32>() else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Usage: /slowmode <seconds>, 0 turns it off".to_string(),
+                )],
+            );
+        };
+        let Some(channel_id) = self.currently_connected_channel else {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: You are not connected to a channel.".to_string(),
+                )],
+            );
+        };
+        (
+            vec![(
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliSetSlowMode(SetSlowMode {
+                        channel_id,
+                        seconds,
+                    })),
+                },
+            )],
+            vec![],
+        )
+    }
+}
@@ -0,0 +1,90 @@
+use crate::client::ChatClientInternal;
+use wg_2024::network::NodeId;
+
+// Longer messages are refused rather than flooding the channel
+const MAX_PARTS: usize = 20;
+
+impl ChatClientInternal {
+    // Largest message `server` takes, None for servers that don't advertise it
+    pub(crate) fn max_message_len(&self, server: NodeId) -> Option<usize> {
+        self.discovered_servers
+            .get(&server)
+            .map(|srv| srv.max_message_len as usize)
+            .filter(|x| *x != 0)
+    }
+
+    // Whether `text` stays within `max` bytes once encrypted for the channel, as the
+    // server sees it
+    fn fits(&self, channel_id: u64, text: String, max: usize) -> bool {
+        self.seal_for_channel(channel_id, text).len() <= max
+    }
+
+    // Splits a message too long for the server into parts numbered "[1/3] ", breaking
+    // between words where possible. None if it would take more than MAX_PARTS parts or
+    // the limit can't even fit the numbering
+    pub(crate) fn split_outgoing(
+        &self,
+        channel_id: u64,
+        text: String,
+        max: usize,
+    ) -> Option<Vec<String>> {
+        if self.fits(channel_id, text.clone(), max) {
+            return Some(vec![text]);
+        }
+        // Room for the widest numbering of up to 9 parts, then of up to 99
+        for (widest, limit) in [("[9/9] ", 10), ("[99/99] ", 100)] {
+            let parts = self.split_parts(channel_id, &text, widest, max)?;
+            if parts.len() > MAX_PARTS {
+                return None;
+            }
+            if parts.len() < limit {
+                let count = parts.len();
+                return Some(
+                    parts
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, part)| format!("[{}/{count}] {part}", i + 1))
+                        .collect(),
+                );
+            }
+        }
+        None
+    }
+
+    fn split_parts(
+        &self,
+        channel_id: u64,
+        text: &str,
+        prefix: &str,
+        max: usize,
+    ) -> Option<Vec<String>> {
+        let mut parts = vec![];
+        let mut rest = text;
+        while !rest.is_empty() {
+            let ends = rest
+                .char_indices()
+                .skip(1)
+                .map(|(i, _)| i)
+                .chain([rest.len()])
+                .collect::<Vec<_>>();
+            let fitting = ends.partition_point(|end| {
+                self.fits(channel_id, format!("{prefix}{}", &rest[..*end]), max)
+            });
+            let end = *ends.get(fitting.checked_sub(1)?)?;
+            let end = if end == rest.len() {
+                end
+            } else {
+                rest[..end]
+                    .rfind(char::is_whitespace)
+                    .filter(|x| *x > 0)
+                    .unwrap_or(end)
+            };
+            parts.push(rest[..end].trim_end().to_string());
+            rest = rest[end..].trim_start();
+            if parts.len() > MAX_PARTS {
+                break;
+            }
+        }
+        Some(parts)
+    }
+}
@@ -0,0 +1,38 @@
+use crate::client::ChatClientInternal;
+use common::slc_commands::{ChannelSnapshot, ClientState};
+use itertools::Itertools;
+
+impl ChatClientInternal {
+    // What a front-end needs to draw its server and channel lists, answered to
+    // ChatClientCommand::GetState
+    pub(crate) fn state_snapshot(&self) -> ClientState {
+        ClientState {
+            discovered_servers: self
+                .discovered_servers
+                .keys()
+                .sorted()
+                .map(|id| (*id, self.server_display_name(*id)))
+                .collect(),
+            connected_server: self.currently_connected_server,
+            connection_state: self.connection_state,
+            username: self
+                .currently_connected_server
+                .and_then(|id| self.server_usernames.get(&id).cloned()),
+            channel: self.currently_connected_channel,
+            channels: self
+                .channels_list
+                .iter()
+                .map(|chan| ChannelSnapshot {
+                    id: chan.channel_id,
+                    name: chan.channel_name.clone(),
+                    kind: chan.channel_kind,
+                    members: chan
+                        .connected_clients
+                        .iter()
+                        .map(|client| client.username.clone())
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::client::ChatClientInternal;
+use chat_common::messages::{ChatMessage, MessageData};
+use common::slc_commands::ChatClientEvent;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::time::Duration;
+use wg_2024::network::NodeId;
+
+// Live messages remembered to spot repeats, these come soon after the original
+const RECENT_MESSAGES: usize = 256;
+
+// Session counters, the single source for /stats and for embedders reading them through
+// `ChatClientInternal::stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    // Chat messages sent, per channel
+    pub sent: HashMap<u64, u64>,
+    // Chat messages received live, per channel. Duplicates and history pages aren't counted
+    pub received: HashMap<u64, u64>,
+    // Failovers and migrations to another server
+    pub reconnects: u64,
+    // Chat messages that couldn't be delivered
+    pub dropped: u64,
+    // Chat messages received more than once
+    pub duplicates: u64,
+    rtt_total: Duration,
+    rtt_samples: u32,
+}
+
+impl ClientStats {
+    pub(crate) fn record_rtt(&mut self, rtt: Duration) {
+        self.rtt_total += rtt;
+        self.rtt_samples += 1;
+    }
+
+    // Mean of the round trip times measured during discovery
+    #[must_use]
+    pub fn average_rtt(&self) -> Option<Duration> {
+        self.rtt_total.checked_div(self.rtt_samples)
+    }
+}
+
+impl ChatClientInternal {
+    #[must_use]
+    pub fn stats(&self) -> &ClientStats {
+        &self.stats
+    }
+
+    // Counts a live chat message, returning false if it was already received. Messages
+    // are told apart by their sender and the local id it gave them, not by when the
+    // server stamped them, which says nothing about repeats. Local ids start over when
+    // the sender restarts, its signing time tells those apart. Messages without a local
+    // id, announcements and those of clients that don't track them, are never repeats
+    pub(crate) fn record_received(&mut self, server_id: NodeId, msg: &MessageData) -> bool {
+        if msg.local_id != 0 {
+            let key = (server_id, msg.username.clone(), msg.local_id, msg.signed_at);
+            if self.recent_messages.contains(&key) {
+                self.stats.duplicates += 1;
+                return false;
+            }
+            if self.recent_messages.len() >= RECENT_MESSAGES {
+                self.recent_messages.pop_front();
+            }
+            self.recent_messages.push_back(key);
+        }
+        *self.stats.received.entry(msg.channel_id).or_default() += 1;
+        true
+    }
+
+    pub(crate) fn cmd_stats(&self) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let mut msg = String::from("[SYSTEM] Session statistics:");
+        let channels = self
+            .stats
+            .sent
+            .keys()
+            .chain(self.stats.received.keys())
+            .unique()
+            .sorted()
+            .collect::<Vec<_>>();
+        for id in channels {
+            let name = self
+                .channels_list
+                .iter()
+                .find(|chan| chan.channel_id == *id)
+                .map_or_else(|| id.to_string(), |chan| chan.channel_name.clone());
+            msg.push_str(&format!(
+                "\n[SYSTEM]    #{name}: {} sent, {} received",
+                self.stats.sent.get(id).copied().unwrap_or_default(),
+                self.stats.received.get(id).copied().unwrap_or_default()
+            ));
+        }
+        let rtt = self
+            .stats
+            .average_rtt()
+            .map_or_else(|| "n/a".to_string(), |rtt| format!("{}ms", rtt.as_millis()));
+        msg.push_str(&format!(
+            "\n[SYSTEM]    Reconnects: {}\n[SYSTEM]    Average RTT: {rtt}\n[SYSTEM]    Dropped: {}, duplicates: {}",
+            self.stats.reconnects, self.stats.dropped, self.stats.duplicates
+        ));
+        (vec![], vec![ChatClientEvent::MessageReceived(msg)])
+    }
+}
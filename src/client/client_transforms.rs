@@ -0,0 +1,64 @@
+use crate::client::ChatClientInternal;
+use std::fmt::{Debug, Formatter};
+
+// Built-in steps of the outgoing message pipeline, in the order given in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextTransform {
+    // Remove surrounding whitespace
+    Trim,
+    // Replace known :shortcodes: with emoji
+    ExpandEmoji,
+    // Upper-case the first letter
+    Capitalize,
+}
+
+// Custom step of the outgoing message pipeline, run after the configured ones.
+// Closures from String to String can be used directly
+pub trait OutgoingTransform: Send {
+    fn transform(&mut self, text: String) -> String;
+}
+
+impl<F: FnMut(String) -> String + Send> OutgoingTransform for F {
+    fn transform(&mut self, text: String) -> String {
+        self(text)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Transforms(Vec<Box<dyn OutgoingTransform>>);
+
+impl Debug for Transforms {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Transforms({})", self.0.len())
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+impl ChatClientInternal {
+    pub fn add_transform(&mut self, transform: Box<dyn OutgoingTransform>) {
+        self.transforms.0.push(transform);
+    }
+
+    // Text of a chat message as it will be sent
+    pub(crate) fn transform_outgoing(&mut self, text: &str) -> String {
+        let mut text = text.to_string();
+        for step in &self.config.outgoing_transforms {
+            text = match step {
+                TextTransform::Trim => text.trim().to_string(),
+                TextTransform::ExpandEmoji => self.expand_emoji(&text),
+                TextTransform::Capitalize => capitalize(&text),
+            };
+        }
+        for transform in &mut self.transforms.0 {
+            text = transform.transform(text);
+        }
+        text
+    }
+}
@@ -0,0 +1,87 @@
+use crate::client::{ChatClientInternal, TypingState};
+use crate::clock::Instant;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, TypingNotification};
+use log::trace;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    fn typing_message(&self, channel_id: u64, typing: bool) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::CliTyping(TypingNotification {
+                channel_id,
+                typing,
+            })),
+        }
+    }
+
+    // Called on every keystroke, only lets a notification through every `typing_interval`
+    pub(crate) fn notify_typing(&mut self, now: Instant) -> Vec<(NodeId, ChatMessage)> {
+        let (Some(server_id), Some(channel_id)) = (
+            self.currently_connected_server,
+            self.currently_connected_channel,
+        ) else {
+            return vec![];
+        };
+        let mut replies = self.stop_typing_unless(server_id, channel_id);
+        match &mut self.typing {
+            Some(state) if now - state.last_sent < self.config.typing_interval => {
+                state.last_keystroke = now;
+            }
+            _ => {
+                trace!(target: self.log_target.as_str(), "Sending typing notification for channel {channel_id}");
+                self.typing = Some(TypingState {
+                    server_id,
+                    channel_id,
+                    last_sent: now,
+                    last_keystroke: now,
+                });
+                replies.push((server_id, self.typing_message(channel_id, true)));
+            }
+        }
+        replies
+    }
+
+    // Stops the current typing notification, if any, unless it is for the given channel
+    fn stop_typing_unless(
+        &mut self,
+        server_id: NodeId,
+        channel_id: u64,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        match self.typing.take() {
+            Some(state) if state.server_id == server_id && state.channel_id == channel_id => {
+                self.typing = Some(state);
+                vec![]
+            }
+            Some(state) => vec![(
+                state.server_id,
+                self.typing_message(state.channel_id, false),
+            )],
+            None => vec![],
+        }
+    }
+
+    pub(crate) fn stop_typing(&mut self) -> Vec<(NodeId, ChatMessage)> {
+        self.typing
+            .take()
+            .map(|state| {
+                (
+                    state.server_id,
+                    self.typing_message(state.channel_id, false),
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+
+    pub(crate) fn poll_typing_timeout(&mut self, now: Instant) -> Vec<(NodeId, ChatMessage)> {
+        match &self.typing {
+            Some(state) if now - state.last_keystroke >= self.config.typing_timeout => {
+                self.stop_typing()
+            }
+            _ => vec![],
+        }
+    }
+}
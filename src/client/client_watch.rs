@@ -0,0 +1,95 @@
+use crate::client::ChatClientInternal;
+use crate::protocol::message_words;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, KeywordSubscription, MessageData};
+use common::slc_commands::ChatClientEvent;
+use itertools::Itertools;
+use wg_2024::network::NodeId;
+
+impl ChatClientInternal {
+    // Raises an alert for every watched word in a message from someone else
+    pub(crate) fn check_watch_words(&self, events: &mut Vec<ChatClientEvent>, msg: &MessageData) {
+        if self.watch_words.is_empty()
+            || self
+                .currently_connected_server
+                .and_then(|id| self.server_usernames.get(&id))
+                == Some(&msg.username)
+        {
+            return;
+        }
+        let words = message_words(&msg.message);
+        for keyword in self.watch_words.iter().filter(|x| words.contains(*x)) {
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Watched word \"{keyword}\" in #{} from @{}",
+                self.channel_display_name(msg.channel_id),
+                msg.username
+            )));
+            events.push(ChatClientEvent::KeywordMatched {
+                channel: msg.channel_id,
+                message_id: msg.message_id,
+                keyword: keyword.clone(),
+            });
+        }
+    }
+
+    // `/watch add|remove [--server] <word>` and `/watch list`. With --server the server
+    // also forwards matching messages of channels we aren't in
+    pub(crate) fn cmd_watch(
+        &mut self,
+        arg: &str,
+        freeform: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let (server_side, word) = match freeform.strip_prefix("--server") {
+            Some(rest) => (true, rest.trim()),
+            None => (false, freeform.trim()),
+        };
+        if server_side && self.currently_connected_server.is_none() {
+            return (
+                vec![],
+                vec![ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Not connected to a server.".to_string(),
+                )],
+            );
+        }
+        let word = word.to_lowercase();
+        let valid = !word.is_empty() && word.chars().all(char::is_alphanumeric);
+        let msg = match arg {
+            "list" if self.watch_words.is_empty() => "[SYSTEM] No watched words".to_string(),
+            "list" => format!(
+                "[SYSTEM] Watched words: {}",
+                self.watch_words.iter().join(", ")
+            ),
+            "add" | "remove" if !valid => {
+                "[SYSTEM] Error: Usage: /watch add|remove [--server] <word>".to_string()
+            }
+            "add" => {
+                self.watch_words.insert(word.clone());
+                format!("[SYSTEM] Watching \"{word}\"")
+            }
+            "remove" => {
+                self.watch_words.remove(&word);
+                format!("[SYSTEM] No longer watching \"{word}\"")
+            }
+            _ => "[SYSTEM] Error: Usage: /watch add|remove [--server] <word> or /watch list"
+                .to_string(),
+        };
+        let mut replies = vec![];
+        if let Some(server_id) = self
+            .currently_connected_server
+            .filter(|_| server_side && valid && matches!(arg, "add" | "remove"))
+        {
+            replies.push((
+                server_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::CliSubscribeKeyword(KeywordSubscription {
+                        keyword: word,
+                        subscribe: arg == "add",
+                    })),
+                },
+            ));
+        }
+        (replies, vec![ChatClientEvent::MessageReceived(msg)])
+    }
+}
@@ -1,28 +1,321 @@
+mod client_bookmarks;
+mod client_channel_delta;
 mod client_command_handling;
+mod client_commands;
+mod client_discovery;
+mod client_emoji;
+mod client_encryption;
+mod client_failover;
+mod client_file_transfer;
+mod client_flow;
+mod client_health;
+mod client_history;
+mod client_input_history;
+mod client_invites;
+mod client_links;
+mod client_masking;
+mod client_mentions;
 mod client_message_handling;
+mod client_moderation;
+mod client_pinning;
+mod client_plugins;
+mod client_reactions;
+mod client_reconnect;
+mod client_requests;
+mod client_send_status;
+mod client_session;
+mod client_signing;
+mod client_slow_mode;
+mod client_splitting;
+mod client_state;
+mod client_stats;
+mod client_transforms;
+mod client_typing;
+mod client_watch;
 
+use crate::clock::{Clock, Instant, SystemClock};
+use crate::compression::{decompress_message, COMPRESSION_CAPABILITY};
+use crate::logging::set_node_level;
+use crate::protocol::{
+    chat_route, is_shared_kind, kind_from_id, parse_server_type, parse_severity,
+    personal_channel_id, ErrorCode, SYSTEM_USERNAME, USER_COLOR_COUNT,
+};
+use crate::secret::SecretKey;
 use chat_common::messages::chat_message::MessageKind;
-use chat_common::messages::{Channel, ChatMessage, ErrorMessage, MessageData};
-use chat_common::packet_handling::{CommandHandler, PacketHandler};
-use common::slc_commands::{ChatClientCommand, ChatClientEvent, ServerType};
-use crossbeam::channel::Sender;
-use log::info;
-use std::collections::{HashMap, HashSet};
+use chat_common::messages::{
+    Channel, ChannelKind, ChatMessage, ConfirmRegistration, Empty, Invite, MessageData, SendMessage,
+};
+use chat_common::packet_handling::{CommandHandler, PacketHandler, Sender};
+use client_bookmarks::Bookmarks;
+use client_file_transfer::FileTransfers;
+use client_flow::FlowControl;
+use client_input_history::InputHistory;
+use client_links::SeenLink;
+use client_pinning::PinnedKeys;
+use client_plugins::Plugins;
+pub use client_plugins::{ClientPlugin, PluginAction};
+use client_reconnect::PendingReconnect;
+use client_requests::{PendingRequests, RequestKind};
+use client_send_status::SendTracker;
+use client_session::ServerSession;
+use client_signing::signing_key;
+pub use client_stats::ClientStats;
+use client_transforms::Transforms;
+pub use client_transforms::{OutgoingTransform, TextTransform};
+use common::slc_commands::{
+    ChatClientCommand, ChatClientEvent, ConnectionState, NoticeSeverity, SendStatus, ServerType,
+};
+use ed25519_dalek::SigningKey;
+use itertools::Itertools;
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::time::Duration;
 use wg_2024::network::NodeId;
 use wg_2024::packet::{NodeType, Packet};
 
+// Spoilers and masked messages whose original text can still be shown
+const HIDDEN_KEPT: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServerSelectionPolicy {
+    // Smallest advertised users/capacity ratio
+    #[default]
+    LowestLoad,
+    // Smallest round trip time, measured by discovery and measured again by /ping
+    LowestRtt,
+    // The server whose discovery response arrived first
+    FirstResponding,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChatClientConfig {
+    // How long to wait for a discovery response before asking again
+    pub discovery_timeout: Duration,
+    // How many times a discovery request is re-sent before giving up on a node
+    pub discovery_retries: u32,
+    // Policy used by `/connect auto`
+    pub selection_policy: ServerSelectionPolicy,
+    // Consecutive delivery failures after which a server is considered stale
+    pub stale_failure_threshold: u32,
+    // Move to another chat server, keeping username and channel, when the current one is stale
+    pub failover: bool,
+    // Without failover, discover the current server again when it stops answering and
+    // reconnect to it, keeping username and channel
+    pub auto_reconnect: bool,
+    // A connected server silent for this long counts as lost, see `auto_reconnect`. Only
+    // servers with heartbeats on ping idle clients
+    pub server_silence_timeout: Duration,
+    // Color usernames in rendered messages with the server-assigned ANSI color. Off by
+    // default, only frontends that render to a terminal should turn it on
+    pub colors: bool,
+    // Minimum time between two typing notifications for the same channel
+    pub typing_interval: Duration,
+    // Time without keystrokes after which the client reports that the user stopped typing
+    pub typing_timeout: Duration,
+    // A sent chat message the server hasn't confirmed by then counts as failed
+    pub delivery_timeout: Duration,
+    // Times an unconfirmed chat message is sent again before it counts as failed
+    pub delivery_retries: u32,
+    // Register, join and channel requests left unanswered this long are reported as failed
+    pub request_timeout: Duration,
+    // Log target of this client, "Client <id>" by default
+    pub log_target: Option<String>,
+    // Number of past inputs kept for recall
+    pub input_history_limit: usize,
+    // File past inputs are saved to in plain text, so that they survive restarts. None
+    // (the default) keeps them in memory only
+    pub input_history_path: Option<PathBuf>,
+    // File bookmarks are saved to, so that they survive restarts
+    pub bookmarks_path: Option<PathBuf>,
+    // File server keys are pinned in, so that an impostor is noticed across restarts
+    pub pinned_keys_path: Option<PathBuf>,
+    // Shortcodes (without colons) added to the built-in emoji table
+    pub emoji: HashMap<String, String>,
+    // Also expand shortcodes in messages from others
+    pub expand_incoming_emoji: bool,
+    // Words shown as asterisks in messages from others, /raw shows the original
+    pub masked_words: Vec<String>,
+    // Secret key our chat messages are signed with, see `signing_key_dir` if None
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub signing_key: Option<SecretKey>,
+    // Where the signing key is kept when none is configured, one file per node id, so
+    // that others still recognize our messages after a restart. A new key every run if
+    // None
+    pub signing_key_dir: Option<PathBuf>,
+    // Shared keys of encrypted group channels, by channel name
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub channel_keys: HashMap<String, SecretKey>,
+    // Applied in order to the text of every chat message we send
+    pub outgoing_transforms: Vec<TextTransform>,
+    // Words that raise an alert when they appear in a message from others
+    pub watch_words: Vec<String>,
+    // Where received files are saved, the system's temporary directory if None
+    pub download_dir: Option<PathBuf>,
+    // Bytes of a file sent in each chunk
+    pub file_chunk_size: usize,
+    // Missing chunks of a file we receive are asked for again after this long without any
+    pub file_chunk_timeout: Duration,
+    // Times missing chunks are asked for before the file counts as failed
+    pub file_chunk_retries: u32,
+    // Time between two bursts of chunks of a file we send, each as large as the
+    // server's send window
+    pub file_chunk_interval: Duration,
+    // Largest file we offer or accept, in bytes
+    pub max_file_size: u64,
+}
+
+impl Default for ChatClientConfig {
+    fn default() -> Self {
+        Self {
+            discovery_timeout: Duration::from_secs(2),
+            discovery_retries: 3,
+            selection_policy: ServerSelectionPolicy::default(),
+            stale_failure_threshold: 3,
+            failover: false,
+            auto_reconnect: false,
+            server_silence_timeout: Duration::from_secs(90),
+            colors: false,
+            typing_interval: Duration::from_secs(3),
+            typing_timeout: Duration::from_secs(5),
+            delivery_timeout: Duration::from_secs(30),
+            delivery_retries: 2,
+            request_timeout: Duration::from_secs(10),
+            log_target: None,
+            input_history_limit: 100,
+            input_history_path: None,
+            bookmarks_path: None,
+            pinned_keys_path: None,
+            emoji: HashMap::new(),
+            expand_incoming_emoji: false,
+            masked_words: vec![],
+            signing_key: None,
+            // Browsers have no file system to keep it in
+            signing_key_dir: if cfg!(feature = "wasm") {
+                None
+            } else {
+                Some(std::env::temp_dir().join("chat_client_keys"))
+            },
+            channel_keys: HashMap::new(),
+            outgoing_transforms: vec![TextTransform::ExpandEmoji],
+            watch_words: vec![],
+            download_dir: None,
+            file_chunk_size: 1024,
+            file_chunk_timeout: Duration::from_secs(5),
+            file_chunk_retries: 5,
+            file_chunk_interval: Duration::from_millis(100),
+            max_file_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DiscoveredServer {
+    // None for types this version doesn't know
+    server_type: Option<ServerType>,
+    name: Option<String>,
+    user_count: u32,
+    channel_count: u32,
+    capacity: Option<u32>,
+    discovered_at: Instant,
+    rtt: Option<Duration>,
+    last_reply: Instant,
+    consecutive_failures: u32,
+    // Empty for servers that don't advertise it
+    version: String,
+    // As advertised at `discovered_at`
+    uptime: Duration,
+    // In bytes, 0 for servers that don't advertise it
+    max_message_len: u32,
+    // Optional features, empty for servers that don't advertise them
+    capabilities: Vec<String>,
+    // Hex of the ed25519 key the server advertised, empty for servers that don't
+    public_key: String,
+}
+
+impl DiscoveredServer {
+    fn is_chat(&self) -> bool {
+        self.server_type == Some(ServerType::ChatServer)
+    }
+}
+
+#[derive(Debug)]
+struct PendingDiscovery {
+    sent_at: Instant,
+    deadline: Instant,
+    attempts: u32,
+    // Sent by /ping, the round trip is shown when the answer comes
+    ping: bool,
+}
+
+#[derive(Debug)]
+struct TypingState {
+    server_id: NodeId,
+    channel_id: u64,
+    last_sent: Instant,
+    last_keystroke: Instant,
+}
+
 #[derive(Debug)]
 pub struct ChatClientInternal {
-    discovered_servers: HashMap<NodeId, String>,
+    config: ChatClientConfig,
+    discovered_servers: HashMap<NodeId, DiscoveredServer>,
     discovered_nodes: HashSet<NodeId>,
+    pending_discoveries: HashMap<NodeId, PendingDiscovery>,
     currently_connected_server: Option<NodeId>,
     currently_connected_channel: Option<u64>,
+    connection_state: ConnectionState,
     server_usernames: HashMap<NodeId, String>,
-    channels_list: Vec<Channel>, // bool is for "is_group_channel"
+    // Alternatives offered by the server after the last refused registration
+    username_suggestions: Vec<String>,
+    channels_list: Vec<Channel>,
+    // Messages written in the channel we were in when we left a server, oldest first,
+    // sent again once we joined it back
+    failover_outbox: Vec<SendMessage>,
+    // Set while waiting for a lost server to answer discovery again
+    reconnect: Option<PendingReconnect>,
+    typing: Option<TypingState>,
+    // Last route our chat traffic took to each destination, for /route
+    routes: HashMap<NodeId, Vec<NodeId>>,
+    // None until the connected server advertises a send window
+    flow: Option<FlowControl>,
+    input_history: InputHistory,
+    bookmarks: Bookmarks,
+    pinned_keys: PinnedKeys,
+    plugins: Plugins,
+    transforms: Transforms,
+    stats: ClientStats,
+    send_tracker: SendTracker,
+    // Register, join and channel requests still waiting for their answer
+    pending_requests: PendingRequests,
+    // Handshake state with every server we said hello to
+    sessions: HashMap<NodeId, ServerSession>,
+    // Recent messages behind a content warning or with masked words, by message id
+    hidden_messages: BTreeMap<u64, MessageData>,
+    // Latest links per channel, oldest first
+    links: HashMap<u64, VecDeque<SeenLink>>,
+    // Timestamp of the last message of each user, per channel
+    last_spoke: HashMap<u64, HashMap<String, u64>>,
+    // Server, sender, local id and signing time of the latest live messages, oldest
+    // first, to spot duplicates
+    recent_messages: VecDeque<(NodeId, String, u64, u64)>,
+    watch_words: BTreeSet<String>,
+    // Oldest message id seen per channel, where `/history --more` continues from
+    history_oldest: HashMap<u64, u64>,
+    signing_key: SigningKey,
+    // Signing key of every user whose messages we verified, pinned on first sight
+    user_keys: HashMap<String, String>,
+    // Invitations not used yet and the server they came from, oldest first
+    invites: Vec<(NodeId, Invite)>,
+    // Until when slow mode keeps us from sending in each channel
+    slow_mode_until: HashMap<u64, Instant>,
+    file_transfers: FileTransfers,
     own_id: u8,
-    // Client ID is the NodeId shifted left by 32 bits, with the last 4 bits set to 0x8
-    // Channels will be random, with the last 4 bits as 0x2
-    // The special "all" channel has only the last 4 bits as 0x1
+    log_target: String,
+    clock: Box<dyn Clock>,
+    // Personal channel other clients send private messages to
     own_channel_id: u64,
 }
 impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
@@ -32,41 +325,54 @@ impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
 
     fn handle_protocol_message(
         &mut self,
-        message: ChatMessage,
+        mut message: ChatMessage,
+        source: NodeId,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>)
     where
         Self: Sized,
     {
-        let mut replies: Vec<(NodeId, ChatMessage)> = vec![];
-        let mut events: Vec<ChatClientEvent> = vec![];
-        info!(target: format!("Client {}", self.own_id).as_str(), "Received message: {:?}", message);
+        let (mut replies, mut events) = self.poll_timers(self.now());
+        info!(target: self.log_target.as_str(), "Received message: {:?}", message);
+        // Another node posing as a server
+        if message.own_id != u32::from(source) {
+            warn!(target: self.log_target.as_str(), "Dropping message from {source} claiming to come from {}", message.own_id);
+            return (replies, events);
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        self.record_server_reply(message.own_id as NodeId);
+        if let Some(kind) = message
+            .message_kind
+            .as_mut()
+            .filter(|_| self.server_has_capability(source, COMPRESSION_CAPABILITY))
+        {
+            decompress_message(kind);
+        }
+        let answered = self.take_answered_request(&message);
         if let Some(kind) = message.message_kind {
             match kind {
                 MessageKind::SrvConfirmReg(reg) => {
-                    match (self.currently_connected_server, reg.successful) {
-                        (Some(server_id), true) if message.own_id == u32::from(server_id) => {
-                            self.server_usernames.insert(server_id, reg.username);
-                        }
-                        (Some(_), true) => {
-                            events.push(ChatClientEvent::MessageReceived("[SYSTEM] Error: Received registration confirmation from another server".to_string()));
-                        }
-                        (Some(_), false) => {
-                            events.push(ChatClientEvent::MessageReceived(format!(
-                                "[SYSTEM] Error: Registration failed - {}",
-                                reg.error.unwrap_or_else(|| "Unknown error".to_string())
-                            )));
-                        }
-                        (None, _) => {
-                            events.push(ChatClientEvent::MessageReceived(format!(
-                                "[SYSTEM] Error: Registration failed, not connected to server - {}",
-                                reg.error.unwrap_or_else(|| "Unknown error".to_string())
-                            )));
-                        }
-                    }
+                    #[allow(clippy::cast_possible_truncation)]
+                    self.msg_srvconfirmreg(&mut events, message.own_id as NodeId, reg);
+                }
+                MessageKind::SrvWelcome(welcome) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    self.msg_srvwelcome(&mut events, message.own_id as NodeId, welcome);
                 }
                 MessageKind::SrvReturnChannels(channels) => match self.currently_connected_server {
                     Some(server_id) if message.own_id == u32::from(server_id) => {
                         self.channels_list = channels.channels;
+                        if let Some(RequestKind::Channels(arg)) = answered.map(|x| x.kind) {
+                            events.push(ChatClientEvent::MessageReceived(
+                                self.channel_list_text(&arg),
+                            ));
+                        }
+                        if self.connection_state == ConnectionState::Connecting {
+                            self.set_connection_state(
+                                &mut events,
+                                server_id,
+                                ConnectionState::Connected,
+                            );
+                        }
                     }
                     Some(_) => {
                         // Ignore for other servers
@@ -75,22 +381,215 @@ impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
                         events.push(ChatClientEvent::MessageReceived("[SYSTEM] Error: Received channel list without being connected to a server".to_string()));
                     }
                 },
+                MessageKind::SrvChannelDelta(delta) => {
+                    if self.currently_connected_server.map(u32::from) == Some(message.own_id) {
+                        self.msg_srvchanneldelta(delta);
+                    }
+                }
                 MessageKind::SrvDistributeMessage(msg) => {
-                    self.msg_srvdistributemessage(&mut events, &msg);
+                    #[allow(clippy::cast_possible_truncation)]
+                    let server_id = message.own_id as NodeId;
+                    if self.record_received(server_id, &msg) {
+                        self.record_seen_message(&msg);
+                        self.record_speaker(&msg);
+                        replies.extend(self.deliver_incoming(server_id, &mut events, &msg));
+                    }
                 }
-                MessageKind::Err(err) => {
-                    events.push(ChatClientEvent::MessageReceived(format!(
+                MessageKind::SrvFlowCredit(credit) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    replies.extend(self.msg_srvflowcredit(
+                        &mut events,
+                        message.own_id as NodeId,
+                        &credit,
+                    ));
+                }
+                MessageKind::SrvPing(..) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    replies.push((
+                        message.own_id as NodeId,
+                        ChatMessage {
+                            own_id: u32::from(self.own_id),
+                            request_id: 0,
+                            message_kind: Some(MessageKind::CliPong(Empty {})),
+                        },
+                    ));
+                }
+                MessageKind::SrvSendReceipt(receipt) => {
+                    self.msg_srvsendreceipt(&mut events, &receipt);
+                }
+                MessageKind::SrvMigrated(migration) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let sender_id = message.own_id as NodeId;
+                    replies.extend(self.msg_srvmigrated(&mut events, sender_id, &migration));
+                }
+                MessageKind::SrvHistory(page) => self.msg_srvhistory(&mut events, page),
+                MessageKind::SrvMissedActivity(summary) => {
+                    self.msg_srvmissedactivity(&mut events, &summary);
+                }
+                MessageKind::SrvModerationStatus(status) => {
+                    self.msg_srvmoderationstatus(&mut events, &status);
+                }
+                MessageKind::SrvInviteReceived(invite) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    self.msg_srvinvitereceived(&mut events, message.own_id as NodeId, invite);
+                }
+                MessageKind::SrvFileOffer(offer) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let server_id = message.own_id as NodeId;
+                    replies.extend(self.msg_srvfileoffer(&mut events, server_id, offer));
+                }
+                MessageKind::SrvFileAnswer(answer) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let server_id = message.own_id as NodeId;
+                    replies.extend(self.msg_srvfileanswer(&mut events, server_id, &answer));
+                }
+                MessageKind::SrvFileChunk(chunk) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let server_id = message.own_id as NodeId;
+                    replies.extend(self.msg_srvfilechunk(&mut events, server_id, chunk));
+                }
+                MessageKind::SrvFileMissing(missing) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let server_id = message.own_id as NodeId;
+                    replies.extend(self.msg_srvfilemissing(&mut events, server_id, &missing));
+                }
+                MessageKind::SrvPendingApproval(msg) => {
+                    self.msg_srvpendingapproval(&mut events, &msg);
+                }
+                MessageKind::SrvSearchResults(results) => {
+                    self.msg_srvsearchresults(&mut events, &results);
+                }
+                MessageKind::SrvReactionUpdate(update) => {
+                    self.msg_srvreactionupdate(&mut events, update);
+                }
+                MessageKind::SrvKicked(kicked) => {
+                    self.msg_srvkicked(&mut events, &kicked);
+                }
+                MessageKind::Err(err)
+                    if ErrorCode::of(&err) == Some(ErrorCode::ChannelCreationForbidden) =>
+                {
+                    events.push(ChatClientEvent::MessageReceived(
+                        "[SYSTEM] Error: This server doesn't let you create channels, join an existing one instead (see /channels)".to_string(),
+                    ));
+                }
+                MessageKind::SrvRemovedFromServer(text) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let sender_id = message.own_id as NodeId;
+                    self.server_usernames.remove(&sender_id);
+                    if self.currently_connected_server == Some(sender_id) {
+                        self.currently_connected_channel = None;
+                        self.set_connection_state(
+                            &mut events,
+                            sender_id,
+                            ConnectionState::Connected,
+                        );
+                    }
+                    events.push(ChatClientEvent::MessageReceived(format!("[SYSTEM] {text}")));
+                }
+                MessageKind::Err(err)
+                    if ErrorCode::of(&err) == Some(ErrorCode::HandshakeRequired) =>
+                {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let server_id = message.own_id as NodeId;
+                    replies.extend(self.msg_handshake_required(&mut events, server_id));
+                }
+                MessageKind::Err(err) if ErrorCode::of(&err) == Some(ErrorCode::SlowMode) => {
+                    self.msg_slow_mode(&mut events, &err);
+                }
+                MessageKind::Err(err)
+                    if ErrorCode::of(&err) == Some(ErrorCode::BannedFromChannel) =>
+                {
+                    events.push(ChatClientEvent::MessageReceived(
+                        "[SYSTEM] Error: You are banned from that channel".to_string(),
+                    ));
+                }
+                MessageKind::Err(err) if ErrorCode::of(&err) == Some(ErrorCode::NotRegistered) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let server_id = message.own_id as NodeId;
+                    match self.recover_registration(&mut events, server_id) {
+                        Some(recovery) => replies.extend(recovery),
+                        None => events.push(ChatClientEvent::MessageReceived(format!(
+                            "[SYSTEM] Error: {} - {}",
+                            err.error_type, err.error_message
+                        ))),
+                    }
+                }
+                MessageKind::Err(err) => match answered {
+                    Some(request) => events.push(ChatClientEvent::MessageReceived(format!(
+                        "[SYSTEM] Error: {} failed: {} - {}",
+                        request.describe(),
+                        err.error_type,
+                        err.error_message
+                    ))),
+                    None => events.push(ChatClientEvent::MessageReceived(format!(
                         "[SYSTEM] Error: {} - {}",
                         err.error_type, err.error_message
-                    )));
-                }
+                    ))),
+                },
                 MessageKind::DsvRes(res) => {
                     #[allow(clippy::cast_possible_truncation)]
-                    self.discovered_servers
-                        .insert(res.server_id as NodeId, res.server_type);
+                    let server_id = res.server_id as NodeId;
+                    let now = self.now();
+                    let pending = self.pending_discoveries.remove(&server_id);
+                    let rtt = pending.as_ref().map(|pending| now - pending.sent_at);
+                    if let Some(rtt) = rtt {
+                        self.stats.record_rtt(rtt);
+                    }
+                    let ping = pending.is_some_and(|x| x.ping);
+                    // Answering a ping doesn't make a server respond first
+                    let discovered_at = self
+                        .discovered_servers
+                        .get(&server_id)
+                        .filter(|_| ping)
+                        .map_or(now, |x| x.discovered_at);
+                    self.check_server_key(&mut events, server_id, &res.public_key);
+                    self.discovered_servers.insert(
+                        server_id,
+                        DiscoveredServer {
+                            server_type: parse_server_type(&res.server_type),
+                            name: res.server_name,
+                            user_count: res.user_count,
+                            channel_count: res.channel_count,
+                            capacity: res.capacity,
+                            discovered_at,
+                            rtt,
+                            last_reply: now,
+                            consecutive_failures: 0,
+                            version: res.version,
+                            uptime: Duration::from_secs(res.uptime_secs),
+                            max_message_len: res.max_message_len,
+                            capabilities: res.capabilities,
+                            public_key: res.public_key,
+                        },
+                    );
+                    if let Some(rtt) = rtt.filter(|_| ping) {
+                        events.push(ChatClientEvent::MessageReceived(format!(
+                            "[SYSTEM] Server {} answered in {}ms",
+                            self.server_display_name(server_id),
+                            rtt.as_millis()
+                        )));
+                    }
+                    replies.extend(self.finish_reconnect(&mut events, server_id));
+                }
+                MessageKind::SrvSystemNotice(notice) => {
+                    events.push(match parse_severity(&notice.severity) {
+                        NoticeSeverity::Info => ChatClientEvent::ServerNotice(notice.text),
+                        NoticeSeverity::Warning => ChatClientEvent::ServerWarning(notice.text),
+                        NoticeSeverity::Critical => ChatClientEvent::ServerCritical(notice.text),
+                    });
+                }
+                MessageKind::SrvTyping(typing) => {
+                    events.push(ChatClientEvent::UserTyping {
+                        channel: typing.channel_id,
+                        username: typing.username,
+                        typing: typing.typing,
+                    });
                 }
                 MessageKind::SrvChannelCreationSuccessful(chan) => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    self.forget_invite(message.own_id as NodeId, chan);
                     self.currently_connected_channel = Some(chan);
+                    replies.extend(self.replay_failover_outbox(&mut events));
                 }
                 _ => {
                     #[allow(clippy::cast_possible_truncation)]
@@ -98,10 +597,11 @@ impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
                         message.own_id as NodeId,
                         ChatMessage {
                             own_id: u32::from(self.own_id),
-                            message_kind: Some(MessageKind::Err(ErrorMessage {
-                                error_type: "INVALID_SRV_MESSAGE".to_string(),
-                                error_message: format!("Invalid message: {kind:?}"),
-                            })),
+                            request_id: 0,
+                            message_kind: Some(MessageKind::Err(
+                                ErrorCode::InvalidSrvMessage
+                                    .message(&format!("Invalid message: {kind:?}")),
+                            )),
                         },
                     ));
                 }
@@ -114,7 +614,56 @@ impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
     where
         Self: Sized,
     {
-        ChatClientEvent::PacketSent(packet)
+        let route = chat_route(&packet);
+        if let Some(hops) = &route {
+            if let Some(destination) = hops.last() {
+                self.routes.insert(*destination, hops.clone());
+            }
+        }
+        ChatClientEvent::PacketSent { packet, route }
+    }
+
+    fn handle_tick(&mut self, now: Instant) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>)
+    where
+        Self: Sized,
+    {
+        self.poll_timers(now)
+    }
+
+    fn report_delivery_failure(
+        &mut self,
+        destination: NodeId,
+        message: ChatMessage,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>)
+    where
+        Self: Sized,
+    {
+        info!(target: self.log_target.as_str(), "Failed to deliver message to {destination}: {:?}", message);
+        let mut events = vec![];
+        let mut replies = vec![];
+        let mut lost = vec![];
+        if let Some(MessageKind::SendMsg(msg)) = message.message_kind {
+            self.stats.dropped += 1;
+            replies.extend(self.release_credit(&mut events, destination, msg.local_id));
+            // What the user wrote, not the sealed and compressed text that went out. A
+            // message without one already failed or got through, it isn't sent again
+            let original = self.take_original(msg.local_id);
+            if let Some(original) = &original {
+                lost.push(original.clone());
+            }
+            let msg = original.unwrap_or(msg);
+            self.set_send_status(&mut events, msg.local_id, SendStatus::Failed);
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[SYSTEM] Error: Could not deliver message: {}",
+                msg.message
+            )));
+            events.push(ChatClientEvent::DeliveryFailed {
+                channel: msg.channel_id,
+                text: msg.message,
+            });
+        }
+        replies.extend(self.record_send_failure(&mut events, destination, lost));
+        (replies, events)
     }
 
     fn handle_controller_command(
@@ -129,30 +678,60 @@ impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
     where
         Self: Sized,
     {
-        match command {
+        let (mut replies, mut events) = self.poll_timers(self.now());
+        let shortcut = match command {
             ChatClientCommand::AddSender(id, sender) => {
                 sender_hash.insert(id, sender);
-                (None, vec![], vec![])
+                None
             }
             ChatClientCommand::RemoveSender(id) => {
                 sender_hash.remove(&id);
-                (None, vec![], vec![])
+                None
+            }
+            ChatClientCommand::Shortcut(p) => Some(p),
+            ChatClientCommand::SetLogLevel(level) => {
+                if set_node_level(&self.log_target, level) {
+                    info!(target: self.log_target.as_str(), "Log level set to {level}");
+                } else {
+                    warn!(target: self.log_target.as_str(), "Can't set the log level, NodeLogFilter isn't installed");
+                }
+                None
             }
-            ChatClientCommand::Shortcut(p) => (Some(p), vec![], vec![]),
             ChatClientCommand::AskServersTypes => {
                 let mut map = HashMap::new();
-                self.discovered_servers.iter().for_each(|(id, srv_type)| {
-                    if srv_type == "chat" {
-                        map.insert(*id, ServerType::ChatServer);
+                self.discovered_servers.iter().for_each(|(id, srv)| {
+                    if let Some(typ) = srv.server_type {
+                        map.insert(*id, typ);
                     }
                 });
-                (None, vec![], vec![ChatClientEvent::ServersTypes(map)])
+                events.push(ChatClientEvent::ServersTypes(map));
+                None
+            }
+            ChatClientCommand::GetState => {
+                events.push(ChatClientEvent::State(self.state_snapshot()));
+                None
+            }
+            ChatClientCommand::NotifyTyping => {
+                replies.extend(self.notify_typing(self.now()));
+                None
+            }
+            ChatClientCommand::PreviousInput => {
+                events.push(ChatClientEvent::InputRecalled(self.previous_input()));
+                None
+            }
+            ChatClientCommand::NextInput => {
+                events.push(ChatClientEvent::InputRecalled(self.next_input()));
+                None
             }
             ChatClientCommand::SendMessage(m) => {
+                self.record_input(&m);
                 let x = self.handle_message(m.as_str());
-                (None, x.0, x.1)
+                replies.extend(x.0);
+                events.extend(x.1);
+                None
             }
-        }
+        };
+        (shortcut, replies, events)
     }
 
     fn add_node(&mut self, id: NodeId, typ: NodeType) -> Option<(NodeId, ChatMessage)> {
@@ -160,13 +739,17 @@ impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
             None
         } else {
             self.discovered_nodes.insert(id);
-            Some((
+            let now = self.now();
+            self.pending_discoveries.insert(
                 id,
-                ChatMessage {
-                    own_id: u32::from(self.own_id),
-                    message_kind: Some(MessageKind::DsvReq("chat".to_string())),
+                PendingDiscovery {
+                    sent_at: now,
+                    deadline: now + self.config.discovery_timeout,
+                    attempts: 1,
+                    ping: false,
                 },
-            ))
+            );
+            Some((id, self.discovery_request()))
         }
     }
 
@@ -174,27 +757,268 @@ impl CommandHandler<ChatClientCommand, ChatClientEvent> for ChatClientInternal {
     where
         Self: Sized,
     {
+        Self::with_config(id, ChatClientConfig::default())
+    }
+}
+
+impl ChatClientInternal {
+    #[must_use]
+    pub fn with_config(id: NodeId, config: ChatClientConfig) -> Self {
+        let log_target = config
+            .log_target
+            .clone()
+            .unwrap_or_else(|| format!("Client {id}"));
         Self {
+            input_history: InputHistory::load(
+                config.input_history_limit,
+                config.input_history_path.clone(),
+                &log_target,
+            ),
+            bookmarks: Bookmarks::load(config.bookmarks_path.clone()),
+            pinned_keys: PinnedKeys::load(config.pinned_keys_path.clone()),
+            plugins: Plugins::default(),
+            transforms: Transforms::default(),
+            stats: ClientStats::default(),
+            send_tracker: SendTracker::default(),
+            pending_requests: PendingRequests::default(),
+            sessions: HashMap::new(),
+            hidden_messages: BTreeMap::new(),
+            links: HashMap::new(),
+            last_spoke: HashMap::new(),
+            recent_messages: VecDeque::new(),
+            watch_words: config
+                .watch_words
+                .iter()
+                .map(|x| x.to_lowercase())
+                .collect(),
+            signing_key: signing_key(
+                id,
+                config.signing_key.as_ref(),
+                config.signing_key_dir.as_deref(),
+                &log_target,
+            ),
+            user_keys: HashMap::new(),
+            invites: vec![],
+            slow_mode_until: HashMap::new(),
+            file_transfers: FileTransfers::default(),
+            config,
             discovered_servers: HashMap::default(),
             discovered_nodes: HashSet::default(),
+            pending_discoveries: HashMap::default(),
             currently_connected_server: None,
             currently_connected_channel: None,
+            connection_state: ConnectionState::Disconnected,
             server_usernames: HashMap::default(),
+            username_suggestions: vec![],
             channels_list: vec![],
+            failover_outbox: vec![],
+            reconnect: None,
+            typing: None,
+            routes: HashMap::new(),
+            flow: None,
+            history_oldest: HashMap::new(),
+            log_target,
             own_id: id,
-            own_channel_id: u64::from(id) << 32 | 0x8,
+            clock: Box::new(SystemClock),
+            own_channel_id: personal_channel_id(id),
         }
     }
-}
 
-impl ChatClientInternal {
-    fn msg_srvdistributemessage(&self, events: &mut Vec<ChatClientEvent>, msg: &MessageData) {
+    // Replaces the system clock, e.g. for frontends that simulate time
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    // Milliseconds since the Unix epoch
+    fn wall_now(&self) -> u64 {
+        self.clock.wall_ms()
+    }
+
+    // Kind of a channel as the server listed it, or as implied by its ID otherwise
+    fn channel_kind(&self, channel_id: u64) -> Option<ChannelKind> {
+        self.channels_list
+            .iter()
+            .find(|chan| chan.channel_id == channel_id)
+            .map(|chan| chan.channel_kind)
+            .or_else(|| kind_from_id(channel_id))
+    }
+
+    fn server_display_name(&self, id: NodeId) -> String {
+        match self
+            .discovered_servers
+            .get(&id)
+            .and_then(|srv| srv.name.as_ref())
+        {
+            Some(name) => format!("{name} ({id})"),
+            None => id.to_string(),
+        }
+    }
+
+    // A discovered server given by its id or its advertised name
+    pub(crate) fn resolve_server(&self, arg: &str) -> Option<NodeId> {
+        self.discovered_servers
+            .iter()
+            .find(|(id, srv)| id.to_string() == arg || srv.name.as_deref() == Some(arg))
+            .map(|(id, _)| *id)
+    }
+
+    // Drives every time-based behavior of the client. Called on every tick, and also
+    // before handling messages and commands so that nothing is handled on stale timers
+    pub(crate) fn poll_timers(
+        &mut self,
+        now: Instant,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ChatClientEvent>) {
+        let (mut replies, mut events) = self.poll_discovery_timeouts(now);
+        replies.extend(self.poll_typing_timeout(now));
+        replies.extend(self.poll_delivery_timeouts(&mut events, now));
+        self.poll_request_timeouts(&mut events, now);
+        self.poll_slow_mode(&mut events, now);
+        replies.extend(self.poll_file_transfers(&mut events, now));
+        replies.extend(self.poll_server_silence(&mut events, now));
+        (replies, events)
+    }
+
+    fn set_connection_state(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        server: NodeId,
+        state: ConnectionState,
+    ) {
+        if self.connection_state != state {
+            self.connection_state = state;
+            events.push(ChatClientEvent::ConnectionStateChanged { server, state });
+        }
+    }
+
+    fn msg_srvconfirmreg(
+        &mut self,
+        events: &mut Vec<ChatClientEvent>,
+        sender_id: NodeId,
+        reg: ConfirmRegistration,
+    ) {
+        match (self.currently_connected_server, reg.successful) {
+            // Already registered there, so this confirms a /nick
+            (Some(server_id), true)
+                if sender_id == server_id && self.server_usernames.contains_key(&server_id) =>
+            {
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] You are now known as @{}",
+                    reg.username
+                )));
+                self.server_usernames.insert(server_id, reg.username);
+            }
+            (Some(server_id), true) if sender_id == server_id => {
+                self.username_suggestions.clear();
+                self.server_usernames
+                    .insert(server_id, reg.username.clone());
+                self.set_connection_state(events, server_id, ConnectionState::Registered);
+                events.push(ChatClientEvent::RegistrationSucceeded {
+                    server: server_id,
+                    username: reg.username,
+                });
+            }
+            (Some(_), true) => {
+                events.push(ChatClientEvent::MessageReceived(
+                    "[SYSTEM] Error: Received registration confirmation from another server"
+                        .to_string(),
+                ));
+            }
+            (Some(_), false) => {
+                let reason = reg.error.unwrap_or_else(|| "Unknown error".to_string());
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Registration failed - {reason}"
+                )));
+                if !reg.suggestions.is_empty() {
+                    events.push(ChatClientEvent::MessageReceived(format!(
+                        "[SYSTEM] Available usernames: {}. Use /register --suggested <n> to pick one.",
+                        reg.suggestions
+                            .iter()
+                            .enumerate()
+                            .map(|(i, name)| format!("{}) {name}", i + 1))
+                            .join(", ")
+                    )));
+                }
+                self.username_suggestions = reg.suggestions;
+                events.push(ChatClientEvent::RegistrationFailed {
+                    server: sender_id,
+                    reason,
+                });
+            }
+            (None, _) => {
+                let reason = reg.error.unwrap_or_else(|| "Unknown error".to_string());
+                events.push(ChatClientEvent::MessageReceived(format!(
+                    "[SYSTEM] Error: Registration failed, not connected to server - {reason}"
+                )));
+                events.push(ChatClientEvent::RegistrationFailed {
+                    server: sender_id,
+                    reason,
+                });
+            }
+        }
+    }
+
+    fn msg_srvdistributemessage(&mut self, events: &mut Vec<ChatClientEvent>, msg: &MessageData) {
+        if msg.username == SYSTEM_USERNAME {
+            let channel_name = self
+                .channels_list
+                .iter()
+                .find(|chan| chan.channel_id == msg.channel_id)
+                .map_or_else(
+                    || msg.channel_id.to_string(),
+                    |chan| chan.channel_name.clone(),
+                );
+            events.push(ChatClientEvent::MessageReceived(format!(
+                "[#{channel_name}] *** {}",
+                msg.message
+            )));
+            return;
+        }
+        let verified = self.verify_incoming(msg);
+        let msg = &self.open_incoming(msg);
+        self.remember_recent(msg);
+        self.check_watch_words(events, msg);
+        self.check_mention(events, msg);
+        let username = self.format_username(&msg.username, msg.color);
+        let text = match &msg.content_warning {
+            Some(label) => {
+                events.push(ChatClientEvent::SpoilerReceived {
+                    channel: msg.channel_id,
+                    message_id: msg.message_id,
+                    label: label.clone(),
+                    text: msg.message.clone(),
+                });
+                self.remember_hidden(msg);
+                format!("[CW: {label}] (hidden — /reveal {})", msg.message_id)
+            }
+            None => {
+                let masked = self.mask_words(&msg.message);
+                let text = if self.config.expand_incoming_emoji {
+                    self.expand_emoji(&masked)
+                } else {
+                    masked.clone()
+                };
+                if masked == msg.message {
+                    text
+                } else {
+                    self.remember_hidden(msg);
+                    format!("{text} (masked — /raw {})", msg.message_id)
+                }
+            }
+        };
+        let text = if verified {
+            text
+        } else {
+            format!("[UNVERIFIED] {text}")
+        };
         if msg.channel_id == self.own_channel_id
             && self.currently_connected_channel == Some(self.own_channel_id)
         {
             events.push(ChatClientEvent::MessageReceived(format!(
-                "[@{}] {}",
-                msg.username, msg.message
+                "[@{username}] {text}"
             )));
         } else {
             match self
@@ -203,27 +1027,103 @@ impl ChatClientInternal {
                 .find(|chan| chan.channel_id == msg.channel_id)
             {
                 Some(chan) => {
-                    if chan.channel_is_group {
+                    if is_shared_kind(chan.channel_kind) {
                         events.push(ChatClientEvent::MessageReceived(format!(
-                            "[#{} @{}] {}",
-                            chan.channel_name, msg.username, msg.message
+                            "[#{} @{username}] {text}",
+                            chan.channel_name
                         )));
                     } else {
                         events.push(ChatClientEvent::MessageReceived(format!(
-                            "[IM @{}] {}",
-                            msg.username, msg.message
+                            "[IM @{username}] {text}"
                         )));
                     }
                 }
                 None => {
                     events.push(ChatClientEvent::MessageReceived(format!(
-                        "[SYSTEM] Error: Received message from unknown channel\n[#{} @{}] {}",
-                        msg.channel_id, msg.username, msg.message
+                        "[SYSTEM] Error: Received message from unknown channel\n[#{} @{username}] {text}",
+                        msg.channel_id
                     )));
                 }
             }
         }
     }
+
+    fn format_username(&self, username: &str, color: u32) -> String {
+        const ANSI_COLORS: [u8; USER_COLOR_COUNT as usize] =
+            [31, 32, 33, 34, 35, 36, 91, 92, 93, 94, 95, 96];
+        if self.config.colors {
+            format!(
+                "\x1b[{}m{username}\x1b[0m",
+                ANSI_COLORS[(color % USER_COLOR_COUNT) as usize]
+            )
+        } else {
+            username.to_string()
+        }
+    }
 }
 #[allow(clippy::module_name_repetitions)]
 pub type ChatClient = PacketHandler<ChatClientCommand, ChatClientEvent, ChatClientInternal>;
+
+#[cfg(test)]
+impl DiscoveredServer {
+    // A chat server that answered discovery at `now`
+    pub(crate) fn chat_server(now: Instant) -> Self {
+        Self {
+            server_type: Some(ServerType::ChatServer),
+            name: None,
+            user_count: 0,
+            channel_count: 0,
+            capacity: None,
+            discovered_at: now,
+            rtt: None,
+            last_reply: now,
+            consecutive_failures: 0,
+            version: String::new(),
+            uptime: Duration::ZERO,
+            max_message_len: 0,
+            capabilities: vec![],
+            public_key: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ChatClientInternal {
+    // Connected to the first of `servers`, registered there as alice and in its "games"
+    // channel. The other servers were discovered too. Never writes a signing key
+    pub(crate) fn registered_in_games(config: ChatClientConfig, servers: &[NodeId]) -> Self {
+        let mut client = Self::with_config(
+            1,
+            ChatClientConfig {
+                signing_key_dir: None,
+                ..config
+            },
+        );
+        let now = client.now();
+        for id in servers {
+            client
+                .discovered_servers
+                .insert(*id, DiscoveredServer::chat_server(now));
+        }
+        let server = servers.first().copied();
+        let games = crate::protocol::group_channel_id("games", 0);
+        client.currently_connected_server = server;
+        client.currently_connected_channel = Some(games);
+        if let Some(server) = server {
+            client.server_usernames.insert(server, "alice".to_string());
+        }
+        client.channels_list = vec![Channel {
+            channel_name: "games".to_string(),
+            channel_id: games,
+            channel_is_group: true,
+            channel_kind: ChannelKind::Group,
+            messages_today: 0,
+            last_activity: 0,
+            unique_speakers: 0,
+            connected_clients: vec![],
+            creator: String::new(),
+            created_at: 0,
+        }];
+        client
+    }
+}
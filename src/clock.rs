@@ -0,0 +1,78 @@
+use std::fmt;
+
+// Time as seen by the client and server cores. Browsers have no std clock, so with the
+// wasm feature instants come from web-time, which is std's own type on other targets
+#[cfg(not(feature = "wasm"))]
+pub use std::time::Instant;
+#[cfg(feature = "wasm")]
+pub use web_time::Instant;
+
+// Where a core reads the current time, for embedders that drive time themselves and for
+// tests that advance it by hand
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+    // Milliseconds since the Unix epoch, for times other nodes see
+    fn wall_ms(&self) -> u64;
+}
+
+impl fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+// The clocks of the system, used unless a core is given another one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_ms(&self) -> u64 {
+        chrono::Utc::now().timestamp_millis().unsigned_abs()
+    }
+}
+
+// A clock tests move forward by hand. Clones share the time
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub(crate) struct ManualClock {
+    start: Instant,
+    elapsed_ms: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    // Wall-clock time the clock starts at, in milliseconds since the Unix epoch
+    pub(crate) const WALL_START_MS: u64 = 1_700_000_000_000;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed_ms: std::sync::Arc::default(),
+        }
+    }
+
+    pub(crate) fn advance(&self, by: std::time::Duration) {
+        let ms = u64::try_from(by.as_millis()).unwrap_or(u64::MAX);
+        self.elapsed_ms
+            .fetch_add(ms, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.elapsed_ms.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + std::time::Duration::from_millis(self.elapsed_ms())
+    }
+
+    fn wall_ms(&self) -> u64 {
+        Self::WALL_START_MS + self.elapsed_ms()
+    }
+}
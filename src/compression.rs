@@ -0,0 +1,161 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{decode_channels, encode_channels, ChannelsList};
+use std::mem;
+
+// Announced in the hello and the welcome by peers that take compressed message texts and
+// channel lists. Compression is per hop: the server inflates what a client sent before
+// filtering and storing it, and deflates again for each recipient that announced it.
+// Nothing is inflated from peers that didn't announce it
+pub const COMPRESSION_CAPABILITY: &str = "deflate";
+
+// Marks a text as deflated and base64 encoded
+const COMPRESSED_PREFIX: &str = "deflate:";
+
+// Shorter texts take about as many fragments either way
+const COMPRESSION_THRESHOLD: usize = 512;
+
+// Payloads never inflate to more than this, whatever a peer sends
+const MAX_INFLATED_LEN: usize = 1 << 20;
+
+fn deflate(bytes: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(bytes, 6)
+}
+
+fn inflate(bytes: &[u8]) -> Option<Vec<u8>> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(bytes, MAX_INFLATED_LEN).ok()
+}
+
+// The compressed form of a long text, None when it isn't worth it. Texts that look
+// compressed already are always compressed, so that they reach the other side unchanged
+#[must_use]
+pub fn compress_text(text: &str) -> Option<String> {
+    let lookalike = text.starts_with(COMPRESSED_PREFIX);
+    if text.len() < COMPRESSION_THRESHOLD && !lookalike {
+        return None;
+    }
+    let compressed = format!(
+        "{COMPRESSED_PREFIX}{}",
+        STANDARD.encode(deflate(text.as_bytes()))
+    );
+    (lookalike || compressed.len() < text.len()).then_some(compressed)
+}
+
+// The original of a compressed text, None if `text` isn't one or is corrupt
+#[must_use]
+pub fn decompress_text(text: &str) -> Option<String> {
+    let encoded = text.strip_prefix(COMPRESSED_PREFIX)?;
+    let deflated = STANDARD.decode(encoded).ok()?;
+    String::from_utf8(inflate(&deflated)?).ok()
+}
+
+fn compress_in_place(text: &mut String) {
+    if let Some(compressed) = compress_text(text) {
+        *text = compressed;
+    }
+}
+
+fn decompress_in_place(text: &mut String) {
+    if let Some(original) = decompress_text(text) {
+        *text = original;
+    }
+}
+
+// Big channel lists go out as their deflated wire encoding instead of the channels
+fn compress_channels(list: &mut ChannelsList) {
+    let encoded = encode_channels(&list.channels);
+    if encoded.len() < COMPRESSION_THRESHOLD {
+        return;
+    }
+    let deflated = deflate(&encoded);
+    if deflated.len() < encoded.len() {
+        list.channels.clear();
+        list.compressed = deflated;
+    }
+}
+
+// A corrupt list leaves the channels empty, as if the server had none
+fn decompress_channels(list: &mut ChannelsList) {
+    if list.compressed.is_empty() {
+        return;
+    }
+    if let Some(channels) = inflate(&mem::take(&mut list.compressed))
+        .as_deref()
+        .and_then(decode_channels)
+    {
+        list.channels = channels;
+    }
+}
+
+// Compresses the texts of the messages that carry chat messages and channel lists, the
+// bulk of what goes over the network
+pub(crate) fn compress_message(kind: &mut MessageKind) {
+    match kind {
+        MessageKind::SrvReturnChannels(list) => compress_channels(list),
+        MessageKind::SendMsg(msg) => compress_in_place(&mut msg.message),
+        MessageKind::SrvDistributeMessage(msg) => compress_in_place(&mut msg.message),
+        MessageKind::SrvHistory(page) => {
+            for msg in &mut page.messages {
+                compress_in_place(&mut msg.message);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn decompress_message(kind: &mut MessageKind) {
+    match kind {
+        MessageKind::SrvReturnChannels(list) => decompress_channels(list),
+        MessageKind::SendMsg(msg) => decompress_in_place(&mut msg.message),
+        MessageKind::SrvDistributeMessage(msg) => decompress_in_place(&mut msg.message),
+        MessageKind::SrvHistory(page) => {
+            for msg in &mut page.messages {
+                decompress_in_place(&mut msg.message);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_text_round_trips_compressed() {
+        let text = "all work and no play makes jack a dull boy ".repeat(40);
+        let compressed = compress_text(&text).expect("long repetitive text compresses");
+        assert!(compressed.starts_with(COMPRESSED_PREFIX));
+        assert!(compressed.len() < text.len());
+        assert_eq!(decompress_text(&compressed).as_deref(), Some(text.as_str()));
+    }
+
+    #[test]
+    fn short_text_is_left_alone() {
+        assert_eq!(compress_text("hello"), None);
+        assert_eq!(decompress_text("hello"), None);
+    }
+
+    #[test]
+    fn lookalike_text_reaches_the_other_side_unchanged() {
+        let text = "deflate:not really";
+        let compressed = compress_text(text).expect("lookalikes are always compressed");
+        assert_eq!(decompress_text(&compressed).as_deref(), Some(text));
+    }
+
+    #[test]
+    fn corrupt_text_does_not_inflate() {
+        assert_eq!(decompress_text("deflate:!!!"), None);
+        assert_eq!(decompress_text("deflate:aGVsbG8="), None);
+    }
+
+    #[test]
+    fn texts_only_inflate_up_to_the_limit() {
+        let bomb = format!(
+            "{COMPRESSED_PREFIX}{}",
+            STANDARD.encode(deflate(&vec![b'a'; MAX_INFLATED_LEN + 1]))
+        );
+        assert_eq!(decompress_text(&bomb), None);
+    }
+}
@@ -1,3 +1,8 @@
 #![allow(dead_code)]
 pub mod client;
+pub mod clock;
+pub mod compression;
+pub mod logging;
+pub mod protocol;
+pub mod secret;
 pub mod server;
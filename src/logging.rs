@@ -0,0 +1,105 @@
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+// Log levels of single nodes, changed at runtime through the controller, and the level
+// of nodes without one. Every node logs under its own target, so the filter can show one
+// node at trace level while the rest of the simulation stays quiet
+#[derive(Debug)]
+struct Levels {
+    default: LevelFilter,
+    nodes: BTreeMap<String, LevelFilter>,
+}
+
+impl Levels {
+    // Records above the global maximum never reach the logger, so it follows the most
+    // verbose level in use, and goes back down when that one is dropped
+    fn max(&self) -> LevelFilter {
+        self.nodes
+            .values()
+            .copied()
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+// Levels of the `NodeLogFilter` installed as the logger, so that nodes can reach them.
+// A process has a single logger, so there is at most one
+static INSTALLED: OnceLock<Arc<RwLock<Levels>>> = OnceLock::new();
+
+// Returns false when no `NodeLogFilter` is installed, levels are then up to the host's
+// own logger and nothing changes
+pub fn set_node_level(target: &str, level: LevelFilter) -> bool {
+    update_levels(|levels| {
+        levels.nodes.insert(target.to_string(), level);
+    })
+}
+
+// Puts a node back at the default level
+pub fn clear_node_level(target: &str) -> bool {
+    update_levels(|levels| {
+        levels.nodes.remove(target);
+    })
+}
+
+fn update_levels(change: impl FnOnce(&mut Levels)) -> bool {
+    let Some(Ok(mut levels)) = INSTALLED.get().map(|x| x.write()) else {
+        return false;
+    };
+    change(&mut levels);
+    log::set_max_level(levels.max());
+    true
+}
+
+#[must_use]
+pub fn node_level(target: &str) -> Option<LevelFilter> {
+    INSTALLED.get()?.read().ok()?.nodes.get(target).copied()
+}
+
+// Passes on the records of each node up to its own level, and those of nodes without
+// one up to the default
+pub struct NodeLogFilter<L> {
+    inner: L,
+    levels: Arc<RwLock<Levels>>,
+}
+
+impl<L: Log + 'static> NodeLogFilter<L> {
+    // Installs `inner` behind the filter as the logger of the process
+    pub fn install(inner: L, default: LevelFilter) -> Result<(), SetLoggerError> {
+        let levels = Arc::new(RwLock::new(Levels {
+            default,
+            nodes: BTreeMap::new(),
+        }));
+        log::set_logger(Box::leak(Box::new(Self {
+            inner,
+            levels: Arc::clone(&levels),
+        })))?;
+        // Can't be set already, there was no logger
+        let _ = INSTALLED.set(levels);
+        log::set_max_level(default);
+        Ok(())
+    }
+}
+
+impl<L: Log> Log for NodeLogFilter<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let Ok(levels) = self.levels.read() else {
+            return false;
+        };
+        let level = levels
+            .nodes
+            .get(metadata.target())
+            .copied()
+            .unwrap_or(levels.default);
+        metadata.level() <= level && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
@@ -0,0 +1,467 @@
+use chat_common::messages::{ChannelKind, ErrorMessage};
+use common::slc_commands::{NoticeSeverity, ServerType};
+use std::collections::HashSet;
+use wg_2024::network::NodeId;
+use wg_2024::packet::{Packet, PacketType};
+
+// Version of this crate, advertised by servers during discovery
+pub const SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Whether we can talk to a peer running `version`. Releases are compatible when they share
+// the major version, or the minor one while still at 0.x. Peers that predate version
+// advertisement send an empty string and are assumed compatible
+#[must_use]
+pub fn is_compatible_version(version: &str) -> bool {
+    if version.is_empty() {
+        return true;
+    }
+    let significant = |x: &str| -> Option<(u64, u64)> {
+        let mut parts = x.split('.').map(str::parse::<u64>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        Some(if major == 0 { (0, minor) } else { (major, 0) })
+    };
+    significant(version).is_some_and(|x| Some(x) == significant(SOFTWARE_VERSION))
+}
+
+// Errors peers report in `ErrorMessage`. The name goes in `error_type` and the number in
+// `code`, peers that predate the codes only send the name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NotRegistered = 1,
+    ChannelNotExists = 2,
+    ChannelAlreadyJoined = 3,
+    ChannelCreationForbidden = 4,
+    ChannelKindMismatch = 5,
+    BannedFromChannel = 6,
+    // 7 and 8 were KICKED and REMOVED_FROM_SERVER, which have their own message kinds now
+    NotOperator = 9,
+    OperatorPresent = 10,
+    MemberActionInvalid = 11,
+    VoteKickInvalid = 12,
+    UsernameTaken = 13,
+    UsernameInvalid = 14,
+    StatusInvalid = 15,
+    ReactionInvalid = 16,
+    MessageRejected = 17,
+    MessageNotPending = 18,
+    MessageNotFound = 19,
+    KeywordInvalid = 20,
+    TooManyKeywords = 21,
+    GuestsReadOnly = 22,
+    RateLimited = 23,
+    BandwidthExceeded = 24,
+    IncompatibleVersion = 25,
+    IdentityMismatch = 26,
+    InvalidCliMessage = 27,
+    InvalidSrvMessage = 28,
+    NotInvited = 29,
+    ChannelReadOnly = 30,
+    SlowMode = 31,
+    MessageTooLong = 32,
+    NoSuchUser = 33,
+    HandshakeRequired = 34,
+    KickCooldown = 35,
+}
+
+const ERROR_NAMES: &[(ErrorCode, &str)] = &[
+    (ErrorCode::NotRegistered, "NOT_REGISTERED"),
+    (ErrorCode::ChannelNotExists, "CHANNEL_NOT_EXISTS"),
+    (ErrorCode::ChannelAlreadyJoined, "CHANNEL_ALREADY_JOINED"),
+    (
+        ErrorCode::ChannelCreationForbidden,
+        "CHANNEL_CREATION_FORBIDDEN",
+    ),
+    (ErrorCode::ChannelKindMismatch, "CHANNEL_KIND_MISMATCH"),
+    (ErrorCode::BannedFromChannel, "BANNED_FROM_CHANNEL"),
+    (ErrorCode::NotOperator, "NOT_OPERATOR"),
+    (ErrorCode::OperatorPresent, "OPERATOR_PRESENT"),
+    (ErrorCode::MemberActionInvalid, "MEMBER_ACTION_INVALID"),
+    (ErrorCode::VoteKickInvalid, "VOTE_KICK_INVALID"),
+    (ErrorCode::UsernameTaken, "USERNAME_TAKEN"),
+    (ErrorCode::UsernameInvalid, "USERNAME_INVALID"),
+    (ErrorCode::StatusInvalid, "STATUS_INVALID"),
+    (ErrorCode::ReactionInvalid, "REACTION_INVALID"),
+    (ErrorCode::MessageRejected, "MESSAGE_REJECTED"),
+    (ErrorCode::MessageNotPending, "MESSAGE_NOT_PENDING"),
+    (ErrorCode::MessageNotFound, "MESSAGE_NOT_FOUND"),
+    (ErrorCode::KeywordInvalid, "KEYWORD_INVALID"),
+    (ErrorCode::TooManyKeywords, "TOO_MANY_KEYWORDS"),
+    (ErrorCode::GuestsReadOnly, "GUESTS_READ_ONLY"),
+    (ErrorCode::RateLimited, "RATE_LIMITED"),
+    (ErrorCode::BandwidthExceeded, "BANDWIDTH_EXCEEDED"),
+    (ErrorCode::IncompatibleVersion, "INCOMPATIBLE_VERSION"),
+    (ErrorCode::IdentityMismatch, "IDENTITY_MISMATCH"),
+    (ErrorCode::InvalidCliMessage, "INVALID_CLI_MESSAGE"),
+    (ErrorCode::InvalidSrvMessage, "INVALID_SRV_MESSAGE"),
+    (ErrorCode::NotInvited, "NOT_INVITED"),
+    (ErrorCode::ChannelReadOnly, "CHANNEL_READ_ONLY"),
+    (ErrorCode::SlowMode, "SLOW_MODE"),
+    (ErrorCode::MessageTooLong, "MESSAGE_TOO_LONG"),
+    (ErrorCode::NoSuchUser, "NO_SUCH_USER"),
+    (ErrorCode::HandshakeRequired, "HANDSHAKE_REQUIRED"),
+    (ErrorCode::KickCooldown, "KICK_COOLDOWN"),
+];
+
+impl ErrorCode {
+    #[must_use]
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        ERROR_NAMES
+            .iter()
+            .find(|(code, _)| *code == self)
+            .map_or("", |(_, name)| name)
+    }
+
+    // The error an `ErrorMessage` reports, None for errors this version doesn't know
+    #[must_use]
+    pub fn of(err: &ErrorMessage) -> Option<Self> {
+        ERROR_NAMES
+            .iter()
+            .find(|(code, name)| {
+                if err.code == 0 {
+                    *name == err.error_type
+                } else {
+                    code.code() == err.code
+                }
+            })
+            .map(|(code, _)| *code)
+    }
+
+    #[must_use]
+    pub fn message(self, text: &str) -> ErrorMessage {
+        ErrorMessage {
+            error_type: self.name().to_string(),
+            error_message: text.to_string(),
+            code: self.code(),
+            retry_after_ms: 0,
+            channel_id: 0,
+        }
+    }
+}
+
+// Standing of a member in a group channel, sent in `ClientData::role`. Sorts owners
+// first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Owner,
+    Operator,
+    Member,
+}
+
+impl Role {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Operator => "operator",
+            Self::Member => "member",
+        }
+    }
+
+    // None for roles this version doesn't know, and from servers that predate roles
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "owner" => Some(Self::Owner),
+            "operator" => Some(Self::Operator),
+            "member" => Some(Self::Member),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_operator(self) -> bool {
+        self != Self::Member
+    }
+}
+
+// What became of a chat message, as told to its sender in `SendReceipt::status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptStatus {
+    Delivered,
+    // Held for approval, `ModerationStatus` messages tell the rest
+    Pending,
+    Rejected,
+}
+
+impl ReceiptStatus {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Delivered => "DELIVERED",
+            Self::Pending => "PENDING",
+            Self::Rejected => "REJECTED",
+        }
+    }
+
+    // None for statuses this version doesn't know
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "DELIVERED" => Some(Self::Delivered),
+            "PENDING" => Some(Self::Pending),
+            "REJECTED" => Some(Self::Rejected),
+            _ => None,
+        }
+    }
+}
+
+// What became of a message held for approval, as told to its author in
+// `ModerationStatus::status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationState {
+    Pending,
+    Approved,
+    Rejected,
+    // No operator decided on it in time
+    Expired,
+}
+
+impl ModerationState {
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pending => "PENDING",
+            Self::Approved => "APPROVED",
+            Self::Rejected => "REJECTED",
+            Self::Expired => "EXPIRED",
+        }
+    }
+
+    // None for states this version doesn't know
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "PENDING" => Some(Self::Pending),
+            "APPROVED" => Some(Self::Approved),
+            "REJECTED" => Some(Self::Rejected),
+            "EXPIRED" => Some(Self::Expired),
+            _ => None,
+        }
+    }
+}
+
+// How `SystemNotice::severity` is spelled on the wire
+#[must_use]
+pub fn severity_name(severity: NoticeSeverity) -> &'static str {
+    match severity {
+        NoticeSeverity::Info => "info",
+        NoticeSeverity::Warning => "warning",
+        NoticeSeverity::Critical => "critical",
+    }
+}
+
+// Severities this version doesn't know, and notices from servers that predate them, are
+// taken as plain information
+#[must_use]
+pub fn parse_severity(name: &str) -> NoticeSeverity {
+    match name {
+        "warning" => NoticeSeverity::Warning,
+        "critical" => NoticeSeverity::Critical,
+        _ => NoticeSeverity::Info,
+    }
+}
+
+// How `DiscoveryResponse::server_type` is spelled on the wire
+#[must_use]
+pub fn server_type_name(typ: ServerType) -> &'static str {
+    match typ {
+        ServerType::ChatServer => "chat",
+        ServerType::MediaServer => "media",
+        ServerType::TextServer => "text",
+    }
+}
+
+// Server type asked for in a `DsvReq` by clients that want every server to answer
+pub const DISCOVERY_ANY_TYPE: &str = "*";
+
+// Whether a server of type `typ` answers a `DsvReq` asking for `requested`. Clients that
+// predate the wildcard ask for "chat", and an empty request is taken as any type
+#[must_use]
+pub fn discovery_matches(requested: &str, typ: ServerType) -> bool {
+    requested.is_empty() || requested == DISCOVERY_ANY_TYPE || requested == server_type_name(typ)
+}
+
+// None for server types this version doesn't know
+#[must_use]
+pub fn parse_server_type(name: &str) -> Option<ServerType> {
+    match name {
+        "chat" => Some(ServerType::ChatServer),
+        "media" => Some(ServerType::MediaServer),
+        "text" => Some(ServerType::TextServer),
+        _ => None,
+    }
+}
+
+// What a server signs with its identity key in the welcome, proving to the client that
+// sent `challenge` in its hello that it owns the key it advertises during discovery
+#[must_use]
+pub fn identity_proof(challenge: &[u8], server_id: NodeId) -> Vec<u8> {
+    let mut bytes = b"chat-server-identity".to_vec();
+    bytes.extend_from_slice(challenge);
+    bytes.push(server_id);
+    bytes
+}
+
+// Reserved author of server-generated messages inside channels
+pub const SYSTEM_USERNAME: &str = "SYSTEM";
+
+// Number of distinct display colors the server hands out to users
+pub const USER_COLOR_COUNT: u32 = 12;
+
+// Stable color index for a username, the same on every server and across restarts
+#[must_use]
+pub fn user_color(username: &str) -> u32 {
+    // FNV-1a, so the result doesn't depend on the std hasher's random seed
+    let hash = username.bytes().fold(0x811c_9dc5_u32, |acc, b| {
+        (acc ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    hash % USER_COLOR_COUNT
+}
+
+// Channel IDs still carry their kind in the low 4 bits, so that peers which don't send
+// an explicit `ChannelKind` yet keep working. New code should only go through these helpers
+const KIND_MASK: u64 = 0xF;
+const ALL_KIND_BITS: u64 = 0x1;
+const GROUP_KIND_BITS: u64 = 0x2;
+const PERSONAL_KIND_BITS: u64 = 0x8;
+
+// The "All" channel every registered client is a member of
+pub const ALL_CHANNEL_ID: u64 = ALL_KIND_BITS;
+
+// The channel used to send private messages to a client
+#[must_use]
+pub fn personal_channel_id(node: impl Into<u64>) -> u64 {
+    node.into() << 32 | PERSONAL_KIND_BITS
+}
+
+// Form of a channel name used to tell whether two names mean the same channel
+#[must_use]
+pub fn normalize_channel_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+// Words of a message as matched against watched keywords: lowercase runs of letters
+// and digits
+#[must_use]
+pub fn message_words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// Derived from the name, so a channel keeps its ID across restarts and between servers.
+// `attempt` is bumped by the caller when the ID is already taken by another channel
+#[must_use]
+pub fn group_channel_id(name: &str, attempt: u32) -> u64 {
+    let normalized = normalize_channel_name(name);
+    let seed = normalized.bytes().chain(attempt.to_le_bytes());
+    // FNV-1a, 64 bit variant
+    let hash = seed.fold(0xcbf2_9ce4_8422_2325_u64, |acc, b| {
+        (acc ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash & !KIND_MASK | GROUP_KIND_BITS
+}
+
+// Kind implied by the legacy bit scheme
+#[must_use]
+pub fn kind_from_id(channel_id: u64) -> Option<ChannelKind> {
+    if channel_id == ALL_CHANNEL_ID {
+        return Some(ChannelKind::All);
+    }
+    match channel_id & KIND_MASK {
+        GROUP_KIND_BITS => Some(ChannelKind::Group),
+        PERSONAL_KIND_BITS => Some(ChannelKind::Personal),
+        _ => None,
+    }
+}
+
+// Kind of a channel as declared by a peer, falling back to the bit scheme when the peer
+// didn't declare one. A declared kind that contradicts the ID is rejected
+#[must_use]
+pub fn resolve_channel_kind(channel_id: u64, declared: Option<ChannelKind>) -> Option<ChannelKind> {
+    let implied = kind_from_id(channel_id)?;
+    declared.map_or(Some(implied), |kind| Some(kind).filter(|x| *x == implied))
+}
+
+// Whether messages in channels of this kind are shared among several members
+#[must_use]
+pub fn is_shared_kind(kind: ChannelKind) -> bool {
+    matches!(kind, ChannelKind::All | ChannelKind::Group)
+}
+
+// Hops taken by a packet carrying chat traffic, as written in its routing header.
+// Acks, nacks and flood packets aren't chat traffic and have no route worth reporting
+#[must_use]
+pub fn chat_route(packet: &Packet) -> Option<Vec<NodeId>> {
+    match packet.pack_type {
+        PacketType::MsgFragment(_) => Some(packet.routing_header.hops.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_channel_ids_are_derived_from_normalized_names() {
+        let id = group_channel_id("Games", 0);
+        assert_eq!(kind_from_id(id), Some(ChannelKind::Group));
+        assert_eq!(id, group_channel_id("  games ", 0));
+        assert_ne!(id, group_channel_id("games", 1));
+        assert_ne!(id, group_channel_id("music", 0));
+        assert_ne!(id, ALL_CHANNEL_ID);
+        assert_eq!(
+            resolve_channel_kind(id, Some(ChannelKind::Group)),
+            Some(ChannelKind::Group)
+        );
+    }
+
+    #[test]
+    fn personal_channel_ids_carry_the_node() {
+        let id = personal_channel_id(7u8);
+        assert_eq!(kind_from_id(id), Some(ChannelKind::Personal));
+        assert_eq!(id >> 32, 7);
+        assert_ne!(id, personal_channel_id(8u8));
+        assert_eq!(kind_from_id(ALL_CHANNEL_ID), Some(ChannelKind::All));
+    }
+
+    #[test]
+    fn declared_kinds_must_match_the_id() {
+        let id = personal_channel_id(7u8);
+        assert_eq!(resolve_channel_kind(id, None), Some(ChannelKind::Personal));
+        assert_eq!(
+            resolve_channel_kind(id, Some(ChannelKind::Personal)),
+            Some(ChannelKind::Personal)
+        );
+        assert_eq!(resolve_channel_kind(id, Some(ChannelKind::Group)), None);
+        assert_eq!(resolve_channel_kind(0x4, None), None);
+    }
+
+    #[test]
+    fn discovery_wildcard_matches_every_server_type() {
+        for typ in [
+            ServerType::ChatServer,
+            ServerType::MediaServer,
+            ServerType::TextServer,
+        ] {
+            assert!(discovery_matches(DISCOVERY_ANY_TYPE, typ));
+            assert!(discovery_matches("", typ));
+            assert!(discovery_matches(server_type_name(typ), typ));
+        }
+    }
+
+    #[test]
+    fn discovery_of_another_type_does_not_match() {
+        assert!(!discovery_matches("media", ServerType::ChatServer));
+        assert!(!discovery_matches("chat", ServerType::TextServer));
+        assert!(!discovery_matches("**", ServerType::ChatServer));
+    }
+}
@@ -1,25 +1,379 @@
+mod server_admin;
+mod server_announcements;
+mod server_bandwidth;
+mod server_bans;
+mod server_channel_delta;
+mod server_channel_merge;
+mod server_compression;
+mod server_federation;
+mod server_file_transfer;
+mod server_filters;
+mod server_heartbeat;
+mod server_invites;
+mod server_keywords;
 mod server_message_handling;
+mod server_migration;
+mod server_missed_activity;
+mod server_moderation;
+mod server_offline;
+mod server_presence;
+mod server_rate_limit;
+mod server_reactions;
+mod server_retransmits;
+mod server_roles;
+mod server_sessions;
+mod server_slow_mode;
+mod server_state;
+mod server_stats;
+mod server_storage;
+mod server_vote_kick;
 
+pub use server_announcements::Announcement;
+pub use server_filters::{FilterDecision, MessageFilter};
+pub use server_state::{ChannelState, DepartureState, ServerState, UserState};
+
+use crate::clock::{Clock, Instant, SystemClock};
+use crate::compression::decompress_message;
+use crate::logging::set_node_level;
+use crate::protocol::{
+    chat_route, discovery_matches, group_channel_id, is_shared_kind, server_type_name, user_color,
+    ErrorCode, ReceiptStatus, Role, ALL_CHANNEL_ID, SOFTWARE_VERSION, SYSTEM_USERNAME,
+};
+use crate::secret::SecretKey;
 use bimap::BiHashMap;
 use chat_common::messages::chat_message::MessageKind;
 use chat_common::messages::{
-    Channel, ChannelsList, ChatMessage, ClientData, DiscoveryResponse, ErrorMessage,
+    Channel, ChannelKind, ChatMessage, ClientData, DiscoveryResponse, FlowCredit, MessageData,
+    SetStatus,
 };
-use chat_common::packet_handling::{CommandHandler, PacketHandler};
-use common::slc_commands::{ServerCommand, ServerEvent};
-use crossbeam::channel::Sender;
-use log::{debug, error, info, trace};
+use chat_common::packet_handling::{CommandHandler, PacketHandler, Sender};
+use common::slc_commands::{ServerCommand, ServerEvent, ServerType};
+use ed25519_dalek::SigningKey;
+use log::{debug, error, info, trace, warn};
 use map_macro::hash_map;
-use std::collections::{HashMap, HashSet};
+use rand::{rng, RngCore};
+use server_bandwidth::BandwidthUsage;
+use server_channel_delta::{channel_delta, is_empty_delta};
+use server_filters::Filters;
+use server_rate_limit::TokenBucket;
+use server_sessions::{needs_session, Session, SERVER_CAPABILITIES};
+use server_stats::ServerCounters;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 use wg_2024::network::NodeId;
 use wg_2024::packet::{NodeType, Packet};
 
+// Who may create new group channels by joining them
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChannelCreationPolicy {
+    #[default]
+    Anyone,
+    // Users registered for at least this long
+    RegisteredFor(Duration),
+    // Users with one of these usernames
+    AllowList(HashSet<String>),
+    // Only the controller, through ServerCommand::CreateChannel
+    Nobody,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChatServerConfig {
+    // Display name advertised to clients during discovery
+    pub name: Option<String>,
+    // Maximum number of registered users, None means unlimited
+    pub capacity: Option<u32>,
+    // Messages kept per channel for history requests
+    pub history_limit: usize,
+    // Maximum number of messages returned by a single history request
+    pub history_page_size: u32,
+    // Key used to encrypt persisted history and state, None stores them in plaintext.
+    // Never serialized, so it has to be given again on every start
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub state_key: Option<SecretKey>,
+    // Bytes of messages a client may have relayed per interval, None means unlimited
+    pub bandwidth_budget: Option<u64>,
+    pub bandwidth_interval: Duration,
+    // Messages and joins a client may send in a burst, None means unlimited
+    pub rate_limit_burst: Option<u32>,
+    // Messages and joins per second a client earns back after a burst
+    pub rate_limit_refill: u32,
+    // Chat messages a client may have sent without an acknowledgement
+    pub send_window: u32,
+    // Log target of this server, "Server <id>" by default
+    pub log_target: Option<String>,
+    pub channel_creation: ChannelCreationPolicy,
+    // Posted in "All" on a schedule, also managed through ServerCommand
+    pub announcements: Vec<Announcement>,
+    // Votes of distinct members needed to kick someone from a channel with no active operator
+    pub vote_kick_threshold: usize,
+    // Votes older than this don't count
+    pub vote_kick_window: Duration,
+    // How long a client kicked from a channel, by vote or by an operator, can't join it again
+    pub kick_cooldown: Duration,
+    // Secret half of the ed25519 key advertised during discovery, which clients pin once
+    // the server proved it owns it in the welcome. A random one is picked when None, so
+    // set it, or keep the state file, to keep the identity of the server across restarts
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub identity_key: Option<SecretKey>,
+    // Message of the day, sent to every client in the handshake and again as a notice
+    // once it registers. ServerCommand::SetMotd changes it at runtime
+    pub motd: Option<String>,
+    // How often registered clients and guests are pinged, None (the default) never pings
+    // them nor drops silent ones. Clients reconnecting on silence need it set
+    pub heartbeat_interval: Option<Duration>,
+    // Pings in a row a client may leave unanswered before it is dropped
+    pub heartbeat_misses: u32,
+    // Least time between two saves of the state file, zero saves on every tick after a
+    // change. Only used with the persistence feature
+    pub store_interval: Duration,
+    // Direct messages kept per client while it can't be reached
+    pub offline_queue_limit: usize,
+    // Longest chat message accepted, in bytes. Advertised during discovery so that clients
+    // split longer ones
+    pub max_message_len: usize,
+}
+
+impl Default for ChatServerConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            capacity: None,
+            history_limit: 500,
+            history_page_size: 50,
+            state_key: None,
+            bandwidth_budget: None,
+            bandwidth_interval: Duration::from_secs(10),
+            rate_limit_burst: None,
+            rate_limit_refill: 2,
+            send_window: 16,
+            log_target: None,
+            channel_creation: ChannelCreationPolicy::default(),
+            announcements: vec![],
+            vote_kick_threshold: 3,
+            vote_kick_window: Duration::from_secs(60),
+            kick_cooldown: Duration::from_secs(300),
+            identity_key: None,
+            motd: None,
+            heartbeat_interval: None,
+            heartbeat_misses: 3,
+            store_interval: Duration::from_secs(5),
+            offline_queue_limit: 100,
+            max_message_len: 2000,
+        }
+    }
+}
+
+// How many alternatives are offered when a username is already taken
+const USERNAME_SUGGESTIONS: usize = 3;
+
+// Clients that haven't sent anything for this long are shown as idle
+const IDLE_AFTER_MS: u64 = 5 * 60 * 1000;
+
+#[derive(Debug, Default)]
+struct ChannelStats {
+    // Day (in days since the epoch) that `messages_today` refers to
+    day: u64,
+    messages_today: u64,
+    last_activity: u64,
+    speakers: HashSet<NodeId>,
+}
+
+impl ChannelStats {
+    fn record_message(&mut self, timestamp: u64, sender: NodeId) {
+        let day = timestamp / 86_400_000;
+        if day != self.day {
+            self.day = day;
+            self.messages_today = 0;
+        }
+        self.messages_today += 1;
+        self.last_activity = timestamp;
+        self.speakers.insert(sender);
+    }
+}
+
+#[derive(Debug)]
+struct ChannelInfo {
+    kind: ChannelKind,
+    members: HashSet<NodeId>,
+    stats: ChannelStats,
+    // Sent privately to every client right after it joins
+    welcome: Option<String>,
+    // Most recent messages, oldest first
+    history: VecDeque<MessageData>,
+    // Operators besides the creator
+    operators: HashSet<NodeId>,
+    // Client that created the channel, None for channels made by the server
+    creator: Option<NodeId>,
+    // Milliseconds since the epoch, 0 if unknown
+    created_at: u64,
+    // Whether messages of non-operators wait for approval
+    moderated: bool,
+    // Messages awaiting approval with their author, by message id
+    pending: BTreeMap<u64, (NodeId, MessageData)>,
+    // Votes to kick each target: voter and when it voted, in milliseconds since the epoch
+    kick_votes: HashMap<NodeId, HashMap<NodeId, u64>>,
+    // When clients were kicked, in milliseconds since the epoch, until their cooldown is over
+    kicked: HashMap<NodeId, u64>,
+    // Clients an operator banned, they can't join again
+    banned: HashSet<NodeId>,
+    // Whether only operators can send messages
+    read_only: bool,
+    // Seconds members have to wait between two messages, 0 when off
+    slow_mode: u64,
+    // When each member last sent a message, in milliseconds since the epoch
+    last_post: HashMap<NodeId, u64>,
+    // Whether only operators and invited clients can join
+    invite_only: bool,
+    // Clients invited since they last joined
+    invited: HashSet<NodeId>,
+    // Who reacted with what to messages still in the history, by message id
+    reactions: HashMap<u64, BTreeMap<String, HashSet<NodeId>>>,
+}
+
+impl ChannelInfo {
+    fn new(kind: ChannelKind, members: HashSet<NodeId>) -> Self {
+        Self {
+            kind,
+            members,
+            stats: ChannelStats::default(),
+            welcome: None,
+            history: VecDeque::new(),
+            operators: HashSet::new(),
+            creator: None,
+            created_at: 0,
+            moderated: false,
+            pending: BTreeMap::new(),
+            kick_votes: HashMap::new(),
+            kicked: HashMap::new(),
+            banned: HashSet::new(),
+            read_only: false,
+            slow_mode: 0,
+            last_post: HashMap::new(),
+            invite_only: false,
+            invited: HashSet::new(),
+            reactions: HashMap::new(),
+        }
+    }
+
+    // Adds a message to the history, forgetting the oldest ones and their reactions
+    // past `limit`
+    fn remember(&mut self, data: MessageData, limit: usize) {
+        self.history.push_back(data);
+        while self.history.len() > limit {
+            if let Some(old) = self.history.pop_front() {
+                self.reactions.remove(&old.message_id);
+            }
+        }
+    }
+
+    // The creator is an operator by default
+    fn is_operator(&self, id: NodeId) -> bool {
+        self.role(id).is_operator()
+    }
+
+    // Shown next to members in the channel list
+    fn role(&self, id: NodeId) -> Role {
+        if self.creator == Some(id) {
+            Role::Owner
+        } else if self.operators.contains(&id) {
+            Role::Operator
+        } else {
+            Role::Member
+        }
+    }
+
+    fn operator_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.creator.into_iter().chain(
+            self.operators
+                .iter()
+                .copied()
+                .filter(|id| self.creator != Some(*id)),
+        )
+    }
+
+    // Whether `id` may read the channel, the checks `msg_clijoin` makes before letting a
+    // client in
+    fn admits(&self, id: NodeId) -> bool {
+        !self.banned.contains(&id)
+            && (!self.invite_only || self.is_operator(id) || self.invited.contains(&id))
+    }
+
+    // "All" and group channels, as opposed to personal ones
+    fn is_shared(&self) -> bool {
+        is_shared_kind(self.kind)
+    }
+}
+
+#[derive(Debug)]
+struct Departure {
+    // Server timestamp at departure, messages with a greater id were missed
+    timestamp: u64,
+    channels: Vec<u64>,
+}
+
 #[derive(Debug)]
 pub struct ChatServerInternal {
+    config: ChatServerConfig,
     own_id: NodeId,
+    log_target: String,
     channels: BiHashMap<u64, String>,
-    channel_info: HashMap<u64, (bool, HashSet<NodeId>)>,
+    channel_info: HashMap<u64, ChannelInfo>,
     usernames: BiHashMap<NodeId, String>,
+    // Last timestamp handed out, used to keep MessageData timestamps strictly increasing
+    last_timestamp: u64,
+    // When each registered client or guest last sent anything, in milliseconds since the
+    // epoch. Dropped when they leave the server
+    last_seen: HashMap<NodeId, u64>,
+    // When each client registered, in milliseconds since the epoch
+    registered_at: HashMap<NodeId, u64>,
+    // Unregistered clients following channels read-only
+    guests: HashSet<NodeId>,
+    // Users that unregistered, by username, used to summarize what they missed
+    departures: HashMap<String, Departure>,
+    // Traffic relayed on behalf of each client in the current interval
+    bandwidth: HashMap<NodeId, BandwidthUsage>,
+    // Requests left to each client that recently sent any, see `rate_limit_burst`
+    rate_limits: HashMap<NodeId, TokenBucket>,
+    // Chat messages received from each registered client, acknowledged for flow control
+    acked_messages: HashMap<NodeId, u64>,
+    // Set once the server handed its state over to another node, which clients must use
+    migrated_to: Option<NodeId>,
+    started_at: Instant,
+    // When each of `config.announcements` is due next
+    announcements_due: Vec<Instant>,
+    // Signs the welcome, see `identity_key`
+    identity: SigningKey,
+    // Clients that completed the handshake
+    sessions: HashMap<NodeId, Session>,
+    // Words each client watches in channels it isn't a member of
+    keywords: HashMap<NodeId, HashSet<String>>,
+    // Random per run, so that clients notice the server restarted
+    incarnation: u64,
+    // Direct messages that couldn't be delivered, oldest first, until the client is back
+    offline_messages: HashMap<NodeId, VecDeque<MessageData>>,
+    // Registered clients that stopped answering pings. They stay registered, so that
+    // direct messages can still be sent to them, and those wait in the offline queue
+    unreachable: HashSet<NodeId>,
+    // Statuses users picked, those still "online" with no text are left out
+    statuses: HashMap<NodeId, SetStatus>,
+    filters: Filters,
+    counters: ServerCounters,
+    // None until the first tick
+    next_heartbeat: Option<Instant>,
+    // Pings each client left unanswered since it last sent anything
+    missed_heartbeats: HashMap<NodeId, u32>,
+    // Local ids of the last chat messages of each client and the status they got
+    recent_sends: HashMap<NodeId, VecDeque<(u64, ReceiptStatus)>>,
+    // Servers each group channel is mirrored with, by channel name
+    federation: HashMap<String, HashSet<NodeId>>,
+    // Federation requests we sent and that weren't answered yet
+    pending_federations: HashSet<(NodeId, String)>,
+    #[cfg(feature = "persistence")]
+    store: Option<server_storage::StateStore>,
+    clock: Box<dyn Clock>,
 }
 impl CommandHandler<ServerCommand, ServerEvent> for ChatServerInternal {
     fn get_node_type() -> NodeType {
@@ -28,69 +382,299 @@ impl CommandHandler<ServerCommand, ServerEvent> for ChatServerInternal {
     #[allow(clippy::cast_possible_truncation)]
     fn handle_protocol_message(
         &mut self,
-        message: ChatMessage,
+        mut message: ChatMessage,
+        source: NodeId,
     ) -> (Vec<(NodeId, ChatMessage)>, Vec<ServerEvent>)
     where
         Self: Sized,
     {
         let mut replies: Vec<(NodeId, ChatMessage)> = vec![];
-        #[allow(clippy::cast_possible_truncation)]
-        let cli_node_id = message.own_id as NodeId;
-        trace!(target: format!("Server {}", self.own_id).as_str(), "Current state: {self:?}");
-        info!(target: format!("Server {}", self.own_id).as_str(), "Received message: {message:?}");
+        let mut events = vec![];
+        trace!(target: self.log_target.as_str(), "Current state: {self:?}");
+        info!(target: self.log_target.as_str(), "Received message: {message:?}");
+        // Clients are known by the id they claim, which has to be the one they send from
+        if message.own_id != u32::from(source) {
+            warn!(target: self.log_target.as_str(), "Client {source} claims to be {}", message.own_id);
+            self.counters.errors_sent += 1;
+            return (
+                vec![(
+                    source,
+                    self.error_message(
+                        ErrorCode::IdentityMismatch,
+                        "The sender id of the message isn't the node it came from",
+                    ),
+                )],
+                vec![],
+            );
+        }
+        let cli_node_id = source;
+        if let Some(new_server_id) = self.migrated_to {
+            debug!(target: self.log_target.as_str(), "Redirecting client {cli_node_id} to node {new_server_id}");
+            return (
+                vec![(cli_node_id, self.migration_notice(new_server_id))],
+                vec![],
+            );
+        }
+        // Sessions don't survive restarts, clients told so greet again
+        if message.message_kind.as_ref().is_some_and(needs_session)
+            && !self.sessions.contains_key(&cli_node_id)
+        {
+            debug!(target: self.log_target.as_str(), "Refusing message from client {cli_node_id} without a session");
+            self.counters.errors_sent += 1;
+            let mut refusal =
+                self.error_message(ErrorCode::HandshakeRequired, "Send a hello first");
+            refusal.request_id = message.request_id;
+            return (vec![(cli_node_id, refusal)], vec![]);
+        }
+        if self.usernames.contains_left(&cli_node_id) || self.guests.contains(&cli_node_id) {
+            let wall_now = self.wall_now();
+            self.last_seen.insert(cli_node_id, wall_now);
+        }
+        self.record_heartbeat(cli_node_id);
+        if let Some(kind) = message
+            .message_kind
+            .as_mut()
+            .filter(|_| self.wants_compression(cli_node_id))
+        {
+            decompress_message(kind);
+        }
         if let Some(kind) = message.message_kind {
             match kind {
+                MessageKind::CliPong(..) => {
+                    trace!(target: self.log_target.as_str(), "Received pong from client {cli_node_id}");
+                }
                 MessageKind::CliRegisterRequest(req) => {
                     self.msg_cliregisterrequest(&mut replies, cli_node_id, req);
                 }
                 MessageKind::CliCancelReg(..) => self.msg_clicancelreq(&mut replies, cli_node_id),
+                MessageKind::CliChangeUsername(name) => {
+                    self.msg_clichangeusername(&mut replies, cli_node_id, name);
+                }
+                MessageKind::CliHello(hello) => self.msg_clihello(&mut replies, cli_node_id, hello),
                 MessageKind::CliRequestChannels(..) => {
-                    info!(target: format!("Server {}", self.own_id).as_str(), "Received channel request");
-                    replies.extend_from_slice(self.generate_channel_updates().as_slice());
+                    info!(target: self.log_target.as_str(), "Received channel request");
+                    let list = self.full_channel_list(cli_node_id);
+                    replies.push((cli_node_id, list));
                 }
                 MessageKind::CliJoin(data) => self.msg_clijoin(&mut replies, &data, cli_node_id),
+                MessageKind::CliSpectate(data) => {
+                    self.msg_clispectate(&mut replies, &data, cli_node_id);
+                }
                 MessageKind::CliLeave(..) => self.msg_clileave(&mut replies, cli_node_id),
-                MessageKind::SendMsg(msg) => self.msg_sendmsg(&mut replies, cli_node_id, &msg),
+                MessageKind::CliFetchHistory(req) => {
+                    self.msg_clifetchhistory(&mut replies, cli_node_id, &req);
+                }
+                MessageKind::CliSearchMessages(req) => {
+                    self.msg_clisearchmessages(&mut replies, cli_node_id, &req);
+                }
+                MessageKind::CliTyping(data) => {
+                    self.msg_clityping(&mut replies, cli_node_id, &data)
+                }
+                MessageKind::CliSetWelcome(data) => {
+                    self.msg_clisetwelcome(&mut replies, cli_node_id, data);
+                }
+                MessageKind::CliApproveMessage(decision) => {
+                    self.msg_clidecidemessage(&mut replies, cli_node_id, &decision, true);
+                }
+                MessageKind::CliRejectMessage(decision) => {
+                    self.msg_clidecidemessage(&mut replies, cli_node_id, &decision, false);
+                }
+                MessageKind::CliKick(data) => {
+                    self.msg_cliremovemember(&mut replies, cli_node_id, &data, false);
+                }
+                MessageKind::CliBan(data) => {
+                    self.msg_cliremovemember(&mut replies, cli_node_id, &data, true);
+                }
+                MessageKind::CliInvite(data) => {
+                    self.msg_cliinvite(&mut replies, cli_node_id, &data);
+                }
+                MessageKind::CliSetInviteOnly(data) => {
+                    self.msg_clisetinviteonly(&mut replies, cli_node_id, &data);
+                }
+                MessageKind::CliOp(data) => {
+                    self.msg_clisetoperator(&mut replies, cli_node_id, &data, true);
+                }
+                MessageKind::CliDeop(data) => {
+                    self.msg_clisetoperator(&mut replies, cli_node_id, &data, false);
+                }
+                MessageKind::CliDeleteChannel(channel_id) => {
+                    self.msg_clideletechannel(&mut replies, &mut events, cli_node_id, channel_id);
+                }
+                kind @ (MessageKind::CliFileOffer(..)
+                | MessageKind::CliFileAnswer(..)
+                | MessageKind::CliFileChunk(..)
+                | MessageKind::CliFileMissing(..)) => {
+                    self.msg_clifiletransfer(&mut replies, &mut events, cli_node_id, kind);
+                }
+                MessageKind::CliReact(data) => {
+                    self.msg_clireact(&mut replies, cli_node_id, &data);
+                }
+                MessageKind::CliSetStatus(data) => {
+                    self.msg_clisetstatus(&mut replies, cli_node_id, data);
+                }
+                MessageKind::CliSetModerated(data) => {
+                    self.msg_clisetmoderated(&mut replies, cli_node_id, &data);
+                }
+                MessageKind::CliSetReadOnly(data) => {
+                    self.msg_clisetreadonly(&mut replies, cli_node_id, &data);
+                }
+                MessageKind::CliSetSlowMode(data) => {
+                    self.msg_clisetslowmode(&mut replies, cli_node_id, &data);
+                }
+                MessageKind::CliSubscribeKeyword(sub) => {
+                    self.msg_clisubscribekeyword(&mut replies, cli_node_id, &sub);
+                }
+                MessageKind::CliVoteKick(data) => {
+                    self.msg_clivotekick(&mut replies, cli_node_id, &data);
+                }
+                MessageKind::SendMsg(msg) => {
+                    // A retransmission is only confirmed again, it was distributed and
+                    // acknowledged the first time
+                    if let Some(status) = self.earlier_send_status(cli_node_id, msg.local_id) {
+                        debug!(target: self.log_target.as_str(), "Message {} retransmitted by {cli_node_id}", msg.local_id);
+                        replies.push((cli_node_id, self.send_receipt(msg.local_id, status)));
+                    } else {
+                        let status = self.msg_sendmsg(&mut replies, &mut events, cli_node_id, &msg);
+                        // Clients that don't track their messages leave the local id at 0
+                        if msg.local_id != 0 {
+                            self.remember_send(cli_node_id, msg.local_id, status);
+                            replies.push((cli_node_id, self.send_receipt(msg.local_id, status)));
+                        }
+                        self.ack_chat_message(&mut replies, cli_node_id);
+                    }
+                }
+                MessageKind::SrvFederate(federate) => {
+                    self.msg_srvfederate(&mut replies, &mut events, cli_node_id, &federate);
+                }
+                MessageKind::SrvUnfederate(federate) => {
+                    self.msg_srvunfederate(&mut events, cli_node_id, &federate);
+                }
+                MessageKind::SrvFederatedMessage(msg) => {
+                    self.msg_srvfederatedmessage(&mut replies, cli_node_id, msg);
+                }
                 MessageKind::Err(e) => {
-                    error!(target: format!("Server {}", self.own_id).as_str(), "Received error message: {e:?}");
+                    error!(target: self.log_target.as_str(), "Received error message: {e:?}");
+                }
+                MessageKind::DsvReq(requested)
+                    if !discovery_matches(&requested, ServerType::ChatServer) =>
+                {
+                    debug!(target: self.log_target.as_str(), "Ignoring discovery of {requested} servers");
                 }
                 MessageKind::DsvReq(..) => {
-                    info!(target: format!("Server {}", self.own_id).as_str(), "Sending back discovery response");
+                    info!(target: self.log_target.as_str(), "Sending back discovery response");
                     replies.push((
                         message.own_id as NodeId,
                         ChatMessage {
                             own_id: u32::from(self.own_id),
+                            request_id: 0,
                             message_kind: Some(MessageKind::DsvRes(DiscoveryResponse {
                                 server_id: u32::from(self.own_id),
-                                server_type: "chat".to_string(),
+                                server_type: server_type_name(ServerType::ChatServer).to_string(),
+                                server_name: self.config.name.clone(),
+                                user_count: self.usernames.len() as u32,
+                                channel_count: self.group_channel_count() as u32,
+                                capacity: self.config.capacity,
+                                version: SOFTWARE_VERSION.to_string(),
+                                uptime_secs: self
+                                    .now()
+                                    .saturating_duration_since(self.started_at)
+                                    .as_secs(),
+                                max_message_len: u32::try_from(self.config.max_message_len)
+                                    .unwrap_or(u32::MAX),
+                                capabilities: SERVER_CAPABILITIES
+                                    .iter()
+                                    .map(ToString::to_string)
+                                    .collect(),
+                                public_key: self
+                                    .identity
+                                    .verifying_key()
+                                    .as_bytes()
+                                    .iter()
+                                    .map(|b| format!("{b:02x}"))
+                                    .collect(),
                             })),
                         },
                     ));
                 }
                 _ => {
                     replies.push((
-                        message.own_id as NodeId,
-                        ChatMessage {
-                            own_id: u32::from(self.own_id),
-                            message_kind: Some(MessageKind::Err(ErrorMessage {
-                                error_type: "INVALID_CLI_MESSAGE".to_string(),
-                                error_message: format!("Invalid message: {kind:?}"),
-                            })),
-                        },
+                        cli_node_id,
+                        self.error_message(
+                            ErrorCode::InvalidCliMessage,
+                            &format!("Invalid message: {kind:?}"),
+                        ),
                     ));
                 }
             }
         }
-        trace!(target: format!("Server {}", self.own_id).as_str(), "Current state: {self:?}");
-        info!(target: format!("Server {}", self.own_id).as_str(), "Sending back replies: {replies:?}");
-        (replies, vec![])
+        // Whatever we tell the client about its request answers it
+        if message.request_id != 0 {
+            for (_, reply) in replies.iter_mut().filter(|(id, _)| *id == cli_node_id) {
+                reply.request_id = message.request_id;
+            }
+        }
+        // It's reachable again, whatever it sent. Registering flushes too, just before
+        if self.usernames.contains_left(&cli_node_id) {
+            self.flush_offline_messages(&mut replies, cli_node_id);
+        }
+        #[cfg(feature = "persistence")]
+        self.mark_state_changed();
+        self.count_errors(&replies);
+        self.compress_replies(&mut replies);
+        trace!(target: self.log_target.as_str(), "Current state: {self:?}");
+        info!(target: self.log_target.as_str(), "Sending back replies: {replies:?}");
+        (replies, events)
     }
 
     fn report_sent_packet(&mut self, packet: Packet) -> ServerEvent
     where
         Self: Sized,
     {
-        ServerEvent::PacketSent(packet)
+        let route = chat_route(&packet);
+        ServerEvent::PacketSent { packet, route }
+    }
+
+    fn handle_tick(&mut self, now: Instant) -> (Vec<(NodeId, ChatMessage)>, Vec<ServerEvent>)
+    where
+        Self: Sized,
+    {
+        let wall_now = self.wall_now();
+        self.expire_bandwidth_windows(wall_now);
+        self.expire_rate_limits(wall_now);
+        let mut replies = vec![];
+        self.expire_held_messages(&mut replies, wall_now);
+        self.merge_duplicate_channels(&mut replies);
+        self.post_due_announcements(&mut replies, now);
+        self.poll_heartbeats(&mut replies, now);
+        #[cfg(feature = "persistence")]
+        self.save_store_if_due(now);
+        self.compress_replies(&mut replies);
+        (replies, vec![])
+    }
+
+    fn report_delivery_failure(
+        &mut self,
+        destination: NodeId,
+        mut message: ChatMessage,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ServerEvent>)
+    where
+        Self: Sized,
+    {
+        error!(target: self.log_target.as_str(), "Failed to deliver message to client {destination}: {message:?}");
+        // Queued as plain text, it's compressed again if the client still takes it
+        if let Some(kind) = message
+            .message_kind
+            .as_mut()
+            .filter(|_| self.wants_compression(destination))
+        {
+            decompress_message(kind);
+        }
+        if let Some(MessageKind::SrvDistributeMessage(data)) = message.message_kind {
+            if self.is_direct_message(&data) {
+                self.queue_offline_message(destination, data);
+            }
+        }
+        (vec![], vec![])
     }
 
     fn handle_controller_command(
@@ -101,8 +685,10 @@ impl CommandHandler<ServerCommand, ServerEvent> for ChatServerInternal {
     where
         Self: Sized,
     {
-        info!(target: format!("Server {}", self.own_id).as_str(), "Received controller command: {command:?}");
-        match command {
+        info!(target: self.log_target.as_str(), "Received controller command: {command:?}");
+        #[cfg(feature = "persistence")]
+        self.mark_state_changed();
+        let (packet, mut replies, events) = match command {
             ServerCommand::AddSender(id, sender) => {
                 sender_hash.insert(id, sender);
                 (None, vec![], vec![])
@@ -112,7 +698,87 @@ impl CommandHandler<ServerCommand, ServerEvent> for ChatServerInternal {
                 (None, vec![], vec![])
             }
             ServerCommand::Shortcut(p) => (Some(p), vec![], vec![]),
-        }
+            ServerCommand::SetLogLevel(level) => {
+                if set_node_level(&self.log_target, level) {
+                    info!(target: self.log_target.as_str(), "Log level set to {level}");
+                } else {
+                    warn!(target: self.log_target.as_str(), "Can't set the log level, NodeLogFilter isn't installed");
+                }
+                (None, vec![], vec![])
+            }
+            ServerCommand::CreateChannel(name) => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_create_channel(&mut replies, &mut events, &name);
+                (None, replies, events)
+            }
+            ServerCommand::DeleteChannel(name) => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_delete_channel(&mut replies, &mut events, &name);
+                (None, replies, events)
+            }
+            ServerCommand::SetReadOnly { channel, read_only } => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_set_read_only(&mut replies, &mut events, &channel, read_only);
+                (None, replies, events)
+            }
+            ServerCommand::PostToChannel { channel, text } => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_post(&mut replies, &mut events, &channel, &text);
+                (None, replies, events)
+            }
+            ServerCommand::Federate { peer, channel } => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_federate(&mut replies, &mut events, peer, &channel);
+                (None, replies, events)
+            }
+            ServerCommand::Unfederate { peer, channel } => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_unfederate(&mut replies, &mut events, peer, &channel);
+                (None, replies, events)
+            }
+            ServerCommand::KickClient(id) => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_kick_client(&mut replies, &mut events, id);
+                (None, replies, events)
+            }
+            ServerCommand::BroadcastNotice { severity, text } => {
+                let (mut replies, mut events) = (vec![], vec![]);
+                self.admin_broadcast(&mut replies, &mut events, severity, &text);
+                (None, replies, events)
+            }
+            ServerCommand::SetMotd(motd) => {
+                let mut events = vec![];
+                self.admin_set_motd(&mut events, motd);
+                (None, vec![], events)
+            }
+            ServerCommand::QueryStats => (None, vec![], vec![self.stats_event()]),
+            ServerCommand::ListUsers => {
+                let mut events = vec![];
+                self.admin_user_list(&mut events);
+                (None, vec![], events)
+            }
+            ServerCommand::AddAnnouncement { interval, text } => {
+                self.add_announcement(self.now(), Announcement { interval, text });
+                (None, vec![], vec![])
+            }
+            ServerCommand::RemoveAnnouncement(text) => {
+                self.remove_announcement(&text);
+                (None, vec![], vec![])
+            }
+            ServerCommand::MergeChannels { source, target } => {
+                let mut replies = vec![];
+                if !self.merge_channels(&mut replies, &source, &target) {
+                    error!(target: self.log_target.as_str(), "Can't merge channel {source} into {target}");
+                }
+                (None, replies, vec![])
+            }
+            ServerCommand::MigrateTo(new_server_id) => {
+                let (replies, events) = self.start_migration(new_server_id);
+                (None, replies, events)
+            }
+        };
+        self.compress_replies(&mut replies);
+        (packet, replies, events)
     }
 
     fn add_node(&mut self, _id: NodeId, _typ: NodeType) -> Option<(NodeId, ChatMessage)> {
@@ -123,65 +789,456 @@ impl CommandHandler<ServerCommand, ServerEvent> for ChatServerInternal {
     where
         Self: Sized,
     {
+        Self::with_config(id, ChatServerConfig::default())
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub type ChatServer = PacketHandler<ServerCommand, ServerEvent, ChatServerInternal>;
+
+impl ChatServerInternal {
+    #[must_use]
+    pub fn with_config(id: NodeId, config: ChatServerConfig) -> Self {
         let mut channels = BiHashMap::default();
-        channels.insert(0x1, "All".to_string());
-        let channel_info = hash_map! {0x1 => (true, HashSet::new())};
-        Self {
+        channels.insert(ALL_CHANNEL_ID, "All".to_string());
+        let channel_info =
+            hash_map! {ALL_CHANNEL_ID => ChannelInfo::new(ChannelKind::All, HashSet::new())};
+        let identity = SigningKey::from_bytes(&config.identity_key.as_ref().map_or_else(
+            || {
+                let mut key = [0u8; 32];
+                rng().fill_bytes(&mut key);
+                key
+            },
+            |key| *key.as_bytes(),
+        ));
+        let mut server = Self {
+            log_target: config
+                .log_target
+                .clone()
+                .unwrap_or_else(|| format!("Server {id}")),
+            config,
             own_id: id,
             channels,
             channel_info,
             usernames: BiHashMap::default(),
+            last_timestamp: 0,
+            last_seen: HashMap::new(),
+            registered_at: HashMap::new(),
+            guests: HashSet::new(),
+            departures: HashMap::new(),
+            bandwidth: HashMap::new(),
+            rate_limits: HashMap::new(),
+            acked_messages: HashMap::new(),
+            migrated_to: None,
+            started_at: SystemClock.now(),
+            announcements_due: vec![],
+            identity,
+            sessions: HashMap::new(),
+            keywords: HashMap::new(),
+            incarnation: rng().next_u64(),
+            offline_messages: HashMap::new(),
+            unreachable: HashSet::new(),
+            statuses: HashMap::new(),
+            filters: Filters::default(),
+            counters: ServerCounters::default(),
+            next_heartbeat: None,
+            missed_heartbeats: HashMap::new(),
+            recent_sends: HashMap::new(),
+            federation: HashMap::new(),
+            pending_federations: HashSet::new(),
+            #[cfg(feature = "persistence")]
+            store: None,
+            clock: Box::new(SystemClock),
+        };
+        server.schedule_announcements(server.started_at);
+        server
+    }
+
+    // Replaces the system clock, e.g. for simulations and tests. Times measured so far
+    // start over from the new clock's now
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+        self.started_at = self.now();
+        self.schedule_announcements(self.started_at);
+        #[cfg(feature = "persistence")]
+        self.rebase_store(self.started_at);
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    // Milliseconds since the Unix epoch
+    fn wall_now(&self) -> u64 {
+        self.clock.wall_ms()
+    }
+
+    // Wall-clock milliseconds, bumped when needed so that it never repeats or goes back.
+    // Since it is unique it also serves as the id of the message it stamps
+    fn next_timestamp(&mut self) -> u64 {
+        let now = self.wall_now();
+        self.last_timestamp = now.max(self.last_timestamp + 1);
+        self.last_timestamp
+    }
+
+    // Acknowledges a chat message, giving the client its credit back
+    fn ack_chat_message(&mut self, replies: &mut Vec<(NodeId, ChatMessage)>, cli_node_id: NodeId) {
+        if let Some(acked) = self.acked_messages.get_mut(&cli_node_id) {
+            *acked += 1;
+            let acked = *acked;
+            replies.push((cli_node_id, self.flow_credit(acked)));
         }
     }
-}
 
-#[allow(clippy::module_name_repetitions)]
-pub type ChatServer = PacketHandler<ServerCommand, ServerEvent, ChatServerInternal>;
+    fn flow_credit(&self, acked: u64) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::SrvFlowCredit(FlowCredit {
+                window: self.config.send_window,
+                acked,
+            })),
+        }
+    }
 
-impl ChatServerInternal {
-    fn generate_channel_updates(&self) -> Vec<(NodeId, ChatMessage)> {
-        let mut updates = vec![];
+    fn may_create_channel(&self, cli_node_id: NodeId) -> bool {
+        if self.guests.contains(&cli_node_id) {
+            return false;
+        }
+        match &self.config.channel_creation {
+            ChannelCreationPolicy::Anyone => true,
+            ChannelCreationPolicy::RegisteredFor(duration) => {
+                let now = self.wall_now();
+                let min = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+                self.registered_at
+                    .get(&cli_node_id)
+                    .is_some_and(|at| now.saturating_sub(*at) >= min)
+            }
+            ChannelCreationPolicy::AllowList(usernames) => self
+                .usernames
+                .get_by_left(&cli_node_id)
+                .is_some_and(|x| usernames.contains(x)),
+            ChannelCreationPolicy::Nobody => false,
+        }
+    }
+
+    // Adds an empty group channel, returning its ID
+    fn create_group_channel(&mut self, name: &str, creator: Option<NodeId>) -> u64 {
+        let mut attempt = 0;
+        let mut id = group_channel_id(name, attempt);
+        while self.channels.contains_left(&id) || self.channel_info.contains_key(&id) {
+            attempt += 1;
+            id = group_channel_id(name, attempt);
+        }
+        debug!(target: self.log_target.as_str(), "Creating new channel with ID {id} and name {name}");
+        self.channels.insert(id, name.to_string());
+        let mut info = ChannelInfo::new(ChannelKind::Group, HashSet::new());
+        info.creator = creator;
+        info.created_at = self.wall_now();
+        self.channel_info.insert(id, info);
+        id
+    }
+
+    fn error_message(&self, error: ErrorCode, text: &str) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::Err(error.message(text))),
+        }
+    }
+
+    fn is_username_available(&self, username: &str) -> bool {
+        !self.usernames.contains_right(username) && !username.eq_ignore_ascii_case(SYSTEM_USERNAME)
+    }
+
+    // Free usernames close to a taken one, offered when a registration is refused
+    fn username_suggestions(&self, taken: &str) -> Vec<String> {
+        (2..)
+            .flat_map(|n| [format!("{taken}{n}"), format!("{taken}_{n}")])
+            .filter(|x| self.is_username_available(x))
+            .take(USERNAME_SUGGESTIONS)
+            .collect()
+    }
+
+    fn group_channel_count(&self) -> usize {
+        self.channel_info
+            .values()
+            .filter(|info| info.is_shared())
+            .count()
+    }
+
+    // Sends a SYSTEM-authored message to every member of a group channel except `except`,
+    // and keeps it in the history for those who join later
+    fn announce_in_channel(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        channel_id: u64,
+        text: &str,
+        except: Option<NodeId>,
+    ) {
+        if self.channel_info.get(&channel_id).map(|x| x.kind) != Some(ChannelKind::Group) {
+            return;
+        }
+        let timestamp = self.next_timestamp();
+        let data = self.system_message_data(channel_id, timestamp, text);
+        let limit = self.config.history_limit;
+        let Some(info) = self.channel_info.get_mut(&channel_id) else {
+            return;
+        };
+        debug!(target: self.log_target.as_str(), "Announcing in channel {channel_id}: {text}");
+        for id in info.members.iter().filter(|x| Some(**x) != except) {
+            replies.push((
+                *id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvDistributeMessage(data.clone())),
+                },
+            ));
+        }
+        info.remember(data, limit);
+    }
+
+    fn system_message(&self, channel_id: u64, timestamp: u64, text: &str) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::SrvDistributeMessage(
+                self.system_message_data(channel_id, timestamp, text),
+            )),
+        }
+    }
+
+    fn system_message_data(&self, channel_id: u64, timestamp: u64, text: &str) -> MessageData {
+        MessageData {
+            username: SYSTEM_USERNAME.to_string(),
+            timestamp,
+            message: text.to_string(),
+            channel_id,
+            // SYSTEM messages are only ever sent in group channels
+            channel_kind: self
+                .channel_info
+                .get(&channel_id)
+                .map_or(ChannelKind::Group, |x| x.kind),
+            color: 0,
+            message_id: timestamp,
+            content_warning: None,
+            signature: String::new(),
+            signer_key: String::new(),
+            signed_at: 0,
+            local_id: 0,
+        }
+    }
+
+    fn client_data(&self, id: NodeId, username: &str, role: Role) -> ClientData {
+        let now = self.wall_now();
+        let presence = match self.last_seen.get(&id) {
+            Some(seen) if now.saturating_sub(*seen) < IDLE_AFTER_MS => "active",
+            _ => "idle",
+        };
+        let status = self.statuses.get(&id);
+        ClientData {
+            username: username.to_string(),
+            id: u64::from(id),
+            presence: presence.to_string(),
+            status: status.map_or_else(|| "online".to_string(), |x| x.status.clone()),
+            status_text: status.map(|x| x.text.clone()).unwrap_or_default(),
+            current_channel: self.current_group_channel(id),
+            color: user_color(username),
+            role: role.name().to_string(),
+        }
+    }
+
+    // The group channel a client is in, other than "All"
+    fn current_group_channel(&self, id: NodeId) -> Option<u64> {
+        self.channel_info
+            .iter()
+            .find(|(_, info)| info.kind == ChannelKind::Group && info.members.contains(&id))
+            .map(|(chan_id, _)| *chan_id)
+    }
+
+    fn channel_list(&self) -> Vec<Channel> {
         let mut channel_list = vec![];
         for (id, name) in &self.channels {
-            trace!(target: format!("Server {}", self.own_id).as_str(), "Adding {name}({id}) to channel list for generation");
-            if let Some((is_group, clients)) = self.channel_info.get(id) {
+            trace!(target: self.log_target.as_str(), "Adding {name}({id}) to channel list for generation");
+            if let Some(info) = self.channel_info.get(id) {
                 let mut clients_res = vec![];
-                for x in clients {
-                    trace!(target: format!("Server {}", self.own_id).as_str(), "Adding client {x} to channel members for generation:");
+                for x in &info.members {
+                    trace!(target: self.log_target.as_str(), "Adding client {x} to channel members for generation:");
                     if let Some(name) = self.usernames.get_by_left(x) {
-                        trace!(target: format!("Server {}", self.own_id).as_str(), "Client {x} has username {name}");
-                        clients_res.push(ClientData {
-                            username: name.clone(),
-                            id: u64::from(*x),
-                        });
-                    } else {
-                        error!(target: format!("Server {}", self.own_id).as_str(), "Client {x} doesn't have a username");
+                        trace!(target: self.log_target.as_str(), "Client {x} has username {name}");
+                        clients_res.push(self.client_data(*x, name, info.role(*x)));
+                    } else if !self.guests.contains(x) {
+                        error!(target: self.log_target.as_str(), "Client {x} doesn't have a username");
                     }
                 }
                 channel_list.push(Channel {
                     channel_name: name.clone(),
                     channel_id: *id,
-                    channel_is_group: *is_group,
+                    channel_is_group: info.is_shared(),
+                    channel_kind: info.kind,
+                    messages_today: info.stats.messages_today,
+                    last_activity: info.stats.last_activity,
+                    unique_speakers: info.stats.speakers.len() as u64,
                     connected_clients: clients_res,
+                    creator: info
+                        .creator
+                        .and_then(|id| self.usernames.get_by_left(&id).cloned())
+                        .unwrap_or_default(),
+                    created_at: info.created_at,
                 });
             } else {
-                error!(target: format!("Server {}", self.own_id).as_str(), "Channel {name}({id}) doesn't have info");
+                error!(target: self.log_target.as_str(), "Channel {name}({id}) doesn't have info");
             }
         }
-        debug!(target: format!("Server {}", self.own_id).as_str(), "Generated channel list: {channel_list:?}");
-        for id in self.usernames.left_values() {
-            trace!(target: format!("Server {}", self.own_id).as_str(), "Adding client {id} to channel updates");
-            updates.push((
-                *id,
-                ChatMessage {
-                    own_id: u32::from(self.own_id),
-                    message_kind: Some(MessageKind::SrvReturnChannels(ChannelsList {
-                        channels: channel_list.clone(),
-                    })),
+        debug!(target: self.log_target.as_str(), "Generated channel list: {channel_list:?}");
+        channel_list
+    }
+
+    // Sends the channel list to every client that can see it, or to clients that take
+    // deltas only what changed since the list they got last. With a scope, only the
+    // client that acted and the members of the channels it touched are told. Others get
+    // the change with their next update: their baseline stays as it was, so nothing is
+    // lost
+    fn generate_channel_updates(
+        &mut self,
+        scope: Option<(NodeId, &[u64])>,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut updates = vec![];
+        let channel_list = Arc::new(self.channel_list());
+        let recipients = self
+            .usernames
+            .left_values()
+            .chain(&self.guests)
+            .chain(self.sessions.keys())
+            .filter(|id| match scope {
+                Some((actor, channels)) => {
+                    **id == actor
+                        || channels.iter().any(|chan| {
+                            self.channel_info
+                                .get(chan)
+                                .is_some_and(|x| x.members.contains(id))
+                        })
+                }
+                None => true,
+            })
+            .copied()
+            .collect::<HashSet<_>>();
+        trace!(target: self.log_target.as_str(), "Sending channel updates to {recipients:?}");
+        // Delta clients are grouped by the list they saw last, each group gets one delta
+        let mut by_baseline: Vec<(Arc<Vec<Channel>>, Vec<NodeId>)> = vec![];
+        let mut full_recipients = vec![];
+        for id in &recipients {
+            let seen = self
+                .sessions
+                .get(id)
+                .filter(|_| self.wants_channel_delta(*id))
+                .and_then(|x| x.channels_seen.clone());
+            match seen {
+                Some(seen) => match by_baseline.iter_mut().find(|(x, _)| Arc::ptr_eq(x, &seen)) {
+                    Some((_, ids)) => ids.push(*id),
+                    None => by_baseline.push((seen, vec![*id])),
                 },
-            ));
+                None => full_recipients.push(*id),
+            }
         }
-        debug!(target: format!("Server {}", self.own_id).as_str(), "Generated channel updates: {updates:?}");
+        for (seen, ids) in by_baseline {
+            let delta = channel_delta(&seen, &channel_list);
+            if is_empty_delta(&delta) {
+                continue;
+            }
+            let message = ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvChannelDelta(delta)),
+            };
+            updates.extend(ids.into_iter().map(|id| (id, message.clone())));
+        }
+        if !full_recipients.is_empty() {
+            let message = self.channel_list_message(channel_list.to_vec());
+            updates.extend(full_recipients.into_iter().map(|id| (id, message.clone())));
+        }
+        for id in &recipients {
+            if let Some(session) = self.sessions.get_mut(id) {
+                session.channels_seen = Some(Arc::clone(&channel_list));
+            }
+        }
+        debug!(target: self.log_target.as_str(), "Generated channel updates: {updates:?}");
         updates
     }
 }
+
+#[cfg(test)]
+impl ChatServerInternal {
+    // Registers `cli_node_id` as `username` and joins it to the group channel `channel`,
+    // created if needed. Returns the channel's ID
+    pub(crate) fn register_in(
+        &mut self,
+        cli_node_id: NodeId,
+        username: &str,
+        channel: &str,
+    ) -> u64 {
+        let mut replies = vec![];
+        self.msg_cliregisterrequest(&mut replies, cli_node_id, username.to_string());
+        self.msg_clijoin(
+            &mut replies,
+            &chat_common::messages::JoinChannel {
+                channel_id: None,
+                channel_name: channel.to_string(),
+            },
+            cli_node_id,
+        );
+        self.channels
+            .get_by_right(channel)
+            .copied()
+            .expect("channel wasn't joined")
+    }
+
+    // Sends `text` from `cli_node_id` to the group channel `channel_id`
+    pub(crate) fn post(
+        &mut self,
+        cli_node_id: NodeId,
+        channel_id: u64,
+        text: &str,
+    ) -> (Vec<(NodeId, ChatMessage)>, ReceiptStatus) {
+        let (mut replies, mut events) = (vec![], vec![]);
+        let message = chat_common::messages::SendMessage {
+            message: text.to_string(),
+            channel_id,
+            channel_kind: Some(ChannelKind::Group),
+            content_warning: None,
+            local_id: 0,
+            signature: String::new(),
+            signer_key: String::new(),
+            signed_at: 0,
+        };
+        let status = self.msg_sendmsg(&mut replies, &mut events, cli_node_id, &message);
+        (replies, status)
+    }
+}
+
+// Errors sent to `id` among `replies`
+#[cfg(test)]
+pub(crate) fn errors_to(replies: &[(NodeId, ChatMessage)], id: NodeId) -> Vec<ErrorCode> {
+    replies
+        .iter()
+        .filter(|(to, _)| *to == id)
+        .filter_map(|(_, msg)| match &msg.message_kind {
+            Some(MessageKind::Err(err)) => ErrorCode::of(err),
+            _ => None,
+        })
+        .collect()
+}
+
+// Texts of the chat messages sent to `id` among `replies`
+#[cfg(test)]
+pub(crate) fn texts_to(replies: &[(NodeId, ChatMessage)], id: NodeId) -> Vec<String> {
+    replies
+        .iter()
+        .filter(|(to, _)| *to == id)
+        .filter_map(|(_, msg)| match &msg.message_kind {
+            Some(MessageKind::SrvDistributeMessage(data)) => Some(data.message.clone()),
+            _ => None,
+        })
+        .collect()
+}
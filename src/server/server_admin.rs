@@ -0,0 +1,219 @@
+use crate::protocol::severity_name;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage, Kicked, SystemNotice};
+use common::slc_commands::{NoticeSeverity, ServerEvent};
+use itertools::Itertools;
+use log::info;
+use std::collections::HashSet;
+use wg_2024::network::NodeId;
+
+// Controller operations on the server, each reported back with a ServerEvent
+impl ChatServerInternal {
+    pub(crate) fn admin_create_channel(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        name: &str,
+    ) {
+        if self.channels.contains_right(name) {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "Channel {name} already exists"
+            )));
+            return;
+        }
+        let id = self.create_group_channel(name, None);
+        replies.extend(self.generate_channel_updates(None));
+        events.push(ServerEvent::ChannelCreated {
+            id,
+            name: name.to_string(),
+        });
+    }
+
+    // Deletes a group channel, its members are told like when they get kicked
+    pub(crate) fn admin_delete_channel(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        name: &str,
+    ) {
+        let Some(id) = self.channels.get_by_right(name).copied().filter(|id| {
+            self.channel_info
+                .get(id)
+                .is_some_and(|x| x.kind == ChannelKind::Group)
+        }) else {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "No group channel named {name}"
+            )));
+            return;
+        };
+        self.delete_channel(replies, events, id, "the server");
+    }
+
+    // Also used when the owner of a channel deletes it
+    pub(crate) fn delete_channel(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        id: u64,
+        deleted_by: &str,
+    ) {
+        let name = self.channels.get_by_left(&id).cloned().unwrap_or_default();
+        info!(target: self.log_target.as_str(), "Deleting channel {name} ({id})");
+        self.unfederate_channel(replies, events, &name);
+        self.channels.remove_by_left(&id);
+        if let Some(info) = self.channel_info.remove(&id) {
+            for member in info.members {
+                replies.push((
+                    member,
+                    ChatMessage {
+                        own_id: u32::from(self.own_id),
+                        request_id: 0,
+                        message_kind: Some(MessageKind::SrvKicked(Kicked {
+                            channel_id: id,
+                            reason: format!("the channel was deleted by {deleted_by}"),
+                        })),
+                    },
+                ));
+            }
+        }
+        replies.extend(self.generate_channel_updates(None));
+        events.push(ServerEvent::ChannelDeleted { id, name });
+    }
+
+    pub(crate) fn admin_set_read_only(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        name: &str,
+        read_only: bool,
+    ) {
+        let found = self
+            .channels
+            .get_by_right(name)
+            .copied()
+            .is_some_and(|id| self.set_read_only(replies, id, read_only));
+        if found {
+            events.push(ServerEvent::ReadOnlyChanged {
+                name: name.to_string(),
+                read_only,
+            });
+        } else {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "No group channel named {name}"
+            )));
+        }
+    }
+
+    // Posts as SYSTEM in a group channel, which is how read-only channels the server
+    // created get their messages. Kept in the history like any other message
+    pub(crate) fn admin_post(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        name: &str,
+        text: &str,
+    ) {
+        let Some(id) = self.channels.get_by_right(name).copied().filter(|id| {
+            self.channel_info
+                .get(id)
+                .is_some_and(|x| x.kind == ChannelKind::Group)
+        }) else {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "No group channel named {name}"
+            )));
+            return;
+        };
+        let timestamp = self.next_timestamp();
+        let data = self.system_message_data(id, timestamp, text);
+        self.distribute_message(replies, self.own_id, data);
+    }
+
+    // Unregisters a client, as if it had asked to
+    pub(crate) fn admin_kick_client(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        id: NodeId,
+    ) {
+        if !self.usernames.contains_left(&id) && !self.guests.contains(&id) {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "Client {id} isn't registered"
+            )));
+            return;
+        }
+        info!(target: self.log_target.as_str(), "Removing client {id} from the server");
+        self.msg_clicancelreq(replies, id);
+        self.guests.remove(&id);
+        replies.push((
+            id,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvRemovedFromServer(
+                    "You were removed from the server".to_string(),
+                )),
+            },
+        ));
+        events.push(ServerEvent::ClientKicked(id));
+    }
+
+    // Sends a notice to every client we know, registered or not, e.g. before a shutdown
+    pub(crate) fn admin_broadcast(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        severity: NoticeSeverity,
+        text: &str,
+    ) {
+        let recipients = self
+            .usernames
+            .left_values()
+            .chain(&self.guests)
+            .chain(self.sessions.keys())
+            .copied()
+            .collect::<HashSet<_>>();
+        for id in recipients {
+            replies.push((id, self.system_notice(severity, text)));
+        }
+        events.push(ServerEvent::NoticeBroadcast {
+            severity,
+            text: text.to_string(),
+        });
+    }
+
+    fn system_notice(&self, severity: NoticeSeverity, text: &str) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::SrvSystemNotice(SystemNotice {
+                severity: severity_name(severity).to_string(),
+                text: text.to_string(),
+            })),
+        }
+    }
+
+    // Clients registering from now on get the new one, None or an empty text removes it
+    pub(crate) fn admin_set_motd(&mut self, events: &mut Vec<ServerEvent>, motd: Option<String>) {
+        let motd = motd.filter(|x| !x.is_empty());
+        info!(target: self.log_target.as_str(), "Message of the day set to {motd:?}");
+        self.config.motd.clone_from(&motd);
+        events.push(ServerEvent::MotdChanged(motd));
+    }
+
+    // Message of the day, sent right after a client registers
+    pub(crate) fn motd_notice(&self) -> Option<ChatMessage> {
+        let text = self.config.motd.as_ref().filter(|x| !x.is_empty())?;
+        Some(self.system_notice(NoticeSeverity::Info, text))
+    }
+
+    pub(crate) fn admin_user_list(&self, events: &mut Vec<ServerEvent>) {
+        events.push(ServerEvent::UserList(
+            self.usernames
+                .iter()
+                .map(|(id, username)| (*id, username.clone()))
+                .sorted()
+                .collect(),
+        ));
+    }
+}
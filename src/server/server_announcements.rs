@@ -0,0 +1,75 @@
+use crate::clock::Instant;
+use crate::protocol::ALL_CHANNEL_ID;
+use crate::server::ChatServerInternal;
+use chat_common::messages::ChatMessage;
+use log::{debug, info};
+use std::mem;
+use std::time::Duration;
+use wg_2024::network::NodeId;
+
+// Text posted to every registered user each `interval`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Announcement {
+    pub interval: Duration,
+    pub text: String,
+}
+
+impl ChatServerInternal {
+    // When each configured announcement is due next, same order as in the config
+    pub(crate) fn schedule_announcements(&mut self, now: Instant) {
+        self.announcements_due = self
+            .config
+            .announcements
+            .iter()
+            .map(|x| now + x.interval)
+            .collect();
+    }
+
+    pub(crate) fn add_announcement(&mut self, now: Instant, announcement: Announcement) {
+        info!(target: self.log_target.as_str(), "Scheduling announcement every {:?}: {}", announcement.interval, announcement.text);
+        self.announcements_due.push(now + announcement.interval);
+        self.config.announcements.push(announcement);
+    }
+
+    // Drops every schedule posting `text`
+    pub(crate) fn remove_announcement(&mut self, text: &str) {
+        info!(target: self.log_target.as_str(), "Removing announcements: {text}");
+        let due = mem::take(&mut self.announcements_due);
+        (self.config.announcements, self.announcements_due) =
+            mem::take(&mut self.config.announcements)
+                .into_iter()
+                .zip(due)
+                .filter(|(x, _)| x.text != text)
+                .unzip();
+    }
+
+    pub(crate) fn post_due_announcements(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        now: Instant,
+    ) {
+        for i in 0..self.config.announcements.len() {
+            let interval = self.config.announcements[i].interval;
+            // A zero interval would post on every tick
+            if interval.is_zero() || self.announcements_due[i] > now {
+                continue;
+            }
+            self.announcements_due[i] = now + interval;
+            let text = self.config.announcements[i].text.clone();
+            debug!(target: self.log_target.as_str(), "Posting announcement: {text}");
+            self.announce(replies, &text);
+        }
+    }
+
+    // Sends a SYSTEM-authored message in "All", reaching every registered user
+    pub(crate) fn announce(&mut self, replies: &mut Vec<(NodeId, ChatMessage)>, text: &str) {
+        let timestamp = self.next_timestamp();
+        let Some(info) = self.channel_info.get(&ALL_CHANNEL_ID) else {
+            return;
+        };
+        for id in &info.members {
+            replies.push((*id, self.system_message(ALL_CHANNEL_ID, timestamp, text)));
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::server::ChatServerInternal;
+use common::slc_commands::ServerEvent;
+use log::{debug, warn};
+use wg_2024::network::NodeId;
+
+#[derive(Debug, Default)]
+pub(crate) struct BandwidthUsage {
+    // Start of the current accounting interval, in milliseconds since the epoch
+    window_start: u64,
+    bytes: u64,
+    // Whether the controller was already told about this interval
+    reported: bool,
+}
+
+impl ChatServerInternal {
+    fn bandwidth_interval_ms(&self) -> u64 {
+        u64::try_from(self.config.bandwidth_interval.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    // Forgets clients whose interval is over, they start from zero on their next message anyway
+    pub(crate) fn expire_bandwidth_windows(&mut self, now: u64) {
+        let interval = self.bandwidth_interval_ms();
+        self.bandwidth
+            .retain(|_, usage| now.saturating_sub(usage.window_start) < interval);
+    }
+
+    // Accounts `bytes` of relayed traffic to a client, returns false if that goes over
+    // its budget, in which case the traffic must not be relayed
+    pub(crate) fn charge_bandwidth(
+        &mut self,
+        events: &mut Vec<ServerEvent>,
+        cli_node_id: NodeId,
+        bytes: u64,
+    ) -> bool {
+        let Some(budget) = self.config.bandwidth_budget else {
+            return true;
+        };
+        let now = self.wall_now();
+        let interval = self.bandwidth_interval_ms();
+        let usage = self.bandwidth.entry(cli_node_id).or_default();
+        if now.saturating_sub(usage.window_start) >= interval {
+            *usage = BandwidthUsage {
+                window_start: now,
+                ..BandwidthUsage::default()
+            };
+        }
+        if usage.bytes + bytes <= budget {
+            usage.bytes += bytes;
+            return true;
+        }
+        debug!(target: self.log_target.as_str(), "Client {cli_node_id} is over its bandwidth budget ({} + {bytes} > {budget})", usage.bytes);
+        if !usage.reported {
+            usage.reported = true;
+            warn!(target: self.log_target.as_str(), "Throttling client {cli_node_id}");
+            events.push(ServerEvent::ClientThrottled {
+                client: cli_node_id,
+                bytes: usage.bytes + bytes,
+                budget,
+            });
+        }
+        false
+    }
+}
@@ -0,0 +1,74 @@
+use crate::protocol::ErrorCode;
+use crate::server::ChatServerInternal;
+use chat_common::messages::{ChannelKind, ChatMessage, MemberAction};
+use log::info;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Kicks a member out of a channel at an operator's request. A ban also keeps it from
+    // joining again
+    pub(crate) fn msg_cliremovemember(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &MemberAction,
+        ban: bool,
+    ) {
+        info!(target: self.log_target.as_str(), "Client {cli_node_id} wants to {} {} in channel {}", if ban { "ban" } else { "kick" }, data.username, data.channel_id);
+        let target = self.usernames.get_by_right(&data.username).copied();
+        let (Some(info), Some(target)) = (self.channel_info.get(&data.channel_id), target) else {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::MemberActionInvalid, "No such user or channel"),
+            ));
+            return;
+        };
+        if info.kind != ChannelKind::Group || target == cli_node_id {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::MemberActionInvalid,
+                    "You can only remove someone else from a group channel",
+                ),
+            ));
+            return;
+        }
+        // Operators can remove members, and only the creator can remove operators
+        let allowed = if info.is_operator(target) {
+            info.creator == Some(cli_node_id)
+        } else {
+            info.is_operator(cli_node_id)
+        };
+        if !allowed {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        if ban {
+            if let Some(info) = self.channel_info.get_mut(&data.channel_id) {
+                info.banned.insert(target);
+                info.operators.remove(&target);
+            }
+            if !self.channel_info[&data.channel_id]
+                .members
+                .contains(&target)
+            {
+                self.announce_in_channel(
+                    replies,
+                    data.channel_id,
+                    &format!("{} was banned", data.username),
+                    None,
+                );
+                return;
+            }
+        }
+        let reason = if ban {
+            "banned"
+        } else {
+            "kicked by an operator"
+        };
+        self.kick_from_channel(replies, data.channel_id, target, reason);
+    }
+}
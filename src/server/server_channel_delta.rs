@@ -0,0 +1,156 @@
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{Channel, ChannelDelta, ChannelsList, ChatMessage, MemberChange};
+use std::collections::HashMap;
+use std::sync::Arc;
+use wg_2024::network::NodeId;
+
+// Clients announcing this in their hello get channel list changes as deltas
+pub(crate) const CHANNEL_DELTA_CAPABILITY: &str = "channel-delta";
+
+// Everything about a channel but its members
+fn same_apart_from_members(a: &Channel, b: &Channel) -> bool {
+    Channel {
+        connected_clients: vec![],
+        ..a.clone()
+    } == Channel {
+        connected_clients: vec![],
+        ..b.clone()
+    }
+}
+
+// What changed between two channel lists. Channels that changed in other ways than
+// their members are sent whole in `added`, replacing the old ones
+pub(crate) fn channel_delta(old: &[Channel], new: &[Channel]) -> ChannelDelta {
+    let old_by_id = old
+        .iter()
+        .map(|chan| (chan.channel_id, chan))
+        .collect::<HashMap<_, _>>();
+    let mut delta = ChannelDelta::default();
+    for chan in new {
+        match old_by_id.get(&chan.channel_id) {
+            Some(prev) if same_apart_from_members(prev, chan) => {
+                for client in &chan.connected_clients {
+                    if !prev.connected_clients.contains(client) {
+                        delta.joined.push(MemberChange {
+                            channel_id: chan.channel_id,
+                            client: client.clone(),
+                        });
+                    }
+                }
+                for client in &prev.connected_clients {
+                    if !chan.connected_clients.contains(client) {
+                        delta.left.push(MemberChange {
+                            channel_id: chan.channel_id,
+                            client: client.clone(),
+                        });
+                    }
+                }
+            }
+            _ => delta.added.push(chan.clone()),
+        }
+    }
+    delta.removed = old
+        .iter()
+        .map(|chan| chan.channel_id)
+        .filter(|id| !new.iter().any(|chan| chan.channel_id == *id))
+        .collect();
+    delta
+}
+
+pub(crate) fn is_empty_delta(delta: &ChannelDelta) -> bool {
+    delta.added.is_empty()
+        && delta.removed.is_empty()
+        && delta.joined.is_empty()
+        && delta.left.is_empty()
+}
+
+impl ChatServerInternal {
+    pub(crate) fn wants_channel_delta(&self, cli_node_id: NodeId) -> bool {
+        self.sessions
+            .get(&cli_node_id)
+            .is_some_and(|x| x.capabilities.contains(CHANNEL_DELTA_CAPABILITY))
+    }
+
+    // The whole channel list for one client, its next delta starts from it
+    pub(crate) fn full_channel_list(&mut self, cli_node_id: NodeId) -> ChatMessage {
+        let channels = Arc::new(self.channel_list());
+        if let Some(session) = self.sessions.get_mut(&cli_node_id) {
+            session.channels_seen = Some(Arc::clone(&channels));
+        }
+        self.channel_list_message(channels.to_vec())
+    }
+
+    // The whole channel list, sent on request and to clients that don't take deltas
+    pub(crate) fn channel_list_message(&self, channels: Vec<Channel>) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::SrvReturnChannels(ChannelsList {
+                channels,
+                compressed: vec![],
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ALL_CHANNEL_ID;
+    use crate::server::ChatServerConfig;
+
+    fn server() -> ChatServerInternal {
+        ChatServerInternal::with_config(1, ChatServerConfig::default())
+    }
+
+    #[test]
+    fn unchanged_lists_make_an_empty_delta() {
+        let server = server();
+        let list = server.channel_list();
+        assert!(is_empty_delta(&channel_delta(&list, &list)));
+    }
+
+    #[test]
+    fn new_channels_and_members_are_sent_alone() {
+        let mut server = server();
+        let old = server.channel_list();
+        server.usernames.insert(5, "alice".to_string());
+        if let Some(info) = server.channel_info.get_mut(&ALL_CHANNEL_ID) {
+            info.members.insert(5);
+        }
+        let games = server.create_group_channel("games", None);
+        let delta = channel_delta(&old, &server.channel_list());
+        assert_eq!(
+            delta.added.iter().map(|x| x.channel_id).collect::<Vec<_>>(),
+            vec![games]
+        );
+        assert_eq!(delta.joined.len(), 1);
+        assert_eq!(delta.joined[0].channel_id, ALL_CHANNEL_ID);
+        assert_eq!(delta.joined[0].client.username, "alice");
+        assert!(delta.removed.is_empty());
+        assert!(delta.left.is_empty());
+    }
+
+    #[test]
+    fn removed_channels_and_members_are_listed() {
+        let mut server = server();
+        server.usernames.insert(5, "alice".to_string());
+        if let Some(info) = server.channel_info.get_mut(&ALL_CHANNEL_ID) {
+            info.members.insert(5);
+        }
+        let games = server.create_group_channel("games", None);
+        let old = server.channel_list();
+        server.channels.remove_by_left(&games);
+        server.channel_info.remove(&games);
+        if let Some(info) = server.channel_info.get_mut(&ALL_CHANNEL_ID) {
+            info.members.remove(&5);
+        }
+        let delta = channel_delta(&old, &server.channel_list());
+        assert_eq!(delta.removed, vec![games]);
+        assert_eq!(delta.left.len(), 1);
+        assert_eq!(delta.left[0].client.username, "alice");
+        assert!(delta.added.is_empty());
+        assert!(delta.joined.is_empty());
+    }
+}
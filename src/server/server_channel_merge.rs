@@ -0,0 +1,171 @@
+use crate::protocol::{group_channel_id, normalize_channel_name};
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage};
+use itertools::Itertools;
+use log::info;
+use std::collections::HashMap;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Group channel whose name matches `name` once normalized
+    pub(crate) fn find_group_channel(&self, name: &str) -> Option<u64> {
+        let normalized = normalize_channel_name(name);
+        self.channels
+            .iter()
+            .filter(|(id, x)| {
+                self.channel_info
+                    .get(id)
+                    .is_some_and(|info| info.kind == ChannelKind::Group)
+                    && normalize_channel_name(x) == normalized
+            })
+            .map(|(id, _)| *id)
+            .min()
+    }
+
+    // Group channels that ended up with the same normalized name, e.g. after restoring or
+    // receiving state from another server, are folded into one canonical channel. Members
+    // of the dropped channels are told the ID they are now in
+    pub(crate) fn merge_duplicate_channels(&mut self, replies: &mut Vec<(NodeId, ChatMessage)>) {
+        let mut by_name: HashMap<String, Vec<u64>> = HashMap::new();
+        for (id, name) in &self.channels {
+            if self
+                .channel_info
+                .get(id)
+                .is_some_and(|info| info.kind == ChannelKind::Group)
+            {
+                by_name
+                    .entry(normalize_channel_name(name))
+                    .or_default()
+                    .push(*id);
+            }
+        }
+        let mut merged = false;
+        for (name, ids) in by_name.into_iter().filter(|(_, ids)| ids.len() > 1) {
+            // The first-attempt ID when present, so that every server picks the same one
+            let canonical = ids
+                .iter()
+                .copied()
+                .find(|id| *id == group_channel_id(&name, 0))
+                .unwrap_or_else(|| ids.iter().copied().min().unwrap_or_default());
+            for duplicate in ids.into_iter().filter(|id| *id != canonical) {
+                self.merge_channel_into(replies, duplicate, canonical);
+            }
+            merged = true;
+        }
+        if merged {
+            replies.extend(self.generate_channel_updates(None));
+        }
+    }
+
+    // Folds the group channel `source` into `target`, for the controller. Returns false
+    // if either isn't a group channel
+    pub(crate) fn merge_channels(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        source: &str,
+        target: &str,
+    ) -> bool {
+        let is_group = |id: &u64| {
+            self.channel_info
+                .get(id)
+                .is_some_and(|info| info.kind == ChannelKind::Group)
+        };
+        let (Some(source), Some(target)) = (
+            self.channels.get_by_right(source).copied().filter(is_group),
+            self.channels.get_by_right(target).copied().filter(is_group),
+        ) else {
+            return false;
+        };
+        if source == target {
+            return false;
+        }
+        self.merge_channel_into(replies, source, target);
+        replies.extend(self.generate_channel_updates(None));
+        true
+    }
+
+    fn merge_channel_into(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        duplicate: u64,
+        canonical: u64,
+    ) {
+        let duplicate_name = self
+            .channels
+            .remove_by_left(&duplicate)
+            .map_or_else(|| duplicate.to_string(), |(_, name)| name);
+        let Some(dropped) = self.channel_info.remove(&duplicate) else {
+            return;
+        };
+        let Some(target) = self.channel_info.get_mut(&canonical) else {
+            return;
+        };
+        info!(target: self.log_target.as_str(), "Merging channel {duplicate} into {canonical}");
+        let moved = dropped
+            .members
+            .iter()
+            .copied()
+            .filter(|x| !target.members.contains(x))
+            .collect::<Vec<_>>();
+        target.members.extend(dropped.members.iter().copied());
+        target.history = target
+            .history
+            .drain(..)
+            .merge_by(
+                dropped.history.into_iter().map(|mut msg| {
+                    msg.channel_id = canonical;
+                    msg
+                }),
+                |a, b| a.timestamp <= b.timestamp,
+            )
+            .collect();
+        while target.history.len() > self.config.history_limit {
+            target.history.pop_front();
+        }
+        if target.welcome.is_none() {
+            target.welcome = dropped.welcome;
+        }
+        if dropped.stats.day == target.stats.day {
+            target.stats.messages_today += dropped.stats.messages_today;
+        } else if dropped.stats.day > target.stats.day {
+            target.stats.day = dropped.stats.day;
+            target.stats.messages_today = dropped.stats.messages_today;
+        }
+        target.stats.last_activity = target.stats.last_activity.max(dropped.stats.last_activity);
+        target.stats.speakers.extend(dropped.stats.speakers);
+        target.operators.extend(dropped.operator_ids());
+        target.banned.extend(dropped.banned);
+        target.invited.extend(dropped.invited);
+        target.reactions.extend(dropped.reactions);
+        target
+            .pending
+            .extend(dropped.pending.into_iter().map(|(id, (author, mut msg))| {
+                msg.channel_id = canonical;
+                (id, (author, msg))
+            }));
+        for id in dropped.members {
+            replies.push((
+                id,
+                ChatMessage {
+                    own_id: self.own_id.into(),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvChannelCreationSuccessful(canonical)),
+                },
+            ));
+        }
+        if let Some(name) = self.channels.get_by_left(&canonical).cloned() {
+            let timestamp = self.next_timestamp();
+            for id in moved {
+                replies.push((
+                    id,
+                    self.system_message(
+                        canonical,
+                        timestamp,
+                        &format!("#{duplicate_name} was merged into #{name}, you are now here"),
+                    ),
+                ));
+            }
+        }
+    }
+}
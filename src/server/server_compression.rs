@@ -0,0 +1,24 @@
+use crate::compression::{compress_message, COMPRESSION_CAPABILITY};
+use crate::server::ChatServerInternal;
+use chat_common::messages::ChatMessage;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    pub(crate) fn wants_compression(&self, cli_node_id: NodeId) -> bool {
+        self.sessions
+            .get(&cli_node_id)
+            .is_some_and(|x| x.capabilities.contains(COMPRESSION_CAPABILITY))
+    }
+
+    // Last thing before replies go out: long message texts are compressed for the
+    // clients that take it. Everything before works on plain text
+    pub(crate) fn compress_replies(&self, replies: &mut [(NodeId, ChatMessage)]) {
+        for (id, reply) in replies.iter_mut() {
+            if let Some(kind) = reply.message_kind.as_mut() {
+                if self.wants_compression(*id) {
+                    compress_message(kind);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use crate::protocol::ErrorCode;
+use crate::server::{ChatServerInternal, FilterDecision};
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage, Federate, FederatedMessage, MessageData};
+use common::slc_commands::ServerEvent;
+use log::{debug, info, warn};
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Asks the server at `peer` to mirror the group channel `name`. Both sides need a
+    // public channel of that name and both admins have to ask: the link is up once the
+    // federate of one side reaches the other side after its admin asked too
+    pub(crate) fn admin_federate(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        peer: NodeId,
+        name: &str,
+    ) {
+        if self.federated_channel_id(name).is_none() {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "No public group channel named {name}, invite-only channels and channels with bans can't be federated"
+            )));
+            return;
+        }
+        if peer == self.own_id || self.usernames.contains_left(&peer) {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "Node {peer} isn't a server"
+            )));
+            return;
+        }
+        if let Some(other) = self.other_federation_peer(name, peer) {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "Channel {name} is already federated with server {other}, a channel can only be linked to one other server"
+            )));
+            return;
+        }
+        info!(target: self.log_target.as_str(), "Asking server {peer} to federate channel {name}");
+        self.pending_federations.insert((peer, name.to_string()));
+        replies.push((peer, self.federate_message(name, false)));
+    }
+
+    pub(crate) fn admin_unfederate(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        peer: NodeId,
+        name: &str,
+    ) {
+        self.pending_federations.remove(&(peer, name.to_string()));
+        if !self.unlink(events, peer, name) {
+            events.push(ServerEvent::AdminCommandFailed(format!(
+                "Channel {name} isn't federated with server {peer}"
+            )));
+            return;
+        }
+        replies.push((peer, self.federate_message(name, true)));
+    }
+
+    // The peer's admin asked to federate. The link only comes up if ours asked for the
+    // same peer and channel, then the peer gets our federate as the confirmation
+    pub(crate) fn msg_srvfederate(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        peer: NodeId,
+        federate: &Federate,
+    ) {
+        let name = federate.channel_name.as_str();
+        if !self.pending_federations.remove(&(peer, name.to_string())) {
+            if !self
+                .federation
+                .get(name)
+                .is_some_and(|peers| peers.contains(&peer))
+            {
+                warn!(target: self.log_target.as_str(), "Ignoring federation of channel {name} by node {peer}, not asked for by the admin");
+            }
+            return;
+        }
+        if self.federated_channel_id(name).is_none() {
+            replies.push((
+                peer,
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    &format!("There's no public group channel named {name} to federate"),
+                ),
+            ));
+            return;
+        }
+        // Our admin asked several servers and another one confirmed first
+        if let Some(other) = self.other_federation_peer(name, peer) {
+            warn!(target: self.log_target.as_str(), "Not federating channel {name} with server {peer}, it already is with server {other}");
+            replies.push((peer, self.federate_message(name, true)));
+            return;
+        }
+        // Already linked when the peer lost the link and asks again
+        if self
+            .federation
+            .entry(name.to_string())
+            .or_default()
+            .insert(peer)
+        {
+            info!(target: self.log_target.as_str(), "Channel {name} federated with server {} ({peer})", federate.server_name);
+            events.push(ServerEvent::FederationLinked {
+                peer,
+                channel: name.to_string(),
+            });
+        }
+        replies.push((peer, self.federate_message(name, false)));
+    }
+
+    pub(crate) fn msg_srvunfederate(
+        &mut self,
+        events: &mut Vec<ServerEvent>,
+        peer: NodeId,
+        federate: &Federate,
+    ) {
+        self.pending_federations
+            .remove(&(peer, federate.channel_name.clone()));
+        self.unlink(events, peer, &federate.channel_name);
+    }
+
+    // A message posted on a linked server, distributed here under the author's name
+    // qualified with that server's. It goes through the channel's rules like messages
+    // of our own clients, with the linked server counting as a single member that isn't
+    // an operator. It isn't relayed any further, links are meant to join pairs of
+    // servers and chains could loop
+    pub(crate) fn msg_srvfederatedmessage(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        peer: NodeId,
+        msg: FederatedMessage,
+    ) {
+        let name = msg.channel_name.as_str();
+        let linked = self
+            .federation
+            .get(name)
+            .is_some_and(|peers| peers.contains(&peer));
+        let Some(channel_id) = self.federated_channel_id(name).filter(|_| linked) else {
+            // Links don't survive restarts, tell the peer to stop sending
+            debug!(target: self.log_target.as_str(), "Dropping message for channel {name} not federated with server {peer}");
+            replies.push((peer, self.federate_message(name, true)));
+            return;
+        };
+        if let Some(refusal) = self.post_refusal(channel_id, peer, msg.data.message.len()) {
+            replies.push((peer, refusal));
+            return;
+        }
+        let mut data = msg.data;
+        let hold = match self.apply_filters(&data.username, channel_id, &mut data.message) {
+            FilterDecision::Reject(reason) => {
+                debug!(target: self.log_target.as_str(), "Federated message from {} rejected by a filter: {reason}", data.username);
+                replies.push((
+                    peer,
+                    self.error_message(ErrorCode::MessageRejected, &reason),
+                ));
+                return;
+            }
+            FilterDecision::Hold => true,
+            FilterDecision::Accept | FilterDecision::Replace(_) => false,
+        };
+        let timestamp = self.next_timestamp();
+        let data = MessageData {
+            timestamp,
+            message_id: timestamp,
+            channel_id,
+            channel_kind: ChannelKind::Group,
+            ..data
+        };
+        let Some(info) = self.channel_info.get_mut(&channel_id) else {
+            return;
+        };
+        if info.slow_mode != 0 {
+            info.last_post.insert(peer, timestamp);
+        }
+        if hold || info.moderated {
+            // Refused when too many are held, the peer has been told
+            self.hold_for_approval(replies, peer, data);
+        } else {
+            self.distribute_message(replies, peer, data);
+        }
+    }
+
+    // A server other than `peer` the channel is linked to. Links join pairs of servers
+    // only: relayed messages aren't relayed any further, so a third server would miss
+    // what the other two post
+    fn other_federation_peer(&self, name: &str, peer: NodeId) -> Option<NodeId> {
+        self.federation
+            .get(name)
+            .and_then(|peers| peers.iter().find(|x| **x != peer))
+            .copied()
+    }
+
+    pub(crate) fn is_federation_peer(&self, id: NodeId) -> bool {
+        self.federation.values().any(|peers| peers.contains(&id))
+    }
+
+    // Passes a message distributed here on to the servers linked to its channel, unless
+    // it came from one of them
+    pub(crate) fn relay_to_federation(
+        &self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        sender: NodeId,
+        data: &MessageData,
+    ) {
+        let Some(name) = self.channels.get_by_left(&data.channel_id) else {
+            return;
+        };
+        let Some(peers) = self.federation.get(name) else {
+            return;
+        };
+        if peers.contains(&sender) {
+            return;
+        }
+        // The channel became private since it was linked
+        if self.federated_channel_id(name).is_none() {
+            debug!(target: self.log_target.as_str(), "Not relaying message of channel {name}, it isn't public anymore");
+            return;
+        }
+        let author = format!("{}@{}", data.username, self.federation_name());
+        for peer in peers {
+            replies.push((
+                *peer,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvFederatedMessage(FederatedMessage {
+                        channel_name: name.clone(),
+                        data: MessageData {
+                            username: author.clone(),
+                            ..data.clone()
+                        },
+                    })),
+                },
+            ));
+        }
+    }
+
+    // Drops the links of a channel about to be deleted, telling the peers
+    pub(crate) fn unfederate_channel(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        name: &str,
+    ) {
+        let Some(peers) = self.federation.remove(name) else {
+            return;
+        };
+        for peer in peers {
+            replies.push((peer, self.federate_message(name, true)));
+            events.push(ServerEvent::FederationUnlinked {
+                peer,
+                channel: name.to_string(),
+            });
+        }
+    }
+
+    fn unlink(&mut self, events: &mut Vec<ServerEvent>, peer: NodeId, name: &str) -> bool {
+        let Some(peers) = self.federation.get_mut(name) else {
+            return false;
+        };
+        if !peers.remove(&peer) {
+            return false;
+        }
+        if peers.is_empty() {
+            self.federation.remove(name);
+        }
+        info!(target: self.log_target.as_str(), "Channel {name} no longer federated with server {peer}");
+        events.push(ServerEvent::FederationUnlinked {
+            peer,
+            channel: name.to_string(),
+        });
+        true
+    }
+
+    // The id of the group channel `name` if it may be mirrored: channels that keep
+    // people out, by invitation or bans, never are
+    fn federated_channel_id(&self, name: &str) -> Option<u64> {
+        self.channels.get_by_right(name).copied().filter(|id| {
+            self.channel_info.get(id).is_some_and(|x| {
+                x.kind == ChannelKind::Group && !x.invite_only && x.banned.is_empty()
+            })
+        })
+    }
+
+    // How users of this server are qualified on linked ones
+    fn federation_name(&self) -> String {
+        self.config
+            .name
+            .clone()
+            .unwrap_or_else(|| self.own_id.to_string())
+    }
+
+    fn federate_message(&self, name: &str, unlink: bool) -> ChatMessage {
+        let federate = Federate {
+            channel_name: name.to_string(),
+            server_name: self.federation_name(),
+        };
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(if unlink {
+                MessageKind::SrvUnfederate(federate)
+            } else {
+                MessageKind::SrvFederate(federate)
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ChatServerConfig;
+
+    fn server(id: NodeId) -> ChatServerInternal {
+        let mut server = ChatServerInternal::with_config(id, ChatServerConfig::default());
+        server.create_group_channel("lobby", None);
+        server
+    }
+
+    // The federate sent to `to` among `replies`
+    fn federate_to(replies: &[(NodeId, ChatMessage)], to: NodeId) -> Federate {
+        replies
+            .iter()
+            .find_map(|(id, msg)| match &msg.message_kind {
+                Some(MessageKind::SrvFederate(federate)) if *id == to => Some(federate.clone()),
+                _ => None,
+            })
+            .expect("no federate sent")
+    }
+
+    fn is_linked(server: &ChatServerInternal, peer: NodeId) -> bool {
+        server
+            .federation
+            .get("lobby")
+            .is_some_and(|peers| peers.contains(&peer))
+    }
+
+    #[test]
+    fn links_come_up_once_both_admins_asked() {
+        let (mut a, mut b) = (server(1), server(2));
+        let (mut replies, mut events) = (vec![], vec![]);
+        a.admin_federate(&mut replies, &mut events, 2, "lobby");
+        let from_a = federate_to(&replies, 2);
+        replies.clear();
+        b.admin_federate(&mut replies, &mut events, 1, "lobby");
+        let from_b = federate_to(&replies, 1);
+        replies.clear();
+        a.msg_srvfederate(&mut replies, &mut events, 2, &from_b);
+        assert!(is_linked(&a, 2));
+        let confirmation = federate_to(&replies, 2);
+        b.msg_srvfederate(&mut replies, &mut events, 1, &from_a);
+        assert!(is_linked(&b, 1));
+        // The confirmation crossing the request changes nothing
+        b.msg_srvfederate(&mut replies, &mut events, 1, &confirmation);
+        assert!(is_linked(&b, 1));
+    }
+
+    #[test]
+    fn federates_the_admin_did_not_ask_for_are_ignored() {
+        let (mut a, mut b) = (server(1), server(2));
+        let (mut replies, mut events) = (vec![], vec![]);
+        a.admin_federate(&mut replies, &mut events, 2, "lobby");
+        let from_a = federate_to(&replies, 2);
+        replies.clear();
+        b.msg_srvfederate(&mut replies, &mut events, 1, &from_a);
+        assert!(!is_linked(&b, 1));
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn invite_only_channels_are_not_federated() {
+        let mut a = server(1);
+        if let Some(id) = a.channels.get_by_right("lobby").copied() {
+            if let Some(info) = a.channel_info.get_mut(&id) {
+                info.invite_only = true;
+            }
+        }
+        let (mut replies, mut events) = (vec![], vec![]);
+        a.admin_federate(&mut replies, &mut events, 2, "lobby");
+        assert!(replies.is_empty());
+        assert!(matches!(
+            events.as_slice(),
+            [ServerEvent::AdminCommandFailed(_)]
+        ));
+    }
+}
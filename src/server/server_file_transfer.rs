@@ -0,0 +1,208 @@
+use crate::protocol::ErrorCode;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, FileAnswer, FileChunk, FileMissing, FileOffer};
+use common::slc_commands::ServerEvent;
+use log::debug;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    pub(crate) fn msg_clifiletransfer(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        cli_node_id: NodeId,
+        kind: MessageKind,
+    ) {
+        match kind {
+            MessageKind::CliFileOffer(offer) => {
+                let peer = offer.peer.clone();
+                self.relay_file_message(replies, events, cli_node_id, &peer, 0, |from| {
+                    MessageKind::SrvFileOffer(FileOffer {
+                        peer: from,
+                        ..offer
+                    })
+                });
+            }
+            MessageKind::CliFileAnswer(answer) => {
+                let peer = answer.peer.clone();
+                self.relay_file_message(replies, events, cli_node_id, &peer, 0, |from| {
+                    MessageKind::SrvFileAnswer(FileAnswer {
+                        peer: from,
+                        ..answer
+                    })
+                });
+            }
+            MessageKind::CliFileChunk(chunk) => {
+                let peer = chunk.peer.clone();
+                let size = chunk.data.len();
+                self.relay_file_message(replies, events, cli_node_id, &peer, size, |from| {
+                    MessageKind::SrvFileChunk(FileChunk {
+                        peer: from,
+                        ..chunk
+                    })
+                });
+            }
+            MessageKind::CliFileMissing(missing) => {
+                let peer = missing.peer.clone();
+                self.relay_file_message(replies, events, cli_node_id, &peer, 0, |from| {
+                    MessageKind::SrvFileMissing(FileMissing {
+                        peer: from,
+                        ..missing
+                    })
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // File transfers go between two registered users without being stored. Every part
+    // names the other user as its peer, the server puts the sender's name there and
+    // passes it on to that user only. `size` is what relaying costs in bandwidth
+    fn relay_file_message(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        cli_node_id: NodeId,
+        peer: &str,
+        size: usize,
+        build: impl FnOnce(String) -> MessageKind,
+    ) {
+        let Some(sender) = self.usernames.get_by_left(&cli_node_id).cloned() else {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotRegistered, "Register before sending files"),
+            ));
+            return;
+        };
+        let Some(target) = self
+            .usernames
+            .get_by_right(peer)
+            .copied()
+            .filter(|x| *x != cli_node_id)
+        else {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::NoSuchUser,
+                    &format!("There's no user named {peer}"),
+                ),
+            ));
+            return;
+        };
+        if !self.charge_bandwidth(events, cli_node_id, size as u64) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::BandwidthExceeded,
+                    "File part not sent, you're sending too much, slow down",
+                ),
+            ));
+            return;
+        }
+        debug!(target: self.log_target.as_str(), "Relaying file transfer message from {sender} to {peer}");
+        replies.push((
+            target,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(build(sender)),
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{errors_to, ChatServerConfig};
+
+    const ALICE: NodeId = 5;
+    const BOB: NodeId = 6;
+
+    fn offer_to(peer: &str) -> MessageKind {
+        MessageKind::CliFileOffer(FileOffer {
+            transfer_id: 3,
+            peer: peer.to_string(),
+            file_name: "notes.txt".to_string(),
+            size: 10,
+            chunk_count: 1,
+        })
+    }
+
+    fn server() -> ChatServerInternal {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        server.register_in(ALICE, "alice", "lobby");
+        server.register_in(BOB, "bob", "lobby");
+        server
+    }
+
+    #[test]
+    fn offers_reach_the_peer_named_after_the_sender() {
+        let mut server = server();
+        let mut replies = vec![];
+        server.msg_clifiletransfer(&mut replies, &mut vec![], ALICE, offer_to("bob"));
+
+        assert_eq!(replies.len(), 1);
+        let (to, msg) = &replies[0];
+        assert_eq!(*to, BOB);
+        let Some(MessageKind::SrvFileOffer(offer)) = &msg.message_kind else {
+            panic!("expected an offer, got {:?}", msg.message_kind);
+        };
+        assert_eq!(offer.peer, "alice");
+        assert_eq!(offer.transfer_id, 3);
+        assert_eq!(offer.file_name, "notes.txt");
+    }
+
+    #[test]
+    fn chunks_and_answers_are_relayed_too() {
+        let mut server = server();
+        let mut replies = vec![];
+        server.msg_clifiletransfer(
+            &mut replies,
+            &mut vec![],
+            BOB,
+            MessageKind::CliFileAnswer(FileAnswer {
+                transfer_id: 3,
+                peer: "alice".to_string(),
+                accepted: true,
+            }),
+        );
+        server.msg_clifiletransfer(
+            &mut replies,
+            &mut vec![],
+            ALICE,
+            MessageKind::CliFileChunk(FileChunk {
+                transfer_id: 3,
+                peer: "bob".to_string(),
+                index: 0,
+                data: b"0123456789".to_vec(),
+            }),
+        );
+
+        assert!(matches!(
+            &replies[..],
+            [
+                (ALICE, ChatMessage { message_kind: Some(MessageKind::SrvFileAnswer(answer)), .. }),
+                (BOB, ChatMessage { message_kind: Some(MessageKind::SrvFileChunk(chunk)), .. }),
+            ] if answer.peer == "bob" && answer.accepted && chunk.peer == "alice" && chunk.data == b"0123456789"
+        ));
+    }
+
+    #[test]
+    fn files_go_only_to_other_registered_users() {
+        let mut server = server();
+        let mut replies = vec![];
+        server.msg_clifiletransfer(&mut replies, &mut vec![], ALICE, offer_to("carol"));
+        server.msg_clifiletransfer(&mut replies, &mut vec![], ALICE, offer_to("alice"));
+        assert_eq!(
+            errors_to(&replies, ALICE),
+            vec![ErrorCode::NoSuchUser, ErrorCode::NoSuchUser]
+        );
+
+        let mut replies = vec![];
+        server.msg_clifiletransfer(&mut replies, &mut vec![], 9, offer_to("bob"));
+        assert_eq!(errors_to(&replies, 9), vec![ErrorCode::NotRegistered]);
+        assert!(replies.iter().all(|(to, _)| *to != BOB));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::server::ChatServerInternal;
+use std::fmt::{Debug, Formatter};
+
+// What a filter wants done with a chat message before it is distributed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecision {
+    // Distribute the message as it is
+    Accept,
+    // Distribute this text instead, later filters see the new text
+    Replace(String),
+    // Keep the message for an operator's approval, as in a moderated channel
+    Hold,
+    // Drop the message and tell the sender why, later filters aren't asked
+    Reject(String),
+}
+
+// Extension point for profanity filters, spam detection and logging. Filters are asked
+// in the order they were added, about every chat message of a registered user
+pub trait MessageFilter: Send {
+    fn filter(&mut self, from: &str, channel: u64, text: &str) -> FilterDecision;
+}
+
+#[derive(Default)]
+pub(crate) struct Filters(Vec<Box<dyn MessageFilter>>);
+
+impl Debug for Filters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Filters({})", self.0.len())
+    }
+}
+
+impl ChatServerInternal {
+    pub fn add_filter(&mut self, filter: Box<dyn MessageFilter>) {
+        self.filters.0.push(filter);
+    }
+
+    // Runs a message through every filter, replacing `text` as they ask. The result is
+    // never Replace
+    pub(crate) fn apply_filters(
+        &mut self,
+        from: &str,
+        channel: u64,
+        text: &mut String,
+    ) -> FilterDecision {
+        let mut hold = false;
+        for filter in &mut self.filters.0 {
+            match filter.filter(from, channel, text) {
+                FilterDecision::Accept => {}
+                FilterDecision::Replace(new_text) => *text = new_text,
+                FilterDecision::Hold => hold = true,
+                FilterDecision::Reject(reason) => return FilterDecision::Reject(reason),
+            }
+        }
+        if hold {
+            FilterDecision::Hold
+        } else {
+            FilterDecision::Accept
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::clock::Instant;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, Empty};
+use log::{debug, info};
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Anything a client sends proves it is alive, a pong is just the cheapest way
+    pub(crate) fn record_heartbeat(&mut self, cli_node_id: NodeId) {
+        self.missed_heartbeats.remove(&cli_node_id);
+        self.unreachable.remove(&cli_node_id);
+    }
+
+    // Pings registered clients and guests once per interval. Guests that missed
+    // `heartbeat_misses` pings in a row are dropped. Registered clients leave their
+    // channels but keep their name, so that direct messages wait for them in the offline
+    // queue, and aren't pinged anymore until they send something
+    pub(crate) fn poll_heartbeats(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        now: Instant,
+    ) {
+        let Some(interval) = self.config.heartbeat_interval else {
+            return;
+        };
+        if self.next_heartbeat.is_some_and(|due| now < due) {
+            return;
+        }
+        self.next_heartbeat = Some(now + interval);
+        let clients = self
+            .usernames
+            .left_values()
+            .filter(|id| !self.unreachable.contains(id))
+            .chain(&self.guests)
+            .copied()
+            .collect::<Vec<_>>();
+        for id in clients {
+            let missed = self.missed_heartbeats.entry(id).or_default();
+            if *missed >= self.config.heartbeat_misses {
+                info!(target: self.log_target.as_str(), "Client {id} missed {missed} heartbeats, dropping it");
+                self.missed_heartbeats.remove(&id);
+                if self.guests.contains(&id) {
+                    self.sessions.remove(&id);
+                } else {
+                    self.unreachable.insert(id);
+                }
+                self.msg_clileave(replies, id);
+                continue;
+            }
+            *missed += 1;
+            debug!(target: self.log_target.as_str(), "Pinging client {id}");
+            replies.push((
+                id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvPing(Empty {})),
+                },
+            ));
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::protocol::ErrorCode;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage, Invite, MemberAction, SetInviteOnly};
+use log::info;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Members invite others to their group channel. Only operators can invite to an
+    // invite-only channel, where nobody else gets in without an invitation
+    pub(crate) fn msg_cliinvite(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &MemberAction,
+    ) {
+        info!(target: self.log_target.as_str(), "Client {cli_node_id} invites {} to channel {}", data.username, data.channel_id);
+        let target = self.usernames.get_by_right(&data.username).copied();
+        let (Some(info), Some(target), Some(from)) = (
+            self.channel_info.get(&data.channel_id),
+            target,
+            self.usernames.get_by_left(&cli_node_id).cloned(),
+        ) else {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::MemberActionInvalid, "No such user or channel"),
+            ));
+            return;
+        };
+        if info.kind != ChannelKind::Group || !info.members.contains(&cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::MemberActionInvalid,
+                    "You can only invite to a group channel you are in",
+                ),
+            ));
+            return;
+        }
+        if info.invite_only && !info.is_operator(cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        if info.members.contains(&target) || info.banned.contains(&target) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::MemberActionInvalid,
+                    &format!("{} can't be invited to this channel", data.username),
+                ),
+            ));
+            return;
+        }
+        let channel_name = self
+            .channels
+            .get_by_left(&data.channel_id)
+            .cloned()
+            .unwrap_or_default();
+        if let Some(info) = self.channel_info.get_mut(&data.channel_id) {
+            info.invited.insert(target);
+        }
+        replies.push((
+            target,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvInviteReceived(Invite {
+                    channel_id: data.channel_id,
+                    channel_name,
+                    from,
+                })),
+            },
+        ));
+    }
+
+    pub(crate) fn msg_clisetinviteonly(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &SetInviteOnly,
+    ) {
+        if !self.is_operator(data.channel_id, cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        let Some(info) = self
+            .channel_info
+            .get_mut(&data.channel_id)
+            .filter(|x| x.kind == ChannelKind::Group)
+        else {
+            return;
+        };
+        info.invite_only = data.invite_only;
+        let text = if data.invite_only {
+            "The channel is now invite-only"
+        } else {
+            "Anyone can join the channel again"
+        };
+        self.announce_in_channel(replies, data.channel_id, text, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{errors_to, ChatServerConfig};
+    use chat_common::messages::JoinChannel;
+
+    const OWNER: NodeId = 5;
+    const BOB: NodeId = 6;
+    const CAROL: NodeId = 7;
+
+    // "secret", made invite-only by its owner alice. bob and carol are in "lobby"
+    fn invite_only_channel() -> (ChatServerInternal, u64) {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let secret = server.register_in(OWNER, "alice", "secret");
+        server.register_in(BOB, "bob", "lobby");
+        server.register_in(CAROL, "carol", "lobby");
+        server.msg_clisetinviteonly(
+            &mut vec![],
+            OWNER,
+            &SetInviteOnly {
+                channel_id: secret,
+                invite_only: true,
+            },
+        );
+        (server, secret)
+    }
+
+    fn invite(
+        server: &mut ChatServerInternal,
+        from: NodeId,
+        channel_id: u64,
+        username: &str,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut replies = vec![];
+        server.msg_cliinvite(
+            &mut replies,
+            from,
+            &MemberAction {
+                channel_id,
+                username: username.to_string(),
+            },
+        );
+        replies
+    }
+
+    fn join(
+        server: &mut ChatServerInternal,
+        cli_node_id: NodeId,
+        channel: &str,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut replies = vec![];
+        server.msg_clijoin(
+            &mut replies,
+            &JoinChannel {
+                channel_id: None,
+                channel_name: channel.to_string(),
+            },
+            cli_node_id,
+        );
+        replies
+    }
+
+    fn is_member(server: &ChatServerInternal, channel_id: u64, id: NodeId) -> bool {
+        server.channel_info[&channel_id].members.contains(&id)
+    }
+
+    #[test]
+    fn invite_only_channels_need_an_invitation() {
+        let (mut server, secret) = invite_only_channel();
+        let replies = join(&mut server, BOB, "secret");
+        assert_eq!(errors_to(&replies, BOB), vec![ErrorCode::NotInvited]);
+        assert!(!is_member(&server, secret, BOB));
+    }
+
+    #[test]
+    fn invitations_are_delivered_and_let_in_once() {
+        let (mut server, secret) = invite_only_channel();
+        let replies = invite(&mut server, OWNER, secret, "bob");
+        assert!(replies.iter().any(|(to, msg)| *to == BOB
+            && matches!(
+                &msg.message_kind,
+                Some(MessageKind::SrvInviteReceived(x))
+                    if x.channel_id == secret && x.channel_name == "secret" && x.from == "alice"
+            )));
+        join(&mut server, BOB, "secret");
+        assert!(is_member(&server, secret, BOB));
+        join(&mut server, BOB, "lobby");
+        let replies = join(&mut server, BOB, "secret");
+        assert_eq!(errors_to(&replies, BOB), vec![ErrorCode::NotInvited]);
+    }
+
+    #[test]
+    fn only_operators_invite_to_invite_only_channels() {
+        let (mut server, secret) = invite_only_channel();
+        invite(&mut server, OWNER, secret, "bob");
+        join(&mut server, BOB, "secret");
+        let replies = invite(&mut server, BOB, secret, "carol");
+        assert_eq!(errors_to(&replies, BOB), vec![ErrorCode::NotOperator]);
+        server.msg_clisetinviteonly(
+            &mut vec![],
+            OWNER,
+            &SetInviteOnly {
+                channel_id: secret,
+                invite_only: false,
+            },
+        );
+        let replies = invite(&mut server, BOB, secret, "carol");
+        assert!(errors_to(&replies, BOB).is_empty());
+    }
+
+    #[test]
+    fn members_and_outsiders_cannot_invite_or_be_invited() {
+        let (mut server, secret) = invite_only_channel();
+        let replies = invite(&mut server, OWNER, secret, "alice");
+        assert_eq!(
+            errors_to(&replies, OWNER),
+            vec![ErrorCode::MemberActionInvalid]
+        );
+        let replies = invite(&mut server, BOB, secret, "carol");
+        assert_eq!(
+            errors_to(&replies, BOB),
+            vec![ErrorCode::MemberActionInvalid]
+        );
+        let replies = invite(&mut server, OWNER, secret, "nobody");
+        assert_eq!(
+            errors_to(&replies, OWNER),
+            vec![ErrorCode::MemberActionInvalid]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::protocol::{message_words, ErrorCode, ALL_CHANNEL_ID};
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, KeywordSubscription, MessageData};
+use log::{debug, info};
+use wg_2024::network::NodeId;
+
+// Keywords a single client may watch
+const MAX_KEYWORDS: usize = 16;
+
+impl ChatServerInternal {
+    pub(crate) fn msg_clisubscribekeyword(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        sub: &KeywordSubscription,
+    ) {
+        info!(target: self.log_target.as_str(), "Received keyword subscription from client {cli_node_id}: {sub:?}");
+        if !self.usernames.contains_left(&cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::NotRegistered,
+                    "Register before watching keywords",
+                ),
+            ));
+            return;
+        }
+        let keyword = sub.keyword.trim().to_lowercase();
+        if keyword.is_empty() || keyword.contains(|c: char| !c.is_alphanumeric()) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::KeywordInvalid, "A keyword is a single word"),
+            ));
+            return;
+        }
+        let keywords = self.keywords.entry(cli_node_id).or_default();
+        if !sub.subscribe {
+            keywords.remove(&keyword);
+            if keywords.is_empty() {
+                self.keywords.remove(&cli_node_id);
+            }
+            return;
+        }
+        if keywords.len() >= MAX_KEYWORDS && !keywords.contains(&keyword) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::TooManyKeywords,
+                    &format!("You can watch at most {MAX_KEYWORDS} keywords"),
+                ),
+            ));
+            return;
+        }
+        keywords.insert(keyword);
+    }
+
+    // Also sends a message of the All channel to registered clients outside of it that
+    // watch one of its words and may read it. Group channels may keep people out, so
+    // their messages are never forwarded, nor are private ones
+    pub(crate) fn forward_to_watchers(
+        &self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        sender: NodeId,
+        data: &MessageData,
+    ) {
+        if self.keywords.is_empty() {
+            return;
+        }
+        let Some(channel_data) = self
+            .channel_info
+            .get(&data.channel_id)
+            .filter(|_| data.channel_id == ALL_CHANNEL_ID)
+        else {
+            return;
+        };
+        let words = message_words(&data.message);
+        for (id, keywords) in &self.keywords {
+            if *id == sender
+                || channel_data.members.contains(id)
+                || !self.usernames.contains_left(id)
+                || !channel_data.admits(*id)
+                || !keywords.iter().any(|x| words.contains(x))
+            {
+                continue;
+            }
+            debug!(target: self.log_target.as_str(), "Forwarding message {} to watcher {id}", data.message_id);
+            replies.push((
+                *id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvDistributeMessage(data.clone())),
+                },
+            ));
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use crate::server::ChatServerInternal;
+use crate::protocol::{
+    personal_channel_id, resolve_channel_kind, user_color, ErrorCode, ReceiptStatus, ALL_CHANNEL_ID,
+};
+use crate::server::server_sessions::SessionState;
+use crate::server::{ChannelInfo, ChatServerInternal, FilterDecision};
 use chat_common::messages::chat_message::MessageKind;
 use chat_common::messages::{
-    ChatMessage, ConfirmRegistration, ErrorMessage, JoinChannel, MessageData, SendMessage,
+    ChannelKind, ChatMessage, ConfirmRegistration, FetchHistory, HistoryPage, JoinChannel,
+    MessageData, SearchMessages, SearchResults, SendMessage, SetWelcome, TypingNotification,
+    TypingStatus,
 };
+use common::slc_commands::ServerEvent;
 use log::{debug, info, trace};
-use rand::{rng, RngCore};
-use std::collections::HashSet;
 use wg_2024::network::NodeId;
 
 impl ChatServerInternal {
@@ -15,147 +20,424 @@ impl ChatServerInternal {
         data: &JoinChannel,
         cli_node_id: NodeId,
     ) {
-        info!(target: format!("Server {}", self.own_id).as_str(), "Received join request: {data:?}");
+        info!(target: self.log_target.as_str(), "Received join request: {data:?}");
+        if !self.take_rate_token(replies, cli_node_id) {
+            return;
+        }
+        let now = self.wall_now();
+        let cooldown = self.kick_cooldown_ms();
         let channelinfo;
         let channel_id;
+        let mut created = false;
         if let (Some(id), Some(data)) = (
             data.channel_id,
             data.channel_id
                 .and_then(|id| self.channel_info.get_mut(&id)),
         ) {
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Joining channel by ID {id}");
+            debug!(target: self.log_target.as_str(), "Joining channel by ID {id}");
             channelinfo = data;
             channel_id = id;
-        } else if let (Some(id), Some(cdata)) = (
-            self.channels.get_by_right(&data.channel_name),
-            self.channels
-                .get_by_right(&data.channel_name)
-                .and_then(|id| self.channel_info.get_mut(id)),
-        ) {
+        } else if let Some((id, cdata)) = self
+            .channels
+            .get_by_right(&data.channel_name)
+            .copied()
+            .or_else(|| self.find_group_channel(&data.channel_name))
+            .and_then(|id| Some((id, self.channel_info.get_mut(&id)?)))
+        {
             channelinfo = cdata;
-            channel_id = *id;
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Joining channel by name {}({id})",data.channel_name);
+            channel_id = id;
+            debug!(target: self.log_target.as_str(), "Joining channel by name {}({id})",data.channel_name);
+        } else if !data.channel_name.is_empty() && !self.may_create_channel(cli_node_id) {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} may not create channel {}", data.channel_name);
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelCreationForbidden,
+                    "You're not allowed to create channels on this server",
+                ),
+            ));
+            return;
         } else if !data.channel_name.is_empty() {
-            let mut id = rng().next_u64() & 0xFFFF_FFFF_FFFF_FFF0 | 0x2;
-            while self.channels.contains_left(&id) || self.channel_info.contains_key(&id) {
-                id = rng().next_u64() & 0xFFFF_FFFF_FFFF_FFF0 | 0x2;
-            }
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Creating new channel with ID {id} and name {}", data.channel_name);
-            self.channels.insert(id, data.channel_name.clone());
-            self.channel_info.insert(id, (true, HashSet::new()));
+            let id = self.create_group_channel(&data.channel_name, Some(cli_node_id));
             // This is safe, since we just inserted the channel
             channelinfo = self.channel_info.get_mut(&id).unwrap();
             channel_id = id;
+            created = true;
             replies.push((
                 cli_node_id,
                 ChatMessage {
                     own_id: self.own_id.into(),
+                    request_id: 0,
                     message_kind: Some(MessageKind::SrvChannelCreationSuccessful(channel_id)),
                 },
             ));
         } else {
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Invalid channel join request from client {cli_node_id}");
+            debug!(target: self.log_target.as_str(), "Invalid channel join request from client {cli_node_id}");
             replies.push((
                 cli_node_id,
-                ChatMessage {
-                    own_id: self.own_id.into(),
-                    message_kind: Some(MessageKind::Err(ErrorMessage {
-                        error_type: "CHANNEL_NOT_EXISTS".to_string(),
-                        error_message: "Channel with that ID doesn't exist".to_string(),
-                    })),
-                },
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    "Channel with that ID doesn't exist",
+                ),
             ));
             return;
         }
-        if channelinfo.1.contains(&cli_node_id) {
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Client {cli_node_id} is already in channel {channel_id}");
+        // Personal channels only ever hold their owner, and their ids are easy to guess
+        if channelinfo.kind == ChannelKind::Personal
+            && channel_id != personal_channel_id(cli_node_id)
+        {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} tried to join personal channel {channel_id}");
             replies.push((
                 cli_node_id,
-                ChatMessage {
-                    own_id: self.own_id.into(),
-                    message_kind: Some(MessageKind::Err(ErrorMessage {
-                        error_type: "CHANNEL_ALREADY_JOINED".to_string(),
-                        error_message: "Channel was already joined!".to_string(),
-                    })),
-                },
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    "Channel with that ID doesn't exist",
+                ),
+            ));
+        } else if channelinfo.banned.contains(&cli_node_id) {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} is banned from channel {channel_id}");
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::BannedFromChannel,
+                    "You are banned from this channel",
+                ),
+            ));
+        } else if let Some(wait_ms) = channelinfo
+            .kicked
+            .get(&cli_node_id)
+            .map(|at| at.saturating_add(cooldown).saturating_sub(now))
+            .filter(|x| *x != 0)
+        {
+            replies.push((
+                cli_node_id,
+                self.kick_cooldown_error(cli_node_id, channel_id, wait_ms),
+            ));
+        } else if channelinfo.invite_only
+            && !channelinfo.is_operator(cli_node_id)
+            && !channelinfo.invited.contains(&cli_node_id)
+        {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} isn't invited to channel {channel_id}");
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::NotInvited,
+                    "This channel is invite-only and you weren't invited",
+                ),
+            ));
+        } else if channelinfo.members.contains(&cli_node_id) {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} is already in channel {channel_id}");
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelAlreadyJoined,
+                    "Channel was already joined!",
+                ),
             ));
         } else {
             {
-                channelinfo.1.insert(cli_node_id);
+                channelinfo.members.insert(cli_node_id);
+                channelinfo.invited.remove(&cli_node_id);
+            }
+            let mut left = vec![];
+            for val in self
+                .channel_info
+                .iter_mut()
+                .filter(|(id, x)| x.kind == ChannelKind::Group && **id != channel_id)
+            {
+                trace!(target: self.log_target.as_str(), "Removing client {cli_node_id} from channel {}", val.0);
+                if val.1.members.remove(&cli_node_id) {
+                    left.push(*val.0);
+                }
             }
-            for val in self.channel_info.iter_mut().filter(|(id, _x)| {
-                **id != 0x1 && **id != u64::from(cli_node_id) << 32 | 0x8 && **id != channel_id
-            }) {
-                trace!(target: format!("Server {}", self.own_id).as_str(), "Removing client {cli_node_id} from channel {}", val.0);
-                val.1 .1.remove(&cli_node_id);
+            if let Some(username) = self.usernames.get_by_left(&cli_node_id).cloned() {
+                for id in &left {
+                    self.announce_in_channel(
+                        replies,
+                        *id,
+                        &format!("{username} left"),
+                        Some(cli_node_id),
+                    );
+                }
+                self.announce_in_channel(
+                    replies,
+                    channel_id,
+                    &format!("{username} joined"),
+                    Some(cli_node_id),
+                );
             }
-            trace!(target: format!("Server {}", self.own_id).as_str(), "Client {cli_node_id} is joining channel {channel_id}");
+            trace!(target: self.log_target.as_str(), "Client {cli_node_id} is joining channel {channel_id}");
             replies.push((
                 cli_node_id,
                 ChatMessage {
                     own_id: self.own_id.into(),
+                    request_id: 0,
                     message_kind: Some(MessageKind::SrvChannelCreationSuccessful(channel_id)),
                 },
             ));
-            replies.extend_from_slice(self.generate_channel_updates().as_slice());
+            left.push(channel_id);
+            // A new channel is news to everybody
+            let scope = (!created).then_some((cli_node_id, left.as_slice()));
+            replies.extend_from_slice(self.generate_channel_updates(scope).as_slice());
+            if let Some(welcome) = self
+                .channel_info
+                .get(&channel_id)
+                .and_then(|info| info.welcome.clone())
+            {
+                trace!(target: self.log_target.as_str(), "Sending welcome message of channel {channel_id} to client {cli_node_id}");
+                let timestamp = self.next_timestamp();
+                replies.push((
+                    cli_node_id,
+                    self.system_message(channel_id, timestamp, &welcome),
+                ));
+            }
         }
     }
 
-    pub(crate) fn msg_sendmsg(
-        &self,
+    pub(crate) fn msg_clisetwelcome(
+        &mut self,
         replies: &mut Vec<(NodeId, ChatMessage)>,
         cli_node_id: NodeId,
-        msg: &SendMessage,
+        data: SetWelcome,
     ) {
-        info!(target: format!("Server {}", self.own_id).as_str(), "Received message: {msg:?}");
-        match (
-            self.channel_info.get(&msg.channel_id),
-            self.usernames.get_by_left(&cli_node_id),
-        ) {
-            (Some(channel_data), Some(username)) => {
-                debug!(target: format!("Server {}", self.own_id).as_str(), "Forwarding message sent by {username}");
-                for id in channel_data.1.iter().filter(|x| **x != cli_node_id) {
-                    trace!(target: format!("Server {}", self.own_id).as_str(), "Forwarding message to client {id}");
-                    replies.push((
-                        *id,
-                        ChatMessage {
-                            own_id: u32::from(self.own_id),
-                            message_kind: Some(MessageKind::SrvDistributeMessage(MessageData {
-                                username: username.clone(),
-                                timestamp: chrono::Utc::now().timestamp_millis().unsigned_abs(),
-                                message: msg.message.clone(),
-                                channel_id: msg.channel_id,
-                            })),
-                        },
-                    ));
-                }
+        info!(target: self.log_target.as_str(), "Received welcome message update: {data:?}");
+        if self.guests.contains(&cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::GuestsReadOnly,
+                    "Guests can't change channels, register first",
+                ),
+            ));
+            return;
+        }
+        match self.channel_info.get_mut(&data.channel_id) {
+            // Channels nobody runs can be changed by any member
+            Some(info)
+                if info.operator_ids().next().is_some() && !info.is_operator(cli_node_id) =>
+            {
+                replies.push((
+                    cli_node_id,
+                    self.error_message(
+                        ErrorCode::NotOperator,
+                        "Only channel operators can do that",
+                    ),
+                ));
             }
-            (_, None) => {
-                debug!(target: format!("Server {}", self.own_id).as_str(), "Client {cli_node_id} is not registered");
+            Some(info)
+                if info.kind == ChannelKind::Group && info.members.contains(&cli_node_id) =>
+            {
+                debug!(target: self.log_target.as_str(), "Client {cli_node_id} set the welcome message of channel {}", data.channel_id);
+                info.welcome = Some(data.text).filter(|x| !x.is_empty());
+                let change = match &info.welcome {
+                    Some(welcome) => format!("changed the welcome message to: {welcome}"),
+                    None => "removed the welcome message".to_string(),
+                };
+                let username = self
+                    .usernames
+                    .get_by_left(&cli_node_id)
+                    .cloned()
+                    .unwrap_or_else(|| format!("Client {cli_node_id}"));
+                self.announce_in_channel(
+                    replies,
+                    data.channel_id,
+                    &format!("{username} {change}"),
+                    None,
+                );
+            }
+            _ => {
+                debug!(target: self.log_target.as_str(), "Client {cli_node_id} can't set the welcome message of channel {}", data.channel_id);
                 replies.push((
                     cli_node_id,
-                    ChatMessage {
-                        own_id: self.own_id.into(),
-                        message_kind: Some(MessageKind::Err(ErrorMessage {
-                            error_type: "NOT_REGISTERED".to_string(),
-                            error_message: "Can't send message, you're not registered".to_string(),
-                        })),
-                    },
+                    self.error_message(
+                        ErrorCode::ChannelNotExists,
+                        "Can't set welcome message, you're not in that channel",
+                    ),
                 ));
             }
-            (None, Some(_)) => {
-                debug!(target: format!("Server {}", self.own_id).as_str(), "Channel doesn't exist");
+        }
+    }
+
+    // Joins a channel as a guest, who can read it but not send. Registered clients are
+    // full members anyway. Only public group channels can be watched, guests never
+    // create channels
+    pub(crate) fn msg_clispectate(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        data: &JoinChannel,
+        cli_node_id: NodeId,
+    ) {
+        let public = data
+            .channel_id
+            .or_else(|| self.channels.get_by_right(&data.channel_name).copied())
+            .and_then(|id| self.channel_info.get(&id))
+            .is_some_and(|x| x.kind == ChannelKind::Group && !x.invite_only);
+        if !public {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} can't spectate {data:?}");
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    "Only public group channels can be watched",
+                ),
+            ));
+            return;
+        }
+        if !self.usernames.contains_left(&cli_node_id) {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} is spectating as a guest");
+            self.guests.insert(cli_node_id);
+        }
+        self.msg_clijoin(replies, data, cli_node_id);
+    }
+
+    // The error for a message of `len` bytes `poster` may not post in a channel now, because
+    // it's too long, the channel is read-only or slow mode holds the poster back
+    pub(crate) fn post_refusal(
+        &self,
+        channel_id: u64,
+        poster: NodeId,
+        len: usize,
+    ) -> Option<ChatMessage> {
+        if len > self.config.max_message_len {
+            return Some(self.error_message(
+                ErrorCode::MessageTooLong,
+                &format!(
+                    "Messages can be up to {} bytes long",
+                    self.config.max_message_len
+                ),
+            ));
+        }
+        if self
+            .channel_info
+            .get(&channel_id)
+            .is_some_and(|x| x.read_only && !x.is_operator(poster))
+        {
+            return Some(self.error_message(
+                ErrorCode::ChannelReadOnly,
+                "Only operators can send messages in this channel",
+            ));
+        }
+        let wait = self.slow_mode_wait(channel_id, poster, self.wall_now());
+        (wait > 0).then(|| self.slow_mode_error(poster, channel_id, wait))
+    }
+
+    // Returns what became of the message
+    pub(crate) fn msg_sendmsg(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        cli_node_id: NodeId,
+        msg: &SendMessage,
+    ) -> ReceiptStatus {
+        info!(target: self.log_target.as_str(), "Received message: {msg:?}");
+        if self.guests.contains(&cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::GuestsReadOnly,
+                    "Guests can't send messages, register first",
+                ),
+            ));
+            return ReceiptStatus::Rejected;
+        }
+        if let Some(refusal) = self.post_refusal(msg.channel_id, cli_node_id, msg.message.len()) {
+            replies.push((cli_node_id, refusal));
+            return ReceiptStatus::Rejected;
+        }
+        if !self.take_rate_token(replies, cli_node_id) {
+            return ReceiptStatus::Rejected;
+        }
+        let Some(username) = self.usernames.get_by_left(&cli_node_id).cloned() else {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} is not registered");
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::NotRegistered,
+                    "Can't send message, you're not registered",
+                ),
+            ));
+            return ReceiptStatus::Rejected;
+        };
+        let Some(channel_data) = self.channel_info.get(&msg.channel_id) else {
+            debug!(target: self.log_target.as_str(), "Channel doesn't exist");
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    "Can't send message, channel doesn't exist",
+                ),
+            ));
+            return ReceiptStatus::Rejected;
+        };
+        if resolve_channel_kind(msg.channel_id, msg.channel_kind) != Some(channel_data.kind) {
+            debug!(target: self.log_target.as_str(), "Message kind {:?} doesn't match channel {}", msg.channel_kind, msg.channel_id);
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelKindMismatch,
+                    "Can't send message, wrong channel kind",
+                ),
+            ));
+            return ReceiptStatus::Rejected;
+        }
+        // Relaying costs the message size once per recipient
+        let recipients = channel_data.members.len().saturating_sub(1);
+        let mut text = msg.message.clone();
+        let hold = match self.apply_filters(&username, msg.channel_id, &mut text) {
+            FilterDecision::Reject(reason) => {
+                debug!(target: self.log_target.as_str(), "Message from {username} rejected by a filter: {reason}");
                 replies.push((
                     cli_node_id,
-                    ChatMessage {
-                        own_id: self.own_id.into(),
-                        message_kind: Some(MessageKind::Err(ErrorMessage {
-                            error_type: "CHANNEL_NOT_EXISTS".to_string(),
-                            error_message: "Can't send message, channel doesn't exist".to_string(),
-                        })),
-                    },
+                    self.error_message(ErrorCode::MessageRejected, &reason),
                 ));
+                return ReceiptStatus::Rejected;
+            }
+            FilterDecision::Hold => true,
+            FilterDecision::Accept | FilterDecision::Replace(_) => false,
+        };
+        // Only messages that would go out use up the quota
+        let cost = (text.len() * recipients) as u64;
+        if !self.charge_bandwidth(events, cli_node_id, cost) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::BandwidthExceeded,
+                    "Message not sent, you're sending too much, slow down",
+                ),
+            ));
+            return ReceiptStatus::Rejected;
+        }
+        let timestamp = self.next_timestamp();
+        let Some(channel_data) = self.channel_info.get_mut(&msg.channel_id) else {
+            return ReceiptStatus::Rejected;
+        };
+        let data = MessageData {
+            username: username.clone(),
+            timestamp,
+            message: text,
+            channel_id: msg.channel_id,
+            channel_kind: channel_data.kind,
+            color: user_color(&username),
+            message_id: timestamp,
+            content_warning: msg.content_warning.clone(),
+            signature: msg.signature.clone(),
+            signer_key: msg.signer_key.clone(),
+            signed_at: msg.signed_at,
+            local_id: msg.local_id,
+        };
+        if channel_data.slow_mode != 0 {
+            channel_data.last_post.insert(cli_node_id, timestamp);
+        }
+        if hold || (channel_data.moderated && !channel_data.is_operator(cli_node_id)) {
+            if self.hold_for_approval(replies, cli_node_id, data) {
+                ReceiptStatus::Pending
+            } else {
+                ReceiptStatus::Rejected
             }
+        } else {
+            debug!(target: self.log_target.as_str(), "Forwarding message sent by {username}");
+            self.distribute_message(replies, cli_node_id, data);
+            ReceiptStatus::Delivered
         }
     }
 
@@ -165,57 +447,116 @@ impl ChatServerInternal {
         cli_node_id: NodeId,
         req: String,
     ) {
-        info!(target: format!("Server {}", self.own_id).as_str(), "Received register request: {req:?}");
-        if self.usernames.contains_left(&cli_node_id) {
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Client {cli_node_id} already registered");
+        info!(target: self.log_target.as_str(), "Received register request: {req:?}");
+        // A client reconnecting after losing us registers again under the same name
+        if self.usernames.get_by_left(&cli_node_id) == Some(&req) {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} registers again as {req}");
             replies.push((
                 cli_node_id,
                 ChatMessage {
                     own_id: self.own_id.into(),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvConfirmReg(ConfirmRegistration {
+                        successful: true,
+                        error: None,
+                        username: req,
+                        suggestions: vec![],
+                    })),
+                },
+            ));
+            self.set_session_state(cli_node_id, SessionState::Registered);
+            replies.extend_from_slice(self.generate_channel_updates(None).as_slice());
+            // The client starts counting its messages afresh
+            self.acked_messages.insert(cli_node_id, 0);
+            replies.push((cli_node_id, self.flow_credit(0)));
+            self.flush_offline_messages(replies, cli_node_id);
+        } else if self.usernames.contains_left(&cli_node_id) {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} already registered");
+            replies.push((
+                cli_node_id,
+                ChatMessage {
+                    own_id: self.own_id.into(),
+                    request_id: 0,
                     message_kind: Some(MessageKind::SrvConfirmReg(ConfirmRegistration {
                         successful: false,
                         error: Some("Client already registered".to_string()),
                         username: req,
+                        suggestions: vec![],
                     })),
                 },
             ));
-        } else if self.usernames.contains_right(&req) {
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Username {req} already exists");
+        } else if self
+            .config
+            .capacity
+            .is_some_and(|cap| self.usernames.len() >= cap as usize)
+        {
+            debug!(target: self.log_target.as_str(), "Server is full, refusing client {cli_node_id}");
             replies.push((
                 cli_node_id,
                 ChatMessage {
                     own_id: self.own_id.into(),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvConfirmReg(ConfirmRegistration {
+                        successful: false,
+                        error: Some("Server is full".to_string()),
+                        username: req,
+                        suggestions: vec![],
+                    })),
+                },
+            ));
+        } else if !self.is_username_available(&req) {
+            debug!(target: self.log_target.as_str(), "Username {req} already exists");
+            replies.push((
+                cli_node_id,
+                ChatMessage {
+                    own_id: self.own_id.into(),
+                    request_id: 0,
                     message_kind: Some(MessageKind::SrvConfirmReg(ConfirmRegistration {
                         successful: false,
                         error: Some("Username already exists".to_string()),
+                        suggestions: self.username_suggestions(&req),
                         username: req,
                     })),
                 },
             ));
         } else {
-            debug!(target: format!("Server {}", self.own_id).as_str(), "Registering client {cli_node_id} with username {req}");
+            debug!(target: self.log_target.as_str(), "Registering client {cli_node_id} with username {req}");
             replies.push((
                 cli_node_id,
                 ChatMessage {
                     own_id: self.own_id.into(),
+                    request_id: 0,
                     message_kind: Some(MessageKind::SrvConfirmReg(ConfirmRegistration {
                         successful: true,
                         error: None,
                         username: req.clone(),
+                        suggestions: vec![],
                     })),
                 },
             ));
             self.usernames.insert(cli_node_id, req.clone());
+            self.counters.registrations += 1;
+            self.guests.remove(&cli_node_id);
+            self.set_session_state(cli_node_id, SessionState::Registered);
+            let wall_now = self.wall_now();
+            self.registered_at.insert(cli_node_id, wall_now);
+            self.last_seen.insert(cli_node_id, wall_now);
             self.channel_info
-                .get_mut(&0x1)
-                .map(|x| x.1.insert(cli_node_id));
-            self.channels
-                .insert(u64::from(cli_node_id) << 32 | 0x8, req);
+                .get_mut(&ALL_CHANNEL_ID)
+                .map(|x| x.members.insert(cli_node_id));
+            self.channels.insert(personal_channel_id(cli_node_id), req);
             self.channel_info.insert(
-                u64::from(cli_node_id) << 32 | 0x8,
-                (false, map_macro::hash_set! {cli_node_id}),
+                personal_channel_id(cli_node_id),
+                ChannelInfo::new(ChannelKind::Personal, map_macro::hash_set! {cli_node_id}),
             );
-            replies.extend_from_slice(self.generate_channel_updates().as_slice());
+            replies.extend_from_slice(self.generate_channel_updates(None).as_slice());
+            self.acked_messages.insert(cli_node_id, 0);
+            replies.push((cli_node_id, self.flow_credit(0)));
+            if let Some(notice) = self.motd_notice() {
+                replies.push((cli_node_id, notice));
+            }
+            self.send_missed_activity(replies, cli_node_id);
+            self.flush_offline_messages(replies, cli_node_id);
         }
     }
 
@@ -224,14 +565,104 @@ impl ChatServerInternal {
         replies: &mut Vec<(NodeId, ChatMessage)>,
         cli_node_id: NodeId,
     ) {
-        info!(target: format!("Server {}", self.own_id).as_str(), "Received cancel registration request");
-        for val in self.channel_info.values_mut() {
-            val.1.retain(|&x| x != cli_node_id);
+        info!(target: self.log_target.as_str(), "Received cancel registration request");
+        let mut left = vec![];
+        for (id, val) in &mut self.channel_info {
+            val.invited.remove(&cli_node_id);
+            if val.members.remove(&cli_node_id) {
+                left.push(*id);
+            }
+        }
+        if let Some(username) = self.usernames.get_by_left(&cli_node_id).cloned() {
+            self.record_departure(&username, &left);
+            for id in &left {
+                self.announce_in_channel(
+                    replies,
+                    *id,
+                    &format!("{username} left"),
+                    Some(cli_node_id),
+                );
+            }
         }
         self.channels
-            .remove_by_left(&(u64::from(cli_node_id) << 32 | 0x8));
+            .remove_by_left(&personal_channel_id(cli_node_id));
         self.usernames.remove_by_left(&cli_node_id);
-        replies.extend_from_slice(self.generate_channel_updates().as_slice());
+        self.registered_at.remove(&cli_node_id);
+        self.last_seen.remove(&cli_node_id);
+        self.acked_messages.remove(&cli_node_id);
+        self.keywords.remove(&cli_node_id);
+        self.statuses.remove(&cli_node_id);
+        self.recent_sends.remove(&cli_node_id);
+        self.unreachable.remove(&cli_node_id);
+        self.set_session_state(cli_node_id, SessionState::Welcomed);
+        replies.extend_from_slice(
+            self.generate_channel_updates(Some((cli_node_id, &left)))
+                .as_slice(),
+        );
+    }
+
+    // Renames a registered user in place, keeping its channels and personal channel
+    pub(crate) fn msg_clichangeusername(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        new_name: String,
+    ) {
+        info!(target: self.log_target.as_str(), "Received username change request: {new_name:?}");
+        let Some(old_name) = self.usernames.get_by_left(&cli_node_id).cloned() else {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::NotRegistered,
+                    "Register before changing your username",
+                ),
+            ));
+            return;
+        };
+        if new_name.is_empty() || new_name.contains([' ', '#', '@']) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::UsernameInvalid,
+                    "Username cannot be empty or contain spaces, '#' or '@'",
+                ),
+            ));
+            return;
+        }
+        if !self.is_username_available(&new_name) || self.channels.contains_right(&new_name) {
+            debug!(target: self.log_target.as_str(), "Username {new_name} already exists");
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::UsernameTaken, "Username already exists"),
+            ));
+            return;
+        }
+        debug!(target: self.log_target.as_str(), "Renaming client {cli_node_id} from {old_name} to {new_name}");
+        self.usernames.insert(cli_node_id, new_name.clone());
+        self.channels
+            .insert(personal_channel_id(cli_node_id), new_name.clone());
+        replies.push((
+            cli_node_id,
+            ChatMessage {
+                own_id: self.own_id.into(),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvConfirmReg(ConfirmRegistration {
+                    successful: true,
+                    error: None,
+                    username: new_name.clone(),
+                    suggestions: vec![],
+                })),
+            },
+        ));
+        if let Some(channel_id) = self.current_group_channel(cli_node_id) {
+            self.announce_in_channel(
+                replies,
+                channel_id,
+                &format!("{old_name} is now known as {new_name}"),
+                Some(cli_node_id),
+            );
+        }
+        replies.extend_from_slice(self.generate_channel_updates(None).as_slice());
     }
 
     pub(crate) fn msg_clileave(
@@ -239,15 +670,260 @@ impl ChatServerInternal {
         replies: &mut Vec<(NodeId, ChatMessage)>,
         cli_node_id: NodeId,
     ) {
-        info!(target: format!("Server {}", self.own_id).as_str(), "Received leave request from client {cli_node_id}");
+        info!(target: self.log_target.as_str(), "Received leave request from client {cli_node_id}");
+        let mut left = vec![];
         for val in self
             .channel_info
             .iter_mut()
-            .filter(|(id, _x)| **id != 0x1 && **id != u64::from(cli_node_id) << 32 | 0x8)
+            .filter(|(_, x)| x.kind == ChannelKind::Group)
         {
-            trace!(target: format!("Server {}", self.own_id).as_str(), "Removing client {cli_node_id} from channel {}", val.0);
-            val.1 .1.remove(&cli_node_id);
+            trace!(target: self.log_target.as_str(), "Removing client {cli_node_id} from channel {}", val.0);
+            if val.1.members.remove(&cli_node_id) {
+                left.push(*val.0);
+            }
+        }
+        if let Some(username) = self.usernames.get_by_left(&cli_node_id).cloned() {
+            for id in &left {
+                self.announce_in_channel(
+                    replies,
+                    *id,
+                    &format!("{username} left"),
+                    Some(cli_node_id),
+                );
+            }
+        }
+        // Guests are only ever in the one group channel they watch
+        if self.guests.remove(&cli_node_id) {
+            debug!(target: self.log_target.as_str(), "Guest {cli_node_id} stopped spectating");
+            self.last_seen.remove(&cli_node_id);
+        }
+        replies.extend_from_slice(
+            self.generate_channel_updates(Some((cli_node_id, &left)))
+                .as_slice(),
+        );
+    }
+
+    pub(crate) fn msg_clityping(
+        &self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &TypingNotification,
+    ) {
+        trace!(target: self.log_target.as_str(), "Received typing notification: {data:?}");
+        // Typing notifications are best-effort, invalid ones are dropped silently
+        let (Some(info), Some(username)) = (
+            self.channel_info.get(&data.channel_id),
+            self.usernames.get_by_left(&cli_node_id),
+        ) else {
+            return;
+        };
+        if info.kind != ChannelKind::Group || !info.members.contains(&cli_node_id) {
+            return;
+        }
+        for id in info.members.iter().filter(|x| **x != cli_node_id) {
+            replies.push((
+                *id,
+                ChatMessage {
+                    own_id: self.own_id.into(),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvTyping(TypingStatus {
+                        username: username.clone(),
+                        channel_id: data.channel_id,
+                        typing: data.typing,
+                    })),
+                },
+            ));
         }
-        replies.extend_from_slice(self.generate_channel_updates().as_slice());
+    }
+
+    pub(crate) fn msg_clifetchhistory(
+        &self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        req: &FetchHistory,
+    ) {
+        info!(target: self.log_target.as_str(), "Received history request: {req:?}");
+        let Some(info) = self
+            .channel_info
+            .get(&req.channel_id)
+            .filter(|info| info.members.contains(&cli_node_id))
+        else {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} can't read history of channel {}", req.channel_id);
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    "Can't read history, you're not in that channel",
+                ),
+            ));
+            return;
+        };
+        let limit = req.limit.clamp(1, self.config.history_page_size) as usize;
+        let older = info
+            .history
+            .iter()
+            .filter(|x| {
+                req.before_message_id
+                    .is_none_or(|before| x.message_id < before)
+            })
+            .collect::<Vec<_>>();
+        let page = older[older.len().saturating_sub(limit)..]
+            .iter()
+            .map(|x| (*x).clone())
+            .collect::<Vec<_>>();
+        debug!(target: self.log_target.as_str(), "Sending {} history messages of channel {}", page.len(), req.channel_id);
+        replies.push((
+            cli_node_id,
+            ChatMessage {
+                own_id: self.own_id.into(),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvHistory(HistoryPage {
+                    channel_id: req.channel_id,
+                    has_more: older.len() > page.len(),
+                    messages: page,
+                })),
+            },
+        ));
+    }
+
+    pub(crate) fn msg_clisearchmessages(
+        &self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        req: &SearchMessages,
+    ) {
+        info!(target: self.log_target.as_str(), "Received search request: {req:?}");
+        let Some(info) = self
+            .channel_info
+            .get(&req.channel_id)
+            .filter(|info| info.members.contains(&cli_node_id))
+        else {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} can't search channel {}", req.channel_id);
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    "Can't search messages, you're not in that channel",
+                ),
+            ));
+            return;
+        };
+        let query = req.query.to_lowercase();
+        let limit = req.limit.clamp(1, self.config.history_page_size) as usize;
+        // Newest matches first, that's usually what the user is looking for
+        let messages = info
+            .history
+            .iter()
+            .rev()
+            .filter(|x| x.message.to_lowercase().contains(&query))
+            .take(limit)
+            .cloned()
+            .collect::<Vec<_>>();
+        debug!(target: self.log_target.as_str(), "Found {} messages matching {:?} in channel {}", messages.len(), req.query, req.channel_id);
+        replies.push((
+            cli_node_id,
+            ChatMessage {
+                own_id: self.own_id.into(),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvSearchResults(SearchResults {
+                    channel_id: req.channel_id,
+                    query: req.query.clone(),
+                    messages,
+                })),
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::SYSTEM_USERNAME;
+    use crate::server::{texts_to, ChatServerConfig};
+
+    const ALICE: NodeId = 5;
+    const BOB: NodeId = 6;
+
+    fn history(server: &ChatServerInternal, cli_node_id: NodeId, channel_id: u64) -> Vec<String> {
+        let mut replies = vec![];
+        server.msg_clifetchhistory(
+            &mut replies,
+            cli_node_id,
+            &FetchHistory {
+                channel_id,
+                before_message_id: None,
+                limit: u32::MAX,
+            },
+        );
+        match replies.pop().and_then(|(_, msg)| msg.message_kind) {
+            Some(MessageKind::SrvHistory(page)) => {
+                page.messages.into_iter().map(|x| x.message).collect()
+            }
+            other => panic!("expected a history page, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn announcements_are_kept_for_later_members() {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let games = server.register_in(ALICE, "alice", "games");
+        server.register_in(BOB, "bob", "games");
+        server.post(ALICE, games, "hi");
+        server.register_in(BOB, "bob", "lobby");
+
+        assert_eq!(
+            history(&server, ALICE, games),
+            vec!["alice joined", "bob joined", "hi", "bob left"]
+        );
+        assert!(server.channel_info[&games]
+            .history
+            .iter()
+            .filter(|x| x.message != "hi")
+            .all(|x| x.username == SYSTEM_USERNAME));
+    }
+
+    #[test]
+    fn announcements_count_against_the_history_limit() {
+        let config = ChatServerConfig {
+            history_limit: 2,
+            ..ChatServerConfig::default()
+        };
+        let mut server = ChatServerInternal::with_config(1, config);
+        let games = server.register_in(ALICE, "alice", "games");
+        server.post(ALICE, games, "hi");
+        server.register_in(BOB, "bob", "games");
+        server.register_in(BOB, "bob", "lobby");
+
+        assert_eq!(
+            history(&server, ALICE, games),
+            vec!["bob joined", "bob left"]
+        );
+    }
+
+    #[test]
+    fn welcome_changes_are_announced() {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let games = server.register_in(ALICE, "alice", "games");
+        server.register_in(BOB, "bob", "games");
+
+        let mut replies = vec![];
+        for text in ["Be nice", ""] {
+            server.msg_clisetwelcome(
+                &mut replies,
+                ALICE,
+                SetWelcome {
+                    channel_id: games,
+                    text: text.to_string(),
+                },
+            );
+        }
+
+        let announced = [
+            "alice changed the welcome message to: Be nice",
+            "alice removed the welcome message",
+        ];
+        assert_eq!(texts_to(&replies, BOB), announced);
+        assert_eq!(texts_to(&replies, ALICE), announced);
+        assert!(history(&server, BOB, games).ends_with(&announced.map(String::from)));
     }
 }
@@ -0,0 +1,121 @@
+use crate::server::{ChatServerInternal, ServerState};
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, Migration};
+use common::slc_commands::ServerEvent;
+use log::info;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Hands the whole server over to `new_server_id`: the exported state goes to the
+    // controller, which builds the new server from it, and clients are told to follow
+    pub(crate) fn start_migration(
+        &mut self,
+        new_server_id: NodeId,
+    ) -> (Vec<(NodeId, ChatMessage)>, Vec<ServerEvent>) {
+        info!(target: self.log_target.as_str(), "Migrating to node {new_server_id}");
+        self.migrated_to = Some(new_server_id);
+        let replies = self
+            .usernames
+            .left_values()
+            .map(|id| (*id, self.migration_notice(new_server_id)))
+            .collect();
+        let state = self.export();
+        (
+            replies,
+            vec![ServerEvent::StateExported {
+                new_server_id,
+                state: Box::new(state),
+            }],
+        )
+    }
+
+    pub(crate) fn migration_notice(&self, new_server_id: NodeId) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::SrvMigrated(Migration {
+                new_server_id: u32::from(new_server_id),
+            })),
+        }
+    }
+
+    // Builds the server that takes over after a migration, on its own node
+    #[must_use]
+    pub fn migrated(new_id: NodeId, mut state: ServerState) -> Self {
+        state.own_id = new_id;
+        Self::restore(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ChatServerConfig;
+    use chat_common::messages::Empty;
+    use chat_common::packet_handling::CommandHandler;
+
+    fn migrated_to(message: &ChatMessage) -> Option<u32> {
+        match &message.message_kind {
+            Some(MessageKind::SrvMigrated(migration)) => Some(migration.new_server_id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn clients_are_told_where_the_server_went() {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        server.register_in(5, "alice", "games");
+        server.register_in(6, "bob", "games");
+        let (replies, events) = server.start_migration(2);
+        let mut told = replies
+            .iter()
+            .filter(|(_, msg)| migrated_to(msg) == Some(2))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        told.sort_unstable();
+        assert_eq!(told, vec![5, 6]);
+        assert!(matches!(
+            events.as_slice(),
+            [ServerEvent::StateExported {
+                new_server_id: 2,
+                ..
+            }]
+        ));
+        // Whatever comes later is redirected
+        let (replies, _) = server.handle_protocol_message(
+            ChatMessage {
+                own_id: 5,
+                request_id: 0,
+                message_kind: Some(MessageKind::CliRequestChannels(Empty {})),
+            },
+            5,
+        );
+        assert_eq!(replies.len(), 1);
+        assert_eq!(migrated_to(&replies[0].1), Some(2));
+    }
+
+    #[test]
+    fn the_new_server_takes_users_and_channels_over() {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let games = server.register_in(5, "alice", "games");
+        let (_, events) = server.start_migration(2);
+        let Some(ServerEvent::StateExported { state, .. }) = events.into_iter().next() else {
+            panic!("no state exported");
+        };
+        let state = state
+            .downcast::<ServerState>()
+            .expect("the exported state isn't a ServerState");
+        let new_server = ChatServerInternal::migrated(2, *state);
+        assert_eq!(new_server.own_id, 2);
+        assert_eq!(new_server.migrated_to, None);
+        assert_eq!(
+            new_server.usernames.get_by_left(&5).map(String::as_str),
+            Some("alice")
+        );
+        assert_eq!(new_server.channels.get_by_right("games"), Some(&games));
+        assert!(new_server
+            .channel_info
+            .get(&games)
+            .is_some_and(|info| info.members.contains(&5)));
+    }
+}
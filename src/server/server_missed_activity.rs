@@ -0,0 +1,91 @@
+use crate::protocol::SYSTEM_USERNAME;
+use crate::server::{ChatServerInternal, Departure};
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelActivity, ChatMessage, MissedActivity};
+use itertools::Itertools;
+use log::{debug, info};
+use wg_2024::network::NodeId;
+
+// How many of the most recent authors are listed per channel
+const SUMMARY_SENDERS: usize = 3;
+
+impl ChatServerInternal {
+    pub(crate) fn record_departure(&mut self, username: &str, channels: &[u64]) {
+        let timestamp = self.next_timestamp();
+        debug!(target: self.log_target.as_str(), "Recording departure of {username} from channels {channels:?}");
+        self.departures.insert(
+            username.to_string(),
+            Departure {
+                timestamp,
+                channels: channels.to_vec(),
+            },
+        );
+    }
+
+    // Tells a returning user what happened in their channels since they left
+    pub(crate) fn send_missed_activity(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+    ) {
+        let Some(username) = self.usernames.get_by_left(&cli_node_id) else {
+            return;
+        };
+        let Some(departure) = self.departures.remove(username) else {
+            return;
+        };
+        let mention = format!("@{username}");
+        let channels = departure
+            .channels
+            .iter()
+            .filter_map(|id| {
+                Some((
+                    id,
+                    self.channel_info.get(id)?,
+                    self.channels.get_by_left(id)?,
+                ))
+            })
+            .filter(|(_, info, _)| info.is_shared())
+            .filter_map(|(id, info, name)| {
+                let missed = info
+                    .history
+                    .iter()
+                    // Announcements, such as the user's own departure, aren't activity
+                    .filter(|x| x.message_id > departure.timestamp && x.username != SYSTEM_USERNAME)
+                    .collect::<Vec<_>>();
+                if missed.is_empty() {
+                    return None;
+                }
+                Some(ChannelActivity {
+                    channel_id: *id,
+                    channel_name: name.clone(),
+                    missed_count: missed.len() as u64,
+                    last_senders: missed
+                        .iter()
+                        .rev()
+                        .map(|x| x.username.clone())
+                        .unique()
+                        .take(SUMMARY_SENDERS)
+                        .collect(),
+                    mentions: missed
+                        .iter()
+                        .filter(|x| x.message.contains(&mention))
+                        .map(|x| (*x).clone())
+                        .collect(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if channels.is_empty() {
+            return;
+        }
+        info!(target: self.log_target.as_str(), "Sending missed activity summary to client {cli_node_id}");
+        replies.push((
+            cli_node_id,
+            ChatMessage {
+                own_id: self.own_id.into(),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvMissedActivity(MissedActivity { channels })),
+            },
+        ));
+    }
+}
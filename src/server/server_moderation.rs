@@ -0,0 +1,470 @@
+use crate::protocol::{ErrorCode, ModerationState};
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{
+    ChannelKind, ChatMessage, MessageData, ModerationDecision, ModerationStatus, SetModerated,
+    SetReadOnly,
+};
+use log::{debug, info, trace};
+use wg_2024::network::NodeId;
+
+// Messages a channel holds for approval at most, more are refused until operators catch up
+const MAX_HELD_MESSAGES: usize = 100;
+// Held messages are dropped after this long, in milliseconds. Approving one stamps it
+// anew, and clients stop trusting signatures made 5 minutes before the stamp
+const HELD_MESSAGE_LIFETIME_MS: u64 = 4 * 60 * 1000;
+
+impl ChatServerInternal {
+    // Sends a message to the members of its channel other than `sender` and stores it
+    pub(crate) fn distribute_message(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        sender: NodeId,
+        data: MessageData,
+    ) {
+        self.forward_to_watchers(replies, sender, &data);
+        self.relay_to_federation(replies, sender, &data);
+        let Some(channel_data) = self.channel_info.get_mut(&data.channel_id) else {
+            return;
+        };
+        let direct = channel_data.kind == ChannelKind::Personal;
+        let mut deferred = vec![];
+        for id in channel_data.members.iter().filter(|x| **x != sender) {
+            // Keep direct messages in order behind those still waiting for an offline client
+            if direct && (self.offline_messages.contains_key(id) || self.unreachable.contains(id)) {
+                deferred.push(*id);
+                continue;
+            }
+            trace!(target: self.log_target.as_str(), "Forwarding message to client {id}");
+            self.counters.messages_relayed += 1;
+            replies.push((
+                *id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvDistributeMessage(data.clone())),
+                },
+            ));
+        }
+        channel_data.stats.record_message(data.timestamp, sender);
+        channel_data.remember(data.clone(), self.config.history_limit);
+        for id in deferred {
+            self.queue_offline_message(id, data.clone());
+        }
+    }
+
+    fn moderation_status(&self, data: &MessageData, status: ModerationState) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::SrvModerationStatus(ModerationStatus {
+                channel_id: data.channel_id,
+                message_id: data.message_id,
+                status: status.name().to_string(),
+            })),
+        }
+    }
+
+    // Keeps a message of a moderated channel until one of its operators decides on it,
+    // or it expires. Returns false if the channel holds too many already
+    pub(crate) fn hold_for_approval(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        author: NodeId,
+        data: MessageData,
+    ) -> bool {
+        let Some(channel_data) = self.channel_info.get(&data.channel_id) else {
+            return false;
+        };
+        if channel_data.pending.len() >= MAX_HELD_MESSAGES {
+            debug!(target: self.log_target.as_str(), "Refusing message from client {author}, channel {} holds too many", data.channel_id);
+            replies.push((
+                author,
+                self.error_message(
+                    ErrorCode::MessageRejected,
+                    "Too many messages await approval in this channel, try again later",
+                ),
+            ));
+            return false;
+        }
+        debug!(target: self.log_target.as_str(), "Holding message {} from client {author} for approval", data.message_id);
+        for id in channel_data.operator_ids() {
+            replies.push((
+                id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvPendingApproval(data.clone())),
+                },
+            ));
+        }
+        // Linked servers don't take statuses, their authors can't be told
+        if !self.is_federation_peer(author) {
+            replies.push((
+                author,
+                self.moderation_status(&data, ModerationState::Pending),
+            ));
+        }
+        if let Some(channel_data) = self.channel_info.get_mut(&data.channel_id) {
+            channel_data.pending.insert(data.message_id, (author, data));
+        }
+        true
+    }
+
+    // Drops held messages no operator decided on in time, telling their authors
+    pub(crate) fn expire_held_messages(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        now: u64,
+    ) {
+        let mut expired = vec![];
+        for info in self.channel_info.values_mut() {
+            // Message ids are their timestamps, the oldest come first
+            while let Some(entry) = info
+                .pending
+                .first_entry()
+                .filter(|x| x.key().saturating_add(HELD_MESSAGE_LIFETIME_MS) <= now)
+            {
+                expired.push(entry.remove());
+            }
+        }
+        for (author, data) in expired {
+            debug!(target: self.log_target.as_str(), "Message {} from client {author} expired awaiting approval", data.message_id);
+            if !self.is_federation_peer(author) {
+                replies.push((
+                    author,
+                    self.moderation_status(&data, ModerationState::Expired),
+                ));
+            }
+        }
+    }
+
+    pub(crate) fn is_operator(&self, channel_id: u64, cli_node_id: NodeId) -> bool {
+        self.channel_info
+            .get(&channel_id)
+            .is_some_and(|info| info.is_operator(cli_node_id))
+    }
+
+    pub(crate) fn msg_clidecidemessage(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        decision: &ModerationDecision,
+        approve: bool,
+    ) {
+        if !self.is_operator(decision.channel_id, cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        let Some((author, mut data)) = self
+            .channel_info
+            .get_mut(&decision.channel_id)
+            .and_then(|info| info.pending.remove(&decision.message_id))
+        else {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::MessageNotPending,
+                    "No such message awaiting approval",
+                ),
+            ));
+            return;
+        };
+        info!(target: self.log_target.as_str(), "Client {cli_node_id} {} message {}", if approve { "approved" } else { "rejected" }, data.message_id);
+        if !self.is_federation_peer(author) {
+            let status = if approve {
+                ModerationState::Approved
+            } else {
+                ModerationState::Rejected
+            };
+            replies.push((author, self.moderation_status(&data, status)));
+        }
+        if approve {
+            // Stamped again so that history stays ordered
+            let timestamp = self.next_timestamp();
+            data.timestamp = timestamp;
+            data.message_id = timestamp;
+            self.distribute_message(replies, author, data);
+        }
+    }
+
+    pub(crate) fn msg_clisetmoderated(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &SetModerated,
+    ) {
+        if !self.is_operator(data.channel_id, cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        let Some(info) = self
+            .channel_info
+            .get_mut(&data.channel_id)
+            .filter(|x| x.kind == ChannelKind::Group)
+        else {
+            return;
+        };
+        info.moderated = data.moderated;
+        let text = if data.moderated {
+            "Messages now need an operator's approval"
+        } else {
+            "Messages no longer need approval"
+        };
+        self.announce_in_channel(replies, data.channel_id, text, None);
+    }
+
+    pub(crate) fn msg_clisetreadonly(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &SetReadOnly,
+    ) {
+        if !self.is_operator(data.channel_id, cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        self.set_read_only(replies, data.channel_id, data.read_only);
+    }
+
+    // Returns whether `channel_id` is a group channel, other channels can't be read-only
+    pub(crate) fn set_read_only(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        channel_id: u64,
+        read_only: bool,
+    ) -> bool {
+        let Some(info) = self
+            .channel_info
+            .get_mut(&channel_id)
+            .filter(|x| x.kind == ChannelKind::Group)
+        else {
+            return false;
+        };
+        info.read_only = read_only;
+        let text = if read_only {
+            "Only operators can send messages now"
+        } else {
+            "Everyone can send messages again"
+        };
+        self.announce_in_channel(replies, channel_id, text, None);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ReceiptStatus;
+    use crate::server::{errors_to, texts_to, ChatServerConfig};
+    use common::slc_commands::ServerEvent;
+
+    const OWNER: NodeId = 5;
+    const MEMBER: NodeId = 6;
+
+    // A channel created by OWNER, which MEMBER joined
+    fn channel() -> (ChatServerInternal, u64) {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let games = server.register_in(OWNER, "alice", "games");
+        server.register_in(MEMBER, "bob", "games");
+        (server, games)
+    }
+
+    fn moderated_channel() -> (ChatServerInternal, u64) {
+        let (mut server, games) = channel();
+        let mut replies = vec![];
+        server.msg_clisetmoderated(
+            &mut replies,
+            OWNER,
+            &SetModerated {
+                channel_id: games,
+                moderated: true,
+            },
+        );
+        (server, games)
+    }
+
+    fn statuses_to(replies: &[(NodeId, ChatMessage)], id: NodeId) -> Vec<String> {
+        replies
+            .iter()
+            .filter(|(to, _)| *to == id)
+            .filter_map(|(_, msg)| match &msg.message_kind {
+                Some(MessageKind::SrvModerationStatus(status)) => Some(status.status.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The message held in `channel_id`, there has to be exactly one
+    fn held_message(server: &ChatServerInternal, channel_id: u64) -> u64 {
+        let pending = &server.channel_info[&channel_id].pending;
+        assert_eq!(pending.len(), 1);
+        pending.keys().copied().next().unwrap_or_default()
+    }
+
+    fn decide(
+        server: &mut ChatServerInternal,
+        operator: NodeId,
+        channel_id: u64,
+        approve: bool,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let message_id = held_message(server, channel_id);
+        let mut replies = vec![];
+        server.msg_clidecidemessage(
+            &mut replies,
+            operator,
+            &ModerationDecision {
+                channel_id,
+                message_id,
+            },
+            approve,
+        );
+        replies
+    }
+
+    fn in_history(server: &ChatServerInternal, channel_id: u64, text: &str) -> bool {
+        server.channel_info[&channel_id]
+            .history
+            .iter()
+            .any(|x| x.message == text)
+    }
+
+    #[test]
+    fn messages_of_members_wait_for_an_operator() {
+        let (mut server, games) = moderated_channel();
+        let (replies, status) = server.post(MEMBER, games, "hi");
+        assert_eq!(status, ReceiptStatus::Pending);
+        assert_eq!(statuses_to(&replies, MEMBER), vec!["PENDING"]);
+        assert!(replies.iter().any(|(to, msg)| *to == OWNER
+            && matches!(&msg.message_kind, Some(MessageKind::SrvPendingApproval(x)) if x.message == "hi")));
+        assert!(texts_to(&replies, OWNER).is_empty());
+        assert!(!in_history(&server, games, "hi"));
+        held_message(&server, games);
+    }
+
+    #[test]
+    fn operators_are_not_held() {
+        let (mut server, games) = moderated_channel();
+        let (replies, status) = server.post(OWNER, games, "hi");
+        assert_eq!(status, ReceiptStatus::Delivered);
+        assert_eq!(texts_to(&replies, MEMBER), vec!["hi"]);
+    }
+
+    #[test]
+    fn approved_messages_are_distributed() {
+        let (mut server, games) = moderated_channel();
+        server.post(MEMBER, games, "hi");
+        let replies = decide(&mut server, OWNER, games, true);
+        assert_eq!(statuses_to(&replies, MEMBER), vec!["APPROVED"]);
+        assert_eq!(texts_to(&replies, OWNER), vec!["hi"]);
+        assert!(in_history(&server, games, "hi"));
+        assert!(server.channel_info[&games].pending.is_empty());
+    }
+
+    #[test]
+    fn rejected_messages_are_dropped() {
+        let (mut server, games) = moderated_channel();
+        server.post(MEMBER, games, "hi");
+        let replies = decide(&mut server, OWNER, games, false);
+        assert_eq!(statuses_to(&replies, MEMBER), vec!["REJECTED"]);
+        assert!(texts_to(&replies, OWNER).is_empty());
+        assert!(!in_history(&server, games, "hi"));
+        assert!(server.channel_info[&games].pending.is_empty());
+    }
+
+    #[test]
+    fn only_operators_decide() {
+        let (mut server, games) = moderated_channel();
+        server.post(MEMBER, games, "hi");
+        let replies = decide(&mut server, MEMBER, games, true);
+        assert_eq!(errors_to(&replies, MEMBER), vec![ErrorCode::NotOperator]);
+        held_message(&server, games);
+    }
+
+    #[test]
+    fn undecided_messages_expire() {
+        let (mut server, games) = moderated_channel();
+        server.post(MEMBER, games, "hi");
+        let held_at = held_message(&server, games);
+        let mut replies = vec![];
+        server.expire_held_messages(&mut replies, held_at + HELD_MESSAGE_LIFETIME_MS - 1);
+        assert!(replies.is_empty());
+        held_message(&server, games);
+        server.expire_held_messages(&mut replies, held_at + HELD_MESSAGE_LIFETIME_MS);
+        assert_eq!(statuses_to(&replies, MEMBER), vec!["EXPIRED"]);
+        assert!(server.channel_info[&games].pending.is_empty());
+        assert!(!in_history(&server, games, "hi"));
+    }
+
+    fn set_read_only(
+        server: &mut ChatServerInternal,
+        cli_node_id: NodeId,
+        channel_id: u64,
+        read_only: bool,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut replies = vec![];
+        server.msg_clisetreadonly(
+            &mut replies,
+            cli_node_id,
+            &SetReadOnly {
+                channel_id,
+                read_only,
+            },
+        );
+        replies
+    }
+
+    #[test]
+    fn only_operators_post_in_read_only_channels() {
+        let (mut server, games) = channel();
+        set_read_only(&mut server, OWNER, games, true);
+        let (replies, status) = server.post(MEMBER, games, "hi");
+        assert_eq!(status, ReceiptStatus::Rejected);
+        assert_eq!(
+            errors_to(&replies, MEMBER),
+            vec![ErrorCode::ChannelReadOnly]
+        );
+        let (replies, status) = server.post(OWNER, games, "news");
+        assert_eq!(status, ReceiptStatus::Delivered);
+        assert_eq!(texts_to(&replies, MEMBER), vec!["news"]);
+        set_read_only(&mut server, OWNER, games, false);
+        assert_eq!(server.post(MEMBER, games, "hi").1, ReceiptStatus::Delivered);
+    }
+
+    #[test]
+    fn only_operators_make_channels_read_only() {
+        let (mut server, games) = channel();
+        let replies = set_read_only(&mut server, MEMBER, games, true);
+        assert_eq!(errors_to(&replies, MEMBER), vec![ErrorCode::NotOperator]);
+        assert!(!server.channel_info[&games].read_only);
+    }
+
+    #[test]
+    fn the_admin_posts_in_read_only_channels_of_the_server() {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let news = server.create_group_channel("news", None);
+        server.register_in(MEMBER, "bob", "news");
+        let (mut replies, mut events) = (vec![], vec![]);
+        server.admin_set_read_only(&mut replies, &mut events, "news", true);
+        assert!(matches!(
+            events.as_slice(),
+            [ServerEvent::ReadOnlyChanged {
+                read_only: true,
+                ..
+            }]
+        ));
+        assert_eq!(server.post(MEMBER, news, "hi").1, ReceiptStatus::Rejected);
+        replies.clear();
+        server.admin_post(&mut replies, &mut events, "news", "Release today");
+        assert_eq!(texts_to(&replies, MEMBER), vec!["Release today"]);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage, MessageData};
+use log::{debug, info};
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Whether a message goes to the personal channel of its recipient
+    pub(crate) fn is_direct_message(&self, data: &MessageData) -> bool {
+        self.channel_info
+            .get(&data.channel_id)
+            .is_some_and(|info| info.kind == ChannelKind::Personal)
+    }
+
+    // Keeps a direct message for a client that can't be reached right now, dropping the
+    // oldest ones past the limit
+    pub(crate) fn queue_offline_message(&mut self, recipient: NodeId, data: MessageData) {
+        debug!(target: self.log_target.as_str(), "Queueing message {} for offline client {recipient}", data.message_id);
+        let queue = self.offline_messages.entry(recipient).or_default();
+        queue.push_back(data);
+        while queue.len() > self.config.offline_queue_limit {
+            queue.pop_front();
+        }
+    }
+
+    // Sends a client back the direct messages it missed while unreachable
+    pub(crate) fn flush_offline_messages(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+    ) {
+        let Some(queue) = self.offline_messages.remove(&cli_node_id) else {
+            return;
+        };
+        info!(target: self.log_target.as_str(), "Delivering {} queued messages to client {cli_node_id}", queue.len());
+        replies.extend(queue.into_iter().map(|data| {
+            (
+                cli_node_id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvDistributeMessage(data)),
+                },
+            )
+        }));
+    }
+}
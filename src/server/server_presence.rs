@@ -0,0 +1,50 @@
+use crate::protocol::ErrorCode;
+use crate::server::ChatServerInternal;
+use chat_common::messages::{ChatMessage, SetStatus};
+use log::info;
+use wg_2024::network::NodeId;
+
+// Statuses a user may pick, "online" being the default
+const STATUSES: &[&str] = &["online", "away", "busy"];
+
+// Longest status text accepted, in characters
+const MAX_STATUS_TEXT: usize = 100;
+
+impl ChatServerInternal {
+    pub(crate) fn msg_clisetstatus(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: SetStatus,
+    ) {
+        info!(target: self.log_target.as_str(), "Client {cli_node_id} sets its status: {data:?}");
+        if !self.usernames.contains_left(&cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotRegistered, "Register before setting a status"),
+            ));
+            return;
+        }
+        if !STATUSES.contains(&data.status.as_str()) || data.text.chars().count() > MAX_STATUS_TEXT
+        {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::StatusInvalid,
+                    &format!(
+                        "The status is one of {} with at most {MAX_STATUS_TEXT} characters of text",
+                        STATUSES.join(", ")
+                    ),
+                ),
+            ));
+            return;
+        }
+        if data.status == "online" && data.text.is_empty() {
+            self.statuses.remove(&cli_node_id);
+        } else {
+            self.statuses.insert(cli_node_id, data);
+        }
+        // Members of shared channels see the change in their next channel list
+        replies.extend(self.generate_channel_updates(None));
+    }
+}
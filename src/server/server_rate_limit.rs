@@ -0,0 +1,152 @@
+use crate::protocol::ErrorCode;
+use crate::server::ChatServerInternal;
+use chat_common::messages::ChatMessage;
+use log::debug;
+use wg_2024::network::NodeId;
+
+// Tokens are counted in thousandths, so that refills don't need floating point
+const TOKEN: u64 = 1000;
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    // In thousandths of a token
+    tokens: u64,
+    // Last refill, in milliseconds since the epoch
+    last_refill: u64,
+}
+
+impl ChatServerInternal {
+    fn rate_limit_capacity(&self) -> Option<u64> {
+        self.config
+            .rate_limit_burst
+            .map(|burst| u64::from(burst) * TOKEN)
+    }
+
+    // Forgets clients whose bucket filled up again, a new one starts full anyway
+    pub(crate) fn expire_rate_limits(&mut self, now: u64) {
+        let Some(capacity) = self.rate_limit_capacity() else {
+            self.rate_limits.clear();
+            return;
+        };
+        let refill = u64::from(self.config.rate_limit_refill);
+        self.rate_limits.retain(|_, bucket| {
+            bucket.tokens.saturating_add(
+                now.saturating_sub(bucket.last_refill)
+                    .saturating_mul(refill),
+            ) < capacity
+        });
+    }
+
+    // Takes a token for a message or join of a client. When its bucket is empty, tells
+    // it to slow down and returns false, in which case the request must be dropped
+    pub(crate) fn take_rate_token(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+    ) -> bool {
+        let Some(capacity) = self.rate_limit_capacity() else {
+            return true;
+        };
+        let now = self.wall_now();
+        // Thousandths of a token per millisecond is tokens per second
+        let refill = u64::from(self.config.rate_limit_refill);
+        let bucket = self.rate_limits.entry(cli_node_id).or_insert(TokenBucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        bucket.tokens = capacity.min(
+            bucket.tokens.saturating_add(
+                now.saturating_sub(bucket.last_refill)
+                    .saturating_mul(refill),
+            ),
+        );
+        bucket.last_refill = now;
+        if bucket.tokens >= TOKEN {
+            bucket.tokens -= TOKEN;
+            return true;
+        }
+        debug!(target: self.log_target.as_str(), "Client {cli_node_id} is rate limited");
+        replies.push((
+            cli_node_id,
+            self.error_message(ErrorCode::RateLimited, "Too many requests, slow down"),
+        ));
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::server::{errors_to, ChatServerConfig};
+    use std::time::Duration;
+
+    // Bursts of 2 requests, one more every second
+    fn limited_server() -> (ChatServerInternal, ManualClock) {
+        let mut server = ChatServerInternal::with_config(
+            1,
+            ChatServerConfig {
+                rate_limit_burst: Some(2),
+                rate_limit_refill: 1,
+                ..ChatServerConfig::default()
+            },
+        );
+        let clock = ManualClock::new();
+        server.set_clock(clock.clone());
+        (server, clock)
+    }
+
+    #[test]
+    fn bursts_are_limited_until_tokens_come_back() {
+        let (mut server, clock) = limited_server();
+        let mut replies = vec![];
+        assert!(server.take_rate_token(&mut replies, 5));
+        assert!(server.take_rate_token(&mut replies, 5));
+        assert!(replies.is_empty());
+        assert!(!server.take_rate_token(&mut replies, 5));
+        assert_eq!(errors_to(&replies, 5), vec![ErrorCode::RateLimited]);
+        // Other clients have their own bucket
+        assert!(server.take_rate_token(&mut replies, 6));
+        clock.advance(Duration::from_millis(999));
+        assert!(!server.take_rate_token(&mut replies, 5));
+        clock.advance(Duration::from_millis(1));
+        assert!(server.take_rate_token(&mut replies, 5));
+        assert!(!server.take_rate_token(&mut replies, 5));
+    }
+
+    #[test]
+    fn refused_requests_do_not_cost_tokens() {
+        let (mut server, clock) = limited_server();
+        let mut replies = vec![];
+        for _ in 0..2 {
+            server.take_rate_token(&mut replies, 5);
+        }
+        for _ in 0..10 {
+            assert!(!server.take_rate_token(&mut replies, 5));
+        }
+        clock.advance(Duration::from_secs(1));
+        assert!(server.take_rate_token(&mut replies, 5));
+    }
+
+    #[test]
+    fn without_a_burst_nothing_is_limited() {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let mut replies = vec![];
+        for _ in 0..1000 {
+            assert!(server.take_rate_token(&mut replies, 5));
+        }
+        assert!(server.rate_limits.is_empty());
+    }
+
+    #[test]
+    fn full_buckets_are_forgotten() {
+        let (mut server, _) = limited_server();
+        let mut replies = vec![];
+        server.take_rate_token(&mut replies, 5);
+        let now = server.wall_now();
+        server.expire_rate_limits(now + 999);
+        assert!(server.rate_limits.contains_key(&5));
+        server.expire_rate_limits(now + 1000);
+        assert!(server.rate_limits.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+use crate::protocol::ErrorCode;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, React, ReactionCount, ReactionUpdate};
+use log::info;
+use wg_2024::network::NodeId;
+
+// Longest reaction accepted, in bytes. Enough for emoji made of several code points
+const MAX_REACTION_LEN: usize = 32;
+// Different reactions a message can have, those already there can still be added to
+const MAX_REACTIONS_PER_MESSAGE: usize = 20;
+
+// Pictographs, which an emoji needs at least one of
+fn is_pictograph(c: char) -> bool {
+    matches!(
+        u32::from(c),
+        0xA9 | 0xAE
+            | 0x203C
+            | 0x2049
+            | 0x2122
+            | 0x2139
+            | 0x2194..=0x21AA
+            | 0x231A..=0x23FF
+            | 0x24C2
+            | 0x25AA..=0x25FE
+            | 0x2600..=0x27BF
+            | 0x2934..=0x2935
+            | 0x2B05..=0x2B55
+            | 0x3030
+            | 0x303D
+            | 0x3297
+            | 0x3299
+            | 0x1F000..=0x1FAFF
+    )
+}
+
+// What joins pictographs into one emoji or changes how they look: zero width joiner,
+// variation selectors, the keycap and tags
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(
+        u32::from(c),
+        0x200D | 0xFE0E | 0xFE0F | 0x20E3 | 0xE0020..=0xE007F
+    )
+}
+
+// Emoji made of pictographs and modifiers, or keycaps like 1️⃣. Text isn't a reaction
+fn is_emoji(text: &str) -> bool {
+    let keycap = text.contains('\u{20E3}');
+    !text.is_empty()
+        && text.len() <= MAX_REACTION_LEN
+        && (keycap || text.chars().any(is_pictograph))
+        && text.chars().all(|c| {
+            is_pictograph(c)
+                || is_emoji_modifier(c)
+                || (keycap && (c.is_ascii_digit() || c == '#' || c == '*'))
+        })
+}
+
+impl ChatServerInternal {
+    // Adds the sender's reaction to a message in one of its channels, or takes it back
+    // if it was already there
+    pub(crate) fn msg_clireact(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &React,
+    ) {
+        info!(target: self.log_target.as_str(), "Client {cli_node_id} reacts to message {} with {}", data.message_id, data.emoji);
+        if self.guests.contains(&cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::GuestsReadOnly,
+                    "Guests can't react, register first",
+                ),
+            ));
+            return;
+        }
+        if !is_emoji(&data.emoji) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::ReactionInvalid, "A reaction is a single emoji"),
+            ));
+            return;
+        }
+        let Some(channel_id) = self
+            .channel_info
+            .iter()
+            .find(|(_, info)| {
+                info.members.contains(&cli_node_id)
+                    && info.history.iter().any(|x| x.message_id == data.message_id)
+            })
+            .map(|(id, _)| *id)
+        else {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::MessageNotFound,
+                    "No recent message with that id in your channels",
+                ),
+            ));
+            return;
+        };
+        let full = self
+            .channel_info
+            .get(&channel_id)
+            .and_then(|info| info.reactions.get(&data.message_id))
+            .is_some_and(|x| x.len() >= MAX_REACTIONS_PER_MESSAGE && !x.contains_key(&data.emoji));
+        if full {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ReactionInvalid,
+                    "That message has too many different reactions, pick one of them",
+                ),
+            ));
+            return;
+        }
+        let Some(info) = self.channel_info.get_mut(&channel_id) else {
+            return;
+        };
+        let reactions = info.reactions.entry(data.message_id).or_default();
+        let users = reactions.entry(data.emoji.clone()).or_default();
+        if !users.remove(&cli_node_id) {
+            users.insert(cli_node_id);
+        }
+        if users.is_empty() {
+            reactions.remove(&data.emoji);
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let update = ReactionUpdate {
+            channel_id,
+            message_id: data.message_id,
+            reactions: reactions
+                .iter()
+                .map(|(emoji, users)| ReactionCount {
+                    emoji: emoji.clone(),
+                    count: users.len() as u32,
+                })
+                .collect(),
+        };
+        if reactions.is_empty() {
+            info.reactions.remove(&data.message_id);
+        }
+        for id in &info.members {
+            replies.push((
+                *id,
+                ChatMessage {
+                    own_id: u32::from(self.own_id),
+                    request_id: 0,
+                    message_kind: Some(MessageKind::SrvReactionUpdate(update.clone())),
+                },
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{errors_to, ChatServerConfig};
+
+    // alice and bob in "games", where alice said "hi". Returns the id of that message
+    fn reacted_channel(config: ChatServerConfig) -> (ChatServerInternal, u64, u64) {
+        let mut server = ChatServerInternal::with_config(1, config);
+        let games = server.register_in(5, "alice", "games");
+        server.register_in(6, "bob", "games");
+        server.post(5, games, "hi");
+        let message_id = server.channel_info[&games]
+            .history
+            .back()
+            .map(|x| x.message_id)
+            .unwrap_or_default();
+        (server, games, message_id)
+    }
+
+    fn react(
+        server: &mut ChatServerInternal,
+        cli_node_id: NodeId,
+        message_id: u64,
+        emoji: &str,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut replies = vec![];
+        server.msg_clireact(
+            &mut replies,
+            cli_node_id,
+            &React {
+                message_id,
+                emoji: emoji.to_string(),
+            },
+        );
+        replies
+    }
+
+    // Counts in the reaction update sent to `id`
+    fn counts_to(replies: &[(NodeId, ChatMessage)], id: NodeId) -> Option<Vec<(String, u32)>> {
+        replies
+            .iter()
+            .find_map(|(to, msg)| match &msg.message_kind {
+                Some(MessageKind::SrvReactionUpdate(update)) if *to == id => Some(
+                    update
+                        .reactions
+                        .iter()
+                        .map(|x| (x.emoji.clone(), x.count))
+                        .collect(),
+                ),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn reactions_are_single_emoji() {
+        for emoji in ["👍", "👍🏽", "❤️", "1️⃣", "👨‍👩‍👧"] {
+            assert!(is_emoji(emoji), "{emoji}");
+        }
+        for text in ["", "ok", "👍 ok", "1", "👍".repeat(20).as_str()] {
+            assert!(!is_emoji(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn reacting_again_takes_the_reaction_back() {
+        let (mut server, _, message_id) = reacted_channel(ChatServerConfig::default());
+        let replies = react(&mut server, 6, message_id, "👍");
+        let expected = vec![("👍".to_string(), 1)];
+        assert_eq!(counts_to(&replies, 5), Some(expected.clone()));
+        assert_eq!(counts_to(&replies, 6), Some(expected));
+        let replies = react(&mut server, 5, message_id, "👍");
+        assert_eq!(counts_to(&replies, 6), Some(vec![("👍".to_string(), 2)]));
+        react(&mut server, 5, message_id, "👍");
+        let replies = react(&mut server, 6, message_id, "👍");
+        assert_eq!(counts_to(&replies, 5), Some(vec![]));
+    }
+
+    #[test]
+    fn only_messages_of_your_channels_can_be_reacted_to() {
+        let (mut server, _, message_id) = reacted_channel(ChatServerConfig::default());
+        server.register_in(7, "carol", "music");
+        let replies = react(&mut server, 7, message_id, "👍");
+        assert_eq!(errors_to(&replies, 7), vec![ErrorCode::MessageNotFound]);
+        let replies = react(&mut server, 6, message_id, "ok");
+        assert_eq!(errors_to(&replies, 6), vec![ErrorCode::ReactionInvalid]);
+    }
+
+    #[test]
+    fn reactions_go_with_their_message_out_of_history() {
+        let config = ChatServerConfig {
+            history_limit: 1,
+            ..ChatServerConfig::default()
+        };
+        let (mut server, games, message_id) = reacted_channel(config);
+        react(&mut server, 6, message_id, "👍");
+        assert!(server.channel_info[&games]
+            .reactions
+            .contains_key(&message_id));
+        server.post(6, games, "bye");
+        assert!(!server.channel_info[&games]
+            .reactions
+            .contains_key(&message_id));
+    }
+}
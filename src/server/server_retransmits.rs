@@ -0,0 +1,51 @@
+use crate::protocol::ReceiptStatus;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChatMessage, SendReceipt};
+use wg_2024::network::NodeId;
+
+// Local ids remembered per client, retransmissions come soon after the original
+const RECENT_SENDS: usize = 64;
+
+impl ChatServerInternal {
+    // What became of a message we already handled, when a client sends it again because
+    // our receipt got lost. Untracked messages (local id 0) are never recognized
+    pub(crate) fn earlier_send_status(
+        &self,
+        cli_node_id: NodeId,
+        local_id: u64,
+    ) -> Option<ReceiptStatus> {
+        if local_id == 0 {
+            return None;
+        }
+        self.recent_sends
+            .get(&cli_node_id)?
+            .iter()
+            .find(|(id, _)| *id == local_id)
+            .map(|(_, status)| *status)
+    }
+
+    pub(crate) fn remember_send(
+        &mut self,
+        cli_node_id: NodeId,
+        local_id: u64,
+        status: ReceiptStatus,
+    ) {
+        let recent = self.recent_sends.entry(cli_node_id).or_default();
+        if recent.len() >= RECENT_SENDS {
+            recent.pop_front();
+        }
+        recent.push_back((local_id, status));
+    }
+
+    pub(crate) fn send_receipt(&self, local_id: u64, status: ReceiptStatus) -> ChatMessage {
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::SrvSendReceipt(SendReceipt {
+                local_id,
+                status: status.name().to_string(),
+            })),
+        }
+    }
+}
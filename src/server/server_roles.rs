@@ -0,0 +1,103 @@
+use crate::protocol::{ErrorCode, Role};
+use crate::server::ChatServerInternal;
+use chat_common::messages::{ChannelKind, ChatMessage, MemberAction};
+use common::slc_commands::ServerEvent;
+use log::info;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    // Operators can make other members operators, only the owner can take it back
+    pub(crate) fn msg_clisetoperator(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &MemberAction,
+        operator: bool,
+    ) {
+        info!(target: self.log_target.as_str(), "Client {cli_node_id} wants to {} {} in channel {}", if operator { "op" } else { "deop" }, data.username, data.channel_id);
+        let target = self.usernames.get_by_right(&data.username).copied();
+        let (Some(info), Some(target)) = (self.channel_info.get(&data.channel_id), target) else {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::MemberActionInvalid, "No such user or channel"),
+            ));
+            return;
+        };
+        if info.kind != ChannelKind::Group
+            || !info.members.contains(&target)
+            || info.role(target) == Role::Owner
+        {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::MemberActionInvalid,
+                    "Only members of a group channel other than its owner can be given or lose operator rights",
+                ),
+            ));
+            return;
+        }
+        let allowed = if operator {
+            info.role(cli_node_id).is_operator()
+        } else {
+            info.role(cli_node_id) == Role::Owner
+        };
+        if !allowed {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        let changed = self
+            .channel_info
+            .get_mut(&data.channel_id)
+            .is_some_and(|info| {
+                if operator {
+                    info.operators.insert(target)
+                } else {
+                    info.operators.remove(&target)
+                }
+            });
+        if !changed {
+            return;
+        }
+        let text = if operator {
+            format!("{} is now an operator", data.username)
+        } else {
+            format!("{} is no longer an operator", data.username)
+        };
+        self.announce_in_channel(replies, data.channel_id, &text, None);
+        replies.extend(self.generate_channel_updates(Some((cli_node_id, &[data.channel_id]))));
+    }
+
+    pub(crate) fn msg_clideletechannel(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        events: &mut Vec<ServerEvent>,
+        cli_node_id: NodeId,
+        channel_id: u64,
+    ) {
+        info!(target: self.log_target.as_str(), "Client {cli_node_id} wants to delete channel {channel_id}");
+        let Some(info) = self.channel_info.get(&channel_id) else {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::ChannelNotExists,
+                    "Channel with that ID doesn't exist",
+                ),
+            ));
+            return;
+        };
+        if info.kind != ChannelKind::Group || info.creator != Some(cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::NotOperator,
+                    "Only the owner can delete a channel",
+                ),
+            ));
+            return;
+        }
+        self.delete_channel(replies, events, channel_id, "its owner");
+    }
+}
@@ -0,0 +1,131 @@
+use crate::compression::COMPRESSION_CAPABILITY;
+use crate::protocol::{identity_proof, is_compatible_version, ErrorCode, SOFTWARE_VERSION};
+use crate::server::server_channel_delta::CHANNEL_DELTA_CAPABILITY;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{Channel, ChatMessage, Hello, Welcome};
+use ed25519_dalek::Signer;
+use log::{debug, info};
+use rand::{rng, RngCore};
+use std::collections::HashSet;
+use std::sync::Arc;
+use wg_2024::network::NodeId;
+
+// Optional protocol features this server implements, announced in the welcome and in
+// discovery responses
+pub(crate) const SERVER_CAPABILITIES: &[&str] = &[
+    "flow-control",
+    "history",
+    "search",
+    "moderation",
+    "send-receipts",
+    "file-transfer",
+    // Texts of encrypted channels are stored and relayed as they are
+    "encryption",
+    CHANNEL_DELTA_CAPABILITY,
+    COMPRESSION_CAPABILITY,
+    // The message of the day comes again as a notice once the client registers
+    "system-notice",
+];
+
+// Everything but the handshake itself, discovery, pongs and what linked servers send
+// needs the session a hello opens
+pub(crate) fn needs_session(kind: &MessageKind) -> bool {
+    !matches!(
+        kind,
+        MessageKind::CliHello(..)
+            | MessageKind::CliPong(..)
+            | MessageKind::DsvReq(..)
+            | MessageKind::Err(..)
+            | MessageKind::SrvFederate(..)
+            | MessageKind::SrvUnfederate(..)
+            | MessageKind::SrvFederatedMessage(..)
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SessionState {
+    // Handshake done, the client may read public state and register
+    Welcomed,
+    Registered,
+}
+
+// What the server knows about a client since its hello
+#[derive(Debug)]
+pub(crate) struct Session {
+    pub(crate) token: u64,
+    pub(crate) version: String,
+    pub(crate) capabilities: HashSet<String>,
+    pub(crate) state: SessionState,
+    // The channel list as of the last update this client got, the next delta is
+    // computed against it. Clients updated together share it
+    pub(crate) channels_seen: Option<Arc<Vec<Channel>>>,
+}
+
+impl ChatServerInternal {
+    pub(crate) fn msg_clihello(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        hello: Hello,
+    ) {
+        info!(target: self.log_target.as_str(), "Received hello from client {cli_node_id}, version {}", hello.version);
+        if !is_compatible_version(&hello.version) {
+            debug!(target: self.log_target.as_str(), "Client {cli_node_id} runs incompatible version {}", hello.version);
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::IncompatibleVersion,
+                    &format!("This server runs version {SOFTWARE_VERSION}"),
+                ),
+            ));
+            return;
+        }
+        let state = if self.usernames.contains_left(&cli_node_id) {
+            SessionState::Registered
+        } else {
+            SessionState::Welcomed
+        };
+        let session = Session {
+            token: rng().next_u64(),
+            version: hello.version,
+            capabilities: hello.capabilities.into_iter().collect(),
+            state,
+            channels_seen: None,
+        };
+        let welcome = Welcome {
+            server_name: self.config.name.clone().unwrap_or_default(),
+            motd: self.config.motd.clone().unwrap_or_default(),
+            session_token: session.token,
+            send_window: self.config.send_window,
+            history_page_size: self.config.history_page_size,
+            incarnation: self.incarnation,
+            identity_signature: self
+                .identity
+                .sign(&identity_proof(&hello.challenge, self.own_id))
+                .to_bytes()
+                .to_vec(),
+            capabilities: SERVER_CAPABILITIES
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        };
+        self.sessions.insert(cli_node_id, session);
+        replies.push((
+            cli_node_id,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvWelcome(welcome)),
+            },
+        ));
+        let list = self.full_channel_list(cli_node_id);
+        replies.push((cli_node_id, list));
+    }
+
+    pub(crate) fn set_session_state(&mut self, cli_node_id: NodeId, state: SessionState) {
+        if let Some(session) = self.sessions.get_mut(&cli_node_id) {
+            session.state = state;
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::protocol::ErrorCode;
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage, SetSlowMode};
+use log::debug;
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    pub(crate) fn msg_clisetslowmode(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &SetSlowMode,
+    ) {
+        if !self.is_operator(data.channel_id, cli_node_id) {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::NotOperator, "Only channel operators can do that"),
+            ));
+            return;
+        }
+        let Some(info) = self
+            .channel_info
+            .get_mut(&data.channel_id)
+            .filter(|x| x.kind == ChannelKind::Group)
+        else {
+            return;
+        };
+        info.slow_mode = u64::from(data.seconds);
+        info.last_post.clear();
+        let text = if data.seconds == 0 {
+            "Slow mode is off".to_string()
+        } else {
+            format!(
+                "Slow mode is on, members can send one message every {}s",
+                data.seconds
+            )
+        };
+        self.announce_in_channel(replies, data.channel_id, &text, None);
+    }
+
+    // Milliseconds until a client may send in a slow-mode channel again, 0 if it may now.
+    // Operators aren't slowed down
+    pub(crate) fn slow_mode_wait(&self, channel_id: u64, cli_node_id: NodeId, now: u64) -> u64 {
+        self.channel_info
+            .get(&channel_id)
+            .filter(|x| x.slow_mode != 0 && !x.is_operator(cli_node_id))
+            .and_then(|x| {
+                let last = x.last_post.get(&cli_node_id)?;
+                Some((last + x.slow_mode * 1000).saturating_sub(now))
+            })
+            .unwrap_or_default()
+    }
+
+    // Tells the client how long it still has to wait, so it can count down
+    pub(crate) fn slow_mode_error(
+        &self,
+        cli_node_id: NodeId,
+        channel_id: u64,
+        wait_ms: u64,
+    ) -> ChatMessage {
+        debug!(target: self.log_target.as_str(), "Client {cli_node_id} has to wait {wait_ms}ms before sending");
+        let mut err = ErrorCode::SlowMode.message(&format!(
+            "Slow mode is on, wait {}s before sending again",
+            wait_ms.div_ceil(1000)
+        ));
+        err.retry_after_ms = wait_ms;
+        err.channel_id = channel_id;
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::Err(err)),
+        }
+    }
+}
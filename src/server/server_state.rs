@@ -0,0 +1,260 @@
+use crate::secret::SecretKey;
+use crate::server::{ChannelInfo, ChannelStats, ChatServerConfig, ChatServerInternal, Departure};
+use bimap::BiHashMap;
+use chat_common::messages::{ChannelKind, MessageData, SetStatus};
+use ed25519_dalek::SigningKey;
+use itertools::Itertools;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use wg_2024::network::NodeId;
+
+// Everything a chat server knows, as a plain value. Collections are sorted so that two
+// snapshots of equivalent servers compare equal. Per-interval bandwidth accounting and
+// pending kick votes are transient and not part of it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerState {
+    pub own_id: NodeId,
+    pub config: ChatServerConfig,
+    pub channels: Vec<ChannelState>,
+    pub users: Vec<UserState>,
+    pub departures: Vec<DepartureState>,
+    pub guests: Vec<NodeId>,
+    // Secret half of the key advertised to clients, which would take a new one for an
+    // impostor
+    pub identity_key: SecretKey,
+    pub last_timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelState {
+    pub id: u64,
+    // None for personal channels of users that unregistered
+    pub name: Option<String>,
+    pub kind: ChannelKind,
+    pub members: Vec<NodeId>,
+    pub welcome: Option<String>,
+    // Oldest first
+    pub history: Vec<MessageData>,
+    pub stats_day: u64,
+    pub messages_today: u64,
+    pub last_activity: u64,
+    pub speakers: Vec<NodeId>,
+    pub operators: Vec<NodeId>,
+    pub creator: Option<NodeId>,
+    pub created_at: u64,
+    pub moderated: bool,
+    pub banned: Vec<NodeId>,
+    pub read_only: bool,
+    pub slow_mode: u64,
+    pub invite_only: bool,
+    pub invited: Vec<NodeId>,
+    // Message id, reaction and who reacted, sorted
+    pub reactions: Vec<(u64, String, Vec<NodeId>)>,
+    // Messages awaiting approval with their author, oldest first
+    pub pending: Vec<(NodeId, MessageData)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserState {
+    pub id: NodeId,
+    pub username: String,
+    pub last_seen: Option<u64>,
+    pub registered_at: Option<u64>,
+    // Chat messages acknowledged so far, for flow control
+    pub acked_messages: u64,
+    // Watched outside the user's channels, sorted
+    pub keywords: Vec<String>,
+    // None while the user is simply online
+    pub status: Option<SetStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepartureState {
+    pub username: String,
+    pub timestamp: u64,
+    pub channels: Vec<u64>,
+}
+
+impl ChatServerInternal {
+    #[must_use]
+    pub fn export(&self) -> ServerState {
+        let mut channels = self
+            .channel_info
+            .iter()
+            .map(|(id, info)| {
+                let mut members = info.members.iter().copied().collect::<Vec<_>>();
+                members.sort_unstable();
+                let mut speakers = info.stats.speakers.iter().copied().collect::<Vec<_>>();
+                speakers.sort_unstable();
+                let mut operators = info.operators.iter().copied().collect::<Vec<_>>();
+                operators.sort_unstable();
+                let mut banned = info.banned.iter().copied().collect::<Vec<_>>();
+                banned.sort_unstable();
+                let mut invited = info.invited.iter().copied().collect::<Vec<_>>();
+                invited.sort_unstable();
+                let reactions = info
+                    .reactions
+                    .iter()
+                    .sorted_by_key(|(id, _)| **id)
+                    .flat_map(|(id, by_emoji)| {
+                        by_emoji.iter().map(|(emoji, users)| {
+                            (*id, emoji.clone(), users.iter().copied().sorted().collect())
+                        })
+                    })
+                    .collect();
+                ChannelState {
+                    id: *id,
+                    name: self.channels.get_by_left(id).cloned(),
+                    kind: info.kind,
+                    members,
+                    welcome: info.welcome.clone(),
+                    history: info.history.iter().cloned().collect(),
+                    stats_day: info.stats.day,
+                    messages_today: info.stats.messages_today,
+                    last_activity: info.stats.last_activity,
+                    speakers,
+                    operators,
+                    creator: info.creator,
+                    created_at: info.created_at,
+                    moderated: info.moderated,
+                    banned,
+                    read_only: info.read_only,
+                    slow_mode: info.slow_mode,
+                    invite_only: info.invite_only,
+                    invited,
+                    reactions,
+                    pending: info.pending.values().cloned().collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        channels.sort_unstable_by_key(|x| x.id);
+        let mut users = self
+            .usernames
+            .iter()
+            .map(|(id, username)| UserState {
+                id: *id,
+                username: username.clone(),
+                last_seen: self.last_seen.get(id).copied(),
+                registered_at: self.registered_at.get(id).copied(),
+                acked_messages: self.acked_messages.get(id).copied().unwrap_or_default(),
+                keywords: self
+                    .keywords
+                    .get(id)
+                    .map(|x| x.iter().cloned().sorted().collect())
+                    .unwrap_or_default(),
+                status: self.statuses.get(id).cloned(),
+            })
+            .collect::<Vec<_>>();
+        users.sort_unstable_by_key(|x| x.id);
+        let mut departures = self
+            .departures
+            .iter()
+            .map(|(username, departure)| DepartureState {
+                username: username.clone(),
+                timestamp: departure.timestamp,
+                channels: departure.channels.clone(),
+            })
+            .collect::<Vec<_>>();
+        departures.sort_unstable_by(|a, b| a.username.cmp(&b.username));
+        let mut guests = self.guests.iter().copied().collect::<Vec<_>>();
+        guests.sort_unstable();
+        ServerState {
+            own_id: self.own_id,
+            config: self.config.clone(),
+            channels,
+            users,
+            departures,
+            guests,
+            identity_key: SecretKey::new(self.identity.to_bytes()),
+            last_timestamp: self.last_timestamp,
+        }
+    }
+
+    #[must_use]
+    pub fn restore(state: ServerState) -> Self {
+        let mut server = Self::with_config(state.own_id, state.config);
+        server.channels = BiHashMap::default();
+        server.channel_info = HashMap::new();
+        for chan in state.channels {
+            if let Some(name) = chan.name {
+                server.channels.insert(chan.id, name);
+            }
+            server.channel_info.insert(
+                chan.id,
+                ChannelInfo {
+                    kind: chan.kind,
+                    members: chan.members.into_iter().collect(),
+                    stats: ChannelStats {
+                        day: chan.stats_day,
+                        messages_today: chan.messages_today,
+                        last_activity: chan.last_activity,
+                        speakers: chan.speakers.into_iter().collect(),
+                    },
+                    welcome: chan.welcome,
+                    history: chan.history.into(),
+                    operators: chan.operators.into_iter().collect(),
+                    creator: chan.creator,
+                    created_at: chan.created_at,
+                    moderated: chan.moderated,
+                    pending: chan
+                        .pending
+                        .into_iter()
+                        .map(|(author, msg)| (msg.message_id, (author, msg)))
+                        .collect(),
+                    kick_votes: HashMap::new(),
+                    kicked: HashMap::new(),
+                    banned: chan.banned.into_iter().collect(),
+                    read_only: chan.read_only,
+                    slow_mode: chan.slow_mode,
+                    last_post: HashMap::new(),
+                    invite_only: chan.invite_only,
+                    invited: chan.invited.into_iter().collect(),
+                    reactions: chan.reactions.into_iter().fold(
+                        HashMap::new(),
+                        |mut acc: HashMap<u64, BTreeMap<String, HashSet<NodeId>>>,
+                         (id, emoji, users)| {
+                            acc.entry(id)
+                                .or_default()
+                                .insert(emoji, users.into_iter().collect());
+                            acc
+                        },
+                    ),
+                },
+            );
+        }
+        for user in state.users {
+            if let Some(seen) = user.last_seen {
+                server.last_seen.insert(user.id, seen);
+            }
+            if let Some(at) = user.registered_at {
+                server.registered_at.insert(user.id, at);
+            }
+            server.acked_messages.insert(user.id, user.acked_messages);
+            if !user.keywords.is_empty() {
+                server
+                    .keywords
+                    .insert(user.id, user.keywords.into_iter().collect());
+            }
+            if let Some(status) = user.status {
+                server.statuses.insert(user.id, status);
+            }
+            server.usernames.insert(user.id, user.username);
+        }
+        for departure in state.departures {
+            server.departures.insert(
+                departure.username,
+                Departure {
+                    timestamp: departure.timestamp,
+                    channels: departure.channels,
+                },
+            );
+        }
+        server.guests = state.guests.into_iter().collect();
+        server.identity = SigningKey::from_bytes(state.identity_key.as_bytes());
+        server.last_timestamp = state.last_timestamp;
+        server
+    }
+}
@@ -0,0 +1,42 @@
+use crate::server::ChatServerInternal;
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage};
+use common::slc_commands::{ServerEvent, ServerStats};
+use wg_2024::network::NodeId;
+
+// Running totals since the server started, reported through ServerCommand::QueryStats
+#[derive(Debug, Default)]
+pub(crate) struct ServerCounters {
+    // Chat messages sent to clients, once per recipient
+    pub(crate) messages_relayed: u64,
+    pub(crate) registrations: u64,
+    pub(crate) errors_sent: u64,
+}
+
+impl ChatServerInternal {
+    pub(crate) fn count_errors(&mut self, replies: &[(NodeId, ChatMessage)]) {
+        self.counters.errors_sent += replies
+            .iter()
+            .filter(|(_, msg)| matches!(msg.message_kind, Some(MessageKind::Err(_))))
+            .count() as u64;
+    }
+
+    pub(crate) fn stats_event(&self) -> ServerEvent {
+        ServerEvent::Stats(ServerStats {
+            messages_relayed: self.counters.messages_relayed,
+            registrations: self.counters.registrations,
+            errors_sent: self.counters.errors_sent,
+            registered_users: self.usernames.len() as u64,
+            // Group channels somebody is in
+            active_channels: self
+                .channel_info
+                .values()
+                .filter(|x| x.kind == ChannelKind::Group && !x.members.is_empty())
+                .count() as u64,
+            uptime_secs: self
+                .now()
+                .saturating_duration_since(self.started_at)
+                .as_secs(),
+        })
+    }
+}
@@ -0,0 +1,256 @@
+#[cfg(feature = "persistence")]
+use crate::clock::Instant;
+use crate::secret::SecretKey;
+#[cfg(feature = "persistence")]
+use crate::server::server_state::ServerState;
+#[cfg(feature = "persistence")]
+use crate::server::{ChatServerConfig, ChatServerInternal};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+#[cfg(feature = "persistence")]
+use log::{info, warn};
+use rand::{rng, RngCore};
+#[cfg(feature = "persistence")]
+use std::io::{Error, ErrorKind};
+#[cfg(feature = "persistence")]
+use std::path::PathBuf;
+#[cfg(feature = "persistence")]
+use std::{fs, io};
+#[cfg(feature = "persistence")]
+use wg_2024::network::NodeId;
+
+// Marks a sealed state file, so a plaintext one can still be loaded after enabling a key
+const SEALED_MAGIC: &[u8; 4] = b"CSS1";
+const NONCE_LEN: usize = 12;
+
+// Prepares serialized server state for writing to disk, encrypting it when a key is set.
+// Encryption only fails for inputs larger than the cipher's limit
+pub(crate) fn seal_state(
+    key: Option<&SecretKey>,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, chacha20poly1305::Error> {
+    let Some(key) = key else {
+        return Ok(plaintext.to_vec());
+    };
+    let mut nonce = [0u8; NONCE_LEN];
+    rng().fill_bytes(&mut nonce);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_bytes()));
+    let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext)?;
+    let mut sealed = Vec::with_capacity(SEALED_MAGIC.len() + NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(SEALED_MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+// Reverses `seal_state`. None means the data is sealed but the key is missing or wrong,
+// or the data was tampered with
+pub(crate) fn open_state(key: Option<&SecretKey>, data: &[u8]) -> Option<Vec<u8>> {
+    let Some(sealed) = data.strip_prefix(SEALED_MAGIC) else {
+        return Some(data.to_vec());
+    };
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key?.as_bytes()));
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+}
+
+// File the server state is saved to, and whether it changed since the last save
+#[cfg(feature = "persistence")]
+#[derive(Debug)]
+pub(crate) struct StateStore {
+    path: PathBuf,
+    dirty: bool,
+    last_save: Instant,
+}
+
+#[cfg(feature = "persistence")]
+impl ChatServerInternal {
+    // Starts a server from the state saved in `path`, or a fresh one if there is no such
+    // file yet. The given config replaces the saved one, and its state key must match
+    // the one the file was sealed with
+    pub fn new_with_store(
+        id: NodeId,
+        config: ChatServerConfig,
+        path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let mut server = match fs::read(&path) {
+            Ok(data) => {
+                let plaintext = open_state(config.state_key.as_ref(), &data).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "can't decrypt the server state")
+                })?;
+                let mut state = serde_json::from_slice::<ServerState>(&plaintext)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                state.own_id = id;
+                state.config = config;
+                Self::restore(state)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::with_config(id, config),
+            Err(e) => return Err(e),
+        };
+        info!(target: server.log_target.as_str(), "Keeping server state in {}", path.display());
+        let now = server.now();
+        server.store = Some(StateStore {
+            path,
+            dirty: true,
+            last_save: now,
+        });
+        Ok(server)
+    }
+
+    // Writes the state now if it changed since the last save. Also called on ticks once
+    // `store_interval` has passed
+    pub fn flush_store(&mut self) -> io::Result<()> {
+        let Some(store) = self.store.as_ref().filter(|x| x.dirty) else {
+            return Ok(());
+        };
+        // The key never goes in the file it protects. Serde skips it already, this keeps
+        // it out whatever the config's serialization turns into
+        let mut state = self.export();
+        state.config.state_key = None;
+        let json = serde_json::to_vec(&state).map_err(Error::other)?;
+        let data = seal_state(self.config.state_key.as_ref(), &json)
+            .map_err(|_| Error::other("can't encrypt the server state"))?;
+        // Written aside first, so that a crash never leaves a truncated file behind
+        let tmp = store.path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &store.path)?;
+        let now = self.now();
+        if let Some(store) = self.store.as_mut() {
+            store.dirty = false;
+            store.last_save = now;
+        }
+        Ok(())
+    }
+
+    // The last save as the new clock sees it, see `set_clock`
+    pub(crate) fn rebase_store(&mut self, now: Instant) {
+        if let Some(store) = self.store.as_mut() {
+            store.last_save = now;
+        }
+    }
+
+    pub(crate) fn mark_state_changed(&mut self) {
+        if let Some(store) = self.store.as_mut() {
+            store.dirty = true;
+        }
+    }
+
+    pub(crate) fn save_store_if_due(&mut self, now: Instant) {
+        if self
+            .store
+            .as_ref()
+            .is_some_and(|x| x.dirty && now - x.last_save >= self.config.store_interval)
+        {
+            if let Err(e) = self.flush_store() {
+                warn!(target: self.log_target.as_str(), "Could not save server state: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATE: &[u8] = br#"{"channels":["games"]}"#;
+
+    #[test]
+    fn sealed_state_opens_with_its_key() {
+        let key = SecretKey::new([7; 32]);
+        let sealed = seal_state(Some(&key), STATE).expect("sealing failed");
+        assert!(sealed.starts_with(SEALED_MAGIC));
+        assert!(!sealed.windows(STATE.len()).any(|x| x == STATE));
+        assert_eq!(open_state(Some(&key), &sealed).as_deref(), Some(STATE));
+    }
+
+    #[test]
+    fn sealed_state_needs_the_right_key() {
+        let sealed = seal_state(Some(&SecretKey::new([7; 32])), STATE).expect("sealing failed");
+        assert_eq!(open_state(None, &sealed), None);
+        assert_eq!(open_state(Some(&SecretKey::new([8; 32])), &sealed), None);
+    }
+
+    #[test]
+    fn tampered_state_does_not_open() {
+        let key = SecretKey::new([7; 32]);
+        let mut sealed = seal_state(Some(&key), STATE).expect("sealing failed");
+        if let Some(last) = sealed.last_mut() {
+            *last ^= 1;
+        }
+        assert_eq!(open_state(Some(&key), &sealed), None);
+        assert_eq!(
+            open_state(Some(&key), &sealed[..SEALED_MAGIC.len() + 4]),
+            None
+        );
+    }
+
+    #[test]
+    fn plaintext_state_is_kept_as_is() {
+        assert_eq!(seal_state(None, STATE).as_deref(), Ok(STATE));
+        // A file saved before a key was set still loads
+        let key = SecretKey::new([7; 32]);
+        assert_eq!(open_state(Some(&key), STATE).as_deref(), Some(STATE));
+    }
+
+    #[cfg(feature = "persistence")]
+    fn state_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "chat_server_state_{}_{name}.json",
+            std::process::id()
+        ))
+    }
+
+    #[cfg(feature = "persistence")]
+    fn sealed_config() -> ChatServerConfig {
+        ChatServerConfig {
+            state_key: Some(SecretKey::new([7; 32])),
+            ..ChatServerConfig::default()
+        }
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn users_channels_and_history_survive_a_restart() {
+        let path = state_path("restart");
+        let mut server = ChatServerInternal::new_with_store(1, sealed_config(), &path)
+            .expect("can't open the store");
+        let games = server.register_in(5, "alice", "games");
+        server.post(5, games, "hi");
+        server.flush_store().expect("can't save the state");
+        let restarted = ChatServerInternal::new_with_store(1, sealed_config(), &path);
+        let _ = fs::remove_file(&path);
+        let restarted = restarted.expect("can't load the state");
+        assert_eq!(
+            restarted.usernames.get_by_left(&5).map(String::as_str),
+            Some("alice")
+        );
+        let info = &restarted.channel_info[&games];
+        assert!(info.members.contains(&5));
+        assert!(info.history.iter().any(|x| x.message == "hi"));
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn sealed_stores_need_their_key() {
+        let path = state_path("key");
+        let mut server = ChatServerInternal::new_with_store(1, sealed_config(), &path)
+            .expect("can't open the store");
+        server.register_in(5, "alice", "games");
+        server.flush_store().expect("can't save the state");
+        let data = fs::read(&path);
+        let without_key = ChatServerInternal::new_with_store(1, ChatServerConfig::default(), &path);
+        let _ = fs::remove_file(&path);
+        assert!(!data
+            .expect("no state saved")
+            .windows(5)
+            .any(|x| x == b"alice"));
+        assert_eq!(
+            without_key.map(|_| ()).map_err(|e| e.kind()),
+            Err(ErrorKind::InvalidData)
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use crate::protocol::ErrorCode;
+use crate::server::{ChatServerInternal, IDLE_AFTER_MS};
+use chat_common::messages::chat_message::MessageKind;
+use chat_common::messages::{ChannelKind, ChatMessage, Kicked, VoteKick};
+use log::{debug, info};
+use wg_2024::network::NodeId;
+
+impl ChatServerInternal {
+    fn vote_kick_window_ms(&self) -> u64 {
+        u64::try_from(self.config.vote_kick_window.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    pub(crate) fn kick_cooldown_ms(&self) -> u64 {
+        u64::try_from(self.config.kick_cooldown.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    // Tells a client kicked from a channel how long it still has to wait to join it again
+    pub(crate) fn kick_cooldown_error(
+        &self,
+        cli_node_id: NodeId,
+        channel_id: u64,
+        wait_ms: u64,
+    ) -> ChatMessage {
+        debug!(target: self.log_target.as_str(), "Client {cli_node_id} has to wait {wait_ms}ms before joining again");
+        let mut err = ErrorCode::KickCooldown.message(&format!(
+            "You were kicked from this channel, wait {}s before joining again",
+            wait_ms.div_ceil(1000)
+        ));
+        err.retry_after_ms = wait_ms;
+        err.channel_id = channel_id;
+        ChatMessage {
+            own_id: u32::from(self.own_id),
+            request_id: 0,
+            message_kind: Some(MessageKind::Err(err)),
+        }
+    }
+
+    // Whether an operator of the channel is in it and was seen recently
+    fn has_active_operator(&self, channel_id: u64, now: u64) -> bool {
+        self.channel_info.get(&channel_id).is_some_and(|info| {
+            info.operator_ids().any(|id| {
+                info.members.contains(&id)
+                    && self
+                        .last_seen
+                        .get(&id)
+                        .is_some_and(|seen| now.saturating_sub(*seen) < IDLE_AFTER_MS)
+            })
+        })
+    }
+
+    // Removes a member from a group channel, telling it and the other members. It can't
+    // join again until the cooldown is over
+    pub(crate) fn kick_from_channel(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        channel_id: u64,
+        target: NodeId,
+        reason: &str,
+    ) {
+        let now = self.wall_now();
+        let cooldown = self.kick_cooldown_ms();
+        let Some(info) = self.channel_info.get_mut(&channel_id) else {
+            return;
+        };
+        if !info.members.remove(&target) {
+            return;
+        }
+        info.kick_votes.remove(&target);
+        info.kicked
+            .retain(|_, at| now.saturating_sub(*at) < cooldown);
+        info.kicked.insert(target, now);
+        let username = self
+            .usernames
+            .get_by_left(&target)
+            .cloned()
+            .unwrap_or_else(|| target.to_string());
+        info!(target: self.log_target.as_str(), "Kicking client {target} from channel {channel_id}: {reason}");
+        replies.push((
+            target,
+            ChatMessage {
+                own_id: u32::from(self.own_id),
+                request_id: 0,
+                message_kind: Some(MessageKind::SrvKicked(Kicked {
+                    channel_id,
+                    reason: reason.to_string(),
+                })),
+            },
+        ));
+        self.announce_in_channel(
+            replies,
+            channel_id,
+            &format!("{username} was kicked ({reason})"),
+            None,
+        );
+        replies.extend(self.generate_channel_updates(None));
+    }
+
+    pub(crate) fn msg_clivotekick(
+        &mut self,
+        replies: &mut Vec<(NodeId, ChatMessage)>,
+        cli_node_id: NodeId,
+        data: &VoteKick,
+    ) {
+        let now = self.wall_now();
+        let target = self.usernames.get_by_right(&data.username).copied();
+        let (Some(info), Some(voter), Some(target)) = (
+            self.channel_info.get(&data.channel_id),
+            self.usernames.get_by_left(&cli_node_id).cloned(),
+            target,
+        ) else {
+            replies.push((
+                cli_node_id,
+                self.error_message(ErrorCode::VoteKickInvalid, "No such user or channel"),
+            ));
+            return;
+        };
+        if info.kind != ChannelKind::Group
+            || !info.members.contains(&cli_node_id)
+            || !info.members.contains(&target)
+            || target == cli_node_id
+        {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::VoteKickInvalid,
+                    "You can only vote to kick another member of your channel",
+                ),
+            ));
+            return;
+        }
+        if self.has_active_operator(data.channel_id, now) {
+            replies.push((
+                cli_node_id,
+                self.error_message(
+                    ErrorCode::OperatorPresent,
+                    "This channel has an active operator, ask them instead",
+                ),
+            ));
+            return;
+        }
+        let window = self.vote_kick_window_ms();
+        let threshold = self.config.vote_kick_threshold;
+        let Some(info) = self.channel_info.get_mut(&data.channel_id) else {
+            return;
+        };
+        let votes = info.kick_votes.entry(target).or_default();
+        // Members who left since they voted don't count anymore
+        votes.retain(|voter, at| info.members.contains(voter) && now.saturating_sub(*at) < window);
+        votes.insert(cli_node_id, now);
+        let count = votes.len();
+        self.announce_in_channel(
+            replies,
+            data.channel_id,
+            &format!(
+                "{voter} voted to kick {} ({count}/{threshold})",
+                data.username
+            ),
+            None,
+        );
+        if count >= threshold {
+            self.kick_from_channel(replies, data.channel_id, target, "vote kick");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{errors_to, ChatServerConfig};
+    use chat_common::messages::JoinChannel;
+
+    // Clients 5 to 8, registered as user5 to user8, in a channel without operators
+    fn channel_without_operator() -> (ChatServerInternal, u64) {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let games = server.create_group_channel("games", None);
+        for id in 5..=8 {
+            server.register_in(id, &format!("user{id}"), "games");
+        }
+        (server, games)
+    }
+
+    fn vote(
+        server: &mut ChatServerInternal,
+        voter: NodeId,
+        channel_id: u64,
+        username: &str,
+    ) -> Vec<(NodeId, ChatMessage)> {
+        let mut replies = vec![];
+        server.msg_clivotekick(
+            &mut replies,
+            voter,
+            &VoteKick {
+                channel_id,
+                username: username.to_string(),
+            },
+        );
+        replies
+    }
+
+    fn is_member(server: &ChatServerInternal, channel_id: u64, id: NodeId) -> bool {
+        server.channel_info[&channel_id].members.contains(&id)
+    }
+
+    #[test]
+    fn enough_votes_kick_a_member() {
+        let (mut server, games) = channel_without_operator();
+        vote(&mut server, 5, games, "user8");
+        vote(&mut server, 6, games, "user8");
+        assert!(is_member(&server, games, 8));
+        let replies = vote(&mut server, 7, games, "user8");
+        assert!(!is_member(&server, games, 8));
+        assert!(replies.iter().any(|(to, msg)| *to == 8
+            && matches!(&msg.message_kind, Some(MessageKind::SrvKicked(x)) if x.channel_id == games)));
+    }
+
+    #[test]
+    fn kicked_members_wait_before_joining_again() {
+        let (mut server, games) = channel_without_operator();
+        for voter in 5..=7 {
+            vote(&mut server, voter, games, "user8");
+        }
+        let mut replies = vec![];
+        server.msg_clijoin(
+            &mut replies,
+            &JoinChannel {
+                channel_id: Some(games),
+                channel_name: String::new(),
+            },
+            8,
+        );
+        assert_eq!(errors_to(&replies, 8), vec![ErrorCode::KickCooldown]);
+        assert!(!is_member(&server, games, 8));
+    }
+
+    #[test]
+    fn votes_of_members_who_left_do_not_count() {
+        let (mut server, games) = channel_without_operator();
+        vote(&mut server, 5, games, "user8");
+        vote(&mut server, 6, games, "user8");
+        let mut replies = vec![];
+        server.msg_clileave(&mut replies, 5);
+        vote(&mut server, 7, games, "user8");
+        assert!(is_member(&server, games, 8));
+    }
+
+    #[test]
+    fn only_other_members_can_be_voted_out() {
+        let (mut server, games) = channel_without_operator();
+        let replies = vote(&mut server, 5, games, "user5");
+        assert_eq!(errors_to(&replies, 5), vec![ErrorCode::VoteKickInvalid]);
+        server.msg_cliregisterrequest(&mut vec![], 9, "user9".to_string());
+        let replies = vote(&mut server, 5, games, "user9");
+        assert_eq!(errors_to(&replies, 5), vec![ErrorCode::VoteKickInvalid]);
+        let replies = vote(&mut server, 9, games, "user8");
+        assert_eq!(errors_to(&replies, 9), vec![ErrorCode::VoteKickInvalid]);
+    }
+
+    #[test]
+    fn active_operators_are_asked_instead() {
+        let mut server = ChatServerInternal::with_config(1, ChatServerConfig::default());
+        let games = server.register_in(5, "owner", "games");
+        server.register_in(6, "user6", "games");
+        server.register_in(7, "user7", "games");
+        let replies = vote(&mut server, 6, games, "user7");
+        assert_eq!(errors_to(&replies, 6), vec![ErrorCode::OperatorPresent]);
+    }
+}